@@ -88,6 +88,7 @@ impl Plugin for Exec {
         let interface = types::NetInterface {
             mac_address: mac_address,
             subnets: Option::from(subnets),
+            stats: None,
         };
         interfaces.insert(name, interface);
 
@@ -96,6 +97,12 @@ impl Plugin for Exec {
             dns_server_ips: None,
             dns_search_domains: None,
             interfaces: Some(interfaces),
+            gateway: None,
+            gateway_reachable: None,
+            warnings: None,
+            changed_sysctls: None,
+            netns_inode: None,
+            firewall_chains: None,
         };
 
         Ok(response)
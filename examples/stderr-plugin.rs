@@ -33,6 +33,12 @@ impl Plugin for Exec {
             dns_server_ips: None,
             dns_search_domains: None,
             interfaces: None,
+            gateway: None,
+            gateway_reachable: None,
+            warnings: None,
+            changed_sysctls: None,
+            netns_inode: None,
+            firewall_chains: None,
         };
 
         Ok(response)
@@ -0,0 +1,32 @@
+#!/bin/bash
+
+# Compare the setup time of the iptables driver with and without
+# iptables-restore for a container with many port mappings.
+
+# Netavark binary
+NETAVARK=${NETAVARK:-./bin/netavark}
+# Number of port mappings
+PORTS=${PORTS:-500}
+
+trap cleanup EXIT
+
+function cleanup() {
+    kill -9 $netnspid
+    rm -f $config
+}
+
+config=$(mktemp)
+jq --argjson n "$PORTS" \
+    '.port_mappings = [range($n) | {host_ip: "", container_port: (10000 + .), host_port: (10000 + .), range: 1, protocol: "tcp"}]' \
+    ./test/testfiles/simplebridge.json >$config
+
+for restore in 0 1; do
+    unshare -n sleep 100 &
+    netnspid=$!
+
+    echo "NETAVARK_IPTABLES_RESTORE=$restore ($PORTS port mappings)"
+    NETAVARK_IPTABLES_RESTORE=$restore NETAVARK_FW=iptables \
+        unshare -n perf stat $NETAVARK -f $config setup /proc/$netnspid/ns/net >/dev/null
+
+    kill -9 $netnspid
+done
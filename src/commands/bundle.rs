@@ -0,0 +1,182 @@
+//! Collects the netavark state and firewall rules for bug reports
+use crate::error::{NetavarkError, NetavarkResult};
+use crate::firewall::{self, iptables};
+use crate::network::state::{self, State};
+
+use clap::Parser;
+use log::debug;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Parser, Debug)]
+pub struct Bundle {}
+
+#[derive(Debug, Serialize)]
+struct BundleOutput {
+    version: &'static str,
+    environment: Environment,
+    /// state of the --config directory, unset without one
+    state: Option<State>,
+    /// netavark rules per command, iptables and ip6tables
+    rules: BTreeMap<&'static str, Vec<String>>,
+    /// parts which could not be collected
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Environment {
+    kernel: Option<String>,
+    firewall_driver: Option<&'static str>,
+}
+
+impl Bundle {
+    /// The bundle command prints the state, the live netavark firewall rules
+    /// and the detected environment as one JSON document. Nothing on the host
+    /// is changed.
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn exec(
+        &self,
+        config_dir: Option<String>,
+        firewall_driver: Option<String>,
+    ) -> NetavarkResult<()> {
+        debug!("{:?}", "Collecting bundle...");
+        let bundle = collect(
+            &config_dir,
+            firewall_driver.as_deref(),
+            iptables::list_netavark_rules,
+        );
+        println!("{}", serde_json::to_string_pretty(&bundle)?);
+        Ok(())
+    }
+}
+
+impl Default for Bundle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collect every part of the bundle, failures are recorded in the bundle so
+/// the remaining parts are still reported.
+fn collect<F>(
+    config_dir: &Option<String>,
+    forced_driver: Option<&str>,
+    list_rules: F,
+) -> BundleOutput
+where
+    F: Fn(bool) -> NetavarkResult<Vec<String>>,
+{
+    let mut errors = Vec::new();
+    let mut record = |part: &str, e: NetavarkError| errors.push(format!("{}: {}", part, e));
+
+    let kernel = match nix::sys::utsname::uname() {
+        Ok(uts) => Some(uts.release().to_string_lossy().to_string()),
+        Err(e) => {
+            record("kernel", std::io::Error::from(e).into());
+            None
+        }
+    };
+    let firewall_driver = match firewall::get_firewall_driver_name(forced_driver) {
+        Ok(name) => Some(name),
+        Err(e) => {
+            record("firewall driver", e);
+            None
+        }
+    };
+
+    let state = match config_dir {
+        Some(dir) => match state::read_state(dir) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                record("state", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let mut rules = BTreeMap::new();
+    for (cmd, ipv6) in [("iptables", false), ("ip6tables", true)] {
+        match list_rules(ipv6) {
+            Ok(r) => {
+                rules.insert(cmd, r);
+            }
+            Err(e) => record(cmd, e),
+        }
+    }
+
+    BundleOutput {
+        version: env!("CARGO_PKG_VERSION"),
+        environment: Environment {
+            kernel,
+            firewall_driver,
+        },
+        state,
+        rules,
+        errors,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::state::{StateFile, VethPair};
+
+    // The bundle has all sections, failed parts are listed as errors
+    #[test]
+    fn test_collect_bundle() {
+        let dir = std::env::temp_dir().join(format!("netavark-bundle-{}", std::process::id()));
+        let config_dir = Some(dir.to_str().unwrap().to_string());
+        let mut state = StateFile::open(config_dir.as_deref().unwrap()).unwrap();
+        state.state.veth_pairs.insert(
+            "c1".to_string(),
+            [(
+                "podman".to_string(),
+                VethPair {
+                    host_interface: "veth0".to_string(),
+                    host_index: 10,
+                    container_interface: "eth0".to_string(),
+                    container_index: 2,
+                },
+            )]
+            .into(),
+        );
+        state.save().unwrap();
+        drop(state);
+
+        let bundle = collect(&config_dir, None, |ipv6| {
+            if ipv6 {
+                Err(NetavarkError::msg("ip6tables not found"))
+            } else {
+                Ok(vec!["-t nat -N NETAVARK-1D8721804F16F".to_string()])
+            }
+        });
+        let json = serde_json::to_value(&bundle).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert!(json["environment"]["kernel"].is_string(), "{}", json);
+        assert_eq!(
+            json["state"]["veth_pairs"]["c1"]["podman"]["host_interface"],
+            "veth0"
+        );
+        assert_eq!(
+            json["rules"],
+            serde_json::json!({"iptables": ["-t nat -N NETAVARK-1D8721804F16F"]})
+        );
+        assert_eq!(
+            json["errors"],
+            serde_json::json!(["ip6tables: ip6tables not found"])
+        );
+
+        // without config directory there is no state
+        let bundle = collect(&None, None, |_| Ok(Vec::new()));
+        let json = serde_json::to_value(&bundle).unwrap();
+        assert!(json["state"].is_null());
+        assert!(json.get("errors").is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+//! Prints the firewall rules setup would create for the given config
+use crate::error::{NetavarkError, NetavarkResult};
+use crate::firewall::{self, FirewallDriver};
+use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::network::{self, core_utils};
+
+use clap::Parser;
+use log::debug;
+
+#[derive(Parser, Debug)]
+pub struct DumpRules {}
+
+impl DumpRules {
+    /// The dump-rules command prints the firewall rules which setup would create
+    /// for the given configuration without applying them.
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn exec(
+        &self,
+        input_file: Option<String>,
+        plugin_directories: Option<Vec<String>>,
+        firewall_driver: Option<String>,
+    ) -> NetavarkResult<()> {
+        debug!("{:?}", "Rendering firewall rules...");
+        let mut network_options = network::types::NetworkOptions::load(input_file)?;
+        // without a config directory names can only refer to the given networks
+        network::state::resolve_allowed_networks(&mut network_options, &None)?;
+
+        let firewall_driver = firewall::get_supported_firewall_driver(firewall_driver.as_deref())?;
+
+        let dns_port = core_utils::get_netavark_dns_port()?;
+
+        let rules = render_rules(
+            &network_options,
+            firewall_driver.as_ref(),
+            dns_port,
+            &plugin_directories,
+        )?;
+        print!("{}", rules);
+        Ok(())
+    }
+}
+
+impl Default for DumpRules {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Render the firewall rules for all networks, each network starts with a
+/// comment line with the network name. Networks are sorted by name so the
+/// output is stable.
+pub(crate) fn render_rules(
+    network_options: &network::types::NetworkOptions,
+    firewall_driver: &dyn FirewallDriver,
+    dns_port: u16,
+    plugin_directories: &Option<Vec<String>>,
+) -> NetavarkResult<String> {
+    let container_ids = network_options.get_container_ids(None)?;
+
+    let mut net_names: Vec<&String> = network_options.network_info.keys().collect();
+    net_names.sort();
+
+    let mut out = String::new();
+    for net_name in net_names {
+        let network = &network_options.network_info[net_name];
+        let per_network_opts = network_options.networks.get(net_name).ok_or_else(|| {
+            NetavarkError::Message(format!(
+                "network options for network {} not found",
+                net_name
+            ))
+        })?;
+
+        let mut driver = get_network_driver(
+            DriverInfo {
+                firewall: firewall_driver,
+                firewall_rules: &[],
+                container_id: &container_ids[net_name],
+                container_name: &network_options.container_name,
+                container_dns_servers: &network_options.dns_servers,
+                // no namespaces are needed to render the rules
+                netns_host: -1,
+                netns_container: -1,
+                netns_path: "",
+                network,
+                per_network_opts,
+                network_count: network_options.network_info.len(),
+                port_mappings: &network_options.port_mappings,
+                dns_port,
+                skip_firewall: false,
+            },
+            plugin_directories,
+        )?;
+        driver.validate()?;
+
+        out.push_str(&format!("# {}\n", net_name));
+        for rule in driver.render_firewall()? {
+            out.push_str(&rule);
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::iptables;
+
+    // Compare the rendered iptables rules against the stored snapshot
+    #[test]
+    fn test_render_rules_iptables() {
+        let opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/portmapping.json".to_owned(),
+        ))
+        .unwrap();
+        let driver = iptables::new_without_conn();
+        let rules = render_rules(&opts, driver.as_ref(), 53, &None).unwrap();
+        let expected = std::fs::read_to_string("src/test/config/portmapping.rules").unwrap();
+        assert_eq!(rules, expected);
+    }
+}
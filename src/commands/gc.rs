@@ -0,0 +1,69 @@
+//! Removes firewall chains of containers which no longer exist
+use crate::error::NetavarkResult;
+use crate::firewall;
+use crate::network::state::StateFile;
+
+use clap::Parser;
+use log::debug;
+use serde::Serialize;
+use std::collections::HashSet;
+
+#[derive(Parser, Debug)]
+pub struct Gc {}
+
+#[derive(Debug, Serialize)]
+struct GcResult {
+    removed_chains: Vec<String>,
+}
+
+impl Gc {
+    /// The gc command removes the port forwarding chains left behind by
+    /// containers whose network namespace is gone, e.g. because teardown
+    /// was never called.
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn exec(
+        &self,
+        config_dir: Option<String>,
+        firewall_driver: Option<String>,
+    ) -> NetavarkResult<()> {
+        debug!("{:?}", "Removing stale firewall chains...");
+        let firewall_driver = firewall::get_supported_firewall_driver(firewall_driver.as_deref())?;
+
+        // hold the state lock so no setup or teardown runs in between,
+        // without state only chains nothing refers to can be removed
+        let mut state = match &config_dir {
+            Some(dir) => Some(StateFile::open(dir)?),
+            None => None,
+        };
+        let (stale, known) = match &state {
+            Some(s) => s.state.partition_port_forward_chains(),
+            None => (HashSet::new(), HashSet::new()),
+        };
+
+        let removed_chains = firewall_driver.remove_stale_port_forward_chains(&stale, &known)?;
+
+        if let Some(state) = &mut state {
+            if !stale.is_empty() {
+                state
+                    .state
+                    .port_forward_chains
+                    .retain(|name, _| !stale.contains(name));
+                state.save()?;
+            }
+        }
+
+        let out = serde_json::to_string(&GcResult { removed_chains })?;
+        println!("{}", out);
+        debug!("{:?}", "GC complete");
+        Ok(())
+    }
+}
+
+impl Default for Gc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,109 @@
+//! Prints the veth pairs of a container recorded by setup
+use crate::error::{NetavarkError, NetavarkResult};
+use crate::network::state::{StateFile, VethPair};
+
+use clap::Parser;
+use log::debug;
+use std::collections::BTreeMap;
+
+#[derive(Parser, Debug)]
+pub struct Interfaces {
+    /// Container id used for setup
+    #[clap(required = true)]
+    container_id: String,
+}
+
+impl Interfaces {
+    /// The interfaces command prints the host and container interface of the
+    /// veth pair of each bridge network of the container from the state,
+    /// without the need for the container configuration.
+    pub fn new(container_id: String) -> Self {
+        Self { container_id }
+    }
+
+    pub fn exec(&self, config_dir: Option<String>) -> NetavarkResult<()> {
+        debug!(
+            "Looking up the interfaces of container {}",
+            self.container_id
+        );
+        let pairs = get_veth_pairs(&config_dir, &self.container_id)?;
+        println!("{}", serde_json::to_string(&pairs)?);
+        Ok(())
+    }
+}
+
+/// Get the recorded veth pairs of the container, the map key is the network name.
+fn get_veth_pairs(
+    config_dir: &Option<String>,
+    container_id: &str,
+) -> NetavarkResult<BTreeMap<String, VethPair>> {
+    let dir = match config_dir {
+        Some(dir) => dir,
+        None => {
+            return Err(NetavarkError::msg(
+                "looking up interfaces requires --config to be specified",
+            ))
+        }
+    };
+    let mut state = StateFile::open(dir)?;
+    match state.state.veth_pairs.remove(container_id) {
+        Some(pairs) => Ok(pairs),
+        None => Err(NetavarkError::Message(format!(
+            "no interfaces recorded for container {}",
+            container_id
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(host: &str, host_index: u32) -> VethPair {
+        VethPair {
+            host_interface: host.to_string(),
+            host_index,
+            container_interface: "eth0".to_string(),
+            container_index: 2,
+        }
+    }
+
+    // The pairs recorded in the state are returned per network
+    #[test]
+    fn test_get_veth_pairs() {
+        let dir = std::env::temp_dir().join(format!("netavark-interfaces-{}", std::process::id()));
+        let config_dir = Some(dir.to_str().unwrap().to_string());
+
+        let mut state = StateFile::open(config_dir.as_deref().unwrap()).unwrap();
+        state.state.veth_pairs.insert(
+            "c1".to_string(),
+            [
+                ("podman".to_string(), pair("veth0", 10)),
+                ("other".to_string(), pair("veth1", 11)),
+            ]
+            .into(),
+        );
+        state.save().unwrap();
+        drop(state);
+
+        let pairs = get_veth_pairs(&config_dir, "c1").unwrap();
+        assert_eq!(
+            pairs,
+            [
+                ("other".to_string(), pair("veth1", 11)),
+                ("podman".to_string(), pair("veth0", 10)),
+            ]
+            .into()
+        );
+        assert_eq!(
+            serde_json::to_string(&pairs["podman"]).unwrap(),
+            r#"{"host_interface":"veth0","host_index":10,"container_interface":"eth0","container_index":2}"#
+        );
+
+        let err = get_veth_pairs(&config_dir, "c2").unwrap_err();
+        assert_eq!(err.to_string(), "no interfaces recorded for container c2");
+        assert!(get_veth_pairs(&None, "c1").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
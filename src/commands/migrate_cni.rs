@@ -0,0 +1,381 @@
+//! Translates a CNI network config file into a netavark network definition
+use crate::error::{NetavarkError, NetavarkResult};
+use crate::network::constants::{
+    DRIVER_BRIDGE, DRIVER_IPVLAN, DRIVER_MACVLAN, IPAM_DHCP, IPAM_HOSTLOCAL, OPTION_MODE,
+    OPTION_MTU,
+};
+use crate::network::types::{LeaseRange, Network, Route, Subnet};
+
+use clap::Parser;
+use ipnet::IpNet;
+use log::{debug, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::IpAddr;
+
+/// CNI plugins whose function netavark provides itself.
+const BUILTIN_PLUGINS: [&str; 3] = ["portmap", "firewall", "tuning"];
+
+#[derive(Parser, Debug)]
+pub struct MigrateCni {
+    /// CNI network config file, a .conflist or a single plugin .conf
+    #[clap(required = true)]
+    cni_file: String,
+}
+
+#[derive(Debug, Serialize)]
+struct MigrateResult {
+    network: Network,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CniPlugin {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(rename = "bridge")]
+    bridge: Option<String>,
+    #[serde(rename = "isGateway", default)]
+    is_gateway: bool,
+    #[serde(rename = "ipMasq", default)]
+    ip_masq: bool,
+    #[serde(rename = "mtu")]
+    mtu: Option<u32>,
+    #[serde(rename = "vlan")]
+    vlan: Option<u16>,
+    #[serde(rename = "master")]
+    master: Option<String>,
+    #[serde(rename = "mode")]
+    mode: Option<String>,
+    #[serde(rename = "ipam")]
+    ipam: Option<CniIpam>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CniIpam {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(rename = "ranges", default)]
+    ranges: Vec<Vec<CniRange>>,
+    #[serde(rename = "routes", default)]
+    routes: Vec<CniRoute>,
+    /// the old host-local format with a single subnet
+    #[serde(flatten)]
+    range: Option<CniRange>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CniRange {
+    #[serde(rename = "subnet")]
+    subnet: IpNet,
+    #[serde(rename = "gateway")]
+    gateway: Option<IpAddr>,
+    #[serde(rename = "rangeStart")]
+    range_start: Option<IpAddr>,
+    #[serde(rename = "rangeEnd")]
+    range_end: Option<IpAddr>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CniRoute {
+    #[serde(rename = "dst")]
+    dst: IpNet,
+    #[serde(rename = "gw")]
+    gw: Option<IpAddr>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CniArgs {
+    #[serde(rename = "podman_options", default)]
+    podman_options: HashMap<String, String>,
+}
+
+impl MigrateCni {
+    /// The migrate-cni command prints the network definition netavark uses
+    /// for the network of the given CNI config, together with warnings for the
+    /// parts which cannot be translated.
+    pub fn new(cni_file: String) -> Self {
+        Self { cni_file }
+    }
+
+    pub fn exec(&self) -> NetavarkResult<()> {
+        debug!("Translating CNI config {}", self.cni_file);
+        let file = File::open(&self.cni_file).map_err(|e| {
+            NetavarkError::Message(format!("failed to open {}: {}", self.cni_file, e))
+        })?;
+        let config: serde_json::Value = serde_json::from_reader(BufReader::new(file))?;
+        let (network, warnings) = translate_cni_config(config)?;
+        for warning in &warnings {
+            warn!("{}", warning);
+        }
+        println!(
+            "{}",
+            serde_json::to_string(&MigrateResult { network, warnings })?
+        );
+        Ok(())
+    }
+}
+
+/// Translate a CNI config, either a plugin list or a single plugin, into a
+/// network. Plugins and fields without an equivalent are returned as warnings.
+fn translate_cni_config(mut config: serde_json::Value) -> NetavarkResult<(Network, Vec<String>)> {
+    let name = match config.get("name").and_then(|n| n.as_str()) {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => return Err(NetavarkError::msg("CNI config has no network name")),
+    };
+    let args: CniArgs = match config.get_mut("args").map(serde_json::Value::take) {
+        Some(args) => serde_json::from_value(args)?,
+        None => CniArgs::default(),
+    };
+    let plugins = match config.get_mut("plugins").map(serde_json::Value::take) {
+        Some(plugins) => serde_json::from_value::<Vec<serde_json::Value>>(plugins)?,
+        None => vec![config],
+    };
+
+    let mut warnings = Vec::new();
+    let mut main: Option<CniPlugin> = None;
+    let mut dns_enabled = false;
+    for plugin in plugins {
+        let plugin: CniPlugin = serde_json::from_value(plugin)?;
+        match plugin.kind.as_str() {
+            DRIVER_BRIDGE | DRIVER_MACVLAN | DRIVER_IPVLAN if main.is_none() => main = Some(plugin),
+            "dnsname" => dns_enabled = true,
+            kind if BUILTIN_PLUGINS.contains(&kind) => {}
+            kind => warnings.push(format!("unsupported CNI plugin {} is ignored", kind)),
+        }
+    }
+    let main = main.ok_or_else(|| {
+        NetavarkError::Message(format!(
+            "CNI config of network {} has no bridge, macvlan or ipvlan plugin",
+            name
+        ))
+    })?;
+
+    let mut options = args.podman_options;
+    if let Some(mtu) = main.mtu {
+        options.insert(OPTION_MTU.to_string(), mtu.to_string());
+    }
+    let mut internal = false;
+    let network_interface = match main.kind.as_str() {
+        DRIVER_BRIDGE => {
+            internal = !main.is_gateway;
+            if main.is_gateway && !main.ip_masq {
+                warnings.push(
+                    "ipMasq false is not supported, connections leaving the network are masqueraded"
+                        .to_string(),
+                );
+            }
+            if let Some(vlan) = main.vlan {
+                warnings.push(format!(
+                    "vlan {} of the bridge plugin is not migrated, set it per container with the pvid option and vlan_filtering",
+                    vlan
+                ));
+            }
+            main.bridge
+        }
+        _ => {
+            if let Some(mode) = main.mode {
+                options.insert(OPTION_MODE.to_string(), mode);
+            }
+            main.master
+        }
+    };
+    if dns_enabled && main.kind != DRIVER_BRIDGE {
+        warnings.push(format!(
+            "dns is only supported for bridge networks, not for {}",
+            main.kind
+        ));
+        dns_enabled = false;
+    }
+
+    let (ipam_driver, subnets, routes) = match main.ipam {
+        Some(ipam) if ipam.kind == IPAM_HOSTLOCAL => {
+            let (subnets, routes) = translate_host_local(ipam, &mut warnings);
+            (IPAM_HOSTLOCAL, subnets, routes)
+        }
+        Some(ipam) if ipam.kind == IPAM_DHCP => (IPAM_DHCP, Vec::new(), Vec::new()),
+        Some(ipam) => {
+            return Err(NetavarkError::Message(format!(
+                "unsupported CNI ipam plugin {}",
+                ipam.kind
+            )))
+        }
+        None => {
+            return Err(NetavarkError::Message(format!(
+                "CNI config of network {} has no ipam config",
+                name
+            )))
+        }
+    };
+
+    let network = Network {
+        dns_enabled,
+        driver: main.kind,
+        id: format!("{:x}", Sha256::digest(name.as_bytes())),
+        internal,
+        ipv6_enabled: subnets.iter().any(|s| s.subnet.addr().is_ipv6()),
+        name,
+        network_interface,
+        options: (!options.is_empty()).then_some(options),
+        ipam_options: Some([("driver".to_string(), ipam_driver.to_string())].into()),
+        subnets: Some(subnets),
+        routes: (!routes.is_empty()).then_some(routes),
+        network_dns_servers: None,
+        extra_rules: None,
+    };
+    Ok((network, warnings))
+}
+
+/// Translate the ranges and routes of the host-local ipam plugin. Only the
+/// first range of each range set is used, default routes are skipped as
+/// netavark adds them itself.
+fn translate_host_local(ipam: CniIpam, warnings: &mut Vec<String>) -> (Vec<Subnet>, Vec<Route>) {
+    let mut subnets = Vec::new();
+    for range in ipam.range.into_iter().chain(
+        ipam.ranges
+            .into_iter()
+            .filter_map(|set| set.into_iter().next()),
+    ) {
+        let lease_range =
+            (range.range_start.is_some() || range.range_end.is_some()).then(|| LeaseRange {
+                start_ip: range.range_start.map(|ip| ip.to_string()),
+                end_ip: range.range_end.map(|ip| ip.to_string()),
+            });
+        subnets.push(Subnet {
+            gateway: range.gateway,
+            lease_range,
+            subnet: range.subnet,
+        });
+    }
+
+    let mut routes = Vec::new();
+    for route in ipam.routes {
+        if route.dst.prefix_len() == 0 {
+            continue;
+        }
+        let gateway = route.gw.or_else(|| {
+            subnets
+                .iter()
+                .filter(|s| s.subnet.addr().is_ipv6() == route.dst.addr().is_ipv6())
+                .find_map(|s| s.gateway)
+        });
+        match gateway {
+            Some(gateway) => routes.push(Route {
+                gateway,
+                destination: route.dst,
+                metric: None,
+            }),
+            None => warnings.push(format!(
+                "route to {} has no gateway and is not migrated",
+                route.dst
+            )),
+        }
+    }
+    (subnets, routes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate(config: serde_json::Value) -> (serde_json::Value, Vec<String>) {
+        let (network, warnings) = translate_cni_config(config).unwrap();
+        (serde_json::to_value(network).unwrap(), warnings)
+    }
+
+    // A bridge network created by podman with the CNI backend
+    #[test]
+    fn test_translate_bridge() {
+        let file = File::open("src/test/config/cni-bridge.conflist").unwrap();
+        let (network, warnings) = translate(serde_json::from_reader(file).unwrap());
+        assert_eq!(
+            network,
+            serde_json::json!({
+                "dns_enabled": true,
+                "driver": "bridge",
+                "id": "25f76a05ffcdce773ae95b4e623b0a940c7ae9d6b84ff211c7bf4c78a8f74414",
+                "internal": false,
+                "ipv6_enabled": true,
+                "name": "podman1",
+                "network_interface": "cni-podman1",
+                "options": {"isolate": "true", "mtu": "1450"},
+                "ipam_options": {"driver": "host-local"},
+                "subnets": [
+                    {
+                        "gateway": "10.89.0.1",
+                        "lease_range": {"start_ip": "10.89.0.10", "end_ip": "10.89.0.100"},
+                        "subnet": "10.89.0.0/24"
+                    },
+                    {"gateway": "fd10:89::1", "lease_range": null, "subnet": "fd10:89::/64"}
+                ],
+                "routes": [
+                    {"gateway": "10.89.0.254", "destination": "192.168.0.0/24", "metric": null}
+                ],
+                "network_dns_servers": null
+            })
+        );
+        assert_eq!(
+            warnings,
+            vec!["unsupported CNI plugin bandwidth is ignored"]
+        );
+    }
+
+    // Single plugin configs, macvlan with dhcp and a bridge without gateway
+    #[test]
+    fn test_translate_single_plugin() {
+        let (network, warnings) = translate(serde_json::json!({
+            "cniVersion": "0.4.0",
+            "name": "lan",
+            "type": "macvlan",
+            "master": "eth0",
+            "mode": "bridge",
+            "ipam": {"type": "dhcp"}
+        }));
+        assert_eq!(network["driver"], "macvlan");
+        assert_eq!(network["network_interface"], "eth0");
+        assert_eq!(network["options"], serde_json::json!({"mode": "bridge"}));
+        assert_eq!(network["ipam_options"]["driver"], "dhcp");
+        assert_eq!(network["subnets"], serde_json::json!([]));
+        assert!(warnings.is_empty());
+
+        let (network, warnings) = translate(serde_json::json!({
+            "name": "isolated",
+            "type": "bridge",
+            "bridge": "cni0",
+            "vlan": 10,
+            "ipam": {"type": "host-local", "subnet": "10.1.0.0/16", "routes": [{"dst": "10.2.0.0/16"}]}
+        }));
+        assert_eq!(network["internal"], true);
+        assert_eq!(network["subnets"][0]["subnet"], "10.1.0.0/16");
+        assert_eq!(network["routes"], serde_json::Value::Null);
+        assert_eq!(
+            warnings,
+            vec![
+                "vlan 10 of the bridge plugin is not migrated, set it per container with the pvid option and vlan_filtering",
+                "route to 10.2.0.0/16 has no gateway and is not migrated",
+            ]
+        );
+    }
+
+    // Configs without a network plugin or with an unknown ipam plugin
+    #[test]
+    fn test_translate_invalid() {
+        let err = |config| translate_cni_config(config).unwrap_err().to_string();
+        assert_eq!(
+            err(serde_json::json!({"name": "a", "plugins": [{"type": "portmap"}]})),
+            "CNI config of network a has no bridge, macvlan or ipvlan plugin"
+        );
+        assert_eq!(
+            err(serde_json::json!({"name": "a", "type": "bridge", "ipam": {"type": "static"}})),
+            "unsupported CNI ipam plugin static"
+        );
+        assert_eq!(
+            err(serde_json::json!({"type": "bridge"})),
+            "CNI config has no network name"
+        );
+    }
+}
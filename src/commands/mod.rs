@@ -1,5 +1,13 @@
+pub mod bundle;
 pub mod dhcp_proxy;
+pub mod dump_rules;
+pub mod gc;
+pub mod interfaces;
+pub mod migrate_cni;
 pub mod setup;
+pub mod setup_firewall;
 pub mod teardown;
 pub mod update;
+pub mod update_dns_rules;
+pub mod validate;
 pub mod version;
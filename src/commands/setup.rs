@@ -1,34 +1,95 @@
 //! Configures the given network namespace with provided specs
 use crate::dns::aardvark::Aardvark;
-use crate::error::{NetavarkError, NetavarkResult};
+use crate::error::{ErrorWrap, NetavarkError, NetavarkResult};
+use crate::exec_netns;
 use crate::firewall;
-use crate::network::driver::{get_network_driver, DriverInfo};
-use crate::network::netlink::LinkID;
+use crate::logging;
+use crate::network::constants::DRIVER_BRIDGE;
+use crate::network::core_utils::join_netns;
+use crate::network::driver::{get_network_driver, DriverInfo, NetworkDriver};
+use crate::network::netlink::{self, LinkID};
 use crate::network::{self};
-use crate::network::{core_utils, types};
+use crate::network::{core_utils, state, types, validation, verify};
+use crate::warnings;
+use crate::wrap;
 
 use clap::builder::NonEmptyStringValueParser;
 use clap::Parser;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
+use netlink_packet_route::nlas::link::{Nla, Stats64Buffer};
 use std::collections::HashMap;
 use std::fs::{self};
+use std::net::IpAddr;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::RawFd;
 use std::path::Path;
+use std::time::Duration;
+
+/// how long to wait for the gateway reply with --verify
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Parser, Debug)]
 pub struct Setup {
     /// Network namespace path
-    #[clap(required = true, value_parser = NonEmptyStringValueParser::new())]
-    network_namespace_path: String,
+    #[clap(
+        required_unless_present_any = ["netns_pid", "print_config"],
+        conflicts_with = "netns_pid",
+        value_parser = NonEmptyStringValueParser::new()
+    )]
+    network_namespace_path: Option<String>,
+    /// Use the network namespace of the process with the given pid instead of a path.
+    #[clap(long)]
+    netns_pid: Option<u32>,
+    /// Check that the container can reach the gateway of each bridge network
+    /// and report the result in the status block, failures do not fail setup.
+    #[clap(long)]
+    verify: bool,
+    /// Add the counters of each container interface after setup to the
+    /// status block as a baseline.
+    #[clap(long)]
+    stats: bool,
+    /// Only create the interfaces, the firewall rules are applied later
+    /// with the setup-firewall command.
+    #[clap(long)]
+    no_firewall: bool,
+    /// Print the effective configuration with all defaults applied as JSON
+    /// and exit without setting up anything.
+    #[clap(long)]
+    print_config: bool,
+    /// Before the setup delete the host veths recorded for containers whose
+    /// network namespace no longer exists.
+    #[clap(long)]
+    remove_orphaned_veths: bool,
+    /// Add the firewall chains netavark created for each network and for
+    /// the container to the status block.
+    #[clap(long)]
+    verbose: bool,
 }
 
 impl Setup {
     /// The setup command configures the given network namespace with the given configuration, creating any interfaces and firewall rules necessary.
     pub fn new(network_namespace_path: String) -> Self {
         Self {
-            network_namespace_path,
+            network_namespace_path: Some(network_namespace_path),
+            netns_pid: None,
+            verify: false,
+            stats: false,
+            no_firewall: false,
+            print_config: false,
+            remove_orphaned_veths: false,
+            verbose: false,
+        }
+    }
+
+    /// The network namespace path, clap makes sure either the path or the pid is set.
+    fn netns_path(&self) -> String {
+        match self.netns_pid {
+            Some(pid) => format!("/proc/{}/ns/net", pid),
+            None => self.network_namespace_path.clone().unwrap_or_default(),
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn exec(
         &self,
         input_file: Option<String>,
@@ -36,17 +97,115 @@ impl Setup {
         aardvark_bin: String,
         plugin_directories: Option<Vec<String>>,
         rootless: bool,
+        quiet: bool,
+        firewall_driver: Option<String>,
     ) -> NetavarkResult<()> {
-        match network::validation::ns_checks(&self.network_namespace_path) {
+        if self.print_config {
+            return print_config(input_file, &config_dir);
+        }
+        let netns_path = self.netns_path();
+        match network::validation::ns_checks(&netns_path) {
             Ok(_) => (),
             Err(e) => {
                 return Err(NetavarkError::wrap("invalid namespace path", e));
             }
         }
+        state::check_config_dir(&config_dir)?;
+        // serialize concurrent setups of the same namespace, a lock which
+        // cannot be taken must not make the setup itself fail
+        let _netns_lock = match state::lock_netns(&config_dir, &netns_path, rootless) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                warn!("failed to lock netns, setting up without lock: {}", e);
+                None
+            }
+        };
+        if self.remove_orphaned_veths {
+            let removed = state::remove_orphaned_veths(&config_dir)?;
+            if !removed.is_empty() {
+                info!("Removed orphaned veths {}", removed.join(", "));
+            }
+        }
+
         debug!("{:?}", "Setting up...");
-        let network_options = network::types::NetworkOptions::load(input_file)?;
+        let mut network_options = network::types::NetworkOptions::load(input_file)?;
+        let container_ids = network_options.get_container_ids(Some(netns_path.as_str()))?;
+        debug!("Setting up container {}", network_options.display_name());
+        if let Some(port_mappings) = &network_options.port_mappings {
+            network::validation::validate_port_mappings(port_mappings)?;
+        }
+
+        state::assign_default_subnets(&mut network_options, &config_dir)?;
+        state::assign_conntrack_zones(&mut network_options, &config_dir)?;
+        state::record_network_subnets(&network_options, &config_dir)?;
+        state::resolve_allowed_networks(&mut network_options, &config_dir)?;
+
+        let allocated =
+            state::allocate_static_ips(&mut network_options, &container_ids, &config_dir)?;
+        state::record_neigh_gc_thresh(&network_options, &container_ids, &config_dir)?;
+        // record the chains before they exist so gc never sees them without owner
+        state::record_port_forward_chains(
+            &network_options,
+            &container_ids,
+            &netns_path,
+            &config_dir,
+        )?;
+
+        let res = self.setup_networks(
+            &network_options,
+            &container_ids,
+            config_dir.clone(),
+            aardvark_bin,
+            plugin_directories,
+            rootless,
+            firewall_driver.as_deref(),
+        );
+        if res.is_err() && !allocated.is_empty() {
+            // do not leak the addresses if setup failed
+            if let Err(e) = state::release_static_ips(&allocated, &container_ids, &config_dir) {
+                error!("failed to release ip addresses after setup failed: {}", e);
+            }
+        }
+        if res.is_err() {
+            if let Err(e) =
+                state::release_neigh_gc_thresh(&network_options, &container_ids, &config_dir)
+            {
+                error!(
+                    "failed to restore the neighbor gc thresholds after setup failed: {}",
+                    e
+                );
+            }
+            if let Err(e) =
+                state::forget_port_forward_chains(&network_options, &container_ids, &config_dir)
+            {
+                error!(
+                    "failed to forget port forwarding chains after setup failed: {}",
+                    e
+                );
+            }
+        }
+        let response = res?;
+        // stdout is reserved for the response and errors
+        if !quiet {
+            let response_json = serde_json::to_string(&response)?;
+            println!("{}", response_json);
+        }
+        Ok(())
+    }
 
-        let firewall_driver = match firewall::get_supported_firewall_driver() {
+    #[allow(clippy::too_many_arguments)]
+    fn setup_networks(
+        &self,
+        network_options: &types::NetworkOptions,
+        container_ids: &HashMap<String, String>,
+        config_dir: Option<String>,
+        aardvark_bin: String,
+        plugin_directories: Option<Vec<String>>,
+        rootless: bool,
+        forced_firewall_driver: Option<&str>,
+    ) -> NetavarkResult<HashMap<String, types::StatusBlock>> {
+        let firewall_driver = match firewall::get_supported_firewall_driver(forced_firewall_driver)
+        {
             Ok(driver) => driver,
             Err(e) => return Err(e),
         };
@@ -55,12 +214,17 @@ impl Setup {
 
         let dns_port = core_utils::get_netavark_dns_port()?;
 
-        let (mut hostns, mut netns) =
-            core_utils::open_netlink_sockets(&self.network_namespace_path)?;
+        let netns_path = self.netns_path();
+        let (mut hostns, mut netns) = core_utils::open_netlink_sockets(&netns_path)?;
+        let netns_inode = wrap!(fs::metadata(&netns_path), format!("stat {}", netns_path))?.ino();
 
         // setup loopback, it should be safe to assume that 1 is the loopback index
         netns.netlink.set_up(LinkID::ID(1))?;
 
+        // drop warnings of earlier operations, only report ours
+        warnings::take();
+        core_utils::take_sysctl_changes();
+
         let mut drivers = Vec::with_capacity(network_options.network_info.len());
 
         // Perform per-network setup
@@ -71,20 +235,33 @@ impl Setup {
                     net_name
                 ))
             })?;
+            let _scope = logging::network_scope(net_name, &container_ids[net_name]);
+
+            let mismatches = validation::static_ip_mismatches(network, per_network_opts);
+            if !mismatches.is_empty() {
+                return Err(NetavarkError::Message(format!(
+                    "static ips do not match the subnets of network {}: {}",
+                    net_name,
+                    mismatches.join(", ")
+                )));
+            }
 
             let mut driver = get_network_driver(
                 DriverInfo {
                     firewall: firewall_driver.as_ref(),
-                    container_id: &network_options.container_id,
+                    firewall_rules: &[],
+                    container_id: &container_ids[net_name],
                     container_name: &network_options.container_name,
                     container_dns_servers: &network_options.dns_servers,
                     netns_host: hostns.fd,
                     netns_container: netns.fd,
-                    netns_path: &self.network_namespace_path,
+                    netns_path: &netns_path,
                     network,
                     per_network_opts,
+                    network_count: network_options.network_info.len(),
                     port_mappings: &network_options.port_mappings,
                     dns_port,
+                    skip_firewall: self.no_firewall,
                 },
                 &plugin_directories,
             )?;
@@ -96,36 +273,98 @@ impl Setup {
         }
 
         let mut aardvark_entries = Vec::new();
+        let mut veth_pairs = HashMap::new();
+        let mut firewall_rules = HashMap::new();
+        let mut route_tables = HashMap::new();
 
         // Only now after we validated all drivers we setup each.
         // If there is an error we have to tear down all previous drivers.
+        firewall_driver.begin_batch();
         for (i, driver) in drivers.iter().enumerate() {
-            let (status, aardvark_entry) =
+            let net_name = driver.network_name();
+            let scope = logging::network_scope(&net_name, &container_ids[&net_name]);
+            let (mut status, aardvark_entry, rules) =
                 match driver.setup((&mut hostns.netlink, &mut netns.netlink)) {
-                    Ok((s, a)) => (s, a),
+                    Ok(res) => res,
                     Err(e) => {
                         // now teardown the already setup drivers
-                        for dri in drivers.iter().take(i) {
-                            match dri.teardown((&mut hostns.netlink, &mut netns.netlink)) {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    error!(
-                                    "failed to cleanup previous networks after setup failed: {}",
-                                    e
-                                )
-                                }
-                            };
-                        }
+                        drop(scope);
+                        // the rules of the failed network are never applied
+                        commit_and_teardown(
+                            firewall_driver.as_ref(),
+                            &drivers[..i],
+                            container_ids,
+                            &mut hostns,
+                            &mut netns,
+                        );
                         return Err(e);
                     }
                 };
 
+            if !rules.is_empty() {
+                if self.verbose {
+                    status.firewall_chains = Some(firewall::FirewallChains::from_rules(&rules));
+                }
+                firewall_rules.insert(net_name.clone(), rules);
+            }
+            let res = self.inspect_network(
+                &mut status,
+                network_options,
+                &net_name,
+                &mut hostns,
+                &mut netns,
+            );
+            match res {
+                Ok((table, pair)) => {
+                    if let Some(table) = table {
+                        route_tables.insert(net_name.clone(), table);
+                    }
+                    if let Some(pair) = pair {
+                        veth_pairs.insert(net_name, pair);
+                    }
+                }
+                Err(e) => {
+                    drop(scope);
+                    // this network is set up as well so it must be removed too
+                    commit_and_teardown(
+                        firewall_driver.as_ref(),
+                        &drivers[..=i],
+                        container_ids,
+                        &mut hostns,
+                        &mut netns,
+                    );
+                    return Err(e);
+                }
+            }
+            add_warnings(&mut status, warnings::take());
+            let changes = core_utils::take_sysctl_changes();
+            if !changes.is_empty() {
+                status.changed_sysctls = Some(changes);
+            }
+            status.netns_inode = Some(netns_inode);
             let _ = response.insert(driver.network_name(), status);
             if let Some(a) = aardvark_entry {
                 aardvark_entries.push(a);
             }
         }
 
+        // all firewall rules of the container are applied together
+        if let Err(e) = firewall_driver.commit_batch() {
+            teardown_drivers(&drivers, container_ids, &mut hostns, &mut netns);
+            return Err(e);
+        }
+
+        let res = state::record_veth_pairs(&veth_pairs, container_ids, &netns_path, &config_dir)
+            .and_then(|_| state::record_route_tables(&route_tables, container_ids, &config_dir))
+            .and_then(|_| {
+                state::record_firewall_rules(&firewall_rules, container_ids, &config_dir)
+            });
+        if let Err(e) = res {
+            teardown_drivers(&drivers, container_ids, &mut hostns, &mut netns);
+            forget_recorded_state(network_options, container_ids, &config_dir);
+            return Err(e);
+        }
+
         if !aardvark_entries.is_empty() {
             if Path::new(&aardvark_bin).exists() {
                 let path = match config_dir {
@@ -172,16 +411,271 @@ impl Setup {
                     .into());
                 }
             } else {
-                info!(
+                warnings::push(format!(
                     "dns disabled because aardvark-dns path {:?} does not exists",
                     &aardvark_bin
-                );
+                ));
+            }
+        }
+
+        merge_dns_search_domains(&mut response);
+
+        let nat = network_options
+            .network_info
+            .values()
+            .any(|n| n.driver == DRIVER_BRIDGE && !n.internal);
+        if nat {
+            if let Some(warning) =
+                core_utils::conntrack_helper_warning(core_utils::CONNTRACK_HELPER_SYSCTL)
+            {
+                warnings::push(warning);
+            }
+        }
+
+        // remaining warnings are not specific to a network so add them to all
+        let remaining = warnings::take();
+        if !remaining.is_empty() {
+            for status in response.values_mut() {
+                add_warnings(status, remaining.clone());
             }
         }
         debug!("{:#?}", response);
-        let response_json = serde_json::to_string(&response)?;
-        println!("{}", response_json);
         debug!("{:?}", "Setup complete");
-        Ok(())
+        Ok(response)
+    }
+
+    /// Gather what is reported and recorded about a network once its driver
+    /// is set up, returns the route table and the veth pair of bridge
+    /// networks.
+    fn inspect_network(
+        &self,
+        status: &mut types::StatusBlock,
+        network_options: &types::NetworkOptions,
+        net_name: &str,
+        hostns: &mut core_utils::NamespaceOptions,
+        netns: &mut core_utils::NamespaceOptions,
+    ) -> NetavarkResult<(Option<u32>, Option<state::VethPair>)> {
+        let mut table = None;
+        let mut pair = None;
+        let network = &network_options.network_info[net_name];
+        if network.driver == DRIVER_BRIDGE {
+            if self.verify {
+                status.gateway_reachable =
+                    Some(verify_gateways(status, hostns.fd, netns.fd, net_name)?);
+            }
+            if core_utils::get_route_table_option(&network.options)? {
+                table = core_utils::find_route_table(&mut netns.netlink)?;
+            }
+            // an adopted interface has no veth peer on the host
+            let per_network_opts = &network_options.networks[net_name];
+            if per_network_opts.adopt_interface.is_none() {
+                pair = Some(get_veth_pair(
+                    &mut hostns.netlink,
+                    &mut netns.netlink,
+                    &per_network_opts.interface_name,
+                )?);
+            }
+        }
+
+        if self.stats {
+            add_interface_stats(status, &mut netns.netlink)?;
+        }
+        Ok((table, pair))
+    }
+}
+
+/// Apply the firewall rules of the networks set up so far and tear these
+/// networks down again, teardown expects their rules to exist.
+fn commit_and_teardown(
+    firewall_driver: &dyn firewall::FirewallDriver,
+    drivers: &[Box<dyn NetworkDriver + '_>],
+    container_ids: &HashMap<String, String>,
+    hostns: &mut core_utils::NamespaceOptions,
+    netns: &mut core_utils::NamespaceOptions,
+) {
+    firewall_driver.commit_batch().unwrap_or_else(|e| {
+        error!(
+            "failed to apply the firewall rules of previous networks: {}",
+            e
+        )
+    });
+    teardown_drivers(drivers, container_ids, hostns, netns);
+}
+
+/// Drop what was recorded in the state for the container after its networks
+/// were torn down again.
+fn forget_recorded_state(
+    network_options: &types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) {
+    let res = state::forget_veth_pairs(network_options, container_ids, config_dir)
+        .and_then(|_| state::forget_route_tables(network_options, container_ids, config_dir))
+        .and_then(|_| state::forget_firewall_rules(network_options, container_ids, config_dir));
+    if let Err(e) = res {
+        error!(
+            "failed to forget the state of the container after setup failed: {}",
+            e
+        );
+    }
+}
+
+/// Tear down the given drivers after the setup of a later step failed.
+fn teardown_drivers(
+    drivers: &[Box<dyn NetworkDriver + '_>],
+    container_ids: &HashMap<String, String>,
+    hostns: &mut core_utils::NamespaceOptions,
+    netns: &mut core_utils::NamespaceOptions,
+) {
+    for dri in drivers {
+        let dri_name = dri.network_name();
+        let _scope = logging::network_scope(&dri_name, &container_ids[&dri_name]);
+        if let Err(e) = dri.teardown((&mut hostns.netlink, &mut netns.netlink)) {
+            error!(
+                "failed to cleanup previous networks after setup failed: {}",
+                e
+            )
+        }
+    }
+}
+
+/// Print the options setup would use, the allow_from and allow_to network
+/// names are resolved to their subnets.
+fn print_config(input_file: Option<String>, config_dir: &Option<String>) -> NetavarkResult<()> {
+    let mut network_options = network::types::NetworkOptions::load(input_file)?;
+    network_options.apply_defaults();
+    state::resolve_allowed_networks(&mut network_options, config_dir)?;
+    println!("{}", serde_json::to_string_pretty(&network_options)?);
+    Ok(())
+}
+
+/// Look up the names and indices of both sides of the veth pair of the
+/// given container interface.
+fn get_veth_pair(
+    hostns: &mut netlink::Socket,
+    netns: &mut netlink::Socket,
+    if_name: &str,
+) -> NetavarkResult<state::VethPair> {
+    let veth = netns
+        .get_link(LinkID::Name(if_name.to_string()))
+        .wrap("get container veth")?;
+    let host_index = veth
+        .nlas
+        .iter()
+        .find_map(|nla| match nla {
+            Nla::Link(index) => Some(*index),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            NetavarkError::Message(format!("no veth peer found for interface {}", if_name))
+        })?;
+    let host_veth = hostns
+        .get_link(LinkID::ID(host_index))
+        .wrap("get host veth")?;
+    let host_interface = host_veth
+        .nlas
+        .into_iter()
+        .find_map(|nla| match nla {
+            Nla::IfName(name) => Some(name),
+            _ => None,
+        })
+        .unwrap_or_default();
+    Ok(state::VethPair {
+        host_interface,
+        host_index,
+        container_interface: if_name.to_string(),
+        container_index: veth.header.index,
+    })
+}
+
+/// Set the stats of all interfaces of the status block from the counters of
+/// the container interfaces.
+fn add_interface_stats(
+    status: &mut types::StatusBlock,
+    netns: &mut netlink::Socket,
+) -> NetavarkResult<()> {
+    for (if_name, interface) in status.interfaces.iter_mut().flatten() {
+        let link = netns
+            .get_link(LinkID::Name(if_name.to_string()))
+            .wrap(format!("get stats of interface {}", if_name))?;
+        interface.stats = link.nlas.iter().find_map(|nla| match nla {
+            Nla::Stats64(bytes) => parse_stats64(bytes),
+            _ => None,
+        });
+    }
+    Ok(())
+}
+
+fn parse_stats64(bytes: &[u8]) -> Option<types::InterfaceStats> {
+    let buf = Stats64Buffer::new_checked(bytes).ok()?;
+    Some(types::InterfaceStats {
+        rx_bytes: buf.rx_bytes(),
+        tx_bytes: buf.tx_bytes(),
+        rx_packets: buf.rx_packets(),
+        tx_packets: buf.tx_packets(),
+        rx_errors: buf.rx_errors(),
+        tx_errors: buf.tx_errors(),
+        rx_dropped: buf.rx_dropped(),
+        tx_dropped: buf.tx_dropped(),
+    })
+}
+
+/// Ping all gateways of the status block from within the container namespace.
+/// Returns false if one of them is not reachable.
+fn verify_gateways(
+    status: &types::StatusBlock,
+    hostns: RawFd,
+    netns: RawFd,
+    net_name: &str,
+) -> NetavarkResult<bool> {
+    let gateways: Vec<IpAddr> = status
+        .interfaces
+        .iter()
+        .flat_map(|interfaces| interfaces.values())
+        .flat_map(|interface| interface.subnets.iter().flatten())
+        .filter_map(|subnet| subnet.gateway)
+        .collect();
+
+    exec_netns!(hostns, netns, res, {
+        gateways
+            .iter()
+            .all(|gw| match verify::ping(*gw, VERIFY_TIMEOUT) {
+                Ok(_) => true,
+                Err(e) => {
+                    warnings::push(format!(
+                        "gateway {} of network {} is not reachable: {}",
+                        gw, net_name, e
+                    ));
+                    false
+                }
+            })
+    });
+    Ok(res)
+}
+
+fn add_warnings(status: &mut types::StatusBlock, new: Vec<String>) {
+    if new.is_empty() {
+        return;
+    }
+    status.warnings.get_or_insert_with(Vec::new).extend(new);
+}
+
+/// The container uses one resolv.conf for all networks, so give every
+/// network the same deduplicated list of search domains.
+fn merge_dns_search_domains(response: &mut HashMap<String, types::StatusBlock>) {
+    let mut names: Vec<&String> = response.keys().collect();
+    names.sort();
+    let mut merged: Vec<String> = Vec::new();
+    for name in names {
+        for domain in response[name].dns_search_domains.iter().flatten() {
+            if !merged.contains(domain) {
+                merged.push(domain.clone());
+            }
+        }
+    }
+    if !merged.is_empty() {
+        for status in response.values_mut() {
+            status.dns_search_domains = Some(merged.clone());
+        }
     }
 }
@@ -0,0 +1,115 @@
+//! Applies the firewall rules of a setup which was run with --no-firewall
+use crate::error::{NetavarkError, NetavarkResult};
+use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::network::{self, core_utils, state};
+use crate::{firewall, logging};
+
+use clap::builder::NonEmptyStringValueParser;
+use clap::Parser;
+use log::{debug, warn};
+use std::collections::HashMap;
+
+#[derive(Parser, Debug)]
+pub struct SetupFirewall {
+    /// Network namespace path
+    #[clap(required = true, value_parser = NonEmptyStringValueParser::new())]
+    network_namespace_path: String,
+}
+
+impl SetupFirewall {
+    /// The setup-firewall command is the second phase of a setup with
+    /// --no-firewall, it applies the firewall and port forwarding rules for
+    /// the already created interfaces. Both commands need the same config.
+    pub fn new(network_namespace_path: String) -> Self {
+        Self {
+            network_namespace_path,
+        }
+    }
+
+    pub fn exec(
+        &self,
+        input_file: Option<String>,
+        config_dir: Option<String>,
+        plugin_directories: Option<Vec<String>>,
+        firewall_driver: Option<String>,
+    ) -> NetavarkResult<()> {
+        if let Err(e) = network::validation::ns_checks(&self.network_namespace_path) {
+            return Err(NetavarkError::wrap("invalid namespace path", e));
+        }
+        let _netns_lock = match state::lock_netns(&config_dir, &self.network_namespace_path, false)
+        {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                warn!("failed to lock netns, setting up without lock: {}", e);
+                None
+            }
+        };
+
+        debug!("{:?}", "Setting up firewall...");
+        let mut network_options = network::types::NetworkOptions::load(input_file)?;
+        let container_ids =
+            network_options.get_container_ids(Some(self.network_namespace_path.as_str()))?;
+        debug!(
+            "Setting up firewall of container {}",
+            network_options.display_name()
+        );
+        if let Some(port_mappings) = &network_options.port_mappings {
+            network::validation::validate_port_mappings(port_mappings)?;
+        }
+        state::lookup_default_subnets(&mut network_options, &config_dir)?;
+        state::lookup_conntrack_zones(&mut network_options, &config_dir)?;
+        state::resolve_allowed_networks(&mut network_options, &config_dir)?;
+        // use the addresses the first phase allocated
+        state::lookup_static_ips(&mut network_options, &container_ids, &config_dir)?;
+        state::record_port_forward_chains(
+            &network_options,
+            &container_ids,
+            &self.network_namespace_path,
+            &config_dir,
+        )?;
+
+        let firewall_driver = firewall::get_supported_firewall_driver(firewall_driver.as_deref())?;
+        let dns_port = core_utils::get_netavark_dns_port()?;
+
+        let mut firewall_rules = HashMap::new();
+        for (net_name, network) in network_options.network_info.iter() {
+            let per_network_opts = network_options.networks.get(net_name).ok_or_else(|| {
+                NetavarkError::Message(format!(
+                    "network options for network {} not found",
+                    net_name
+                ))
+            })?;
+            let _scope = logging::network_scope(net_name, &container_ids[net_name]);
+
+            let mut driver = get_network_driver(
+                DriverInfo {
+                    firewall: firewall_driver.as_ref(),
+                    firewall_rules: &[],
+                    container_id: &container_ids[net_name],
+                    container_name: &network_options.container_name,
+                    container_dns_servers: &network_options.dns_servers,
+                    // only firewall rules are added, no namespace is entered
+                    netns_host: -1,
+                    netns_container: -1,
+                    netns_path: &self.network_namespace_path,
+                    network,
+                    per_network_opts,
+                    network_count: network_options.network_info.len(),
+                    port_mappings: &network_options.port_mappings,
+                    dns_port,
+                    skip_firewall: false,
+                },
+                &plugin_directories,
+            )?;
+            driver.validate()?;
+            let rules = driver.setup_firewall()?;
+            if !rules.is_empty() {
+                firewall_rules.insert(net_name.clone(), rules);
+            }
+        }
+        state::record_firewall_rules(&firewall_rules, &container_ids, &config_dir)?;
+
+        debug!("{:?}", "Firewall setup complete");
+        Ok(())
+    }
+}
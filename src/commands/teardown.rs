@@ -1,13 +1,13 @@
 use crate::dns::aardvark::{Aardvark, AardvarkEntry};
 use crate::error::{NetavarkError, NetavarkErrorList, NetavarkResult};
 use crate::network::constants::DRIVER_BRIDGE;
-use crate::network::core_utils;
 use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::network::{core_utils, state};
 
-use crate::{firewall, network};
+use crate::{firewall, logging, network};
 use clap::builder::NonEmptyStringValueParser;
 use clap::Parser;
-use log::debug;
+use log::{debug, error};
 use std::path::Path;
 
 #[derive(Parser, Debug)]
@@ -15,6 +15,10 @@ pub struct Teardown {
     /// Network namespace path
     #[clap(required = true, value_parser = NonEmptyStringValueParser::new())]
     network_namespace_path: String,
+    /// Only tear down the given network, the container stays connected to
+    /// all other networks of the configuration.
+    #[clap(long, value_parser = NonEmptyStringValueParser::new())]
+    network: Option<String>,
 }
 
 impl Teardown {
@@ -22,6 +26,7 @@ impl Teardown {
     pub fn new(network_namespace_path: String) -> Self {
         Self {
             network_namespace_path,
+            network: None,
         }
     }
 
@@ -32,9 +37,34 @@ impl Teardown {
         aardvark_bin: String,
         plugin_directories: Option<Vec<String>>,
         rootless: bool,
+        firewall_driver: Option<String>,
     ) -> NetavarkResult<()> {
         debug!("{:?}", "Tearing down..");
-        let network_options = network::types::NetworkOptions::load(input_file)?;
+        let mut network_options = network::types::NetworkOptions::load(input_file)?;
+        if let Some(name) = &self.network {
+            network_options.retain_network(name)?;
+        }
+        let container_ids =
+            network_options.get_container_ids(Some(self.network_namespace_path.as_str()))?;
+        debug!("Tearing down container {}", network_options.display_name());
+        // wait for a setup of the same namespace to finish, the lock file is
+        // deleted again at the end
+        let netns_lock =
+            match state::lock_netns(&config_dir, &self.network_namespace_path, rootless) {
+                Ok(lock) => Some(lock),
+                Err(e) => {
+                    // just log we still try to do as much as possible for cleanup
+                    error!("failed to lock netns: {}", e);
+                    None
+                }
+            };
+        state::lookup_default_subnets(&mut network_options, &config_dir)?;
+        state::lookup_conntrack_zones(&mut network_options, &config_dir)?;
+        state::resolve_allowed_networks(&mut network_options, &config_dir)?;
+        let allocated =
+            state::lookup_static_ips(&mut network_options, &container_ids, &config_dir)?;
+        let firewall_rules =
+            state::lookup_firewall_rules(&network_options, &container_ids, &config_dir)?;
 
         let mut error_list = NetavarkErrorList::new();
 
@@ -42,12 +72,16 @@ impl Teardown {
 
         let mut aardvark_entries = Vec::new();
         for (key, network) in &network_options.network_info {
+            let container_id = match container_ids.get(key) {
+                Some(id) => id,
+                None => continue,
+            };
             if network.dns_enabled && network.driver == DRIVER_BRIDGE {
                 aardvark_entries.push(AardvarkEntry {
                     network_name: key,
                     network_gateways: Vec::new(),
                     network_dns_servers: &None,
-                    container_id: &network_options.container_id,
+                    container_id,
                     container_ips_v4: Vec::new(),
                     container_ips_v6: Vec::new(),
                     container_names: Vec::new(),
@@ -58,7 +92,7 @@ impl Teardown {
 
         if !aardvark_entries.is_empty() {
             // stop dns server first before netavark clears the interface
-            let path = match config_dir {
+            let path = match &config_dir {
                 Some(dir) => Path::new(&dir).join("aardvark-dns"),
                 None => {
                     return Err(NetavarkError::msg(
@@ -80,10 +114,11 @@ impl Teardown {
             }
         }
 
-        let firewall_driver = match firewall::get_supported_firewall_driver() {
-            Ok(driver) => driver,
-            Err(e) => return Err(e),
-        };
+        let firewall_driver =
+            match firewall::get_supported_firewall_driver(firewall_driver.as_deref()) {
+                Ok(driver) => driver,
+                Err(e) => return Err(e),
+            };
 
         let (mut hostns, mut netns) =
             core_utils::open_netlink_sockets(&self.network_namespace_path)?;
@@ -99,11 +134,16 @@ impl Teardown {
                     continue;
                 }
             };
+            let _scope = logging::network_scope(net_name, &container_ids[net_name]);
 
             let driver = match get_network_driver(
                 DriverInfo {
                     firewall: firewall_driver.as_ref(),
-                    container_id: &network_options.container_id,
+                    firewall_rules: firewall_rules
+                        .get(net_name)
+                        .map(Vec::as_slice)
+                        .unwrap_or_default(),
+                    container_id: &container_ids[net_name],
                     container_name: &network_options.container_name,
                     container_dns_servers: &network_options.dns_servers,
                     netns_host: hostns.fd,
@@ -111,8 +151,10 @@ impl Teardown {
                     netns_path: &self.network_namespace_path,
                     network,
                     per_network_opts,
+                    network_count: network_options.network_info.len(),
                     port_mappings: &network_options.port_mappings,
                     dns_port,
+                    skip_firewall: false,
                 },
                 &plugin_directories,
             ) {
@@ -132,6 +174,53 @@ impl Teardown {
             };
         }
 
+        if !allocated.is_empty() {
+            if let Err(err) = state::release_static_ips(&allocated, &container_ids, &config_dir) {
+                error_list.push(err);
+            }
+        }
+        // keep the chains recorded when teardown failed so gc can remove them
+        if error_list.is_empty() {
+            if let Err(err) =
+                state::forget_port_forward_chains(&network_options, &container_ids, &config_dir)
+            {
+                error_list.push(err);
+            }
+            if let Err(err) =
+                state::forget_veth_pairs(&network_options, &container_ids, &config_dir)
+            {
+                error_list.push(err);
+            }
+            if let Err(err) =
+                state::forget_firewall_rules(&network_options, &container_ids, &config_dir)
+            {
+                error_list.push(err);
+            }
+            if let Err(err) =
+                state::release_neigh_gc_thresh(&network_options, &container_ids, &config_dir)
+            {
+                error_list.push(err);
+            }
+            match state::forget_route_tables(&network_options, &container_ids, &config_dir) {
+                Ok(tables) => {
+                    for table in tables {
+                        if let Err(err) =
+                            core_utils::remove_route_table_rules(&mut netns.netlink, table)
+                        {
+                            error_list.push(err);
+                        }
+                    }
+                }
+                Err(err) => error_list.push(err),
+            }
+        }
+
+        if let Some(lock) = netns_lock {
+            if let Err(err) = lock.remove() {
+                error_list.push(err);
+            }
+        }
+
         if !error_list.is_empty() {
             return Err(NetavarkError::List(error_list));
         }
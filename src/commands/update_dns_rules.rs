@@ -0,0 +1,77 @@
+//! Replaces the dns rules of a network after its resolver changed the port
+use crate::error::{NetavarkError, NetavarkResult};
+use crate::firewall;
+use crate::firewall::iptables::MAX_HASH_SIZE;
+use crate::network::core_utils::CoreUtils;
+use crate::network::{self, bridge, state};
+
+use clap::builder::NonEmptyStringValueParser;
+use clap::Parser;
+use log::debug;
+
+#[derive(Parser, Debug)]
+pub struct UpdateDnsRules {
+    /// Network of the configuration whose dns rules are replaced
+    #[clap(required = true, value_parser = NonEmptyStringValueParser::new())]
+    network_name: String,
+    /// Port the resolver on the gateway of the network listens on now
+    #[clap(long, required = true)]
+    dns_port: u16,
+}
+
+impl UpdateDnsRules {
+    /// The update-dns-rules command only replaces the dns redirect and allow
+    /// rules of one network, interfaces and all other rules stay untouched.
+    pub fn new(network_name: String, dns_port: u16) -> Self {
+        Self {
+            network_name,
+            dns_port,
+        }
+    }
+
+    pub fn exec(
+        &self,
+        input_file: Option<String>,
+        config_dir: Option<String>,
+        firewall_driver: Option<String>,
+    ) -> NetavarkResult<()> {
+        debug!("Updating the dns rules of network {}", self.network_name);
+        if self.dns_port == 0 {
+            return Err(NetavarkError::msg("invalid dns port 0"));
+        }
+        let mut network_options = network::types::NetworkOptions::load(input_file)?;
+        state::lookup_default_subnets(&mut network_options, &config_dir)?;
+        let network = network_options
+            .network_info
+            .get(&self.network_name)
+            .ok_or_else(|| {
+                NetavarkError::Message(format!(
+                    "network info for network {} not found",
+                    self.network_name
+                ))
+            })?;
+        if !bridge::has_dns_rules(network, self.dns_port)? {
+            return Err(NetavarkError::Message(format!(
+                "network {} has no dns rules, only non internal bridge networks with dns_enabled or the dns_port option have them",
+                network.name
+            )));
+        }
+
+        let network_hash_name = CoreUtils::create_network_hash(&network.name, MAX_HASH_SIZE);
+        let firewall_driver = firewall::get_supported_firewall_driver(firewall_driver.as_deref())?;
+        let rules = firewall_driver.update_dns_rules(
+            &network_hash_name,
+            network.subnets.as_deref().unwrap_or_default(),
+            self.dns_port,
+        )?;
+        // teardown must remove the new rules instead of the old ones
+        state::replace_firewall_rules(
+            &network.name,
+            &firewall::dns_rule_comment(&network_hash_name),
+            &rules,
+            &config_dir,
+        )?;
+        debug!("Dns rules update complete");
+        Ok(())
+    }
+}
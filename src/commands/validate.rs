@@ -0,0 +1,257 @@
+//! Validates the given config without applying it
+use crate::error::{NetavarkError, NetavarkResult};
+use crate::firewall::fwnone;
+use crate::network::driver::{get_network_driver, DriverInfo};
+use crate::network::{self, core_utils, state, validation};
+
+use clap::Parser;
+use log::debug;
+use serde::Serialize;
+
+#[derive(Parser, Debug)]
+pub struct Validate {}
+
+#[derive(Debug, Serialize)]
+struct ValidateResult {
+    valid: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    problems: Vec<Problem>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct Problem {
+    /// network the problem was found in, unset for problems of the whole config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    network: Option<String>,
+    error: String,
+}
+
+impl Problem {
+    fn new(network: Option<&str>, err: NetavarkError) -> Self {
+        Problem {
+            network: network.map(|n| n.to_string()),
+            error: err.to_string(),
+        }
+    }
+}
+
+impl Validate {
+    /// The validate command runs the checks setup would do for the given
+    /// configuration without making any changes to the host.
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn exec(
+        &self,
+        input_file: Option<String>,
+        plugin_directories: Option<Vec<String>>,
+    ) -> NetavarkResult<()> {
+        debug!("{:?}", "Validating...");
+        let problems = match network::types::NetworkOptions::load(input_file) {
+            Ok(mut network_options) => validate_config(&mut network_options, &plugin_directories)?,
+            Err(e) => vec![Problem::new(None, e)],
+        };
+
+        let out = serde_json::to_string(&ValidateResult {
+            valid: problems.is_empty(),
+            problems,
+        })?;
+        println!("{}", out);
+        Ok(())
+    }
+}
+
+impl Default for Validate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Run the validation of setup for all networks and collect the problems.
+/// Only errors which are not caused by the config are returned as error.
+fn validate_config(
+    network_options: &mut network::types::NetworkOptions,
+    plugin_directories: &Option<Vec<String>>,
+) -> NetavarkResult<Vec<Problem>> {
+    let mut problems = Vec::new();
+
+    let container_ids = match network_options.get_container_ids(None) {
+        Ok(ids) => ids,
+        Err(e) => return Ok(vec![Problem::new(None, e)]),
+    };
+
+    for name in network_options.networks.keys() {
+        if !network_options.network_info.contains_key(name) {
+            problems.push(Problem::new(
+                Some(name),
+                NetavarkError::Message(format!("network info for network {} not found", name)),
+            ));
+        }
+    }
+
+    if let Some(port_mappings) = &network_options.port_mappings {
+        if let Err(e) = validation::validate_port_mappings(port_mappings) {
+            problems.push(Problem::new(None, e));
+        }
+    }
+    // without a config directory names can only refer to the given networks
+    if let Err(e) = state::resolve_allowed_networks(network_options, &None) {
+        problems.push(Problem::new(None, e));
+    }
+    if let Err(e) = state::preview_default_subnets(network_options) {
+        problems.push(Problem::new(None, e));
+    }
+    // use placeholder addresses for the networks netavark allocates for
+    if let Err(e) = state::preview_static_ips(network_options, &container_ids) {
+        problems.push(Problem::new(None, e));
+    }
+
+    // the drivers do not use the firewall to validate
+    let firewall_driver = fwnone::new()?;
+    let dns_port = core_utils::get_netavark_dns_port()?;
+
+    let mut net_names: Vec<&String> = network_options.network_info.keys().collect();
+    net_names.sort();
+
+    for net_name in net_names {
+        let network = &network_options.network_info[net_name];
+        let per_network_opts = match network_options.networks.get(net_name) {
+            Some(opts) => opts,
+            None => {
+                problems.push(Problem::new(
+                    Some(net_name),
+                    NetavarkError::Message(format!(
+                        "network options for network {} not found",
+                        net_name
+                    )),
+                ));
+                continue;
+            }
+        };
+
+        for e in validation::validate_network_addresses(network, per_network_opts) {
+            problems.push(Problem::new(Some(net_name), e));
+        }
+
+        let driver = get_network_driver(
+            DriverInfo {
+                firewall: firewall_driver.as_ref(),
+                firewall_rules: &[],
+                container_id: &container_ids[net_name],
+                container_name: &network_options.container_name,
+                container_dns_servers: &network_options.dns_servers,
+                // nothing is set up so no namespaces are needed
+                netns_host: -1,
+                netns_container: -1,
+                netns_path: "",
+                network,
+                per_network_opts,
+                network_count: network_options.network_info.len(),
+                port_mappings: &network_options.port_mappings,
+                dns_port,
+                skip_firewall: false,
+            },
+            plugin_directories,
+        );
+        if let Err(e) = driver.and_then(|mut driver| driver.validate()) {
+            problems.push(Problem::new(Some(net_name), e));
+        }
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(file: &str) -> network::types::NetworkOptions {
+        network::types::NetworkOptions::load(Some(format!("src/test/config/{}", file))).unwrap()
+    }
+
+    fn errors(problems: &[Problem]) -> Vec<&str> {
+        problems.iter().map(|p| p.error.as_str()).collect()
+    }
+
+    // Valid configs, with static and with allocated addresses, have no problems
+    #[test]
+    fn test_validate_valid_config() {
+        for file in ["portmapping.json", "ipam-allocation.json"] {
+            let problems = validate_config(&mut load(file), &None).unwrap();
+            assert_eq!(problems, vec![], "{}", file);
+        }
+    }
+
+    // All problems of the networks are reported with the network name
+    #[test]
+    fn test_validate_invalid_config() {
+        let mut opts = load("portmapping.json");
+        let network = opts.network_info.get_mut("podman").unwrap();
+        network.dns_enabled = true;
+        network.subnets.as_mut().unwrap()[0].gateway = Some("10.89.0.1".parse().unwrap());
+        let per_net = opts.networks.get_mut("podman").unwrap();
+        per_net.static_ips = Some(vec!["10.90.0.2".parse().unwrap()]);
+        per_net.aliases = Some(vec!["bad_alias".to_string()]);
+
+        let problems = validate_config(&mut opts, &None).unwrap();
+        assert!(problems
+            .iter()
+            .all(|p| p.network.as_deref() == Some("podman")));
+        assert_eq!(
+            errors(&problems),
+            vec![
+                "gateway 10.89.0.1 is not a host address of subnet 10.88.0.0/16",
+                "static ip 10.90.0.2 is not in subnet 10.88.0.0/16",
+                "invalid dns name \"bad_alias\": label must only contain letters, digits and hyphens",
+            ]
+        );
+    }
+
+    // Driver specific options and fields are checked by the driver
+    #[test]
+    fn test_validate_driver_options() {
+        let mut opts = load("portmapping.json");
+        opts.network_info.get_mut("podman").unwrap().options =
+            Some([("mtu".to_string(), "big".to_string())].into());
+        let problems = validate_config(&mut opts, &None).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(
+            problems[0].error.starts_with("unable to parse \"mtu\""),
+            "{:?}",
+            problems
+        );
+
+        let mut opts = load("portmapping.json");
+        opts.networks.get_mut("podman").unwrap().static_mac = Some("zz:00".to_string());
+        let problems = validate_config(&mut opts, &None).unwrap();
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+
+        let mut opts = load("portmapping.json");
+        opts.networks.get_mut("podman").unwrap().interface_name = String::new();
+        opts.network_info.get_mut("podman").unwrap().driver = "macvlan".to_string();
+        let problems = validate_config(&mut opts, &None).unwrap();
+        assert_eq!(errors(&problems), vec!["no container interface name given"]);
+    }
+
+    // Networks without info or without options are reported
+    #[test]
+    fn test_validate_missing_network() {
+        let mut opts = load("portmapping.json");
+        let per_net = opts.networks.remove("podman").unwrap();
+        opts.networks.insert("other".to_string(), per_net);
+        let problems = validate_config(&mut opts, &None).unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                Problem {
+                    network: Some("other".to_string()),
+                    error: "network info for network other not found".to_string(),
+                },
+                Problem {
+                    network: Some("podman".to_string()),
+                    error: "network options for network podman not found".to_string(),
+                },
+            ]
+        );
+    }
+}
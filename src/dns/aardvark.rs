@@ -224,7 +224,7 @@ impl Aardvark {
             match Aardvark::commit_entry(entry, file) {
                 Err(er) => {
                     // drop lockfile when commit is completed
-                    if let Err(er) = lockfile.unlock() {
+                    if let Err(er) = FileExt::unlock(&lockfile) {
                         return Err(std::io::Error::new(
                             std::io::ErrorKind::Other,
                             format!(
@@ -243,7 +243,7 @@ impl Aardvark {
         }
 
         // drop lockfile when commit is completed
-        if let Err(er) = lockfile.unlock() {
+        if let Err(er) = FileExt::unlock(&lockfile) {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 format!(
@@ -134,6 +134,20 @@ impl NetavarkError {
             _ => self,
         }
     }
+    /// short name of the error type, used to categorize errors in metrics
+    pub fn category(&self) -> &'static str {
+        match self.unwrap() {
+            NetavarkError::Message(_) | NetavarkError::Chain(_, _) => "message",
+            NetavarkError::ExitCode(_, _) => "exit_code",
+            NetavarkError::Io(_) => "io",
+            NetavarkError::Dbus(_) | NetavarkError::DbusVariant(_) => "dbus",
+            NetavarkError::Sysctl(_) => "sysctl",
+            NetavarkError::Serde(_) => "serde",
+            NetavarkError::Netlink(_) => "netlink",
+            NetavarkError::DHCPProxy(_) => "dhcp_proxy",
+            NetavarkError::List(_) => "list",
+        }
+    }
 }
 
 impl fmt::Display for NetavarkError {
@@ -147,7 +161,10 @@ impl fmt::Display for NetavarkError {
             NetavarkError::DbusVariant(e) => write!(f, "DBus Variant Error: {}", e),
             NetavarkError::Sysctl(e) => write!(f, "Sysctl error: {}", e),
             NetavarkError::Serde(e) => write!(f, "JSON Decoding error: {}", e),
-            NetavarkError::Netlink(e) => write!(f, "Netlink error: {}", e),
+            NetavarkError::Netlink(e) => match errno_name(e.raw_code()) {
+                Some(name) => write!(f, "Netlink error: {}: {}", name, e),
+                None => write!(f, "Netlink error: {}", e),
+            },
             NetavarkError::DHCPProxy(e) => write!(f, "dhcp proxy error: {}", e),
             NetavarkError::List(list) => {
                 if list.0.len() == 1 {
@@ -164,6 +181,14 @@ impl fmt::Display for NetavarkError {
     }
 }
 
+/// name of the errno in a netlink error message, e.g. EEXIST
+fn errno_name(code: i32) -> Option<String> {
+    match nix::errno::Errno::from_i32(code.abs()) {
+        nix::errno::Errno::UnknownErrno => None,
+        errno => Some(format!("{:?}", errno)),
+    }
+}
+
 impl Error for NetavarkError {}
 
 impl From<std::io::Error> for NetavarkError {
@@ -3,9 +3,12 @@ use crate::firewall;
 use crate::network::internal_types::{PortForwardConfig, TearDownNetwork, TeardownPortForward};
 use crate::network::types::PortMapping;
 use crate::network::{internal_types, types};
+use crate::warnings;
 use core::convert::TryFrom;
+use ipnet::IpNet;
 use log::{debug, info};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
 use std::vec::Vec;
 use zbus::{
     blocking::Connection,
@@ -15,6 +18,10 @@ use zbus::{
 const ZONENAME: &str = "netavark_zone";
 const POLICYNAME: &str = "netavark_policy";
 const PORTPOLICYNAME: &str = "netavark_portfwd";
+const RENDER_NOT_SUPPORTED: &str = "dump-rules is not supported by the firewalld driver";
+
+/// firewalld forward port entry: port, protocol, to-port, to-addr
+type PortTuple = (String, String, String, String);
 
 // Firewalld driver - uses a dbus connection to communicate with firewalld.
 pub struct FirewallD {
@@ -26,7 +33,62 @@ pub fn new(conn: Connection) -> Result<Box<dyn firewall::FirewallDriver>, Netava
 }
 
 impl firewall::FirewallDriver for FirewallD {
-    fn setup_network(&self, network_setup: internal_types::SetupNetwork) -> NetavarkResult<()> {
+    fn setup_network(
+        &self,
+        network_setup: internal_types::SetupNetwork,
+    ) -> NetavarkResult<Vec<firewall::FirewallRule>> {
+        if network_setup.snat_ip.is_some() {
+            return Err(NetavarkError::msg(
+                "snat_ip is not supported by the firewalld driver",
+            ));
+        }
+        if !network_setup.allowed.is_empty() {
+            return Err(NetavarkError::msg(
+                "allow_from and allow_to are not supported by the firewalld driver",
+            ));
+        }
+        if network_setup
+            .net
+            .extra_rules
+            .iter()
+            .flatten()
+            .next()
+            .is_some()
+        {
+            return Err(NetavarkError::msg(
+                "extra_rules are not supported by the firewalld driver",
+            ));
+        }
+        if network_setup.default_drop {
+            return Err(NetavarkError::msg(
+                "default_policy drop is not supported by the firewalld driver",
+            ));
+        }
+        if network_setup.egress_only {
+            return Err(NetavarkError::msg(
+                "egress_only is not supported by the firewalld driver",
+            ));
+        }
+        if network_setup.conn_limit.is_some() || network_setup.conn_rate_limit.is_some() {
+            return Err(NetavarkError::msg(
+                "conn_limit and conn_rate_limit are not supported by the firewalld driver",
+            ));
+        }
+        if network_setup.block_host_access {
+            return Err(NetavarkError::msg(
+                "block_host_access is not supported by the firewalld driver",
+            ));
+        }
+        if network_setup.conntrack_zone.is_some() {
+            return Err(NetavarkError::msg(
+                "conntrack_zone is not supported by the firewalld driver",
+            ));
+        }
+        if network_setup.mss_clamp {
+            // mss clamping is enabled by default for small mtus, do not fail for it
+            warnings::push("mss clamping is not supported by the firewalld driver".to_string());
+        }
+
         let mut need_reload = false;
 
         need_reload |= match create_zone_if_not_exist(&self.conn, ZONENAME) {
@@ -84,7 +146,7 @@ impl firewall::FirewallDriver for FirewallD {
             };
         }
 
-        Ok(())
+        Ok(Vec::new())
     }
 
     fn teardown_network(&self, tear: TearDownNetwork) -> NetavarkResult<()> {
@@ -108,7 +170,10 @@ impl firewall::FirewallDriver for FirewallD {
         Ok(())
     }
 
-    fn setup_port_forward(&self, setup_portfw: PortForwardConfig) -> Result<(), NetavarkError> {
+    fn setup_port_forward(
+        &self,
+        setup_portfw: PortForwardConfig,
+    ) -> NetavarkResult<Vec<firewall::FirewallRule>> {
         // NOTE: There is a serious TOCTOU risk in this function if netavark
         // is either run in parallel, or is not the only thing to edit this
         // policy.
@@ -170,60 +235,15 @@ impl firewall::FirewallDriver for FirewallD {
         // prevention - if two ports end up mapped to different containers,
         // that is not detected, and firewalld will allow it to happen.
         // Only one of them will win and be active, though.
-        match setup_portfw.port_mappings {
-            Some(ports) => {
-                for port in ports {
-                    if !port.host_ip.is_empty() {
-                        port_forwarding_rules
-                            .append(Value::new(make_port_tuple(port, &port.host_ip)))?;
-                    } else {
-                        if let Some(v4) = setup_portfw.container_ip_v4 {
-                            port_forwarding_rules
-                                .append(Value::new(make_port_tuple(port, &v4.to_string())))?;
-                        }
-                        if let Some(v6) = setup_portfw.container_ip_v6 {
-                            port_forwarding_rules
-                                .append(Value::new(make_port_tuple(port, &v6.to_string())))?;
-                        }
-                    }
-                }
-            }
-            None => {}
-        };
+        let (port_tuples, rich_rules_to_add) = get_port_forward_rules(&setup_portfw);
+        for port_tuple in port_tuples {
+            port_forwarding_rules.append(Value::new(port_tuple))?;
+        }
 
-        // dns port forwarding requires rich rules as we also want to match destination ip
-        // only bother if configured dns port isn't 53
         let mut rich_rules_option: Option<Array> = None;
-        if setup_portfw.dns_port != 53 && !setup_portfw.dns_server_ips.is_empty() {
-            let mut rich_rules: Array;
-            match policy_config.get("rich_rules") {
-                Some(a) => match a {
-                    Value::Array(arr) => rich_rules = arr.clone(),
-                    _ => {
-                        return Err(NetavarkError::msg(
-                            "forward-port in firewalld policy object has a bad type",
-                        ))
-                    }
-                },
-                None => {
-                    // No existing rules
-                    // Make us a new array.
-                    let sig = match Signature::try_from("s") {
-                        Ok(s) => s,
-                        Err(e) => {
-                            return Err(NetavarkError::wrap(
-                                "Error creating signature for new DBus array",
-                                e.into(),
-                            ))
-                        }
-                    };
-                    rich_rules = Array::new(sig);
-                }
-            }
-            for dns_ip in setup_portfw.dns_server_ips {
-                let ip_family = if dns_ip.is_ipv6() { "ipv6" } else { "ipv4" };
-                let rule = format!("rule family=\"{}\" destination address=\"{}\" forward-port port=\"53\" protocol=\"udp\" to-port=\"{}\" to-addr=\"{}\"",
-                                   ip_family, dns_ip, setup_portfw.dns_port, dns_ip);
+        if !rich_rules_to_add.is_empty() {
+            let mut rich_rules = get_rich_rules(&policy_config)?;
+            for rule in rich_rules_to_add {
                 rich_rules.append(Value::new(rule))?;
             }
             rich_rules_option = Some(rich_rules)
@@ -262,7 +282,7 @@ impl firewall::FirewallDriver for FirewallD {
             }
         };
 
-        Ok(())
+        Ok(Vec::new())
     }
 
     fn teardown_port_forward(&self, teardown_pf: TeardownPortForward) -> NetavarkResult<()> {
@@ -356,48 +376,47 @@ impl firewall::FirewallDriver for FirewallD {
             port_forwarding_rules_option = Some(port_forwarding_rules)
         }
 
-        // iterate through rich rules to remove dns forwarding if this
-        // is the last container of the network e.g. teardown complete
-        // only bother if configured dns port isn't 53
+        // iterate through rich rules to remove the port forwarding rules of this
+        // container and the dns forwarding if this is the last container of the
+        // network e.g. teardown complete, only bother if configured dns port isn't 53
         let mut rich_rules_option: Option<Array> = None;
-        let mut old_rich_rules_option: Option<Array> = None;
-        if teardown_pf.complete_teardown
-            && teardown_pf.config.dns_port != 53
-            && !teardown_pf.config.dns_server_ips.is_empty()
-        {
-            if let Some(a) = policy_config.get("rich_rules") {
-                match a {
-                    Value::Array(arr) => old_rich_rules_option = Some(arr.clone()),
-                    _ => {
-                        return Err(NetavarkError::msg(
-                            "forward-port in firewalld policy object has a bad type",
-                        ))
-                    }
-                }
-            }
-        }
-        if let Some(old_rich_rules) = old_rich_rules_option {
-            let mut rules_to_delete: Vec<String> = vec![];
-            for dns_ip in teardown_pf.config.dns_server_ips {
-                let ip_family = if dns_ip.is_ipv6() { "ipv6" } else { "ipv4" };
-                let rule = format!("rule family=\"{}\" destination address=\"{}\" forward-port port=\"53\" protocol=\"udp\" to-port=\"{}\" to-addr=\"{}\"",
-                                   ip_family, dns_ip, teardown_pf.config.dns_port, dns_ip);
-                rules_to_delete.push(rule);
-            }
-            let sig = match Signature::try_from("s") {
-                Ok(s) => s,
-                Err(e) => {
-                    return Err(NetavarkError::wrap(
-                        "Error creating signature for new DBus array",
-                        e.into(),
+        if let Some(a) = policy_config.get("rich_rules") {
+            let old_rich_rules = match a {
+                Value::Array(arr) => arr,
+                _ => {
+                    return Err(NetavarkError::msg(
+                        "rich_rules in firewalld policy object has a bad type",
                     ))
                 }
             };
-            let mut rich_rules = Array::new(sig);
+            let mut rules_to_delete: Vec<String> = vec![];
+            if teardown_pf.complete_teardown && teardown_pf.config.dns_port != 53 {
+                for dns_ip in teardown_pf.config.dns_server_ips {
+                    rules_to_delete.push(make_dns_rich_rule(dns_ip, teardown_pf.config.dns_port));
+                }
+            }
+            // port forwarding rules with allowed sources always end with the container ip
+            let container_addrs: Vec<String> = [
+                teardown_pf.config.container_ip_v4,
+                teardown_pf.config.container_ip_v6,
+            ]
+            .iter()
+            .flatten()
+            .map(|ip| format!("to-addr=\"{}\"", ip))
+            .collect();
+
+            let mut rich_rules = new_rich_rules()?;
+            let mut changed = false;
             for rule in old_rich_rules.iter() {
                 match rule {
                     Value::Str(old_rule) => {
-                        if !rules_to_delete.contains(&old_rule.to_string()) {
+                        let old_rule = old_rule.as_str();
+                        if rules_to_delete.iter().any(|r| r == old_rule)
+                            || (old_rule.contains(" source address=")
+                                && container_addrs.iter().any(|a| old_rule.ends_with(a)))
+                        {
+                            changed = true;
+                        } else {
                             rich_rules.append(rule.clone())?;
                         }
                     }
@@ -408,7 +427,9 @@ impl firewall::FirewallDriver for FirewallD {
                     }
                 }
             }
-            rich_rules_option = Some(rich_rules);
+            if changed {
+                rich_rules_option = Some(rich_rules);
+            }
         }
 
         // Firewalld won't alter keys we don't mention, so make a new config
@@ -448,6 +469,48 @@ impl firewall::FirewallDriver for FirewallD {
 
         Ok(())
     }
+
+    // the dbus calls change the zones and policies in place, there are no
+    // rules which could be printed
+    fn render_network(
+        &self,
+        _network_setup: internal_types::SetupNetwork,
+    ) -> NetavarkResult<Vec<String>> {
+        Err(NetavarkError::msg(RENDER_NOT_SUPPORTED))
+    }
+
+    fn render_port_forward(&self, _setup_portfw: PortForwardConfig) -> NetavarkResult<Vec<String>> {
+        Err(NetavarkError::msg(RENDER_NOT_SUPPORTED))
+    }
+
+    // firewalld does not report installed rules so there is nothing to remove
+    fn remove_rules(
+        &self,
+        _rules: &[firewall::FirewallRule],
+        _complete_teardown: bool,
+    ) -> NetavarkResult<()> {
+        Ok(())
+    }
+
+    // port forwarding uses the netavark policy, there are no per container chains
+    fn remove_stale_port_forward_chains(
+        &self,
+        _stale: &HashSet<String>,
+        _known: &HashSet<String>,
+    ) -> NetavarkResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn update_dns_rules(
+        &self,
+        _network_hash_name: &str,
+        _subnets: &[types::Subnet],
+        _dns_port: u16,
+    ) -> NetavarkResult<Vec<firewall::FirewallRule>> {
+        Err(NetavarkError::msg(
+            "updating the dns rules is not supported by the firewalld driver",
+        ))
+    }
 }
 
 /// Create a firewalld zone to hold all our interfaces.
@@ -658,7 +721,7 @@ fn add_policy_if_not_exist(
 /// (port, protocol, to-port, to-addr)
 /// Port, to-port can be ranges (separated via hyphen)
 /// Also accepts IP address to forward to.
-fn make_port_tuple(port: &PortMapping, addr: &str) -> (String, String, String, String) {
+fn make_port_tuple(port: &PortMapping, addr: &str) -> PortTuple {
     if port.range > 1 {
         // Subtract 1 as these are 1-indexed strings - range of 2 is 1000-1001
         let end_host_range = port.host_port + port.range - 1;
@@ -680,3 +743,118 @@ fn make_port_tuple(port: &PortMapping, addr: &str) -> (String, String, String, S
         to_return
     }
 }
+
+/// Get the forward port tuples and rich rules for the port forwarding config.
+/// Rich rules are needed when we have to match on the source or destination
+/// address which forward_ports cannot do.
+fn get_port_forward_rules(setup_portfw: &PortForwardConfig) -> (Vec<PortTuple>, Vec<String>) {
+    let mut port_tuples = Vec::new();
+    let mut rich_rules = Vec::new();
+    if let Some(ports) = setup_portfw.port_mappings {
+        for port in ports {
+            if let Some(sources) = &port.allowed_sources {
+                if !sources.is_empty() {
+                    for addr in [setup_portfw.container_ip_v4, setup_portfw.container_ip_v6]
+                        .iter()
+                        .flatten()
+                    {
+                        for source in sources {
+                            if source.addr().is_ipv6() != addr.is_ipv6() {
+                                continue;
+                            }
+                            if let Some(rule) = make_port_rich_rule(port, source, addr) {
+                                rich_rules.push(rule);
+                            }
+                        }
+                    }
+                    continue;
+                }
+            }
+            if !port.host_ip.is_empty() {
+                port_tuples.push(make_port_tuple(port, &port.host_ip));
+            } else {
+                if let Some(v4) = setup_portfw.container_ip_v4 {
+                    port_tuples.push(make_port_tuple(port, &v4.to_string()));
+                }
+                if let Some(v6) = setup_portfw.container_ip_v6 {
+                    port_tuples.push(make_port_tuple(port, &v6.to_string()));
+                }
+            }
+        }
+    }
+
+    // dns port forwarding requires rich rules as we also want to match destination ip
+    // only bother if configured dns port isn't 53
+    if setup_portfw.dns_port != 53 {
+        for dns_ip in setup_portfw.dns_server_ips {
+            rich_rules.push(make_dns_rich_rule(dns_ip, setup_portfw.dns_port));
+        }
+    }
+    (port_tuples, rich_rules)
+}
+
+/// Get the rich rules of the given policy config, if there are none
+/// a new empty array is returned.
+fn get_rich_rules<'a>(policy_config: &HashMap<&str, Value<'a>>) -> NetavarkResult<Array<'a>> {
+    match policy_config.get("rich_rules") {
+        Some(Value::Array(arr)) => Ok(arr.clone()),
+        Some(_) => Err(NetavarkError::msg(
+            "rich_rules in firewalld policy object has a bad type",
+        )),
+        // No existing rules
+        // Make us a new array.
+        None => new_rich_rules(),
+    }
+}
+
+/// Make a new empty array for rich rules.
+fn new_rich_rules<'a>() -> NetavarkResult<Array<'a>> {
+    let sig = match Signature::try_from("s") {
+        Ok(s) => s,
+        Err(e) => {
+            return Err(NetavarkError::wrap(
+                "Error creating signature for new DBus array",
+                e.into(),
+            ))
+        }
+    };
+    Ok(Array::new(sig))
+}
+
+/// Make the rich rule to forward dns traffic to the dns port.
+fn make_dns_rich_rule(dns_ip: &IpAddr, dns_port: u16) -> String {
+    let ip_family = if dns_ip.is_ipv6() { "ipv6" } else { "ipv4" };
+    format!("rule family=\"{}\" destination address=\"{}\" forward-port port=\"53\" protocol=\"udp\" to-port=\"{}\" to-addr=\"{}\"",
+            ip_family, dns_ip, dns_port, dns_ip)
+}
+
+/// Make a rich rule which only forwards the port from the given source subnet.
+/// Returns None if the host ip of the port does not match the family of the address.
+fn make_port_rich_rule(port: &PortMapping, source: &IpNet, addr: &IpAddr) -> Option<String> {
+    let ip_family = if addr.is_ipv6() { "ipv6" } else { "ipv4" };
+    let mut destination = String::new();
+    if let Ok(host_ip) = port.host_ip.parse::<IpAddr>() {
+        if host_ip.is_ipv6() != addr.is_ipv6() {
+            return None;
+        }
+        if !host_ip.is_unspecified() {
+            destination = format!(" destination address=\"{}\"", host_ip);
+        }
+    }
+    let (host_port, container_port) = if port.range > 1 {
+        (
+            format!("{}-{}", port.host_port, port.host_port + port.range - 1),
+            format!(
+                "{}-{}",
+                port.container_port,
+                port.container_port + port.range - 1
+            ),
+        )
+    } else {
+        (port.host_port.to_string(), port.container_port.to_string())
+    };
+    Some(format!(
+        "rule family=\"{}\" source address=\"{}\"{} forward-port port=\"{}\" protocol=\"{}\" to-port=\"{}\" to-addr=\"{}\"",
+        ip_family, source, destination, host_port, port.protocol, container_port, addr
+    ))
+}
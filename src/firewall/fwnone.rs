@@ -3,6 +3,8 @@ use crate::firewall::NetavarkResult;
 use crate::network::internal_types::{
     PortForwardConfig, SetupNetwork, TearDownNetwork, TeardownPortForward,
 };
+use crate::network::types;
+use std::collections::HashSet;
 
 // Iptables driver - uses direct iptables commands via the iptables crate.
 pub struct Fwnone {}
@@ -12,8 +14,11 @@ pub fn new() -> NetavarkResult<Box<dyn firewall::FirewallDriver>> {
 }
 
 impl firewall::FirewallDriver for Fwnone {
-    fn setup_network(&self, _network_setup: SetupNetwork) -> NetavarkResult<()> {
-        Ok(())
+    fn setup_network(
+        &self,
+        _network_setup: SetupNetwork,
+    ) -> NetavarkResult<Vec<firewall::FirewallRule>> {
+        Ok(Vec::new())
     }
 
     // teardown_network should only be called in the case of
@@ -22,11 +27,47 @@ impl firewall::FirewallDriver for Fwnone {
         Ok(())
     }
 
-    fn setup_port_forward(&self, _setup_portfw: PortForwardConfig) -> NetavarkResult<()> {
-        Ok(())
+    fn setup_port_forward(
+        &self,
+        _setup_portfw: PortForwardConfig,
+    ) -> NetavarkResult<Vec<firewall::FirewallRule>> {
+        Ok(Vec::new())
     }
 
     fn teardown_port_forward(&self, _tear: TeardownPortForward) -> NetavarkResult<()> {
         Ok(())
     }
+
+    fn remove_rules(
+        &self,
+        _rules: &[firewall::FirewallRule],
+        _complete_teardown: bool,
+    ) -> NetavarkResult<()> {
+        Ok(())
+    }
+
+    fn render_network(&self, _network_setup: SetupNetwork) -> NetavarkResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn render_port_forward(&self, _setup_portfw: PortForwardConfig) -> NetavarkResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn remove_stale_port_forward_chains(
+        &self,
+        _stale: &HashSet<String>,
+        _known: &HashSet<String>,
+    ) -> NetavarkResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn update_dns_rules(
+        &self,
+        _network_hash_name: &str,
+        _subnets: &[types::Subnet],
+        _dns_port: u16,
+    ) -> NetavarkResult<Vec<firewall::FirewallRule>> {
+        Ok(Vec::new())
+    }
 }
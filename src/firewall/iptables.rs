@@ -1,25 +1,130 @@
 use crate::error::{NetavarkError, NetavarkResult};
 use crate::firewall;
 use crate::firewall::firewalld;
+use crate::firewall::varktables::helpers::{
+    chain_exists, move_jump_rule, move_rule_to_top, remove_if_rule_exists, JumpPosition,
+};
 use crate::firewall::varktables::types::TeardownPolicy::OnComplete;
 use crate::firewall::varktables::types::{
-    create_network_chains, get_network_chains, get_port_forwarding_chains, TeardownPolicy,
+    allow_jump_rule, create_network_chains, drop_log_prefix, forward_jump_rule, get_allow_chains,
+    get_conn_limit_chains, get_conntrack_chains, get_default_drop_chains, get_dns_allow_chains,
+    get_dns_redirect_chains, get_extra_rule_chains, get_host_access_chains, get_mss_clamp_chains,
+    get_network_chains, get_port_forwarding_chains, installed_rules, migrate_network_rules,
+    remove_dns_rules, remove_port_forward_rules_by_id, remove_stale_port_forward_chains,
+    remove_unused_allow_chain, render_network_chains, restore_network_chains, TeardownPolicy,
+    VarkChain, VarkRule,
 };
+use crate::firewall::{FirewallRule, RuleTeardown};
+use crate::network::internal_types::IsolateOption;
 use crate::network::internal_types::{
     PortForwardConfig, SetupNetwork, TearDownNetwork, TeardownPortForward,
 };
 use crate::network::types;
+use crate::warnings;
 use iptables;
 use iptables::IPTables;
-use log::{debug, warn};
+use log::debug;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::env;
 use zbus::blocking::Connection;
 
 pub(crate) const MAX_HASH_SIZE: usize = 13;
 
+pub use crate::firewall::varktables::types::port_forward_chain_name;
+
+/// When set to "1" or "true" rules are added with a single iptables-restore
+/// call per ip family instead of one iptables call per rule. The rules of all
+/// networks and port mappings of a container are applied together by setup.
+pub const IPTABLES_RESTORE_ENV: &str = "NETAVARK_IPTABLES_RESTORE";
+
+/// Position of the FORWARD jump into NETAVARK_FORWARD, "top" (default),
+/// "bottom" or "after:CHAIN" to place it after the jump into CHAIN.
+pub const FORWARD_POSITION_ENV: &str = "NETAVARK_FORWARD_POSITION";
+
+#[derive(Debug, PartialEq)]
+enum ForwardPosition {
+    Top,
+    Bottom,
+    After(String),
+}
+
+fn get_forward_position() -> NetavarkResult<ForwardPosition> {
+    let value = env::var(FORWARD_POSITION_ENV).unwrap_or_default();
+    match value.as_str() {
+        "" | "top" => Ok(ForwardPosition::Top),
+        "bottom" => Ok(ForwardPosition::Bottom),
+        v => match v.strip_prefix("after:") {
+            Some(chain) if !chain.is_empty() => Ok(ForwardPosition::After(chain.to_string())),
+            _ => Err(NetavarkError::Message(format!(
+                "invalid {} value {:?}, must be top, bottom or after:CHAIN",
+                FORWARD_POSITION_ENV, v
+            ))),
+        },
+    }
+}
+
+// move the FORWARD jump into NETAVARK_FORWARD to the configured position, it
+// is moved back when the position changed since it was created
+fn move_forward_jump(conn: &IPTables, position: &ForwardPosition) -> NetavarkResult<()> {
+    let position = match position {
+        ForwardPosition::Top => JumpPosition::Top,
+        ForwardPosition::Bottom => JumpPosition::Bottom,
+        ForwardPosition::After(chain) => JumpPosition::After(chain),
+    };
+    let rule = forward_jump_rule();
+    if !move_jump_rule(
+        conn,
+        "filter",
+        "FORWARD",
+        &rule,
+        "NETAVARK_FORWARD",
+        position,
+    )? {
+        if let JumpPosition::After(chain) = position {
+            warnings::push(format!(
+                "no rule jumping to {} in the FORWARD chain, keeping the netavark jump at the top",
+                chain
+            ));
+        }
+        move_jump_rule(
+            conn,
+            "filter",
+            "FORWARD",
+            &rule,
+            "NETAVARK_FORWARD",
+            JumpPosition::Top,
+        )?;
+    }
+    Ok(())
+}
+
 // Iptables driver - uses direct iptables commands via the iptables crate.
 pub struct IptablesDriver {
     conn: IPTables,
     conn6: IPTables,
+    restore: bool,
+    /// chains collected between begin_batch and commit_batch
+    batch: RefCell<Option<Batch>>,
+}
+
+/// A chain collected for a batch, the connection is stored as ip family
+/// because the chains must not borrow the driver.
+struct BatchedChain {
+    ipv6: bool,
+    table: String,
+    chain_name: String,
+    create: bool,
+    rules: Vec<VarkRule>,
+}
+
+#[derive(Default)]
+struct Batch {
+    chains: Vec<BatchedChain>,
+    /// ip families (v4, v6) with network chains, their jumps must be moved
+    families: (bool, bool),
+    /// ip families (v4, v6) with isolated networks
+    isolated: (bool, bool),
 }
 
 pub fn new() -> NetavarkResult<Box<dyn firewall::FirewallDriver>> {
@@ -35,13 +140,109 @@ pub fn new() -> NetavarkResult<Box<dyn firewall::FirewallDriver>> {
     let driver = IptablesDriver {
         conn: ipt,
         conn6: ipt6,
+        restore: matches!(
+            env::var(IPTABLES_RESTORE_ENV).as_deref(),
+            Ok("1") | Ok("true")
+        ),
+        batch: RefCell::new(None),
     };
     Ok(Box::new(driver))
 }
 
-impl firewall::FirewallDriver for IptablesDriver {
-    fn setup_network(&self, network_setup: SetupNetwork) -> NetavarkResult<()> {
-        let interface = match network_setup.net.network_interface {
+// iptables driver which never talks to iptables, it can only be used to render rules
+#[cfg(test)]
+pub(crate) fn new_without_conn() -> Box<dyn firewall::FirewallDriver> {
+    let conn = |cmd| IPTables {
+        cmd,
+        has_check: true,
+        has_wait: true,
+        is_numeric: false,
+    };
+    Box::new(IptablesDriver {
+        conn: conn("iptables"),
+        conn6: conn("ip6tables"),
+        restore: false,
+        batch: RefCell::new(None),
+    })
+}
+
+/// List the rules of the netavark chains and the rules jumping to them in all
+/// tables, in the iptables -S format prefixed with the table.
+pub fn list_netavark_rules(ipv6: bool) -> NetavarkResult<Vec<String>> {
+    let conn = match iptables::new(ipv6) {
+        Ok(c) => c,
+        Err(e) => return Err(NetavarkError::Message(format!("iptables: {}", e))),
+    };
+    let mut rules = Vec::new();
+    for table in ["filter", "nat", "mangle", "raw"] {
+        let listed = conn
+            .list_table(table)
+            .map_err(|e| NetavarkError::Message(format!("list {} table: {}", table, e)))?;
+        rules.extend(filter_netavark_rules(table, listed));
+    }
+    Ok(rules)
+}
+
+fn filter_netavark_rules(table: &str, rules: Vec<String>) -> Vec<String> {
+    rules
+        .into_iter()
+        .filter(|rule| rule.contains("NETAVARK"))
+        .map(|rule| format!("-t {} {}", table, rule))
+        .collect()
+}
+
+impl IptablesDriver {
+    fn create_chains(&self, chains: Vec<VarkChain<'_>>) -> NetavarkResult<()> {
+        if let Some(batch) = self.batch.borrow_mut().as_mut() {
+            batch
+                .chains
+                .extend(chains.into_iter().map(|c| BatchedChain {
+                    ipv6: std::ptr::eq(c.driver, &self.conn6),
+                    table: c.table,
+                    chain_name: c.chain_name,
+                    create: c.create,
+                    rules: c.rules,
+                }));
+            return Ok(());
+        }
+        if self.restore {
+            restore_network_chains(chains)
+        } else {
+            create_network_chains(chains)
+        }
+    }
+
+    // move the FORWARD jumps once the chains of the given (v4, v6) families exist
+    fn move_jumps(
+        &self,
+        families: (bool, bool),
+        isolated: (bool, bool),
+        forward_position: &ForwardPosition,
+    ) -> NetavarkResult<()> {
+        if families.0 {
+            move_forward_jump(&self.conn, forward_position)?;
+        }
+        if families.1 {
+            move_forward_jump(&self.conn6, forward_position)?;
+        }
+
+        // isolation inserts its FORWARD jump at the top, the allowed flows
+        // must still be accepted first
+        if isolated.0 {
+            move_rule_to_top(&self.conn, "filter", "FORWARD", &allow_jump_rule())?;
+        }
+        if isolated.1 {
+            move_rule_to_top(&self.conn6, "filter", "FORWARD", &allow_jump_rule())?;
+        }
+        Ok(())
+    }
+
+    // get the chains for all subnets of the network
+    fn network_chains<'a>(
+        &'a self,
+        network_setup: &'a SetupNetwork,
+    ) -> NetavarkResult<Vec<VarkChain<'a>>> {
+        let interface = match &network_setup.net.network_interface {
             Some(iface) => iface,
             None => {
                 return Err(std::io::Error::new(
@@ -52,7 +253,11 @@ impl firewall::FirewallDriver for IptablesDriver {
             }
         };
 
-        if let Some(subnet) = network_setup.net.subnets {
+        let log_prefix = network_setup
+            .log_drops
+            .then(|| drop_log_prefix(&network_setup.net.name));
+        let mut chains = Vec::new();
+        if let Some(subnet) = &network_setup.net.subnets {
             for network in subnet {
                 let is_ipv6 = network.subnet.network().is_ipv6();
                 let mut conn = &self.conn;
@@ -60,21 +265,199 @@ impl firewall::FirewallDriver for IptablesDriver {
                     conn = &self.conn6;
                 }
 
-                let chains = get_network_chains(
+                chains.extend(get_network_chains(
                     conn,
                     network.subnet,
                     &network_setup.network_hash_name,
-                    is_ipv6,
                     interface.to_string(),
                     network_setup.isolation,
-                );
+                    network_setup.snat_ip,
+                    network_setup.ipv6_nat,
+                    network_setup.random_fully,
+                ));
+                chains.extend(get_allow_chains(
+                    conn,
+                    network.subnet,
+                    &network_setup.network_hash_name,
+                    is_ipv6,
+                    &network_setup.allowed,
+                ));
+                if network_setup.mss_clamp {
+                    chains.extend(get_mss_clamp_chains(conn, network.subnet));
+                }
+                if network_setup.conntrack_zone.is_some() || !network_setup.conntrack_helpers {
+                    chains.extend(get_conntrack_chains(
+                        conn,
+                        interface,
+                        network_setup.conntrack_zone,
+                    ));
+                }
+                chains.extend(get_conn_limit_chains(
+                    conn,
+                    network.subnet,
+                    &network_setup.network_hash_name,
+                    network_setup.conn_limit,
+                    network_setup.conn_rate_limit,
+                ));
+                if let (Some(port), Some(gateway)) = (network_setup.dns_port, network.gateway) {
+                    chains.extend(get_dns_allow_chains(
+                        conn,
+                        network.subnet,
+                        &network_setup.network_hash_name,
+                        gateway,
+                        port,
+                    ));
+                }
+                if network_setup.block_host_access {
+                    chains.extend(get_host_access_chains(
+                        conn,
+                        network.subnet,
+                        network.gateway,
+                    ));
+                }
+                if network_setup.default_drop || network_setup.egress_only {
+                    chains.extend(get_default_drop_chains(
+                        conn,
+                        network.subnet,
+                        &network_setup.network_hash_name,
+                        interface,
+                        network_setup.egress_only,
+                        log_prefix.as_deref(),
+                    ));
+                }
+                // the extra rules go last so they are appended after ours
+                chains.extend(get_extra_rule_chains(
+                    conn,
+                    network.subnet,
+                    &network_setup.network_hash_name,
+                    is_ipv6,
+                    network_setup.net.extra_rules.as_deref().unwrap_or_default(),
+                ));
+            }
+        }
+        Ok(chains)
+    }
+
+    // get the port forwarding chains for the v4 and v6 container address
+    fn port_forward_chains<'a>(
+        &'a self,
+        setup_portfw: &PortForwardConfig,
+    ) -> NetavarkResult<Vec<VarkChain<'a>>> {
+        let mut chains = Vec::new();
+        if let Some(v4) = setup_portfw.container_ip_v4 {
+            let subnet_v4 = match setup_portfw.subnet_v4 {
+                Some(s) => s,
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        "ipv4 address but provided but no v4 subnet provided",
+                    )
+                    .into())
+                }
+            };
+            chains.extend(get_port_forwarding_chains(
+                &self.conn,
+                setup_portfw,
+                &v4,
+                &subnet_v4,
+                false,
+            )?);
+        }
+        if let Some(v6) = setup_portfw.container_ip_v6 {
+            let subnet_v6 = match setup_portfw.subnet_v6 {
+                Some(s) => s,
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        "ipv6 address but provided but no v6 subnet provided",
+                    )
+                    .into())
+                }
+            };
+            chains.extend(get_port_forwarding_chains(
+                &self.conn6,
+                setup_portfw,
+                &v6,
+                &subnet_v6,
+                true,
+            )?);
+        };
+        Ok(chains)
+    }
+}
 
-                create_network_chains(chains)?;
+impl firewall::FirewallDriver for IptablesDriver {
+    fn setup_network(&self, network_setup: SetupNetwork) -> NetavarkResult<Vec<FirewallRule>> {
+        let forward_position = get_forward_position()?;
+        for network in network_setup.net.subnets.iter().flatten() {
+            let conn = if network.subnet.addr().is_ipv6() {
+                &self.conn6
+            } else {
+                &self.conn
+            };
+            migrate_network_rules(conn, &network_setup.network_hash_name)?;
+        }
+        let chains = self.network_chains(&network_setup)?;
+        // the network rules are only removed by a complete teardown
+        let rules = installed_rules(&chains, RuleTeardown::OnComplete);
+        self.create_chains(chains)?;
 
-                add_firewalld_if_possible(&network);
+        let subnets = network_setup.net.subnets.iter().flatten();
+        let (v6, v4): (Vec<_>, Vec<_>) = subnets.partition(|s| s.subnet.addr().is_ipv6());
+        let families = (!v4.is_empty(), !v6.is_empty());
+        let isolated = match network_setup.isolation {
+            IsolateOption::Nomal | IsolateOption::Strict => families,
+            IsolateOption::Never => (false, false),
+        };
+
+        if let Some(subnet) = &network_setup.net.subnets {
+            for network in subnet {
+                add_firewalld_if_possible(network);
             }
         }
-        Ok(())
+
+        if let Some(batch) = self.batch.borrow_mut().as_mut() {
+            batch.families = (
+                batch.families.0 || families.0,
+                batch.families.1 || families.1,
+            );
+            batch.isolated = (
+                batch.isolated.0 || isolated.0,
+                batch.isolated.1 || isolated.1,
+            );
+            return Ok(rules);
+        }
+        self.move_jumps(families, isolated, &forward_position)?;
+        Ok(rules)
+    }
+
+    fn begin_batch(&self) {
+        if self.restore {
+            *self.batch.borrow_mut() = Some(Batch::default());
+        }
+    }
+
+    fn commit_batch(&self) -> NetavarkResult<()> {
+        let batch = match self.batch.borrow_mut().take() {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+        let forward_position = get_forward_position()?;
+        let chains = batch
+            .chains
+            .into_iter()
+            .map(|c| VarkChain {
+                chain_name: c.chain_name,
+                create: c.create,
+                driver: if c.ipv6 { &self.conn6 } else { &self.conn },
+                rules: c.rules,
+                table: c.table,
+                td_policy: None,
+            })
+            .collect::<Vec<_>>();
+        debug!("applying the batched rules of {} chains", chains.len());
+        restore_network_chains(chains)?;
+        self.move_jumps(batch.families, batch.isolated, &forward_position)
     }
 
     // teardown_network should only be called in the case of
@@ -91,6 +474,10 @@ impl firewall::FirewallDriver for IptablesDriver {
             }
         };
 
+        let log_prefix = tear
+            .config
+            .log_drops
+            .then(|| drop_log_prefix(&tear.config.net.name));
         // Remove network specific general NAT rules
         if let Some(subnet) = tear.config.net.subnets {
             for network in subnet {
@@ -103,11 +490,78 @@ impl firewall::FirewallDriver for IptablesDriver {
                     conn,
                     network.subnet,
                     &tear.config.network_hash_name,
-                    is_ipv6,
                     interface.to_string(),
                     tear.config.isolation,
-                );
+                    tear.config.snat_ip,
+                    tear.config.ipv6_nat,
+                    tear.config.random_fully,
+                )
+                .into_iter()
+                .chain(get_allow_chains(
+                    conn,
+                    network.subnet,
+                    &tear.config.network_hash_name,
+                    is_ipv6,
+                    &tear.config.allowed,
+                ))
+                .chain(if tear.config.mss_clamp {
+                    get_mss_clamp_chains(conn, network.subnet)
+                } else {
+                    Vec::new()
+                })
+                .chain(if tear.config.default_drop || tear.config.egress_only {
+                    get_default_drop_chains(
+                        conn,
+                        network.subnet,
+                        &tear.config.network_hash_name,
+                        &interface,
+                        tear.config.egress_only,
+                        log_prefix.as_deref(),
+                    )
+                } else {
+                    Vec::new()
+                })
+                .chain(get_conn_limit_chains(
+                    conn,
+                    network.subnet,
+                    &tear.config.network_hash_name,
+                    tear.config.conn_limit,
+                    tear.config.conn_rate_limit,
+                ))
+                .chain(match (tear.config.dns_port, network.gateway) {
+                    (Some(port), Some(gateway)) => get_dns_allow_chains(
+                        conn,
+                        network.subnet,
+                        &tear.config.network_hash_name,
+                        gateway,
+                        port,
+                    ),
+                    _ => Vec::new(),
+                })
+                .chain(if tear.config.block_host_access {
+                    get_host_access_chains(conn, network.subnet, network.gateway)
+                } else {
+                    Vec::new()
+                })
+                .chain(
+                    if tear.config.conntrack_zone.is_some() || !tear.config.conntrack_helpers {
+                        get_conntrack_chains(conn, &interface, tear.config.conntrack_zone)
+                    } else {
+                        Vec::new()
+                    },
+                )
+                .chain(get_extra_rule_chains(
+                    conn,
+                    network.subnet,
+                    &tear.config.network_hash_name,
+                    is_ipv6,
+                    tear.config.net.extra_rules.as_deref().unwrap_or_default(),
+                ))
+                .collect::<Vec<_>>();
 
+                if tear.complete_teardown {
+                    migrate_network_rules(conn, &tear.config.network_hash_name)?;
+                }
                 for c in &chains {
                     c.remove_rules(tear.complete_teardown)?;
                 }
@@ -123,6 +577,9 @@ impl firewall::FirewallDriver for IptablesDriver {
                 }
 
                 if tear.complete_teardown {
+                    if !tear.config.allowed.from.is_empty() || !tear.config.allowed.to.is_empty() {
+                        remove_unused_allow_chain(conn)?;
+                    }
                     rm_firewalld_if_possible(&network)
                 }
             }
@@ -130,38 +587,14 @@ impl firewall::FirewallDriver for IptablesDriver {
         Result::Ok(())
     }
 
-    fn setup_port_forward(&self, setup_portfw: PortForwardConfig) -> NetavarkResult<()> {
-        if let Some(v4) = setup_portfw.container_ip_v4 {
-            let subnet_v4 = match setup_portfw.subnet_v4 {
-                Some(s) => s,
-                None => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "ipv4 address but provided but no v4 subnet provided",
-                    )
-                    .into())
-                }
-            };
-            let chains =
-                get_port_forwarding_chains(&self.conn, &setup_portfw, &v4, &subnet_v4, false)?;
-            create_network_chains(chains)?;
-        }
-        if let Some(v6) = setup_portfw.container_ip_v6 {
-            let subnet_v6 = match setup_portfw.subnet_v6 {
-                Some(s) => s,
-                None => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "ipv6 address but provided but no v6 subnet provided",
-                    )
-                    .into())
-                }
-            };
-            let chains =
-                get_port_forwarding_chains(&self.conn6, &setup_portfw, &v6, &subnet_v6, true)?;
-            create_network_chains(chains)?;
-        };
-        Result::Ok(())
+    fn setup_port_forward(
+        &self,
+        setup_portfw: PortForwardConfig,
+    ) -> NetavarkResult<Vec<FirewallRule>> {
+        let chains = self.port_forward_chains(&setup_portfw)?;
+        let rules = installed_rules(&chains, RuleTeardown::Always);
+        self.create_chains(chains)?;
+        Ok(rules)
     }
 
     fn teardown_port_forward(&self, tear: TeardownPortForward) -> NetavarkResult<()> {
@@ -183,14 +616,20 @@ impl firewall::FirewallDriver for IptablesDriver {
             for chain in &chains {
                 chain.remove_rules(tear.complete_teardown)?;
             }
+            // the container might have been renamed after setup
+            remove_port_forward_rules_by_id(&self.conn, &tear.config)?;
+            // the port forwarding chain belongs to the container, so it is
+            // removed on every teardown and not only on complete ones
             for chain in &chains {
-                if !tear.complete_teardown || !chain.create {
+                if !chain.create {
                     continue;
                 }
                 match &chain.td_policy {
                     None => {}
                     Some(policy) => {
-                        if *policy == TeardownPolicy::OnComplete {
+                        if *policy == TeardownPolicy::OnComplete
+                            && chain_exists(chain.driver, &chain.table, &chain.chain_name)?
+                        {
                             chain.remove()?;
                         }
                     }
@@ -216,14 +655,20 @@ impl firewall::FirewallDriver for IptablesDriver {
             for chain in &chains {
                 chain.remove_rules(tear.complete_teardown)?;
             }
+            // the container might have been renamed after setup
+            remove_port_forward_rules_by_id(&self.conn6, &tear.config)?;
+            // the port forwarding chain belongs to the container, so it is
+            // removed on every teardown and not only on complete ones
             for chain in &chains {
-                if !tear.complete_teardown || !chain.create {
+                if !chain.create {
                     continue;
                 }
                 match &chain.td_policy {
                     None => {}
                     Some(policy) => {
-                        if *policy == TeardownPolicy::OnComplete {
+                        if *policy == TeardownPolicy::OnComplete
+                            && chain_exists(chain.driver, &chain.table, &chain.chain_name)?
+                        {
                             chain.remove()?;
                         }
                     }
@@ -232,10 +677,83 @@ impl firewall::FirewallDriver for IptablesDriver {
         }
         Result::Ok(())
     }
+
+    fn remove_rules(&self, rules: &[FirewallRule], complete_teardown: bool) -> NetavarkResult<()> {
+        for rule in rules.iter().filter(|r| r.removed_on(complete_teardown)) {
+            let conn = if rule.ipv6 { &self.conn6 } else { &self.conn };
+            remove_if_rule_exists(conn, &rule.table, &rule.chain, &rule.rule)?;
+        }
+        Ok(())
+    }
+
+    fn render_network(&self, network_setup: SetupNetwork) -> NetavarkResult<Vec<String>> {
+        let chains = self.network_chains(&network_setup)?;
+        Ok(render_network_chains(&chains))
+    }
+
+    fn render_port_forward(&self, setup_portfw: PortForwardConfig) -> NetavarkResult<Vec<String>> {
+        let chains = self.port_forward_chains(&setup_portfw)?;
+        Ok(render_network_chains(&chains))
+    }
+
+    fn remove_stale_port_forward_chains(
+        &self,
+        stale: &HashSet<String>,
+        known: &HashSet<String>,
+    ) -> NetavarkResult<Vec<String>> {
+        let mut removed = remove_stale_port_forward_chains(&self.conn, stale, known)?;
+        for chain in remove_stale_port_forward_chains(&self.conn6, stale, known)? {
+            if !removed.contains(&chain) {
+                removed.push(chain);
+            }
+        }
+        Ok(removed)
+    }
+
+    fn update_dns_rules(
+        &self,
+        network_hash_name: &str,
+        subnets: &[types::Subnet],
+        dns_port: u16,
+    ) -> NetavarkResult<Vec<FirewallRule>> {
+        let mut chains = Vec::new();
+        for (conn, is_ipv6) in [(&self.conn, false), (&self.conn6, true)] {
+            let subnets: Vec<&types::Subnet> = subnets
+                .iter()
+                .filter(|s| s.subnet.addr().is_ipv6() == is_ipv6)
+                .collect();
+            if subnets.is_empty() {
+                continue;
+            }
+            remove_dns_rules(conn, network_hash_name)?;
+            let gateways: Vec<_> = subnets.iter().filter_map(|s| s.gateway).collect();
+            chains.extend(get_dns_redirect_chains(
+                conn,
+                network_hash_name,
+                &gateways,
+                dns_port,
+                is_ipv6,
+            ));
+            for subnet in subnets {
+                if let Some(gateway) = subnet.gateway {
+                    chains.extend(get_dns_allow_chains(
+                        conn,
+                        subnet.subnet,
+                        network_hash_name,
+                        gateway,
+                        dns_port,
+                    ));
+                }
+            }
+        }
+        let rules = installed_rules(&chains, RuleTeardown::OnComplete);
+        self.create_chains(chains)?;
+        Ok(rules)
+    }
 }
 
 /// Check if firewalld is running
-fn is_firewalld_running(conn: &Connection) -> bool {
+pub(crate) fn is_firewalld_running(conn: &Connection) -> bool {
     conn.call_method(
         Some("org.freedesktop.DBus"),
         "/org/freedesktop/DBus",
@@ -263,11 +781,10 @@ fn add_firewalld_if_possible(net: &types::Subnet) {
 
     match firewalld::add_source_subnets_to_zone(&conn, "trusted", vec![net.clone()]) {
         Ok(_) => {}
-        Err(e) => warn!(
+        Err(e) => warnings::push(format!(
             "Error adding subnet {} from firewalld trusted zone: {}",
-            net.subnet.to_string(),
-            e
-        ),
+            net.subnet, e
+        )),
     }
 }
 
@@ -293,10 +810,9 @@ fn rm_firewalld_if_possible(net: &types::Subnet) {
         &("trusted", net.subnet.to_string()),
     ) {
         Ok(_) => {}
-        Err(e) => warn!(
+        Err(e) => warnings::push(format!(
             "Error removing subnet {} from firewalld trusted zone: {}",
-            net.subnet.to_string(),
-            e
-        ),
+            net.subnet, e
+        )),
     };
 }
@@ -2,7 +2,9 @@ use crate::error::{NetavarkError, NetavarkResult};
 use crate::network::internal_types::{
     PortForwardConfig, SetupNetwork, TearDownNetwork, TeardownPortForward,
 };
+use crate::network::types;
 use log::{debug, info};
+use std::collections::HashSet;
 use std::env;
 use zbus::blocking::Connection;
 
@@ -11,18 +13,138 @@ pub mod fwnone;
 pub mod iptables;
 mod varktables;
 
+pub use varktables::types::dns_rule_comment;
+
+/// A rule installed by a firewall driver, teardown deletes exactly this rule
+/// instead of deriving it from the network config again.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirewallRule {
+    pub ipv6: bool,
+    pub table: String,
+    pub chain: String,
+    /// matches and target of the rule, e.g. "-s 10.88.0.0/16 -j ACCEPT"
+    pub rule: String,
+    pub teardown: RuleTeardown,
+}
+
+/// When teardown removes an installed rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleTeardown {
+    /// on every teardown of the container
+    Always,
+    /// only on the teardown of the last container of the network
+    OnComplete,
+    /// never, the rule is shared by all networks
+    Never,
+}
+
+impl FirewallRule {
+    /// Whether a teardown removes this rule.
+    pub fn removed_on(&self, complete_teardown: bool) -> bool {
+        match self.teardown {
+            RuleTeardown::Always => true,
+            RuleTeardown::OnComplete => complete_teardown,
+            RuleTeardown::Never => false,
+        }
+    }
+}
+
+/// A chain netavark created, e.g. the nat chain of a network.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct FirewallChain {
+    pub ipv6: bool,
+    pub table: String,
+    pub chain: String,
+}
+
+/// The netavark chains the firewall rules of one container and network are
+/// in. The network chains are shared with the other containers of the
+/// network, or all networks like NETAVARK_FORWARD, the container chains like
+/// the port forwarding chain only belong to the container.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirewallChains {
+    pub network: Vec<FirewallChain>,
+    pub container: Vec<FirewallChain>,
+}
+
+impl FirewallChains {
+    /// Collect the chains of the installed rules, builtin chains like
+    /// POSTROUTING are skipped.
+    pub fn from_rules(rules: &[FirewallRule]) -> Self {
+        let mut chains = FirewallChains::default();
+        for rule in rules.iter().filter(|r| r.chain.starts_with("NETAVARK")) {
+            let chain = FirewallChain {
+                ipv6: rule.ipv6,
+                table: rule.table.clone(),
+                chain: rule.chain.clone(),
+            };
+            if varktables::types::is_container_chain(&rule.chain) {
+                chains.container.push(chain);
+            } else {
+                chains.network.push(chain);
+            }
+        }
+        for list in [&mut chains.network, &mut chains.container] {
+            list.sort();
+            list.dedup();
+        }
+        chains
+    }
+}
+
 /// Firewall drivers have the ability to set up per-network firewall forwarding
 /// and port mappings.
 pub trait FirewallDriver {
-    /// Set up firewall rules for the given network,
-    fn setup_network(&self, network_setup: SetupNetwork) -> NetavarkResult<()>;
+    /// Set up firewall rules for the given network, returns the installed rules.
+    fn setup_network(&self, network_setup: SetupNetwork) -> NetavarkResult<Vec<FirewallRule>>;
     /// Tear down firewall rules for the given network.
     fn teardown_network(&self, tear: TearDownNetwork) -> NetavarkResult<()>;
 
-    /// Set up port-forwarding firewall rules for a given container.
-    fn setup_port_forward(&self, setup_pw: PortForwardConfig) -> NetavarkResult<()>;
+    /// Set up port-forwarding firewall rules for a given container, returns
+    /// the installed rules.
+    fn setup_port_forward(&self, setup_pw: PortForwardConfig) -> NetavarkResult<Vec<FirewallRule>>;
     /// Tear down port-forwarding firewall rules for a single container.
     fn teardown_port_forward(&self, teardown_pf: TeardownPortForward) -> NetavarkResult<()>;
+    /// Remove the rules returned by the setup functions which the teardown
+    /// removes, rules which no longer exist are skipped.
+    fn remove_rules(&self, rules: &[FirewallRule], complete_teardown: bool) -> NetavarkResult<()>;
+
+    /// Collect the rules of the following setup calls and only apply them
+    /// with commit_batch, so the rules of all networks of a container are
+    /// applied at once. Drivers which cannot batch apply them right away.
+    fn begin_batch(&self) {}
+    /// Apply the rules collected since begin_batch.
+    fn commit_batch(&self) -> NetavarkResult<()> {
+        Ok(())
+    }
+
+    /// Render the firewall rules setup_network would create as text,
+    /// without changing anything on the system.
+    fn render_network(&self, network_setup: SetupNetwork) -> NetavarkResult<Vec<String>>;
+    /// Render the port-forwarding firewall rules setup_port_forward would
+    /// create as text, without changing anything on the system.
+    fn render_port_forward(&self, setup_pw: PortForwardConfig) -> NetavarkResult<Vec<String>>;
+
+    /// Replace the dns redirect and allow rules of the network with rules
+    /// for the given resolver port, e.g. after the resolver was restarted on
+    /// another port. Nothing else is changed, returns the installed rules.
+    fn update_dns_rules(
+        &self,
+        network_hash_name: &str,
+        subnets: &[types::Subnet],
+        dns_port: u16,
+    ) -> NetavarkResult<Vec<FirewallRule>>;
+
+    /// Remove the port-forwarding chains which are no longer used. The
+    /// `stale` chains belong to containers which no longer exist, unknown
+    /// chains are only removed when nothing refers to them, `known` chains
+    /// are kept. Returns the names of the removed chains.
+    fn remove_stale_port_forward_chains(
+        &self,
+        stale: &HashSet<String>,
+        known: &HashSet<String>,
+    ) -> NetavarkResult<Vec<String>>;
 }
 
 /// Types of firewall backend
@@ -33,11 +155,19 @@ enum FirewallImpl {
     Fwnone,
 }
 
+/// The driver given with the --firewall-driver option takes precedence over
+/// the NETAVARK_FW env var.
+fn get_forced_driver(forced: Option<&str>) -> Option<String> {
+    forced
+        .map(String::from)
+        .or_else(|| env::var("NETAVARK_FW").ok())
+}
+
 /// What firewall implementations does this system support?
-fn get_firewall_impl() -> NetavarkResult<FirewallImpl> {
-    // First, check the NETAVARK_FW env var.
+fn get_firewall_impl(forced: Option<&str>) -> NetavarkResult<FirewallImpl> {
+    // First, check the forced driver.
     // It respects "firewalld", "iptables", "nftables", "none".
-    if let Ok(var) = env::var("NETAVARK_FW") {
+    if let Some(var) = forced {
         debug!("Forcibly using firewall driver {}", var);
         match var.to_lowercase().as_str() {
             "firewalld" => {
@@ -50,6 +180,11 @@ fn get_firewall_impl() -> NetavarkResult<FirewallImpl> {
                         ))
                     }
                 };
+                if !iptables::is_firewalld_running(&conn) {
+                    return Err(NetavarkError::msg(
+                        "firewalld firewall backend requested but firewalld is not running",
+                    ));
+                }
                 return Ok(FirewallImpl::Firewalld(conn));
             }
             "iptables" => return Ok(FirewallImpl::Iptables),
@@ -85,10 +220,29 @@ fn get_firewall_impl() -> NetavarkResult<FirewallImpl> {
     // }
 }
 
+/// Name of the firewall driver setup would use, `forced` is the driver of
+/// the --firewall-driver option.
+pub fn get_firewall_driver_name(forced: Option<&str>) -> NetavarkResult<&'static str> {
+    Ok(
+        match get_firewall_impl(get_forced_driver(forced).as_deref())? {
+            FirewallImpl::Iptables => "iptables",
+            FirewallImpl::Firewalld(_) => "firewalld",
+            FirewallImpl::Nftables => "nftables",
+            FirewallImpl::Fwnone => "none",
+        },
+    )
+}
+
 /// Get the preferred firewall implementation for the current system
-/// configuration.
-pub fn get_supported_firewall_driver() -> NetavarkResult<Box<dyn FirewallDriver>> {
-    match get_firewall_impl() {
+/// configuration, `forced` is the driver of the --firewall-driver option.
+pub fn get_supported_firewall_driver(
+    forced: Option<&str>,
+) -> NetavarkResult<Box<dyn FirewallDriver>> {
+    new_firewall_driver(get_forced_driver(forced).as_deref())
+}
+
+fn new_firewall_driver(forced: Option<&str>) -> NetavarkResult<Box<dyn FirewallDriver>> {
+    match get_firewall_impl(forced) {
         Ok(fw) => match fw {
             FirewallImpl::Iptables => {
                 info!("Using iptables firewall driver");
@@ -112,3 +266,80 @@ pub fn get_supported_firewall_driver() -> NetavarkResult<Box<dyn FirewallDriver>
         Err(e) => Err(e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn driver_name(forced: &str) -> NetavarkResult<&'static str> {
+        Ok(match get_firewall_impl(Some(forced))? {
+            FirewallImpl::Iptables => "iptables",
+            FirewallImpl::Firewalld(_) => "firewalld",
+            FirewallImpl::Nftables => "nftables",
+            FirewallImpl::Fwnone => "none",
+        })
+    }
+
+    // Only netavark chains are reported, the port forwarding chain belongs to
+    // the container
+    #[test]
+    fn test_firewall_chains_from_rules() {
+        let rule = |ipv6, table: &str, chain: &str| FirewallRule {
+            ipv6,
+            table: table.to_string(),
+            chain: chain.to_string(),
+            rule: "-j ACCEPT".to_string(),
+            teardown: RuleTeardown::OnComplete,
+        };
+        let chain = |ipv6, table: &str, chain: &str| FirewallChain {
+            ipv6,
+            table: table.to_string(),
+            chain: chain.to_string(),
+        };
+        let chains = FirewallChains::from_rules(&[
+            rule(false, "nat", "POSTROUTING"),
+            rule(false, "nat", "NETAVARK-1D8721804F16F"),
+            rule(false, "nat", "NETAVARK-1D8721804F16F"),
+            rule(true, "nat", "NETAVARK-1D8721804F16F"),
+            rule(false, "filter", "NETAVARK_FORWARD"),
+            rule(false, "nat", "NETAVARK-DN-1D8721804F16F-a5f2"),
+        ]);
+        assert_eq!(
+            chains,
+            FirewallChains {
+                network: vec![
+                    chain(false, "filter", "NETAVARK_FORWARD"),
+                    chain(false, "nat", "NETAVARK-1D8721804F16F"),
+                    chain(true, "nat", "NETAVARK-1D8721804F16F"),
+                ],
+                container: vec![chain(false, "nat", "NETAVARK-DN-1D8721804F16F-a5f2")],
+            }
+        );
+    }
+
+    // The forced driver is used regardless of the case
+    #[test]
+    fn test_forced_firewall_driver() {
+        assert_eq!(driver_name("iptables").unwrap(), "iptables");
+        assert_eq!(driver_name("IPTables").unwrap(), "iptables");
+        assert_eq!(driver_name("nftables").unwrap(), "nftables");
+        assert_eq!(driver_name("none").unwrap(), "none");
+        assert_eq!(
+            driver_name("ufw").unwrap_err().to_string(),
+            "Must provide a valid firewall backend, got ufw"
+        );
+    }
+
+    // Forcing a backend which is not available fails instead of falling back
+    #[test]
+    fn test_forced_firewall_driver_unavailable() {
+        assert!(new_firewall_driver(Some("none")).is_ok());
+        assert_eq!(
+            new_firewall_driver(Some("nftables"))
+                .err()
+                .unwrap()
+                .to_string(),
+            "nftables support presently not available"
+        );
+    }
+}
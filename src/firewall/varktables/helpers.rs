@@ -51,8 +51,242 @@ pub fn add_chain_unique(driver: &IPTables, table: &str, new_chain: &str) -> Neta
     }
 }
 
+// move an existing rule to the top of the chain, if the rule does not
+// exist nothing is done
+pub fn move_rule_to_top(
+    driver: &IPTables,
+    table: &str,
+    chain: &str,
+    rule: &str,
+) -> NetavarkResult<()> {
+    let rules = match driver.list(table, chain) {
+        Ok(r) => r,
+        Err(e) => return Err(NetavarkError::Message(e.to_string())),
+    };
+    let wanted = format!("-A {} {}", chain, rule);
+    let mut appended = rules.iter().filter(|r| r.starts_with("-A "));
+    if appended.next() == Some(&wanted) || !appended.any(|r| *r == wanted) {
+        return Ok(());
+    }
+    debug!("moving rule {} to the top of chain {}", rule, chain);
+    if let Err(e) = driver
+        .delete(table, chain, rule)
+        .and_then(|_| driver.insert(table, chain, rule, 1))
+    {
+        return Err(NetavarkError::Message(format!(
+            "unable to move rule '{}' to the top of chain '{}': {}",
+            rule, chain, e
+        )));
+    }
+    Ok(())
+}
+
+// returns a bool as to whether the rule jumps to the target chain
+fn jumps_to(rule: &str, target: &str) -> bool {
+    let mut words = rule.split_whitespace();
+    while let Some(word) = words.next() {
+        if (word == "-j" || word == "--jump") && words.next() == Some(target) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Where move_jump_rule puts a rule in its chain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JumpPosition<'a> {
+    /// first rule after the jumps into other netavark chains, e.g. the
+    /// isolation chains, which must be evaluated first
+    Top,
+    /// last rule of the chain
+    Bottom,
+    /// directly after the last rule jumping to the chain
+    After(&'a str),
+}
+
+// returns a bool as to whether the rule jumps to a netavark chain
+fn jumps_to_netavark(rule: &str) -> bool {
+    let mut words = rule.split_whitespace();
+    while let Some(word) = words.next() {
+        if (word == "-j" || word == "--jump")
+            && words.next().map_or(false, |t| t.starts_with("NETAVARK"))
+        {
+            return true;
+        }
+    }
+    false
+}
+
+// get the current index of the rule jumping to target in the chain rules
+// listed by iptables -S and the index it should be moved to, counted without
+// the rule itself. None if the rule does not exist, the wanted index is None
+// if there is no rule jumping to the chain of JumpPosition::After.
+fn jump_rule_indexes(
+    rules: &[String],
+    chain: &str,
+    target: &str,
+    position: JumpPosition,
+) -> Option<(usize, Option<usize>)> {
+    let prefix = format!("-A {} ", chain);
+    let appended: Vec<&str> = rules
+        .iter()
+        .filter_map(|r| r.strip_prefix(&prefix))
+        .collect();
+    let current = appended.iter().position(|r| jumps_to(r, target))?;
+    let others: Vec<&str> = appended
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != current)
+        .map(|(_, r)| *r)
+        .collect();
+    let wanted = match position {
+        JumpPosition::Top => Some(others.iter().take_while(|r| jumps_to_netavark(r)).count()),
+        JumpPosition::Bottom => Some(others.len()),
+        JumpPosition::After(after) => others
+            .iter()
+            .rposition(|r| jumps_to(r, after))
+            .map(|i| i + 1),
+    };
+    Some((current, wanted))
+}
+
+// move an existing rule jumping to target to the given position, in both
+// directions. The rule is inserted at the new position before the old one is
+// deleted by its number, so the jump is never missing from the chain. If the
+// rule does not exist nothing is done, false is returned if there is no rule
+// jumping to the chain of JumpPosition::After.
+pub fn move_jump_rule(
+    driver: &IPTables,
+    table: &str,
+    chain: &str,
+    rule: &str,
+    target: &str,
+    position: JumpPosition,
+) -> NetavarkResult<bool> {
+    let rules = match driver.list(table, chain) {
+        Ok(r) => r,
+        Err(e) => return Err(NetavarkError::Message(e.to_string())),
+    };
+    let (current, wanted) = match jump_rule_indexes(&rules, chain, target, position) {
+        Some((current, Some(wanted))) => (current, wanted),
+        Some((_, None)) => return Ok(false),
+        None => return Ok(true),
+    };
+    if current == wanted {
+        return Ok(true);
+    }
+    // iptables rule numbers start at 1, the wanted index does not count the
+    // rule itself
+    let (insert_at, delete_at) = if wanted < current {
+        (wanted + 1, current + 2)
+    } else {
+        (wanted + 2, current + 1)
+    };
+    debug!(
+        "moving rule {} to position {} of chain {}",
+        rule, insert_at, chain
+    );
+    let res = driver
+        .insert(table, chain, rule, insert_at as i32)
+        .and_then(|_| driver.execute(table, &format!("-D {} {}", chain, delete_at)))
+        .and_then(|output| match output.status.success() {
+            true => Ok(()),
+            false => Err(String::from_utf8_lossy(&output.stderr).into()),
+        });
+    if let Err(e) = res {
+        return Err(NetavarkError::Message(format!(
+            "unable to move rule '{}' in chain '{}': {}",
+            rule, chain, e
+        )));
+    }
+    Ok(true)
+}
+
+// get the rules listed by iptables -S which jump to target but do not have the
+// marker comment, i.e. rules created by an older netavark version. The rules
+// are returned without the "-A chain" prefix so they can be deleted.
+fn outdated_jump_rules<'a>(
+    rules: &'a [String],
+    chain: &str,
+    target: &str,
+    marker: &str,
+) -> Vec<&'a str> {
+    let prefix = format!("-A {} ", chain);
+    rules
+        .iter()
+        .filter_map(|r| r.strip_prefix(&prefix))
+        .filter(|r| jumps_to(r, target))
+        .filter(|r| {
+            let words: Vec<&str> = r.split_whitespace().collect();
+            !words
+                .windows(2)
+                .any(|w| w[0] == "--comment" && w[1].trim_matches('"') == marker)
+        })
+        .collect()
+}
+
+// remove the rules jumping to target which were created by an older netavark
+// version, setup adds them again with the current marker. Returns the number
+// of removed rules.
+pub fn migrate_jump_rules(
+    driver: &IPTables,
+    table: &str,
+    chain: &str,
+    target: &str,
+    marker: &str,
+) -> NetavarkResult<usize> {
+    let rules = match driver.list(table, chain) {
+        Ok(r) => r,
+        Err(e) => return Err(NetavarkError::Message(e.to_string())),
+    };
+    let outdated = outdated_jump_rules(&rules, chain, target, marker);
+    for rule in &outdated {
+        debug!("migrating outdated rule {} of chain {}", rule, chain);
+        if let Err(e) = driver.delete(table, chain, rule) {
+            return Err(NetavarkError::Message(format!(
+                "failed to remove outdated rule '{}' from chain '{}': {}",
+                rule, chain, e
+            )));
+        }
+    }
+    Ok(outdated.len())
+}
+
+// remove all rules of the chain with a comment starting with the given text
+pub fn remove_rules_with_comment(
+    driver: &IPTables,
+    table: &str,
+    chain: &str,
+    comment: &str,
+) -> NetavarkResult<()> {
+    let rules = match driver.list(table, chain) {
+        Ok(r) => r,
+        Err(e) => return Err(NetavarkError::Message(e.to_string())),
+    };
+    let prefix = format!("-A {} ", chain);
+    // iptables -S prints the comment in double quotes
+    let exact = format!("--comment \"{}\"", comment);
+    let with_suffix = format!("--comment \"{} ", comment);
+    for rule in rules {
+        let rule = match rule.strip_prefix(&prefix) {
+            Some(r) => r,
+            None => continue,
+        };
+        if !rule.contains(&exact) && !rule.contains(&with_suffix) {
+            continue;
+        }
+        if let Err(e) = driver.delete(table, chain, rule) {
+            return Err(NetavarkError::Message(format!(
+                "failed to remove rule '{}' from table '{}': {}",
+                rule, chain, e
+            )));
+        }
+    }
+    Ok(())
+}
+
 // returns a bool as to whether the chain exists
-fn chain_exists(driver: &IPTables, table: &str, chain: &str) -> NetavarkResult<bool> {
+pub fn chain_exists(driver: &IPTables, table: &str, chain: &str) -> NetavarkResult<bool> {
     let c = match driver.list_chains(table) {
         Ok(b) => b,
         Err(e) => return Err(NetavarkError::Message(e.to_string())),
@@ -116,3 +350,91 @@ fn debug_rule_no_exists(table: &str, chain: &str, rule: String) {
         rule, table, chain
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[&str]) -> Vec<String> {
+        std::iter::once("-P FORWARD ACCEPT")
+            .chain(rules.iter().copied())
+            .map(|r| r.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_jump_rule_indexes() {
+        let forward = rules(&[
+            "-A FORWARD -m comment --comment \"netavark firewall plugin rules\" -j NETAVARK_FORWARD",
+            "-A FORWARD -j ufw-before-forward",
+            "-A FORWARD -j ufw-after-forward",
+        ]);
+        let indexes =
+            |position| jump_rule_indexes(&forward, "FORWARD", "NETAVARK_FORWARD", position);
+        assert_eq!(indexes(JumpPosition::Bottom), Some((0, Some(2))));
+        assert_eq!(indexes(JumpPosition::Top), Some((0, Some(0))));
+        assert_eq!(
+            indexes(JumpPosition::After("ufw-before-forward")),
+            Some((0, Some(1)))
+        );
+        assert_eq!(
+            indexes(JumpPosition::After("ufw-after-forward")),
+            Some((0, Some(2)))
+        );
+        assert_eq!(indexes(JumpPosition::After("missing")), Some((0, None)));
+        assert_eq!(
+            jump_rule_indexes(&forward, "FORWARD", "NETAVARK_ALLOW", JumpPosition::Bottom),
+            None
+        );
+
+        // already in place
+        let forward = rules(&[
+            "-A FORWARD -j ufw-before-forward",
+            "-A FORWARD -j NETAVARK_FORWARD",
+        ]);
+        assert_eq!(
+            jump_rule_indexes(
+                &forward,
+                "FORWARD",
+                "NETAVARK_FORWARD",
+                JumpPosition::After("ufw-before-forward")
+            ),
+            Some((1, Some(1)))
+        );
+
+        // back to the top, behind the other netavark jumps
+        let forward = rules(&[
+            "-A FORWARD -j NETAVARK_ALLOW",
+            "-A FORWARD -j NETAVARK_ISOLATION_2",
+            "-A FORWARD -j ufw-before-forward",
+            "-A FORWARD -j NETAVARK_FORWARD",
+        ]);
+        assert_eq!(
+            jump_rule_indexes(&forward, "FORWARD", "NETAVARK_FORWARD", JumpPosition::Top),
+            Some((3, Some(2)))
+        );
+    }
+
+    #[test]
+    fn test_outdated_jump_rules() {
+        let postrouting = rules(&[
+            "-A POSTROUTING -s 10.88.0.0/16 -j NETAVARK-1D8721804F16F",
+            "-A POSTROUTING -s 10.89.0.0/24 -m comment --comment netavark:v0 -j NETAVARK-1D8721804F16F",
+            "-A POSTROUTING -s 10.90.0.0/24 -m comment --comment netavark:v1 -j NETAVARK-1D8721804F16F",
+            "-A POSTROUTING -s 10.91.0.0/24 -j NETAVARK-OTHER",
+            "-A POSTROUTING -j MASQUERADE",
+        ]);
+        assert_eq!(
+            outdated_jump_rules(
+                &postrouting,
+                "POSTROUTING",
+                "NETAVARK-1D8721804F16F",
+                "netavark:v1"
+            ),
+            vec![
+                "-s 10.88.0.0/16 -j NETAVARK-1D8721804F16F",
+                "-s 10.89.0.0/24 -m comment --comment netavark:v0 -j NETAVARK-1D8721804F16F",
+            ]
+        );
+    }
+}
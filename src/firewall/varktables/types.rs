@@ -1,38 +1,69 @@
 use crate::error::{NetavarkError, NetavarkResult};
+use crate::firewall::iptables::MAX_HASH_SIZE;
 use crate::firewall::varktables::helpers::{
-    add_chain_unique, append_unique, remove_if_rule_exists,
+    add_chain_unique, append_unique, chain_exists, migrate_jump_rules, remove_if_rule_exists,
+    remove_rules_with_comment,
 };
 use crate::firewall::varktables::types::TeardownPolicy::{Never, OnComplete};
-use crate::network::internal_types::{IsolateOption, PortForwardConfig};
+use crate::firewall::{FirewallRule, RuleTeardown};
+use crate::network::core_utils::CoreUtils;
+use crate::network::internal_types::{AllowedNetworks, IsolateOption, PortForwardConfig};
+use crate::network::types::ExtraRule;
+use crate::network::validation::{
+    extra_rule_is_ipv6, EXTRA_RULE_CHAIN_FORWARD, EXTRA_RULE_CHAIN_NAT,
+};
 use ipnet::IpNet;
 use iptables::IPTables;
 use log::debug;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use std::net::IpAddr;
+use std::process::{Command, Stdio};
 
 //  Chain names
 const NAT: &str = "nat";
 const FILTER: &str = "filter";
+const MANGLE: &str = "mangle";
+const RAW: &str = "raw";
 const POSTROUTING: &str = "POSTROUTING";
 const PREROUTING: &str = "PREROUTING";
 const NETAVARK_FORWARD: &str = "NETAVARK_FORWARD";
 const OUTPUT: &str = "OUTPUT";
 const FORWARD: &str = "FORWARD";
+const INPUT: &str = "INPUT";
 const ACCEPT: &str = "ACCEPT";
 const NETAVARK_HOSTPORT_DNAT: &str = "NETAVARK-HOSTPORT-DNAT";
 const NETAVARK_HOSTPORT_SETMARK: &str = "NETAVARK-HOSTPORT-SETMARK";
 const NETAVARK_HOSTPORT_MASK: &str = "NETAVARK-HOSTPORT-MASQ";
 const MASQUERADE: &str = "MASQUERADE";
+const SNAT: &str = "SNAT";
 const MARK: &str = "MARK";
 const DNAT: &str = "DNAT";
 const NETAVARK_ISOLATION_1: &str = "NETAVARK_ISOLATION_1";
 const NETAVARK_ISOLATION_2: &str = "NETAVARK_ISOLATION_2";
 const NETAVARK_ISOLATION_3: &str = "NETAVARK_ISOLATION_3";
+const NETAVARK_ALLOW: &str = "NETAVARK_ALLOW";
+// iptables rejects comments longer than this
+const MAX_COMMENT_LEN: usize = 255;
+const CONTAINER_COMMENT: &str = " container: ";
 
 const CONTAINER_DN_CHAIN: &str = "NETAVARK-DN-";
 
+/// Version of the rule layout, bump it when the rules of a network change in a
+/// way setup has to migrate. It is part of the comment on the POSTROUTING jump
+/// of each subnet.
+const RULES_VERSION: u32 = 1;
+const DEFAULT_DROP_CHAIN: &str = "NETAVARK-DP-";
+const EXTRA_RULE_CHAIN: &str = "NETAVARK-EX-";
+
 const HEXMARK: &str = "0x2000";
 
 const MULTICAST_NET_V4: &str = "224.0.0.0/4";
+/// rate of the LOG rule for dropped packets, so they cannot flood the log
+const DROP_LOG_LIMIT: &str = "5/min";
+const DROP_LOG_BURST: u32 = 10;
+// prefix of the hashlimit tables of conn_rate_limit
+const CONN_RATE_LIMIT_NAME: &str = "nv";
 const MULTICAST_NET_V6: &str = "ff00::/8";
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -189,14 +220,244 @@ pub fn create_network_chains(chains: Vec<VarkChain<'_>>) -> NetavarkResult<()> {
     Ok(())
 }
 
+/// Like create_network_chains but apply all chains and rules with a single
+/// iptables-restore call per ip family. Only missing chains and rules are
+/// added, restore runs with --noflush so other rules are never touched.
+pub fn restore_network_chains(chains: Vec<VarkChain<'_>>) -> NetavarkResult<()> {
+    let mut drivers: Vec<&IPTables> = Vec::new();
+    for c in &chains {
+        if !drivers.iter().any(|d| d.cmd == c.driver.cmd) {
+            drivers.push(c.driver);
+        }
+    }
+
+    for driver in drivers {
+        let family: Vec<&VarkChain> = chains
+            .iter()
+            .filter(|c| c.driver.cmd == driver.cmd)
+            .collect();
+        // read the chains once per table instead of checking every chain
+        // with its own iptables call, the rules are still checked with
+        // iptables -C because only iptables knows if two rules are the same
+        let mut saved: HashMap<&str, HashSet<String>> = HashMap::new();
+        for c in &family {
+            if !saved.contains_key(c.table.as_str()) {
+                saved.insert(&c.table, save_chains(driver, &c.table)?);
+            }
+        }
+        let input = restore_input(&family, &saved, |table, chain, rule| {
+            match driver.exists(table, chain, rule) {
+                Ok(b) => Ok(b),
+                Err(e) => Err(NetavarkError::Message(e.to_string())),
+            }
+        })?;
+        if !input.is_empty() {
+            run_restore(driver, &input)?;
+        }
+    }
+    Ok(())
+}
+
+// parse the chain declarations of iptables-save
+fn parse_chains(output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .filter_map(|line| line.strip_prefix(':')?.split_whitespace().next())
+        .map(|chain| chain.to_string())
+        .collect()
+}
+
+fn save_chains(driver: &IPTables, table: &str) -> NetavarkResult<HashSet<String>> {
+    let cmd = format!("{}-save", driver.cmd);
+    let output = Command::new(&cmd)
+        .args(["-t", table])
+        .output()
+        .map_err(|e| NetavarkError::Message(format!("failed to run {}: {}", cmd, e)))?;
+    if !output.status.success() {
+        return Err(NetavarkError::Message(format!(
+            "{} -t {} failed: {}",
+            cmd,
+            table,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(parse_chains(&String::from_utf8_lossy(&output.stdout)))
+}
+
+// build the iptables-restore input for the chains of one ip family, the
+// chains which are already saved and the rules for which rule_exists returns
+// true are skipped
+fn restore_input<R>(
+    chains: &[&VarkChain<'_>],
+    saved: &HashMap<&str, HashSet<String>>,
+    mut rule_exists: R,
+) -> NetavarkResult<String>
+where
+    R: FnMut(&str, &str, &str) -> NetavarkResult<bool>,
+{
+    // table name, chain declarations, rules; in the order of the chains
+    let mut tables: Vec<(String, Vec<String>, Vec<String>)> = Vec::new();
+    fn table_index(tables: &mut Vec<(String, Vec<String>, Vec<String>)>, name: &str) -> usize {
+        match tables.iter().position(|(t, _, _)| t == name) {
+            Some(i) => i,
+            None => {
+                tables.push((name.to_string(), Vec::new(), Vec::new()));
+                tables.len() - 1
+            }
+        }
+    }
+
+    // we have to declare the chains first because rules might jump to them
+    let mut new_chains = HashSet::new();
+    let mut checked_chains = HashSet::new();
+    for c in chains {
+        if !c.create || !checked_chains.insert((&c.table, &c.chain_name)) {
+            continue;
+        }
+        if !saved
+            .get(c.table.as_str())
+            .map_or(false, |t| t.contains(&c.chain_name))
+        {
+            let i = table_index(&mut tables, &c.table);
+            tables[i].1.push(format!(":{} - [0:0]", c.chain_name));
+            new_chains.insert((&c.table, &c.chain_name));
+        }
+    }
+
+    let mut seen_rules = HashSet::new();
+    for c in chains {
+        let new_chain = new_chains.contains(&(&c.table, &c.chain_name));
+        for rule in &c.rules {
+            if !seen_rules.insert((&c.table, &c.chain_name, &rule.rule)) {
+                continue;
+            }
+            // a new chain is empty so there is no need to check the rule
+            if !new_chain && rule_exists(&c.table, &c.chain_name, &rule.rule)? {
+                continue;
+            }
+            let line = match rule.position {
+                None => format!("-A {} {}", c.chain_name, rule.rule.trim_end()),
+                Some(pos) => format!("-I {} {} {}", c.chain_name, pos, rule.rule.trim_end()),
+            };
+            let i = table_index(&mut tables, &c.table);
+            tables[i].2.push(line);
+        }
+    }
+
+    let mut input = String::new();
+    for (table, decls, rules) in tables {
+        input.push_str(&format!("*{}\n", table));
+        for line in decls.iter().chain(rules.iter()) {
+            input.push_str(line);
+            input.push('\n');
+        }
+        input.push_str("COMMIT\n");
+    }
+    Ok(input)
+}
+
+fn run_restore(driver: &IPTables, input: &str) -> NetavarkResult<()> {
+    let cmd = format!("{}-restore", driver.cmd);
+    debug!("applying rules with {}:\n{}", cmd, input);
+
+    let mut command = Command::new(&cmd);
+    command.arg("--noflush");
+    if driver.has_wait {
+        command.arg("--wait");
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| NetavarkError::Message(format!("failed to run {}: {}", cmd, e)))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(NetavarkError::Message(format!(
+            "{} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+// render the chains as iptables commands in the same order create_network_chains
+// would apply them, nothing is changed on the system
+pub fn render_network_chains(chains: &[VarkChain<'_>]) -> Vec<String> {
+    let mut rules = Vec::new();
+    for c in chains {
+        if c.create {
+            rules.push(format!(
+                "{} -t {} -N {}",
+                c.driver.cmd, c.table, c.chain_name
+            ));
+        }
+    }
+    for c in chains {
+        for rule in &c.rules {
+            match rule.position {
+                None => rules.push(format!(
+                    "{} -t {} -A {} {}",
+                    c.driver.cmd,
+                    c.table,
+                    c.chain_name,
+                    rule.rule.trim_end()
+                )),
+                Some(pos) => rules.push(format!(
+                    "{} -t {} -I {} {} {}",
+                    c.driver.cmd,
+                    c.table,
+                    c.chain_name,
+                    pos,
+                    rule.rule.trim_end()
+                )),
+            }
+        }
+    }
+    rules
+}
+
+// the rules of the chains as setup reports them, rules without teardown policy
+// get the `default` one
+pub fn installed_rules(chains: &[VarkChain<'_>], default: RuleTeardown) -> Vec<FirewallRule> {
+    let mut rules: Vec<FirewallRule> = Vec::new();
+    for c in chains {
+        for rule in &c.rules {
+            let installed = FirewallRule {
+                ipv6: c.driver.cmd == "ip6tables",
+                table: c.table.clone(),
+                chain: c.chain_name.clone(),
+                rule: rule.rule.trim_end().to_string(),
+                teardown: match rule.td_policy {
+                    Some(Never) => RuleTeardown::Never,
+                    Some(OnComplete) => RuleTeardown::OnComplete,
+                    None => default,
+                },
+            };
+            if !rules.contains(&installed) {
+                rules.push(installed);
+            }
+        }
+    }
+    rules
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn get_network_chains<'a>(
     conn: &'a IPTables,
     network: IpNet,
     network_hash_name: &'a str,
-    is_ipv6: bool,
     interface_name: String,
     isolation: IsolateOption,
+    snat_ip: Option<IpAddr>,
+    ipv6_nat: bool,
+    random_fully: bool,
 ) -> Vec<VarkChain<'a>> {
+    let is_ipv6 = network.addr().is_ipv6();
     let mut chains = Vec::new();
     let prefixed_network_hash_name = format!("{}-{}", "NETAVARK", network_hash_name);
 
@@ -218,17 +479,32 @@ pub fn get_network_chains<'a>(
     if is_ipv6 {
         multicast_dest = MULTICAST_NET_V6;
     }
-    hashed_network_chain.build_rule(VarkRule::new(
-        format!("! -d {} -j {}", multicast_dest, MASQUERADE),
-        Some(TeardownPolicy::OnComplete),
-    ));
+    // use the requested source address if it matches the ip family, otherwise masquerade,
+    // ipv6 subnets are routed when nat is turned off
+    let nat_target = match snat_ip {
+        Some(ip) if ip.is_ipv6() == is_ipv6 => Some(format!("{} --to-source {}", SNAT, ip)),
+        _ if is_ipv6 && !ipv6_nat => None,
+        _ => Some(MASQUERADE.to_string()),
+    };
+    if let Some(nat_target) = nat_target {
+        let random = if random_fully { " --random-fully" } else { "" };
+        hashed_network_chain.build_rule(VarkRule::new(
+            format!("! -d {} -j {}{}", multicast_dest, nat_target, random),
+            Some(TeardownPolicy::OnComplete),
+        ));
+    }
     chains.push(hashed_network_chain);
 
     // POSTROUTING
     let mut postrouting_chain =
         VarkChain::new(conn, NAT.to_string(), POSTROUTING.to_string(), None);
     postrouting_chain.build_rule(VarkRule::new(
-        format!("-s {} -j {}", network, prefixed_network_hash_name),
+        format!(
+            "-s {} -m comment --comment {} -j {}",
+            network,
+            rules_version_marker(),
+            prefixed_network_hash_name
+        ),
         Some(TeardownPolicy::OnComplete),
     ));
     chains.push(postrouting_chain);
@@ -339,10 +615,7 @@ pub fn get_network_chains<'a>(
     chains.push(netavark_isolation_chain_3);
 
     forward_chain.build_rule(VarkRule {
-        rule: format!(
-            "-m comment --comment 'netavark firewall plugin rules' -j {}",
-            NETAVARK_FORWARD
-        ),
+        rule: forward_jump_rule(),
         position: Some(ind),
         td_policy: Some(TeardownPolicy::Never),
     });
@@ -382,6 +655,618 @@ pub fn get_network_chains<'a>(
     chains
 }
 
+/// The comment marking the rules of the current rules version.
+pub fn rules_version_marker() -> String {
+    format!("netavark:v{}", RULES_VERSION)
+}
+
+/// Remove the POSTROUTING jumps of the network created by an older netavark
+/// version, rules without a marker predate the versioning. Setup adds them
+/// again in the current form, teardown needs this to be able to remove the
+/// network chain.
+pub fn migrate_network_rules(conn: &IPTables, network_hash_name: &str) -> NetavarkResult<()> {
+    let migrated = migrate_jump_rules(
+        conn,
+        NAT,
+        POSTROUTING,
+        &format!("{}-{}", "NETAVARK", network_hash_name),
+        &rules_version_marker(),
+    )?;
+    if migrated > 0 {
+        debug!(
+            "migrated {} rules of network {} to rules version {}",
+            migrated, network_hash_name, RULES_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// The jump from FORWARD into NETAVARK_FORWARD.
+pub fn forward_jump_rule() -> String {
+    format!(
+        "-m comment --comment 'netavark firewall plugin rules' -j {}",
+        NETAVARK_FORWARD
+    )
+}
+
+/// The jump into NETAVARK_ALLOW, it has to be the first FORWARD rule so the
+/// allowed flows are accepted before the isolation rules drop them.
+pub fn allow_jump_rule() -> String {
+    format!("-j {}", NETAVARK_ALLOW)
+}
+
+/// Get the chains accepting the allowed flows between the network subnet
+/// and the allowed subnets of the same ip family, replies are accepted as well.
+/// The rules carry the network hash in their comment, so two networks allowing
+/// the same flow each have their own rule and teardown only removes its own.
+pub fn get_allow_chains<'a>(
+    conn: &'a IPTables,
+    network: IpNet,
+    network_hash_name: &str,
+    is_ipv6: bool,
+    allowed: &AllowedNetworks,
+) -> Vec<VarkChain<'a>> {
+    let same_family = |net: &&IpNet| net.addr().is_ipv6() == is_ipv6;
+    let flows: Vec<(IpNet, IpNet)> = allowed
+        .from
+        .iter()
+        .filter(same_family)
+        .map(|from| (*from, network))
+        .chain(
+            allowed
+                .to
+                .iter()
+                .filter(same_family)
+                .map(|to| (network, *to)),
+        )
+        .collect();
+    if flows.is_empty() {
+        return Vec::new();
+    }
+
+    let mut allow_chain =
+        VarkChain::new(conn, FILTER.to_string(), NETAVARK_ALLOW.to_string(), None);
+    allow_chain.create = true;
+    // invalid packets are not accepted here but go back to FORWARD, where
+    // NETAVARK_FORWARD drops them
+    allow_chain.build_rule(VarkRule::new(
+        "-m conntrack --ctstate INVALID -j RETURN".to_string(),
+        Some(TeardownPolicy::Never),
+    ));
+    let comment = format!(
+        "-m comment --comment 'netavark allow: {}'",
+        network_hash_name
+    );
+    for (src, dst) in flows {
+        allow_chain.build_rule(VarkRule::new(
+            format!("-s {} -d {} {} -j {}", src, dst, comment, ACCEPT),
+            Some(TeardownPolicy::OnComplete),
+        ));
+        allow_chain.build_rule(VarkRule::new(
+            format!(
+                "-s {} -d {} -m conntrack --ctstate RELATED,ESTABLISHED {} -j {}",
+                dst, src, comment, ACCEPT
+            ),
+            Some(TeardownPolicy::OnComplete),
+        ));
+    }
+
+    let mut forward_chain = VarkChain::new(conn, FILTER.to_string(), FORWARD.to_string(), None);
+    forward_chain.build_rule(VarkRule {
+        rule: allow_jump_rule(),
+        position: Some(1),
+        td_policy: Some(TeardownPolicy::Never),
+    });
+
+    vec![allow_chain, forward_chain]
+}
+
+/// Remove the NETAVARK_ALLOW chain and its FORWARD jump once no network has
+/// allowed flows left in it, only the INVALID rule remains then. The chain is
+/// shared by all networks so teardown of one network never removes it.
+pub fn remove_unused_allow_chain(conn: &IPTables) -> NetavarkResult<()> {
+    if !chain_exists(conn, FILTER, NETAVARK_ALLOW)? {
+        return Ok(());
+    }
+    let rules = match conn.list(FILTER, NETAVARK_ALLOW) {
+        Ok(r) => r,
+        Err(e) => return Err(NetavarkError::Message(e.to_string())),
+    };
+    // [0] is the chain itself (-N NETAVARK_ALLOW), [1] the INVALID rule
+    if rules.len() > 2 {
+        return Ok(());
+    }
+    remove_if_rule_exists(conn, FILTER, FORWARD, &allow_jump_rule())?;
+    let removed = conn
+        .flush_chain(FILTER, NETAVARK_ALLOW)
+        .and_then(|_| conn.delete_chain(FILTER, NETAVARK_ALLOW));
+    match removed {
+        Ok(_) => Ok(()),
+        Err(e) => Err(NetavarkError::Message(e.to_string())),
+    }
+}
+
+/// Name of the port forwarding chain of the container in the network, the
+/// same container keeps its chain across restarts.
+pub fn port_forward_chain_name(network_name: &str, container_id: &str) -> String {
+    CONTAINER_DN_CHAIN.to_owned()
+        + &CoreUtils::create_network_hash(
+            &format!("{}-{}", network_name, container_id),
+            MAX_HASH_SIZE,
+        )
+}
+
+/// Remove the port forwarding chains which are not used anymore together
+/// with the rules jumping to them. The chains in `stale` belong to containers
+/// which no longer exist, chains which are neither stale nor in `known` are
+/// only removed when no rule jumps to them. Returns the removed chains.
+pub fn remove_stale_port_forward_chains(
+    conn: &IPTables,
+    stale: &HashSet<String>,
+    known: &HashSet<String>,
+) -> NetavarkResult<Vec<String>> {
+    let chains = match conn.list_chains(NAT) {
+        Ok(c) => c,
+        Err(e) => return Err(NetavarkError::Message(e.to_string())),
+    };
+    let rules = match conn.list_table(NAT) {
+        Ok(r) => r,
+        Err(e) => return Err(NetavarkError::Message(e.to_string())),
+    };
+
+    let mut removed = Vec::new();
+    for chain in chains {
+        if !chain.starts_with(CONTAINER_DN_CHAIN) {
+            continue;
+        }
+        let jumps: Vec<(&str, &str)> = rules
+            .iter()
+            .filter_map(|rule| {
+                let rule = rule.strip_prefix("-A ")?;
+                let (from, rule) = rule.split_once(' ')?;
+                let mut tokens = rule.split_whitespace();
+                while let Some(token) = tokens.next() {
+                    if token == "-j" && tokens.next() == Some(chain.as_str()) {
+                        return Some((from, rule));
+                    }
+                }
+                None
+            })
+            .collect();
+        if !stale.contains(&chain) && (known.contains(&chain) || !jumps.is_empty()) {
+            continue;
+        }
+
+        debug!("removing stale port forwarding chain {}", chain);
+        for (from, rule) in jumps {
+            if let Err(e) = conn.delete(NAT, from, rule) {
+                return Err(NetavarkError::Message(format!(
+                    "failed to remove rule '{}' from table '{}': {}",
+                    rule, from, e
+                )));
+            }
+        }
+        if let Err(e) = conn.flush_chain(NAT, &chain) {
+            return Err(NetavarkError::Message(e.to_string()));
+        }
+        if let Err(e) = conn.delete_chain(NAT, &chain) {
+            return Err(NetavarkError::Message(e.to_string()));
+        }
+        removed.push(chain);
+    }
+    Ok(removed)
+}
+
+/// The part of the port forwarding rule comments identifying the container.
+pub fn port_forward_comment_id(pfwd: &PortForwardConfig) -> String {
+    format!("dnat name: {} id: {}", pfwd.network_name, pfwd.container_id)
+}
+
+/// Remove the port forwarding rules of the container regardless of the
+/// container name in their comment, the container might have been renamed
+/// since the rules were added.
+pub fn remove_port_forward_rules_by_id(
+    conn: &IPTables,
+    pfwd: &PortForwardConfig,
+) -> NetavarkResult<()> {
+    let comment = port_forward_comment_id(pfwd);
+    // the reject rules of ports with allowed sources
+    remove_rules_with_comment(conn, FILTER, INPUT, &comment)?;
+    if !chain_exists(conn, NAT, NETAVARK_HOSTPORT_DNAT)? {
+        return Ok(());
+    }
+    remove_rules_with_comment(conn, NAT, NETAVARK_HOSTPORT_DNAT, &comment)
+}
+
+/// Get the chains clamping the tcp mss of connections forwarded from or to
+/// the network subnet to the path mtu.
+pub fn get_mss_clamp_chains(conn: &IPTables, network: IpNet) -> Vec<VarkChain<'_>> {
+    let mut forward_chain = VarkChain::new(conn, MANGLE.to_string(), FORWARD.to_string(), None);
+    for direction in ["-s", "-d"] {
+        forward_chain.build_rule(VarkRule::new(
+            format!(
+                "{} {} -p tcp --tcp-flags SYN,RST SYN -j TCPMSS --clamp-mss-to-pmtu",
+                direction, network
+            ),
+            Some(TeardownPolicy::OnComplete),
+        ));
+    }
+    vec![forward_chain]
+}
+
+/// Get the chains with the user provided extra rules of the network for one
+/// ip family, the rules must have been checked with validate_extra_rule().
+/// The forward rules go into their own chain of the network which is only
+/// reached by traffic from or to the subnet, so they cannot match the traffic
+/// of other networks.
+pub fn get_extra_rule_chains<'a>(
+    conn: &'a IPTables,
+    network: IpNet,
+    network_hash_name: &str,
+    is_ipv6: bool,
+    extra_rules: &[ExtraRule],
+) -> Vec<VarkChain<'a>> {
+    let extra_chain_name = format!("{}{}", EXTRA_RULE_CHAIN, network_hash_name);
+    let mut chains: Vec<VarkChain> = Vec::new();
+    for extra in extra_rules {
+        // rules without addresses are added for both families
+        if matches!(extra_rule_is_ipv6(&extra.rule), Ok(Some(v6)) if v6 != is_ipv6) {
+            continue;
+        }
+        let (table, chain_name, td_policy) = match extra.chain.as_str() {
+            EXTRA_RULE_CHAIN_FORWARD => (FILTER, extra_chain_name.clone(), Some(OnComplete)),
+            EXTRA_RULE_CHAIN_NAT => (NAT, format!("{}-{}", "NETAVARK", network_hash_name), None),
+            _ => continue,
+        };
+        let rule = VarkRule::new(
+            extra.rule.split_whitespace().collect::<Vec<_>>().join(" "),
+            Some(TeardownPolicy::OnComplete),
+        );
+        match chains
+            .iter_mut()
+            .find(|c| c.table == table && c.chain_name == chain_name)
+        {
+            Some(chain) => chain.build_rule(rule),
+            None => {
+                let mut chain = VarkChain::new(conn, table.to_string(), chain_name, td_policy);
+                chain.create = true;
+                chain.build_rule(rule);
+                chains.push(chain);
+            }
+        }
+    }
+
+    if chains.iter().any(|c| c.chain_name == extra_chain_name) {
+        let mut netavark_forward_chain =
+            VarkChain::new(conn, FILTER.to_string(), NETAVARK_FORWARD.to_string(), None);
+        for direction in ["-s", "-d"] {
+            netavark_forward_chain.build_rule(VarkRule::new(
+                format!("{} {} -j {}", direction, network, extra_chain_name),
+                Some(TeardownPolicy::OnComplete),
+            ));
+        }
+        chains.push(netavark_forward_chain);
+    }
+    chains
+}
+
+/// The LOG prefix of the packets dropped in the network, the kernel allows at
+/// most 29 characters so long network names are cut.
+pub fn drop_log_prefix(network_name: &str) -> String {
+    let name: String = network_name.chars().take(18).collect();
+    format!("netavark {}: ", name)
+}
+
+/// Get the chains of the default_policy=drop option. The network gets its own
+/// filter chain which only accepts replies, published ports, ICMP and traffic
+/// within the bridge, all other forwarded connections into the network are
+/// dropped. The jump goes right after the INVALID rule of NETAVARK_FORWARD so
+/// the accept rules of other networks cannot bypass it. With egress_only only
+/// replies and traffic from the bridge are accepted. With a log prefix the
+/// dropped packets are logged rate limited before the DROP.
+pub fn get_default_drop_chains<'a>(
+    conn: &'a IPTables,
+    network: IpNet,
+    network_hash_name: &str,
+    interface: &str,
+    egress_only: bool,
+    log_prefix: Option<&str>,
+) -> Vec<VarkChain<'a>> {
+    let chain_name = format!("{}{}", DEFAULT_DROP_CHAIN, network_hash_name);
+    let mut drop_chain = VarkChain::new(
+        conn,
+        FILTER.to_string(),
+        chain_name.clone(),
+        Some(OnComplete),
+    );
+    drop_chain.create = true;
+    let icmp = if network.addr().is_ipv6() {
+        "ipv6-icmp"
+    } else {
+        "icmp"
+    };
+    let mut rules = if egress_only {
+        vec![
+            format!("-m conntrack --ctstate RELATED,ESTABLISHED -j {}", ACCEPT),
+            format!("-i {} -j {}", interface, ACCEPT),
+        ]
+    } else {
+        vec![
+            // DNAT covers the published ports, their source restrictions are
+            // already applied by the DNAT rules
+            format!(
+                "-m conntrack --ctstate RELATED,ESTABLISHED,DNAT -j {}",
+                ACCEPT
+            ),
+            format!("-i {} -j {}", interface, ACCEPT),
+            format!("-p {} -j {}", icmp, ACCEPT),
+        ]
+    };
+    if let Some(prefix) = log_prefix {
+        rules.push(format!(
+            "-m limit --limit {} --limit-burst {} -j LOG --log-prefix \"{}\"",
+            DROP_LOG_LIMIT, DROP_LOG_BURST, prefix
+        ));
+    }
+    rules.push("-j DROP".to_string());
+    for rule in rules {
+        drop_chain.build_rule(VarkRule::new(rule, Some(TeardownPolicy::OnComplete)));
+    }
+
+    let mut netavark_forward_chain =
+        VarkChain::new(conn, FILTER.to_string(), NETAVARK_FORWARD.to_string(), None);
+    netavark_forward_chain.build_rule(VarkRule {
+        rule: format!("-d {} -j {}", network, chain_name),
+        position: Some(2),
+        td_policy: Some(TeardownPolicy::OnComplete),
+    });
+
+    vec![drop_chain, netavark_forward_chain]
+}
+
+/// Get the chains giving connections started from the network interface a
+/// conntrack template without helper, so no conntrack helper is assigned by a
+/// later CT rule of the host. With a zone the connections are put into the
+/// conntrack zone as well, so overlapping subnets of other networks cannot
+/// clash. Only the original direction is zoned, replies and connections from
+/// outside into the network, e.g. port forwarding, stay in the default zone.
+/// The first CT rule matching a packet wins, so the rule goes to the top.
+pub fn get_conntrack_chains<'a>(
+    conn: &'a IPTables,
+    interface: &str,
+    zone: Option<u16>,
+) -> Vec<VarkChain<'a>> {
+    let mut prerouting_chain = VarkChain::new(conn, RAW.to_string(), PREROUTING.to_string(), None);
+    let rule = match zone {
+        Some(zone) => format!("-i {} -j CT --zone-orig {}", interface, zone),
+        None => format!("-i {} -j CT", interface),
+    };
+    prerouting_chain.build_rule(VarkRule {
+        rule,
+        position: Some(1),
+        td_policy: Some(TeardownPolicy::OnComplete),
+    });
+    vec![prerouting_chain]
+}
+
+/// Whether the chain belongs to a single container instead of the network.
+pub fn is_container_chain(chain: &str) -> bool {
+    chain.starts_with(CONTAINER_DN_CHAIN)
+}
+
+/// Comment of the dns redirect and allow rules of a network, so they can be
+/// replaced when the port of the resolver changes.
+pub fn dns_rule_comment(network_hash_name: &str) -> String {
+    format!("dns network: {}", network_hash_name)
+}
+
+/// Get the rules redirecting dns queries to port 53 of the dns servers of one
+/// ip family to the port the resolver listens on, none for port 53.
+fn dns_redirect_rules(
+    network_hash_name: &str,
+    dns_server_ips: &[IpAddr],
+    dns_port: u16,
+    is_ipv6: bool,
+) -> Vec<VarkRule> {
+    let mut rules = Vec::new();
+    if dns_port == 53 {
+        return rules;
+    }
+    for dns_ip in dns_server_ips {
+        if is_ipv6 != dns_ip.is_ipv6() {
+            continue;
+        }
+        let mut ip_value = dns_ip.to_string();
+        if is_ipv6 {
+            ip_value = format!("[{}]", ip_value)
+        }
+        for proto in ["udp", "tcp"] {
+            rules.push(VarkRule::new(
+                format!(
+                    "-d {} -p {} --dport {} -m comment --comment '{}' -j {} --to-destination {}:{}",
+                    dns_ip,
+                    proto,
+                    53,
+                    dns_rule_comment(network_hash_name),
+                    DNAT,
+                    ip_value,
+                    dns_port
+                ),
+                Some(TeardownPolicy::OnComplete),
+            ));
+        }
+    }
+    rules
+}
+
+/// Get the chains redirecting the dns queries of the network to the resolver
+/// port without any port forwarding rules of the containers.
+pub fn get_dns_redirect_chains<'a>(
+    conn: &'a IPTables,
+    network_hash_name: &str,
+    dns_server_ips: &[IpAddr],
+    dns_port: u16,
+    is_ipv6: bool,
+) -> Vec<VarkChain<'a>> {
+    let rules = dns_redirect_rules(network_hash_name, dns_server_ips, dns_port, is_ipv6);
+    if rules.is_empty() {
+        return Vec::new();
+    }
+    let mut chains = Vec::new();
+    let mut netavark_hostport_dn_chain = VarkChain::new(
+        conn,
+        NAT.to_string(),
+        NETAVARK_HOSTPORT_DNAT.to_string(),
+        None,
+    );
+    netavark_hostport_dn_chain.create = true;
+    for rule in rules {
+        netavark_hostport_dn_chain.build_rule(rule);
+    }
+    chains.push(netavark_hostport_dn_chain);
+    for chain in [PREROUTING, OUTPUT] {
+        let mut jump_chain = VarkChain::new(conn, NAT.to_string(), chain.to_string(), None);
+        jump_chain.build_rule(VarkRule::new(
+            format!("-j {} -m addrtype --dst-type LOCAL", NETAVARK_HOSTPORT_DNAT),
+            Some(TeardownPolicy::Never),
+        ));
+        chains.push(jump_chain);
+    }
+    chains
+}
+
+/// Remove the dns redirect and allow rules of the network regardless of the
+/// port they use.
+pub fn remove_dns_rules(conn: &IPTables, network_hash_name: &str) -> NetavarkResult<()> {
+    let comment = dns_rule_comment(network_hash_name);
+    if chain_exists(conn, NAT, NETAVARK_HOSTPORT_DNAT)? {
+        remove_rules_with_comment(conn, NAT, NETAVARK_HOSTPORT_DNAT, &comment)?;
+    }
+    remove_rules_with_comment(conn, FILTER, INPUT, &comment)
+}
+
+/// Get the rules accepting dns queries from the network to the resolver on its
+/// gateway, so a host firewall dropping the input traffic does not break name
+/// resolution in the containers. The port is the one the resolver listens on,
+/// queries to port 53 are redirected to it by the port forwarding rules.
+pub fn get_dns_allow_chains<'a>(
+    conn: &'a IPTables,
+    network: IpNet,
+    network_hash_name: &str,
+    gateway: IpAddr,
+    dns_port: u16,
+) -> Vec<VarkChain<'a>> {
+    let mut input_chain = VarkChain::new(conn, FILTER.to_string(), INPUT.to_string(), None);
+    for proto in ["udp", "tcp"] {
+        input_chain.build_rule(VarkRule {
+            rule: format!(
+                "-s {} -d {} -p {} --dport {} -m comment --comment '{}' -j {}",
+                network,
+                gateway,
+                proto,
+                dns_port,
+                dns_rule_comment(network_hash_name),
+                ACCEPT
+            ),
+            position: Some(1),
+            td_policy: Some(TeardownPolicy::OnComplete),
+        });
+    }
+    vec![input_chain]
+}
+
+/// Get the rules of the block_host_access option, new connections from the
+/// network to the addresses of the host other than the gateway are dropped.
+/// Traffic to the gateway, including the dns queries, and forwarded traffic,
+/// e.g. to the internet, is not affected. Only local destinations are matched
+/// so multicast such as the ipv6 neighbor discovery keeps working.
+pub fn get_host_access_chains<'a>(
+    conn: &'a IPTables,
+    network: IpNet,
+    gateway: Option<IpAddr>,
+) -> Vec<VarkChain<'a>> {
+    let except_gateway = match gateway {
+        Some(gateway) => format!(" ! -d {}", gateway),
+        None => String::new(),
+    };
+    let mut input_chain = VarkChain::new(conn, FILTER.to_string(), INPUT.to_string(), None);
+    input_chain.build_rule(VarkRule {
+        rule: format!(
+            "-s {}{} -m addrtype --dst-type LOCAL -m conntrack ! --ctstate RELATED,ESTABLISHED -j DROP",
+            network, except_gateway
+        ),
+        position: Some(1),
+        td_policy: Some(TeardownPolicy::OnComplete),
+    });
+    vec![input_chain]
+}
+
+/// Get the rules limiting the connections started by each container of the
+/// network, connections above conn_limit concurrent ones are rejected and new
+/// connections above conn_rate_limit per second are dropped. The rules go
+/// right after the INVALID rule of NETAVARK_FORWARD like the default_policy
+/// jump so the accept rules of the networks cannot bypass them.
+pub fn get_conn_limit_chains<'a>(
+    conn: &'a IPTables,
+    network: IpNet,
+    network_hash_name: &str,
+    conn_limit: Option<u32>,
+    conn_rate_limit: Option<u32>,
+) -> Vec<VarkChain<'a>> {
+    let mut netavark_forward_chain =
+        VarkChain::new(conn, FILTER.to_string(), NETAVARK_FORWARD.to_string(), None);
+    let mask = if network.addr().is_ipv6() { 128 } else { 32 };
+    if let Some(limit) = conn_limit {
+        netavark_forward_chain.build_rule(VarkRule {
+            rule: format!(
+                "-s {} -m conntrack --ctstate NEW -m connlimit --connlimit-above {} --connlimit-mask {} -j REJECT",
+                network, limit, mask
+            ),
+            position: Some(2),
+            td_policy: Some(TeardownPolicy::OnComplete),
+        });
+    }
+    if let Some(rate) = conn_rate_limit {
+        // the name of the hashlimit table must be shorter than 16 characters
+        netavark_forward_chain.build_rule(VarkRule {
+            rule: format!(
+                "-s {} -m conntrack --ctstate NEW -m hashlimit --hashlimit-above {}/sec --hashlimit-burst {} --hashlimit-mode srcip --hashlimit-name {}{} -j DROP",
+                network, rate, rate, CONN_RATE_LIMIT_NAME, network_hash_name
+            ),
+            position: Some(2),
+            td_policy: Some(TeardownPolicy::OnComplete),
+        });
+    }
+    if netavark_forward_chain.rules.is_empty() {
+        return Vec::new();
+    }
+    vec![netavark_forward_chain]
+}
+
+/// The comment of the port forwarding rules. The container name is cut so
+/// the comment fits into the 256 characters iptables allows and characters
+/// which could break the quoting of the rule are replaced.
+fn port_forward_comment(pfwd: &PortForwardConfig) -> String {
+    let id = port_forward_comment_id(pfwd);
+    let name: String = pfwd
+        .container_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_COMMENT_LEN.saturating_sub(id.len() + CONTAINER_COMMENT.len()))
+        .collect();
+    if name.is_empty() {
+        id
+    } else {
+        format!("{}{}{}", id, CONTAINER_COMMENT, name)
+    }
+}
+
 pub fn get_port_forwarding_chains<'a>(
     conn: &'a IPTables,
     pfwd: &PortForwardConfig,
@@ -395,20 +1280,19 @@ pub fn get_port_forwarding_chains<'a>(
     }
     let mut chains = Vec::new();
     // Set up all chains
-    let network_dn_chain_name = CONTAINER_DN_CHAIN.to_owned() + &pfwd.network_hash_name;
+    let network_dn_chain_name = port_forward_chain_name(&pfwd.network_name, &pfwd.container_id);
 
-    let comment_dn_network_cid = format!(
-        "-m comment --comment 'dnat name: {} id: {}'",
-        pfwd.network_name, pfwd.container_id
-    );
+    // only the network name and container id are used to find the rules,
+    // the container name just makes them readable
+    let comment_dn_network_cid = format!("-m comment --comment '{}'", port_forward_comment(pfwd));
 
     // // NETAVARK-HASH
 
-    // NETAVARK-DN-HASH
+    // NETAVARK-DN-HASH, one chain per container and network
     let mut netavark_hashed_dn_chain = VarkChain::new(
         conn,
         NAT.to_string(),
-        CONTAINER_DN_CHAIN.to_string() + &pfwd.network_hash_name,
+        network_dn_chain_name.clone(),
         Some(OnComplete),
     );
 
@@ -470,6 +1354,9 @@ pub fn get_port_forwarding_chains<'a>(
     netavark_hostport_masq_chain.create = true;
     chains.push(netavark_hostport_masq_chain);
 
+    // INPUT, connections to ports with allowed sources which are not forwarded
+    let mut input_chain = VarkChain::new(conn, FILTER.to_string(), INPUT.to_string(), None);
+
     //  POSTROUTING
     let mut postrouting = VarkChain::new(conn, NAT.to_string(), POSTROUTING.to_string(), None);
     // This rule must be in the first position
@@ -487,24 +1374,13 @@ pub fn get_port_forwarding_chains<'a>(
     }
 
     // Create redirection for aardvark-dns on non-standard port
-    if pfwd.dns_port != 53 {
-        for dns_ip in pfwd.dns_server_ips {
-            if is_ipv6 != dns_ip.is_ipv6() {
-                continue;
-            }
-            let mut ip_value = dns_ip.to_string();
-            if is_ipv6 {
-                ip_value = format!("[{}]", ip_value)
-            }
-            netavark_hostport_dn_chain.create = true;
-            netavark_hostport_dn_chain.build_rule(VarkRule::new(
-                format!(
-                    "-j {} -d {} -p {} --dport {} --to-destination {}:{}",
-                    DNAT, dns_ip, "udp", 53, ip_value, pfwd.dns_port
-                ),
-                Some(TeardownPolicy::OnComplete),
-            ));
-        }
+    for rule in dns_redirect_rules(
+        &pfwd.network_hash_name,
+        pfwd.dns_server_ips,
+        pfwd.dns_port,
+        is_ipv6,
+    ) {
+        netavark_hostport_dn_chain.build_rule(rule);
     }
 
     match pfwd.port_mappings {
@@ -608,7 +1484,35 @@ pub fn get_port_forwarding_chains<'a>(
                 if let Some(host_ip) = host_ip {
                     dnat_rule = format!("{} -d {}", dnat_rule, host_ip)
                 }
-                netavark_hashed_dn_chain.build_rule(VarkRule::new(dnat_rule, None));
+
+                match &i.allowed_sources {
+                    // only dnat connections from the allowed sources, if none of them
+                    // match the ip family no dnat rule is added so the port is not
+                    // reachable for this family at all.
+                    Some(sources) if !sources.is_empty() => {
+                        for source in sources {
+                            if is_ipv6 != source.addr().is_ipv6() {
+                                continue;
+                            }
+                            netavark_hashed_dn_chain.build_rule(VarkRule::new(
+                                format!("{} -s {}", dnat_rule, source),
+                                None,
+                            ));
+                        }
+                        // other sources are not forwarded and would reach the
+                        // host port instead, reject them in both families
+                        let mut reject_rule = format!("-p {} --dport {}", i.protocol, &host_port);
+                        if let Some(host_ip) = host_ip {
+                            reject_rule = format!("{} -d {}", reject_rule, host_ip);
+                        }
+                        input_chain.build_rule(VarkRule {
+                            rule: format!("{} {} -j REJECT", reject_rule, comment_dn_network_cid),
+                            position: Some(1),
+                            td_policy: None,
+                        });
+                    }
+                    _ => netavark_hashed_dn_chain.build_rule(VarkRule::new(dnat_rule, None)),
+                }
             }
         }
         None => {}
@@ -619,6 +1523,690 @@ pub fn get_port_forwarding_chains<'a>(
     chains.push(netavark_hostport_dn_chain);
     chains.push(prerouting_chain);
     chains.push(output_chain);
+    chains.push(input_chain);
 
     Ok(chains)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_conn() -> IPTables {
+        IPTables {
+            cmd: "iptables",
+            has_check: true,
+            has_wait: true,
+            is_numeric: false,
+        }
+    }
+
+    fn chain_rules(chains: &[VarkChain], chain_name: &str) -> Vec<String> {
+        chains
+            .iter()
+            .filter(|c| c.chain_name == chain_name)
+            .flat_map(|c| c.rules.iter().map(|r| r.rule.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_network_chains_snat() {
+        let conn = fake_conn();
+        let chains = get_network_chains(
+            &conn,
+            "10.88.0.0/16".parse().unwrap(),
+            "1D8721804F16F",
+            "podman0".to_string(),
+            IsolateOption::Never,
+            Some("192.0.2.10".parse().unwrap()),
+            false,
+            false,
+        );
+        assert_eq!(
+            chain_rules(&chains, "NETAVARK-1D8721804F16F"),
+            vec![
+                "-d 10.88.0.0/16 -j ACCEPT",
+                "! -d 224.0.0.0/4 -j SNAT --to-source 192.0.2.10"
+            ]
+        );
+    }
+
+    // every rule is reported once with the table, chain and policy it is added with
+    #[test]
+    fn test_installed_rules() {
+        let conn = fake_conn();
+        let mut chains = get_network_chains(
+            &conn,
+            "10.88.0.0/16".parse().unwrap(),
+            "1D8721804F16F",
+            "podman0".to_string(),
+            IsolateOption::Never,
+            None,
+            false,
+            false,
+        );
+        chains.extend(get_mss_clamp_chains(&conn, "10.88.0.0/16".parse().unwrap()));
+        let rules = installed_rules(&chains, RuleTeardown::OnComplete);
+
+        let specs: Vec<(String, String, String)> = chains
+            .iter()
+            .flat_map(|c| {
+                c.rules
+                    .iter()
+                    .map(|r| (c.table.clone(), c.chain_name.clone(), r.rule.clone()))
+            })
+            .collect();
+        assert_eq!(
+            rules
+                .iter()
+                .map(|r| (r.table.clone(), r.chain.clone(), r.rule.clone()))
+                .collect::<Vec<_>>(),
+            specs
+        );
+        assert!(rules.iter().all(|r| !r.ipv6));
+
+        let teardown = |chain: &str, rule: &str| {
+            rules
+                .iter()
+                .find(|r| r.chain == chain && r.rule == rule)
+                .map(|r| r.teardown)
+        };
+        assert_eq!(
+            teardown(FORWARD, &forward_jump_rule()),
+            Some(RuleTeardown::Never)
+        );
+        assert_eq!(
+            teardown("NETAVARK-1D8721804F16F", "-d 10.88.0.0/16 -j ACCEPT"),
+            Some(RuleTeardown::OnComplete)
+        );
+
+        // the same rule of two subnets is only reported once
+        chains.extend(get_mss_clamp_chains(&conn, "10.88.0.0/16".parse().unwrap()));
+        assert_eq!(installed_rules(&chains, RuleTeardown::OnComplete), rules);
+    }
+
+    #[test]
+    fn test_network_chains_snat_other_family() {
+        let conn = fake_conn();
+        let chains = get_network_chains(
+            &conn,
+            "fd10:88::/64".parse().unwrap(),
+            "1D8721804F16F",
+            "podman0".to_string(),
+            IsolateOption::Never,
+            Some("192.0.2.10".parse().unwrap()),
+            true,
+            false,
+        );
+        assert_eq!(
+            chain_rules(&chains, "NETAVARK-1D8721804F16F"),
+            vec!["-d fd10:88::/64 -j ACCEPT", "! -d ff00::/8 -j MASQUERADE"]
+        );
+    }
+
+    // ipv6 subnets are routed without masquerade when ipv6_nat is off
+    #[test]
+    fn test_network_chains_ipv6_nat() {
+        let conn = fake_conn();
+        let rules = |ipv6_nat| {
+            let chains = get_network_chains(
+                &conn,
+                "fd10:88::/64".parse().unwrap(),
+                "1D8721804F16F",
+                "podman0".to_string(),
+                IsolateOption::Never,
+                None,
+                ipv6_nat,
+                false,
+            );
+            chain_rules(&chains, "NETAVARK-1D8721804F16F")
+        };
+        assert_eq!(rules(false), vec!["-d fd10:88::/64 -j ACCEPT"]);
+        assert_eq!(
+            rules(true),
+            vec!["-d fd10:88::/64 -j ACCEPT", "! -d ff00::/8 -j MASQUERADE"]
+        );
+    }
+
+    // the nat target gets --random-fully appended when requested
+    #[test]
+    fn test_network_chains_random_fully() {
+        let conn = fake_conn();
+        let rules = |snat_ip: Option<IpAddr>| {
+            let chains = get_network_chains(
+                &conn,
+                "10.88.0.0/16".parse().unwrap(),
+                "1D8721804F16F",
+                "podman0".to_string(),
+                IsolateOption::Never,
+                snat_ip,
+                false,
+                true,
+            );
+            chain_rules(&chains, "NETAVARK-1D8721804F16F")
+        };
+        assert_eq!(
+            rules(None),
+            vec![
+                "-d 10.88.0.0/16 -j ACCEPT",
+                "! -d 224.0.0.0/4 -j MASQUERADE --random-fully"
+            ]
+        );
+        assert_eq!(
+            rules(Some("192.0.2.10".parse().unwrap())),
+            vec![
+                "-d 10.88.0.0/16 -j ACCEPT",
+                "! -d 224.0.0.0/4 -j SNAT --to-source 192.0.2.10 --random-fully"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_restore_input() {
+        let conn = fake_conn();
+        let chains = get_network_chains(
+            &conn,
+            "10.88.0.0/16".parse().unwrap(),
+            "1D8721804F16F",
+            "podman0".to_string(),
+            IsolateOption::Never,
+            None,
+            false,
+            false,
+        );
+        let refs: Vec<&VarkChain> = chains.iter().collect();
+        // the builtin chains exist and already contain the jump
+        let nat = parse_chains(
+            "*nat
+:PREROUTING ACCEPT [0:0]
+:INPUT ACCEPT [0:0]
+:OUTPUT ACCEPT [0:0]
+:POSTROUTING ACCEPT [4:240]
+-A POSTROUTING -s 10.88.0.0/16 -m comment --comment netavark:v1 -j NETAVARK-1D8721804F16F
+COMMIT
+",
+        );
+        let mut checked = Vec::new();
+        let input = restore_input(&refs, &HashMap::from([(NAT, nat)]), |table, chain, _| {
+            checked.push(format!("{} {}", table, chain));
+            Ok(chain == "POSTROUTING")
+        })
+        .unwrap();
+
+        assert!(input.starts_with("*nat\n:NETAVARK-1D8721804F16F - [0:0]\n"));
+        assert!(input.contains("-A NETAVARK-1D8721804F16F -d 10.88.0.0/16 -j ACCEPT\n"));
+        assert!(!input.contains("-A POSTROUTING"));
+        assert!(input.contains("*filter\n"));
+        assert_eq!(input.matches("COMMIT\n").count(), 2);
+        // the rules of the new chains are not checked
+        assert!(checked.contains(&"nat POSTROUTING".to_string()));
+        assert!(!checked.contains(&"nat NETAVARK-1D8721804F16F".to_string()));
+    }
+
+    #[test]
+    fn test_allow_chains() {
+        let conn = fake_conn();
+        let allowed = AllowedNetworks {
+            from: vec![
+                "10.89.0.0/24".parse().unwrap(),
+                "fd90::/64".parse().unwrap(),
+            ],
+            to: vec!["10.89.2.0/24".parse().unwrap()],
+        };
+        let chains = get_allow_chains(
+            &conn,
+            "10.89.1.0/24".parse().unwrap(),
+            "HASH",
+            false,
+            &allowed,
+        );
+        assert_eq!(
+            chain_rules(&chains, "NETAVARK_ALLOW"),
+            vec![
+                "-m conntrack --ctstate INVALID -j RETURN",
+                "-s 10.89.0.0/24 -d 10.89.1.0/24 -m comment --comment 'netavark allow: HASH' -j ACCEPT",
+                "-s 10.89.1.0/24 -d 10.89.0.0/24 -m conntrack --ctstate RELATED,ESTABLISHED -m comment --comment 'netavark allow: HASH' -j ACCEPT",
+                "-s 10.89.1.0/24 -d 10.89.2.0/24 -m comment --comment 'netavark allow: HASH' -j ACCEPT",
+                "-s 10.89.2.0/24 -d 10.89.1.0/24 -m conntrack --ctstate RELATED,ESTABLISHED -m comment --comment 'netavark allow: HASH' -j ACCEPT",
+            ]
+        );
+        assert_eq!(chain_rules(&chains, "FORWARD"), vec!["-j NETAVARK_ALLOW"]);
+
+        // no rules for the other ip family
+        let chains = get_allow_chains(
+            &conn,
+            "fd99::/64".parse().unwrap(),
+            "HASH",
+            true,
+            &AllowedNetworks {
+                from: vec!["10.89.0.0/24".parse().unwrap()],
+                to: vec![],
+            },
+        );
+        assert!(chains.is_empty());
+    }
+
+    #[test]
+    fn test_dns_redirect() {
+        let conn = fake_conn();
+        let dns_server_ips = vec!["10.88.0.1".parse().unwrap(), "fd10::1".parse().unwrap()];
+        let pfwd = PortForwardConfig {
+            container_id: "ad1df727792c".to_string(),
+            container_name: "web".to_string(),
+            port_mappings: &None,
+            network_name: "podman".to_string(),
+            network_hash_name: "1D8721804F16F".to_string(),
+            container_ip_v4: Some("10.88.0.2".parse().unwrap()),
+            subnet_v4: Some("10.88.0.0/16".parse().unwrap()),
+            container_ip_v6: None,
+            subnet_v6: None,
+            dns_port: 1153,
+            dns_server_ips: &dns_server_ips,
+        };
+        let ip = "10.88.0.2".parse().unwrap();
+        let net = "10.88.0.0/16".parse().unwrap();
+        let chains = get_port_forwarding_chains(&conn, &pfwd, &ip, &net, false).unwrap();
+        assert_eq!(
+            chain_rules(&chains, NETAVARK_HOSTPORT_DNAT),
+            vec![
+                "-d 10.88.0.1 -p udp --dport 53 -m comment --comment 'dns network: 1D8721804F16F' -j DNAT --to-destination 10.88.0.1:1153",
+                "-d 10.88.0.1 -p tcp --dport 53 -m comment --comment 'dns network: 1D8721804F16F' -j DNAT --to-destination 10.88.0.1:1153",
+            ]
+        );
+
+        // the same rules without port forwarding, ipv6 addresses in brackets
+        let chains = get_dns_redirect_chains(&conn, "1D8721804F16F", &dns_server_ips, 1153, true);
+        assert_eq!(
+            chain_rules(&chains, NETAVARK_HOSTPORT_DNAT),
+            vec![
+                "-d fd10::1 -p udp --dport 53 -m comment --comment 'dns network: 1D8721804F16F' -j DNAT --to-destination [fd10::1]:1153",
+                "-d fd10::1 -p tcp --dport 53 -m comment --comment 'dns network: 1D8721804F16F' -j DNAT --to-destination [fd10::1]:1153",
+            ]
+        );
+        assert!(chains[0].create);
+        assert_eq!(
+            chain_rules(&chains, PREROUTING),
+            vec!["-j NETAVARK-HOSTPORT-DNAT -m addrtype --dst-type LOCAL"]
+        );
+        assert!(
+            get_dns_redirect_chains(&conn, "1D8721804F16F", &dns_server_ips, 53, true).is_empty()
+        );
+
+        // nothing is redirected to the standard port
+        let pfwd = PortForwardConfig {
+            dns_port: 53,
+            ..pfwd
+        };
+        let chains = get_port_forwarding_chains(&conn, &pfwd, &ip, &net, false).unwrap();
+        assert!(chain_rules(&chains, NETAVARK_HOSTPORT_DNAT).is_empty());
+    }
+
+    #[test]
+    fn test_port_forward_comment() {
+        let conn = fake_conn();
+        let port_mappings = Some(vec![crate::network::types::PortMapping {
+            container_port: 80,
+            host_ip: "".to_string(),
+            host_port: 8080,
+            protocol: "tcp".to_string(),
+            range: 1,
+            allowed_sources: None,
+        }]);
+        let dns_server_ips = Vec::new();
+        let mut pfwd = PortForwardConfig {
+            container_id: "ad1df727792c".to_string(),
+            container_name: "web".to_string(),
+            port_mappings: &port_mappings,
+            network_name: "podman".to_string(),
+            network_hash_name: "1D8721804F16F".to_string(),
+            container_ip_v4: Some("10.88.0.2".parse().unwrap()),
+            subnet_v4: Some("10.88.0.0/16".parse().unwrap()),
+            container_ip_v6: None,
+            subnet_v6: None,
+            dns_port: 53,
+            dns_server_ips: &dns_server_ips,
+        };
+        let ip = "10.88.0.2".parse().unwrap();
+        let net = "10.88.0.0/16".parse().unwrap();
+
+        let chain = port_forward_chain_name("podman", "ad1df727792c");
+        let chains = get_port_forwarding_chains(&conn, &pfwd, &ip, &net, false).unwrap();
+        assert_eq!(
+            chain_rules(&chains, NETAVARK_HOSTPORT_DNAT),
+            vec![format!("-j {} -p tcp --dport 8080 -m comment --comment 'dnat name: podman id: ad1df727792c container: web'", chain)]
+        );
+
+        // without a name only the id is used
+        pfwd.container_name = "".to_string();
+        let chains = get_port_forwarding_chains(&conn, &pfwd, &ip, &net, false).unwrap();
+        assert_eq!(
+            chain_rules(&chains, NETAVARK_HOSTPORT_DNAT),
+            vec![format!("-j {} -p tcp --dport 8080 -m comment --comment 'dnat name: podman id: ad1df727792c'", chain)]
+        );
+
+        // quotes are replaced and long names are cut to the comment limit
+        pfwd.container_name = format!("it's \"{}\"", "a".repeat(300));
+        let comment = port_forward_comment(&pfwd);
+        assert_eq!(comment.len(), MAX_COMMENT_LEN);
+        assert!(comment.starts_with("dnat name: podman id: ad1df727792c container: it_s__aaa"));
+    }
+
+    // only the allowed sources are forwarded, the host port is rejected for
+    // all others in both families
+    #[test]
+    fn test_port_forward_allowed_sources() {
+        let conn = fake_conn();
+        let port_mappings = Some(vec![crate::network::types::PortMapping {
+            container_port: 80,
+            host_ip: "".to_string(),
+            host_port: 8080,
+            protocol: "tcp".to_string(),
+            range: 1,
+            allowed_sources: Some(vec!["192.0.2.0/24".parse().unwrap()]),
+        }]);
+        let dns_server_ips = Vec::new();
+        let pfwd = PortForwardConfig {
+            container_id: "ad1df727792c".to_string(),
+            container_name: "".to_string(),
+            port_mappings: &port_mappings,
+            network_name: "podman".to_string(),
+            network_hash_name: "1D8721804F16F".to_string(),
+            container_ip_v4: Some("10.88.0.2".parse().unwrap()),
+            subnet_v4: Some("10.88.0.0/16".parse().unwrap()),
+            container_ip_v6: Some("fd10:88::2".parse().unwrap()),
+            subnet_v6: Some("fd10:88::/64".parse().unwrap()),
+            dns_port: 53,
+            dns_server_ips: &dns_server_ips,
+        };
+        let reject = "-p tcp --dport 8080 -m comment --comment 'dnat name: podman id: ad1df727792c' -j REJECT";
+        let chain = port_forward_chain_name("podman", "ad1df727792c");
+
+        let ip = "10.88.0.2".parse().unwrap();
+        let net = "10.88.0.0/16".parse().unwrap();
+        let chains = get_port_forwarding_chains(&conn, &pfwd, &ip, &net, false).unwrap();
+        let dnat: Vec<String> = chain_rules(&chains, &chain)
+            .into_iter()
+            .filter(|r| r.starts_with("-j DNAT"))
+            .collect();
+        assert_eq!(
+            dnat,
+            vec!["-j DNAT -p tcp --to-destination 10.88.0.2:80 --destination-port 8080 -s 192.0.2.0/24"]
+        );
+        assert_eq!(chain_rules(&chains, INPUT), vec![reject]);
+
+        // no dnat for the family without allowed source, still rejected
+        let ip = "fd10:88::2".parse().unwrap();
+        let net = "fd10:88::/64".parse().unwrap();
+        let chains = get_port_forwarding_chains(&conn, &pfwd, &ip, &net, true).unwrap();
+        assert!(!chain_rules(&chains, &chain)
+            .iter()
+            .any(|r| r.starts_with("-j DNAT")));
+        assert_eq!(chain_rules(&chains, INPUT), vec![reject]);
+    }
+
+    // the host port is matched and the connection is sent to the container port
+    #[test]
+    fn test_port_forward_dnat_container_port() {
+        let conn = fake_conn();
+        let port_mappings = Some(vec![crate::network::types::PortMapping {
+            container_port: 80,
+            host_ip: "".to_string(),
+            host_port: 8080,
+            protocol: "tcp".to_string(),
+            range: 1,
+            allowed_sources: None,
+        }]);
+        let dns_server_ips = Vec::new();
+        let pfwd = PortForwardConfig {
+            container_id: "ad1df727792c".to_string(),
+            container_name: "web".to_string(),
+            port_mappings: &port_mappings,
+            network_name: "podman".to_string(),
+            network_hash_name: "1D8721804F16F".to_string(),
+            container_ip_v4: Some("10.88.0.2".parse().unwrap()),
+            subnet_v4: Some("10.88.0.0/16".parse().unwrap()),
+            container_ip_v6: None,
+            subnet_v6: None,
+            dns_port: 53,
+            dns_server_ips: &dns_server_ips,
+        };
+        let ip = "10.88.0.2".parse().unwrap();
+        let net = "10.88.0.0/16".parse().unwrap();
+
+        let chain = port_forward_chain_name("podman", "ad1df727792c");
+        let chains = get_port_forwarding_chains(&conn, &pfwd, &ip, &net, false).unwrap();
+        let dnat: Vec<String> = chain_rules(&chains, &chain)
+            .into_iter()
+            .filter(|r| r.starts_with("-j DNAT"))
+            .collect();
+        assert_eq!(
+            dnat,
+            vec!["-j DNAT -p tcp --to-destination 10.88.0.2:80 --destination-port 8080"]
+        );
+    }
+
+    #[test]
+    fn test_port_forward_chain_name() {
+        let chain = port_forward_chain_name("podman", "ad1df727792c");
+        assert!(chain.starts_with(CONTAINER_DN_CHAIN));
+        // iptables chain names are limited to 28 characters
+        assert!(chain.len() <= 28);
+        // the same container gets the same chain again after a restart
+        assert_eq!(chain, port_forward_chain_name("podman", "ad1df727792c"));
+        assert_ne!(chain, port_forward_chain_name("podman", "6ce776ea58b5"));
+        assert_ne!(chain, port_forward_chain_name("other", "ad1df727792c"));
+    }
+
+    #[test]
+    fn test_conntrack_chains() {
+        let conn = fake_conn();
+        let chains = get_conntrack_chains(&conn, "podman0", Some(4242));
+        assert_eq!(chains[0].table, RAW);
+        assert_eq!(
+            chain_rules(&chains, PREROUTING),
+            vec!["-i podman0 -j CT --zone-orig 4242"]
+        );
+        assert_eq!(chains[0].rules[0].position, Some(1));
+
+        // without a zone the template only has no helper
+        let chains = get_conntrack_chains(&conn, "podman0", None);
+        assert_eq!(chain_rules(&chains, PREROUTING), vec!["-i podman0 -j CT"]);
+    }
+
+    #[test]
+    fn test_dns_allow_chains() {
+        let conn = fake_conn();
+        let chains = get_dns_allow_chains(
+            &conn,
+            "10.88.0.0/16".parse().unwrap(),
+            "HASH",
+            "10.88.0.1".parse().unwrap(),
+            1153,
+        );
+        assert_eq!(chains[0].table, FILTER);
+        assert_eq!(
+            chain_rules(&chains, INPUT),
+            vec![
+                "-s 10.88.0.0/16 -d 10.88.0.1 -p udp --dport 1153 -m comment --comment 'dns network: HASH' -j ACCEPT",
+                "-s 10.88.0.0/16 -d 10.88.0.1 -p tcp --dport 1153 -m comment --comment 'dns network: HASH' -j ACCEPT",
+            ]
+        );
+        assert_eq!(chains[0].rules[0].position, Some(1));
+    }
+
+    #[test]
+    fn test_host_access_chains() {
+        let conn = fake_conn();
+        let chains = get_host_access_chains(
+            &conn,
+            "10.88.0.0/16".parse().unwrap(),
+            Some("10.88.0.1".parse().unwrap()),
+        );
+        assert_eq!(chains[0].table, FILTER);
+        assert_eq!(
+            chain_rules(&chains, INPUT),
+            vec!["-s 10.88.0.0/16 ! -d 10.88.0.1 -m addrtype --dst-type LOCAL -m conntrack ! --ctstate RELATED,ESTABLISHED -j DROP"]
+        );
+        assert_eq!(chains[0].rules[0].position, Some(1));
+
+        let chains = get_host_access_chains(&conn, "fd10::/64".parse().unwrap(), None);
+        assert_eq!(
+            chain_rules(&chains, INPUT),
+            vec!["-s fd10::/64 -m addrtype --dst-type LOCAL -m conntrack ! --ctstate RELATED,ESTABLISHED -j DROP"]
+        );
+    }
+
+    #[test]
+    fn test_conn_limit_chains() {
+        let conn = fake_conn();
+        let chains = get_conn_limit_chains(
+            &conn,
+            "10.88.0.0/16".parse().unwrap(),
+            "HASH",
+            Some(10),
+            None,
+        );
+        assert_eq!(
+            chain_rules(&chains, NETAVARK_FORWARD),
+            vec!["-s 10.88.0.0/16 -m conntrack --ctstate NEW -m connlimit --connlimit-above 10 --connlimit-mask 32 -j REJECT"]
+        );
+        assert_eq!(chains[0].rules[0].position, Some(2));
+        assert!(
+            get_conn_limit_chains(&conn, "10.88.0.0/16".parse().unwrap(), "HASH", None, None)
+                .is_empty()
+        );
+
+        let chains = get_conn_limit_chains(
+            &conn,
+            "fd10::/64".parse().unwrap(),
+            "HASH",
+            Some(10),
+            Some(50),
+        );
+        assert_eq!(
+            chain_rules(&chains, NETAVARK_FORWARD),
+            vec![
+                "-s fd10::/64 -m conntrack --ctstate NEW -m connlimit --connlimit-above 10 --connlimit-mask 128 -j REJECT",
+                "-s fd10::/64 -m conntrack --ctstate NEW -m hashlimit --hashlimit-above 50/sec --hashlimit-burst 50 --hashlimit-mode srcip --hashlimit-name nvHASH -j DROP",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_default_drop_chains() {
+        let conn = fake_conn();
+        let chains = get_default_drop_chains(
+            &conn,
+            "fd10::/64".parse().unwrap(),
+            "HASH",
+            "podman0",
+            false,
+            None,
+        );
+        assert_eq!(
+            chain_rules(&chains, "NETAVARK-DP-HASH"),
+            vec![
+                "-m conntrack --ctstate RELATED,ESTABLISHED,DNAT -j ACCEPT",
+                "-i podman0 -j ACCEPT",
+                "-p ipv6-icmp -j ACCEPT",
+                "-j DROP",
+            ]
+        );
+        assert_eq!(
+            chain_rules(&chains, NETAVARK_FORWARD),
+            vec!["-d fd10::/64 -j NETAVARK-DP-HASH"]
+        );
+        assert_eq!(chains[1].rules[0].position, Some(2));
+
+        // egress only networks accept neither published ports nor ICMP
+        let chains = get_default_drop_chains(
+            &conn,
+            "10.88.0.0/16".parse().unwrap(),
+            "HASH",
+            "podman0",
+            true,
+            None,
+        );
+        assert_eq!(
+            chain_rules(&chains, "NETAVARK-DP-HASH"),
+            vec![
+                "-m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT",
+                "-i podman0 -j ACCEPT",
+                "-j DROP",
+            ]
+        );
+
+        // the LOG rule goes right before the DROP
+        let chains = get_default_drop_chains(
+            &conn,
+            "10.88.0.0/16".parse().unwrap(),
+            "HASH",
+            "podman0",
+            true,
+            Some("netavark podman: "),
+        );
+        assert_eq!(
+            chain_rules(&chains, "NETAVARK-DP-HASH")[2..],
+            [
+                "-m limit --limit 5/min --limit-burst 10 -j LOG --log-prefix \"netavark podman: \"",
+                "-j DROP",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mss_clamp_chains() {
+        let conn = fake_conn();
+        let chains = get_mss_clamp_chains(&conn, "10.88.0.0/16".parse().unwrap());
+        assert_eq!(chains[0].table, MANGLE);
+        assert_eq!(
+            chain_rules(&chains, FORWARD),
+            vec![
+                "-s 10.88.0.0/16 -p tcp --tcp-flags SYN,RST SYN -j TCPMSS --clamp-mss-to-pmtu",
+                "-d 10.88.0.0/16 -p tcp --tcp-flags SYN,RST SYN -j TCPMSS --clamp-mss-to-pmtu",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extra_rule_chains() {
+        let conn = fake_conn();
+        let rule = |chain: &str, rule: &str| ExtraRule {
+            chain: chain.to_string(),
+            rule: rule.to_string(),
+        };
+        let extra_rules = vec![
+            rule("forward", "-p tcp  --dport 22 -j DROP"),
+            rule("forward", "-s fd00::/64 -j DROP"),
+            rule("nat", "-d 192.168.1.0/24 -j RETURN"),
+        ];
+
+        let net = "10.88.0.0/16".parse().unwrap();
+        let chains = get_extra_rule_chains(&conn, net, "1D8721804F16F", false, &extra_rules);
+        assert_eq!(chains.len(), 3);
+        assert_eq!(
+            chain_rules(&chains, "NETAVARK-EX-1D8721804F16F"),
+            vec!["-p tcp --dport 22 -j DROP"]
+        );
+        // only traffic of the network reaches the forward rules
+        assert_eq!(
+            chain_rules(&chains, NETAVARK_FORWARD),
+            vec![
+                "-s 10.88.0.0/16 -j NETAVARK-EX-1D8721804F16F",
+                "-d 10.88.0.0/16 -j NETAVARK-EX-1D8721804F16F",
+            ]
+        );
+        assert_eq!(
+            chain_rules(&chains, "NETAVARK-1D8721804F16F"),
+            vec!["-d 192.168.1.0/24 -j RETURN"]
+        );
+
+        let net = "fd10::/64".parse().unwrap();
+        let chains = get_extra_rule_chains(&conn, net, "1D8721804F16F", true, &extra_rules);
+        assert_eq!(chains.len(), 2);
+        assert_eq!(
+            chain_rules(&chains, "NETAVARK-EX-1D8721804F16F"),
+            vec!["-p tcp --dport 22 -j DROP", "-s fd00::/64 -j DROP"]
+        );
+    }
+}
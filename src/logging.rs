@@ -0,0 +1,56 @@
+//! Tag log messages with the network and container being processed.
+//!
+//! While a [`NetworkScope`] is alive every log line carries
+//! `network=NAME container=ID` so the logs of hosts with many tenants can be
+//! split per network.
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use env_logger::fmt::Formatter;
+use log::Record;
+
+static SCOPE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Tags the log lines until it is dropped.
+pub struct NetworkScope {
+    previous: Option<String>,
+}
+
+/// Tag all following log lines with the given network and container id.
+pub fn network_scope(network: &str, container_id: &str) -> NetworkScope {
+    let tag = format!("network={} container={}", network, container_id);
+    let previous = match SCOPE.lock() {
+        Ok(mut scope) => scope.replace(tag),
+        Err(_) => None,
+    };
+    NetworkScope { previous }
+}
+
+impl Drop for NetworkScope {
+    fn drop(&mut self) {
+        if let Ok(mut scope) = SCOPE.lock() {
+            *scope = self.previous.take();
+        }
+    }
+}
+
+/// The tag of the current scope, if any.
+pub fn current_tag() -> Option<String> {
+    SCOPE.lock().ok().and_then(|scope| scope.clone())
+}
+
+/// env_logger format, the default one without timestamp plus the scope tag.
+pub fn format(buf: &mut Formatter, record: &Record) -> io::Result<()> {
+    let level = buf.default_styled_level(record.level());
+    match current_tag() {
+        Some(tag) => writeln!(
+            buf,
+            "[{:<5} {} {}] {}",
+            level,
+            record.target(),
+            tag,
+            record.args()
+        ),
+        None => writeln!(buf, "[{:<5} {}] {}", level, record.target(), record.args()),
+    }
+}
@@ -1,10 +1,23 @@
 use clap::{Parser, Subcommand};
+use log::warn;
+use std::env;
+use std::time::Instant;
 
+use netavark::commands::bundle;
 use netavark::commands::dhcp_proxy;
+use netavark::commands::dump_rules;
+use netavark::commands::gc;
+use netavark::commands::interfaces;
+use netavark::commands::migrate_cni;
 use netavark::commands::setup;
+use netavark::commands::setup_firewall;
 use netavark::commands::teardown;
 use netavark::commands::update;
+use netavark::commands::update_dns_rules;
+use netavark::commands::validate;
 use netavark::commands::version;
+use netavark::logging;
+use netavark::metrics;
 
 #[derive(Parser, Debug)]
 #[clap(version = env!("CARGO_PKG_VERSION"))]
@@ -24,6 +37,18 @@ struct Opts {
     /// Path to netavark plugin directories, can be set multiple times to specify more than one directory.
     #[clap(long, long = "plugin-directory")]
     plugin_directories: Option<Vec<String>>,
+    /// Prometheus textfile to record setup and teardown metrics in.
+    /// Can also be set with the NETAVARK_METRICS_FILE env var.
+    #[clap(long)]
+    metrics_file: Option<String>,
+    /// Do not print the result of a successful setup, errors are still
+    /// printed as JSON and the exit code tells if the command succeeded.
+    #[clap(short, long)]
+    quiet: bool,
+    /// Firewall driver to use instead of the detected one: iptables,
+    /// nftables, firewalld or none. Takes precedence over NETAVARK_FW.
+    #[clap(long)]
+    firewall_driver: Option<String>,
     /// Netavark trig command
     #[clap(subcommand)]
     subcmd: SubCommand,
@@ -33,18 +58,34 @@ struct Opts {
 enum SubCommand {
     /// Configures the given network namespace with the given configuration.
     Setup(setup::Setup),
+    /// Applies the firewall rules of a setup run with --no-firewall.
+    SetupFirewall(setup_firewall::SetupFirewall),
     /// Updates network dns servers for an already configured network.
     Update(update::Update),
+    /// Replaces the dns rules of a network after its resolver changed the port.
+    UpdateDnsRules(update_dns_rules::UpdateDnsRules),
     /// Undo any configuration applied via setup command.
     Teardown(teardown::Teardown),
     /// Display info about netavark.
     Version(version::Version),
     /// Start dhcp-proxy
     DHCPProxy(dhcp_proxy::Opts),
+    /// Print the firewall rules setup would create without applying them.
+    DumpRules(dump_rules::DumpRules),
+    /// Remove firewall chains of containers which no longer exist.
+    Gc(gc::Gc),
+    /// Check the configuration without applying it.
+    Validate(validate::Validate),
+    /// Print the veth pairs setup created for a container.
+    Interfaces(interfaces::Interfaces),
+    /// Print the state, firewall rules and environment for bug reports.
+    Bundle(bundle::Bundle),
+    /// Translate a CNI network config into a netavark network definition.
+    MigrateCni(migrate_cni::MigrateCni),
 }
 
 fn main() {
-    env_logger::builder().format_timestamp(None).init();
+    env_logger::builder().format(logging::format).init();
     let opts = Opts::parse();
 
     // aardvark config directory must be supplied by parent or it defaults to /tmp/aardvark
@@ -53,6 +94,16 @@ fn main() {
     let aardvark_bin = opts
         .aardvark_binary
         .unwrap_or_else(|| String::from("/usr/libexec/podman/aardvark-dns"));
+    let metrics_file = opts
+        .metrics_file
+        .or_else(|| env::var(metrics::METRICS_FILE_ENV).ok());
+    let firewall_driver = opts.firewall_driver;
+    let operation = match opts.subcmd {
+        SubCommand::Setup(_) => Some("setup"),
+        SubCommand::Teardown(_) => Some("teardown"),
+        _ => None,
+    };
+    let start = Instant::now();
     let result = match opts.subcmd {
         SubCommand::Setup(setup) => setup.exec(
             opts.file,
@@ -60,6 +111,8 @@ fn main() {
             aardvark_bin,
             opts.plugin_directories,
             rootless,
+            opts.quiet,
+            firewall_driver,
         ),
         SubCommand::Teardown(teardown) => teardown.exec(
             opts.file,
@@ -67,12 +120,32 @@ fn main() {
             aardvark_bin,
             opts.plugin_directories,
             rootless,
+            firewall_driver,
         ),
+        SubCommand::SetupFirewall(setup_firewall) => {
+            setup_firewall.exec(opts.file, config, opts.plugin_directories, firewall_driver)
+        }
         SubCommand::Update(mut update) => update.exec(config, aardvark_bin, rootless),
+        SubCommand::UpdateDnsRules(update) => update.exec(opts.file, config, firewall_driver),
         SubCommand::Version(version) => version.exec(),
         SubCommand::DHCPProxy(proxy) => dhcp_proxy::serve(proxy),
+        SubCommand::DumpRules(dump) => {
+            dump.exec(opts.file, opts.plugin_directories, firewall_driver)
+        }
+        SubCommand::Gc(gc) => gc.exec(config, firewall_driver),
+        SubCommand::Validate(validate) => validate.exec(opts.file, opts.plugin_directories),
+        SubCommand::Interfaces(interfaces) => interfaces.exec(config),
+        SubCommand::Bundle(bundle) => bundle.exec(config, firewall_driver),
+        SubCommand::MigrateCni(migrate) => migrate.exec(),
     };
 
+    if let (Some(path), Some(operation)) = (&metrics_file, operation) {
+        // metrics must never fail the actual operation
+        if let Err(err) = metrics::record(path, operation, start.elapsed(), &result) {
+            warn!("failed to record metrics: {}", err);
+        }
+    }
+
     match result {
         Ok(_) => {}
         Err(err) => {
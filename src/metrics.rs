@@ -0,0 +1,267 @@
+//! Prometheus textfile metrics for netavark operations.
+//!
+//! Each invocation reads the existing metrics file, updates the counters for
+//! the current operation and writes the file back. A lock file next to the
+//! metrics file serializes concurrent netavark invocations and the file is
+//! replaced atomically so the textfile collector never reads a partial file.
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use fs2::FileExt;
+
+use crate::error::{NetavarkError, NetavarkResult};
+
+/// env var to enable metrics if the --metrics-file option is not given
+pub const METRICS_FILE_ENV: &str = "NETAVARK_METRICS_FILE";
+
+const OPERATIONS_TOTAL: &str = "netavark_operations_total";
+const ERRORS_TOTAL: &str = "netavark_operation_errors_total";
+const DURATION_SECONDS: &str = "netavark_operation_duration_seconds";
+
+/// upper bounds of the duration histogram buckets in seconds
+const DURATION_BUCKETS: [f64; 8] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+#[derive(Debug, PartialEq)]
+struct Histogram {
+    /// cumulative count per bucket, the last entry is the +Inf bucket
+    buckets: Vec<f64>,
+    sum: f64,
+    count: f64,
+}
+
+#[derive(Default, Debug, PartialEq)]
+struct Metrics {
+    /// operation -> count
+    operations: BTreeMap<String, f64>,
+    /// (operation, category) -> count
+    errors: BTreeMap<(String, String), f64>,
+    /// operation -> durations
+    durations: BTreeMap<String, Histogram>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: vec![0.0; DURATION_BUCKETS.len() + 1],
+            sum: 0.0,
+            count: 0.0,
+        }
+    }
+}
+
+impl Metrics {
+    fn parse(content: &str) -> Self {
+        let mut metrics = Metrics::default();
+        for line in content.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            let (name, labels, value) = match parse_sample(line) {
+                Some(s) => s,
+                // ignore lines we do not understand, the file gets rewritten anyway
+                None => continue,
+            };
+            let operation = match labels.get("operation") {
+                Some(op) => op.to_string(),
+                None => continue,
+            };
+            match name {
+                OPERATIONS_TOTAL => {
+                    metrics.operations.insert(operation, value);
+                }
+                ERRORS_TOTAL => {
+                    if let Some(category) = labels.get("category") {
+                        metrics
+                            .errors
+                            .insert((operation, category.to_string()), value);
+                    }
+                }
+                _ => {
+                    let hist = metrics.durations.entry(operation).or_default();
+                    match name.strip_prefix(DURATION_SECONDS) {
+                        Some("_sum") => hist.sum = value,
+                        Some("_count") => hist.count = value,
+                        Some("_bucket") => {
+                            let index = match labels.get("le") {
+                                Some(&"+Inf") => Some(DURATION_BUCKETS.len()),
+                                Some(le) => DURATION_BUCKETS
+                                    .iter()
+                                    .position(|b| le.parse::<f64>() == Ok(*b)),
+                                None => None,
+                            };
+                            if let Some(i) = index {
+                                hist.buckets[i] = value;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        metrics
+    }
+
+    fn record(&mut self, operation: &str, duration: Duration, error: Option<&str>) {
+        *self.operations.entry(operation.to_string()).or_default() += 1.0;
+        if let Some(category) = error {
+            *self
+                .errors
+                .entry((operation.to_string(), category.to_string()))
+                .or_default() += 1.0;
+        }
+
+        let secs = duration.as_secs_f64();
+        let hist = self.durations.entry(operation.to_string()).or_default();
+        for (i, bound) in DURATION_BUCKETS.iter().enumerate() {
+            if secs <= *bound {
+                hist.buckets[i] += 1.0;
+            }
+        }
+        hist.buckets[DURATION_BUCKETS.len()] += 1.0;
+        hist.sum += secs;
+        hist.count += 1.0;
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format!(
+            "# HELP {} Number of netavark operations.\n# TYPE {} counter\n",
+            OPERATIONS_TOTAL, OPERATIONS_TOTAL
+        ));
+        for (op, value) in &self.operations {
+            out.push_str(&format!(
+                "{}{{operation=\"{}\"}} {}\n",
+                OPERATIONS_TOTAL, op, value
+            ));
+        }
+        out.push_str(&format!(
+            "# HELP {} Number of failed netavark operations by error category.\n# TYPE {} counter\n",
+            ERRORS_TOTAL, ERRORS_TOTAL
+        ));
+        for ((op, category), value) in &self.errors {
+            out.push_str(&format!(
+                "{}{{operation=\"{}\",category=\"{}\"}} {}\n",
+                ERRORS_TOTAL, op, category, value
+            ));
+        }
+        out.push_str(&format!(
+            "# HELP {} Duration of netavark operations.\n# TYPE {} histogram\n",
+            DURATION_SECONDS, DURATION_SECONDS
+        ));
+        for (op, hist) in &self.durations {
+            for (i, value) in hist.buckets.iter().enumerate() {
+                let le = match DURATION_BUCKETS.get(i) {
+                    Some(b) => b.to_string(),
+                    None => "+Inf".to_string(),
+                };
+                out.push_str(&format!(
+                    "{}_bucket{{operation=\"{}\",le=\"{}\"}} {}\n",
+                    DURATION_SECONDS, op, le, value
+                ));
+            }
+            out.push_str(&format!(
+                "{}_sum{{operation=\"{}\"}} {}\n",
+                DURATION_SECONDS, op, hist.sum
+            ));
+            out.push_str(&format!(
+                "{}_count{{operation=\"{}\"}} {}\n",
+                DURATION_SECONDS, op, hist.count
+            ));
+        }
+        out
+    }
+}
+
+/// parse a sample line in the form `name{label="value",...} value`
+fn parse_sample(line: &str) -> Option<(&str, BTreeMap<&str, &str>, f64)> {
+    let (name, rest) = line.split_once('{')?;
+    let (labels_str, value) = rest.rsplit_once('}')?;
+    let mut labels = BTreeMap::new();
+    for label in labels_str.split(',') {
+        let (key, val) = label.split_once('=')?;
+        labels.insert(key.trim(), val.trim().trim_matches('"'));
+    }
+    Some((name.trim(), labels, value.trim().parse().ok()?))
+}
+
+/// Record the operation with its duration and result in the metrics file.
+pub fn record<T>(
+    path: &str,
+    operation: &str,
+    duration: Duration,
+    result: &NetavarkResult<T>,
+) -> NetavarkResult<()> {
+    let path = Path::new(path);
+    let lockfile_path = path.with_extension("lock");
+    let lockfile = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lockfile_path)
+        .map_err(|e| {
+            NetavarkError::Message(format!(
+                "failed to open/create metrics lockfile {:?}: {}",
+                &lockfile_path, e
+            ))
+        })?;
+    lockfile.lock_exclusive().map_err(|e| {
+        NetavarkError::Message(format!(
+            "failed to acquire exclusive lock on {:?}: {}",
+            &lockfile_path, e
+        ))
+    })?;
+
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(NetavarkError::wrap("read metrics file", e.into())),
+    };
+    let mut metrics = Metrics::parse(&content);
+    metrics.record(
+        operation,
+        duration,
+        result.as_ref().err().map(|e| e.category()),
+    );
+
+    // write to a temp file first and rename it so readers never see a partial file
+    let tmp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(metrics.render().as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_roundtrip() {
+        let mut metrics = Metrics::default();
+        metrics.record("setup", Duration::from_millis(20), None);
+        metrics.record("setup", Duration::from_secs(10), Some("netlink"));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("netavark_operations_total{operation=\"setup\"} 2\n"));
+        assert!(rendered.contains(
+            "netavark_operation_errors_total{operation=\"setup\",category=\"netlink\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "netavark_operation_duration_seconds_bucket{operation=\"setup\",le=\"0.05\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "netavark_operation_duration_seconds_bucket{operation=\"setup\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(
+            rendered.contains("netavark_operation_duration_seconds_count{operation=\"setup\"} 2\n")
+        );
+
+        // parsing the rendered output must give us the same metrics back
+        assert_eq!(Metrics::parse(&rendered), metrics);
+    }
+}
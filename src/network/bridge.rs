@@ -1,37 +1,81 @@
-use std::{collections::HashMap, net::IpAddr, os::unix::prelude::RawFd, sync::Once};
+use std::{
+    collections::HashMap, net::IpAddr, os::unix::prelude::RawFd, sync::Once, time::Duration,
+};
 
 use ipnet::IpNet;
 use log::{debug, error};
 use netlink_packet_route::{
-    nlas::link::{Info, InfoData, InfoKind, Nla, VethInfo},
-    LinkMessage,
+    address,
+    nlas::link::{Info, InfoBridge, InfoData, InfoKind, Nla, VethInfo},
+    LinkMessage, BRIDGE_VLAN_INFO_PVID, BRIDGE_VLAN_INFO_UNTAGGED, IFF_UP,
 };
+use sysctl::Sysctl;
 
 use crate::{
     dns::aardvark::AardvarkEntry,
     error::{ErrorWrap, NetavarkError, NetavarkErrorList, NetavarkResult},
     exec_netns,
-    firewall::iptables::MAX_HASH_SIZE,
-    network::{constants, core_utils::disable_ipv6_autoconf, types},
+    firewall::{iptables::MAX_HASH_SIZE, FirewallRule},
+    network::{constants, core_utils::disable_ipv6_autoconf, types, validation, verify},
+    warnings,
 };
 
 use super::{
     constants::{
         ISOLATE_OPTION_FALSE, ISOLATE_OPTION_STRICT, ISOLATE_OPTION_TRUE,
-        NO_CONTAINER_INTERFACE_ERROR, OPTION_ISOLATE, OPTION_METRIC, OPTION_MTU,
-        OPTION_NO_DEFAULT_ROUTE,
+        NO_CONTAINER_INTERFACE_ERROR, OPTION_BLOCK_HOST_ACCESS, OPTION_BRIDGE_ADDRESSES,
+        OPTION_CONNTRACK_HELPERS, OPTION_CONNTRACK_ZONE, OPTION_CONN_LIMIT, OPTION_CONN_RATE_LIMIT,
+        OPTION_DEFAULT_POLICY, OPTION_DEFAULT_PVID, OPTION_DNS_PORT, OPTION_EGRESS_ONLY,
+        OPTION_GROUP_FWD_MASK, OPTION_GRO_FLUSH_TIMEOUT, OPTION_ICMP_REDIRECTS, OPTION_IPV6_NAT,
+        OPTION_IP_CONFLICT_CHECK, OPTION_ISOLATE, OPTION_KEEP_CARRIER, OPTION_LOG_DROPS,
+        OPTION_MCAST_FLOOD, OPTION_METRIC, OPTION_MODE, OPTION_MSS_CLAMP, OPTION_MTU,
+        OPTION_MULTICAST_SNOOPING, OPTION_NAPI_DEFER_HARD_IRQS, OPTION_NEIGH_GC_THRESH,
+        OPTION_NO_DEFAULT_ROUTE, OPTION_NO_SUBNET_ROUTE, OPTION_NUM_RX_QUEUES,
+        OPTION_NUM_TX_QUEUES, OPTION_PEER_ADDRESS, OPTION_POLICY_ROUTING, OPTION_QDISC,
+        OPTION_RANDOM_FULLY, OPTION_RP_FILTER, OPTION_SNAT_IP, OPTION_TXQUEUELEN,
+        OPTION_VLAN_FILTERING,
     },
     core_utils::{self, get_ipam_addresses, join_netns, parse_option, CoreUtils},
     driver::{self, DriverInfo},
     internal_types::{
-        IPAMAddresses, IsolateOption, PortForwardConfig, SetupNetwork, TearDownNetwork,
-        TeardownPortForward,
+        AllowedNetworks, IPAMAddresses, IsolateOption, PortForwardConfig, SetupNetwork,
+        TearDownNetwork, TeardownPortForward,
     },
     netlink,
     types::StatusBlock,
 };
 
 const NO_BRIDGE_NAME_ERROR: &str = "no bridge interface name given";
+/// vlan the kernel assigns as pvid to new bridge ports by default
+const DEFAULT_BRIDGE_PVID: u16 = 1;
+
+/// upper limit for the number of veth rx/tx queues
+const MAX_VETH_QUEUES: u32 = 256;
+
+/// upper limit for the transmit queue length of the container veth
+const MAX_TXQUEUELEN: u32 = 100_000;
+
+/// upper limit of the conn_rate_limit option, older kernels cannot match
+/// higher hashlimit rates
+const MAX_CONN_RATE_LIMIT: u32 = 10_000;
+
+/// mtu of ethernet interfaces
+const DEFAULT_MTU: u32 = 1500;
+
+/// group_fwd_mask bits the kernel never forwards: STP (bit 0),
+/// MAC pause (bit 1) and LACP (bit 2)
+const GROUP_FWD_MASK_RESTRICTED: u16 = 0x0007;
+
+/// policy routing tables are numbered by this offset plus the container interface index
+const POLICY_ROUTING_TABLE_OFFSET: u32 = 1000;
+/// priority of the policy routing rules, lower than the main table rule (32766)
+const POLICY_ROUTING_PRIORITY: u32 = 100;
+
+/// how long to wait for an answer to the arp probe of the ip_conflict_check option
+const IP_CONFLICT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// qdiscs accepted by the qdisc option, they work without parameters
+const QDISCS: [&str; 3] = ["fq", "fq_codel", "cake"];
 
 struct InternalData {
     /// interface name of the veth pair inside the container netns
@@ -44,13 +88,112 @@ struct InternalData {
     ipam: IPAMAddresses,
     /// mtu for the network interfaces (0 if default)
     mtu: u32,
-    /// if this network should be isolated from others
-    isolate: IsolateOption,
+    /// options of the firewall rules
+    firewall: FirewallOptions,
     /// Route metric for any default routes added for the network
     metric: Option<u32>,
     /// if set, no default gateway will be added
     no_default_route: bool,
-    // TODO: add vlan
+    /// enable vlan filtering on the bridge
+    vlan_filtering: bool,
+    /// pvid new ports of the bridge get, set when the bridge is created
+    default_pvid: Option<u16>,
+    /// pvid for the container bridge port
+    pvid: Option<u16>,
+    /// tagged vlans for the container bridge port
+    tagged_vlans: Vec<u16>,
+    /// number of rx queues for the veth pair, None keeps the kernel default
+    num_rx_queues: Option<u32>,
+    /// number of tx queues for the veth pair, None keeps the kernel default
+    num_tx_queues: Option<u32>,
+    /// transmit queue length of the container veth, 0 keeps the kernel default
+    txqueuelen: u32,
+    /// sysfs values set on the container veth, e.g. gro_flush_timeout
+    veth_sysfs_values: Vec<(&'static str, String)>,
+    /// add a routing table and source rules for the container interface
+    policy_routing: bool,
+    /// keep the kernel defaults for sending and accepting icmp redirects on the bridge
+    icmp_redirects: bool,
+    /// additional dns search domains for the container
+    dns_search_domains: Vec<String>,
+    /// return the host nameservers when no dns servers are configured
+    host_dns: bool,
+    /// do not add the connected subnet routes, route everything via the gateway
+    no_subnet_route: bool,
+    /// link local group addresses the bridge forwards
+    group_fwd_mask: Option<u16>,
+    /// igmp and mld snooping of the bridge, unset keeps the kernel default
+    multicast_snooping: Option<bool>,
+    /// flooding of unknown multicast to the host veth port, unset keeps the kernel default
+    mcast_flood: Option<bool>,
+    /// reverse path filter mode of the container interface
+    rp_filter: Option<u8>,
+    /// addresses added to the bridge in addition to the gateways
+    bridge_addresses: Vec<IpNet>,
+    /// subnets whose traffic is dropped inside the container
+    blackhole_routes: Vec<IpNet>,
+    /// point-to-point peer of the container address of the same ip family
+    peer_address: Option<IpAddr>,
+    /// alias set on the bridge and the container interface
+    ifalias: Option<String>,
+    /// minimum host wide neighbor table gc thresholds 1 to 3
+    neigh_gc_thresh: [Option<u32>; 3],
+    /// probe the ipv4 container addresses before they are assigned
+    ip_conflict_check: Option<ConflictAction>,
+    /// only switch between the containers, the bridge has no address and
+    /// the host does not route or nat the network
+    l2: bool,
+    /// attach a dummy interface so the bridge has carrier without containers
+    keep_carrier: bool,
+    /// sysctls set in the container namespace
+    sysctls: Vec<(String, String)>,
+    /// root qdisc of the container interface, unset keeps the kernel default
+    qdisc: Option<String>,
+    /// add the static routes to the routing table of the container
+    route_table: bool,
+    /// existing host interface moved into the container instead of a veth pair
+    adopt_interface: Option<String>,
+}
+
+/// What to do when the container address is already used on the bridge.
+#[derive(Clone, Copy, PartialEq)]
+enum ConflictAction {
+    Error,
+    Warn,
+}
+
+/// Network options which only affect the firewall rules.
+struct FirewallOptions {
+    /// if this network should be isolated from others
+    isolate: IsolateOption,
+    /// source address for outgoing traffic instead of masquerade
+    snat_ip: Option<IpAddr>,
+    /// subnets allowed to talk to the network despite isolation
+    allowed: AllowedNetworks,
+    /// clamp the tcp mss of forwarded connections to the path mtu
+    mss_clamp: bool,
+    /// conntrack zone of the traffic from the network
+    conntrack_zone: Option<u16>,
+    /// allow conntrack helpers for the traffic from the network
+    conntrack_helpers: bool,
+    /// masquerade the ipv6 subnets instead of routing them
+    ipv6_nat: bool,
+    /// fully randomize the source ports of nat connections
+    random_fully: bool,
+    /// drop connections into the network which are not explicitly allowed
+    default_drop: bool,
+    /// only accept replies to connections started by the containers
+    egress_only: bool,
+    /// log the packets dropped by default_drop or egress_only
+    log_drops: bool,
+    /// maximum concurrent connections started by each container
+    conn_limit: Option<u32>,
+    /// maximum new connections per second started by each container
+    conn_rate_limit: Option<u32>,
+    /// port of the resolver on the gateway dns queries are redirected to
+    dns_port: Option<u16>,
+    /// drop connections from the network to the host other than the gateway
+    block_host_access: bool,
 }
 
 pub struct Bridge<'a> {
@@ -69,12 +212,73 @@ impl driver::NetworkDriver for Bridge<'_> {
         self.info.network.name.clone()
     }
 
+    fn setup_firewall(&self) -> NetavarkResult<Vec<FirewallRule>> {
+        let data = match &self.data {
+            Some(d) => d,
+            None => {
+                return Err(NetavarkError::msg(
+                    "must call validate() before setup_firewall()",
+                ))
+            }
+        };
+        if self.info.network.internal || data.l2 {
+            return Ok(Vec::new());
+        }
+        self.apply_firewall(data)
+    }
+
+    fn render_firewall(&self) -> NetavarkResult<Vec<String>> {
+        let data = match &self.data {
+            Some(d) => d,
+            None => {
+                return Err(NetavarkError::msg(
+                    "must call validate() before render_firewall()",
+                ))
+            }
+        };
+        // internal and l2 networks do not get any firewall rules
+        if self.info.network.internal || data.l2 {
+            return Ok(Vec::new());
+        }
+
+        let (sn, spf) = self.get_firewall_conf(
+            &data.ipam.container_addresses,
+            &data.ipam.nameservers,
+            &data.firewall,
+        )?;
+        let mut rules = self.info.firewall.render_network(sn)?;
+        rules.extend(self.info.firewall.render_port_forward(spf)?);
+        Ok(rules)
+    }
+
     fn validate(&mut self) -> NetavarkResult<()> {
         let bridge_name = get_interface_name(self.info.network.network_interface.clone())?;
         if self.info.per_network_opts.interface_name.is_empty() {
             return Err(NetavarkError::msg(NO_CONTAINER_INTERFACE_ERROR));
         }
-        let ipam = get_ipam_addresses(self.info.per_network_opts, self.info.network)?;
+        let mut ipam = get_ipam_addresses(self.info.per_network_opts, self.info.network)?;
+
+        let l2 = get_mode_option(&self.info.network.options)?;
+        let keep_carrier: bool =
+            parse_option(&self.info.network.options, OPTION_KEEP_CARRIER)?.unwrap_or(false);
+        let sysctls = core_utils::get_netns_sysctls(
+            &self.info.network.options,
+            &self.info.per_network_opts.sysctls,
+        )?;
+        let qdisc = get_qdisc_option(&self.info.network.options)?;
+        let route_table = core_utils::get_route_table_option(&self.info.network.options)?;
+        if l2 {
+            if self.info.network.dns_enabled {
+                return Err(NetavarkError::msg(
+                    "dns is not supported with bridge mode l2, the bridge has no address",
+                ));
+            }
+            // the network is routed by some other host, not via the bridge
+            ipam.gateway_addresses = Vec::new();
+            for addr in ipam.net_addresses.iter_mut() {
+                addr.gateway = None;
+            }
+        }
 
         let mtu: u32 = parse_option(&self.info.network.options, OPTION_MTU)?.unwrap_or(0);
         let isolate: IsolateOption = get_isolate_option(&self.info.network.options)?;
@@ -82,10 +286,153 @@ impl driver::NetworkDriver for Bridge<'_> {
         let no_default_route: bool =
             parse_option(&self.info.network.options, OPTION_NO_DEFAULT_ROUTE)?.unwrap_or(false);
 
-        let static_mac = match &self.info.per_network_opts.static_mac {
-            Some(mac) => Some(CoreUtils::decode_address_from_hex(mac)?),
-            None => None,
-        };
+        let vlan_filtering: bool =
+            parse_option(&self.info.network.options, OPTION_VLAN_FILTERING)?.unwrap_or(false);
+        let default_pvid: Option<u16> =
+            parse_option(&self.info.network.options, OPTION_DEFAULT_PVID)?;
+        let pvid = self.info.per_network_opts.pvid;
+        let tagged_vlans = self
+            .info
+            .per_network_opts
+            .tagged_vlans
+            .clone()
+            .unwrap_or_default();
+        if !vlan_filtering && (pvid.is_some() || !tagged_vlans.is_empty()) {
+            return Err(NetavarkError::msg(format!(
+                "pvid and tagged_vlans require the {} option",
+                OPTION_VLAN_FILTERING
+            )));
+        }
+        if !vlan_filtering && default_pvid.is_some() {
+            return Err(NetavarkError::msg(format!(
+                "{} requires the {} option",
+                OPTION_DEFAULT_PVID, OPTION_VLAN_FILTERING
+            )));
+        }
+        for vid in default_pvid
+            .iter()
+            .chain(pvid.iter())
+            .chain(tagged_vlans.iter())
+        {
+            if !(1..=4094).contains(vid) {
+                return Err(NetavarkError::Message(format!(
+                    "invalid vlan id {}, must be between 1 and 4094",
+                    vid
+                )));
+            }
+        }
+
+        let adopt_interface = self.info.per_network_opts.adopt_interface.clone();
+        if let Some(name) = &adopt_interface {
+            if name.is_empty() {
+                return Err(NetavarkError::msg("adopt_interface must not be empty"));
+            }
+            // the adopted interface is not connected to the bridge
+            if self.info.per_network_opts.static_mac.is_some()
+                || pvid.is_some()
+                || !tagged_vlans.is_empty()
+            {
+                return Err(NetavarkError::msg(
+                    "static_mac, pvid and tagged_vlans can not be used with adopt_interface",
+                ));
+            }
+        }
+
+        let snat_ip: Option<IpAddr> = parse_option(&self.info.network.options, OPTION_SNAT_IP)?;
+        if let Some(ip) = snat_ip {
+            check_host_address(ip)?;
+        }
+        // ipv6 is masqueraded like ipv4 unless turned off
+        let ipv6_nat: bool =
+            parse_option(&self.info.network.options, OPTION_IPV6_NAT)?.unwrap_or(true);
+        let random_fully = get_random_fully_option(&self.info.network.options)?;
+
+        let num_rx_queues = get_queues_option(&self.info.network.options, OPTION_NUM_RX_QUEUES)?;
+        let veth_sysfs_values = get_veth_sysfs_options(&self.info.network.options)?;
+        let num_tx_queues = get_queues_option(&self.info.network.options, OPTION_NUM_TX_QUEUES)?;
+        let txqueuelen = get_txqueuelen_option(&self.info.network.options)?;
+        let policy_routing: bool =
+            parse_option(&self.info.network.options, OPTION_POLICY_ROUTING)?.unwrap_or(false);
+        if adopt_interface.is_some() && (self.info.network.dns_enabled || policy_routing) {
+            return Err(NetavarkError::msg(
+                "dns and policy_routing can not be used with adopt_interface, the gateway on the bridge is not reachable",
+            ));
+        }
+        let icmp_redirects: bool =
+            parse_option(&self.info.network.options, OPTION_ICMP_REDIRECTS)?.unwrap_or(false);
+        let dns_search_domains = core_utils::get_dns_search_domains(&self.info.network.options)?;
+        let host_dns = core_utils::get_host_dns_option(self.info.network)?;
+        let no_subnet_route: bool =
+            parse_option(&self.info.network.options, OPTION_NO_SUBNET_ROUTE)?.unwrap_or(false);
+        if no_subnet_route {
+            core_utils::validate_no_subnet_route(self.info.network, &ipam, no_default_route)?;
+        }
+        let allowed = core_utils::get_allowed_networks(&self.info.network.options)?;
+        let group_fwd_mask = get_group_fwd_mask_option(&self.info.network.options)?;
+        let multicast_snooping: Option<bool> =
+            parse_option(&self.info.network.options, OPTION_MULTICAST_SNOOPING)?;
+        let mcast_flood: Option<bool> =
+            parse_option(&self.info.network.options, OPTION_MCAST_FLOOD)?;
+        let bridge_addresses = get_bridge_addresses_option(self.info.network)?;
+        if l2 && !bridge_addresses.is_empty() {
+            return Err(NetavarkError::Message(format!(
+                "{} cannot be used with bridge mode l2",
+                OPTION_BRIDGE_ADDRESSES
+            )));
+        }
+        let peer_address =
+            get_peer_address_option(&self.info.network.options, &ipam.container_addresses)?;
+        let ifalias = core_utils::get_ifalias_option(&self.info.network.options)?;
+        let blackhole_routes = core_utils::get_blackhole_routes_option(&self.info.network.options)?;
+        let neigh_gc_thresh = get_neigh_gc_thresh_options(&self.info.network.options)?;
+        let ip_conflict_check = get_ip_conflict_check_option(&self.info.network.options)?;
+        let mss_clamp = get_mss_clamp_option(&self.info.network.options, mtu)?;
+        let default_drop = get_default_policy_option(&self.info.network.options)?;
+        let egress_only: bool =
+            parse_option(&self.info.network.options, OPTION_EGRESS_ONLY)?.unwrap_or(false);
+        if egress_only
+            && self
+                .info
+                .port_mappings
+                .as_ref()
+                .map_or(false, |ports| !ports.is_empty())
+        {
+            return Err(NetavarkError::Message(format!(
+                "port mappings cannot be used with the {} option of network {}",
+                OPTION_EGRESS_ONLY, self.info.network.name
+            )));
+        }
+        let log_drops: bool =
+            parse_option(&self.info.network.options, OPTION_LOG_DROPS)?.unwrap_or(false);
+        if log_drops && !default_drop && !egress_only {
+            return Err(NetavarkError::Message(format!(
+                "{} requires {} drop or {}",
+                OPTION_LOG_DROPS, OPTION_DEFAULT_POLICY, OPTION_EGRESS_ONLY
+            )));
+        }
+        let (conn_limit, conn_rate_limit) = get_conn_limit_options(&self.info.network.options)?;
+        let dns_port = get_dns_port_option(
+            &self.info.network.options,
+            self.info.network.dns_enabled,
+            self.info.dns_port,
+        )?;
+        let block_host_access: bool =
+            parse_option(&self.info.network.options, OPTION_BLOCK_HOST_ACCESS)?.unwrap_or(false);
+        let rp_filter = get_rp_filter_option(&self.info.network.options, self.info.network_count)?;
+        let conntrack_zone =
+            get_conntrack_zone_option(&self.info.network.options, &self.info.network.name)?;
+        let conntrack_helpers: bool =
+            parse_option(&self.info.network.options, OPTION_CONNTRACK_HELPERS)?.unwrap_or(false);
+        for extra in self.info.network.extra_rules.iter().flatten() {
+            validation::validate_extra_rule(extra)?;
+        }
+
+        let static_mac = core_utils::get_container_mac(
+            &self.info.network.options,
+            &self.info.per_network_opts.static_mac,
+            &self.info.network.name,
+            self.info.container_id,
+        )?;
 
         self.data = Some(InternalData {
             bridge_interface_name: bridge_name,
@@ -93,9 +440,54 @@ impl driver::NetworkDriver for Bridge<'_> {
             mac_address: static_mac,
             ipam,
             mtu,
-            isolate,
+            firewall: FirewallOptions {
+                isolate,
+                snat_ip,
+                allowed,
+                mss_clamp,
+                conntrack_zone,
+                conntrack_helpers,
+                ipv6_nat,
+                random_fully,
+                default_drop,
+                egress_only,
+                log_drops,
+                conn_limit,
+                conn_rate_limit,
+                dns_port,
+                block_host_access,
+            },
             metric: Some(metric),
             no_default_route,
+            vlan_filtering,
+            default_pvid,
+            pvid,
+            tagged_vlans,
+            num_rx_queues,
+            num_tx_queues,
+            txqueuelen,
+            veth_sysfs_values,
+            policy_routing,
+            icmp_redirects,
+            dns_search_domains,
+            host_dns,
+            no_subnet_route,
+            group_fwd_mask,
+            multicast_snooping,
+            mcast_flood,
+            rp_filter,
+            bridge_addresses,
+            blackhole_routes,
+            peer_address,
+            ifalias,
+            neigh_gc_thresh,
+            ip_conflict_check,
+            l2,
+            keep_carrier,
+            sysctls,
+            qdisc,
+            route_table,
+            adopt_interface,
         });
         Ok(())
     }
@@ -103,7 +495,7 @@ impl driver::NetworkDriver for Bridge<'_> {
     fn setup(
         &self,
         netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
-    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry>)> {
+    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry>, Vec<FirewallRule>)> {
         let data = match &self.data {
             Some(d) => d,
             None => return Err(NetavarkError::msg("must call validate() before setup()")),
@@ -119,13 +511,19 @@ impl driver::NetworkDriver for Bridge<'_> {
             data.bridge_interface_name, data.ipam.gateway_addresses
         );
 
-        setup_ipv4_fw_sysctl()?;
-        if data.ipam.ipv6_enabled {
-            setup_ipv6_fw_sysctl()?;
+        if !data.l2 {
+            setup_ipv4_fw_sysctl()?;
+            if data.ipam.ipv6_enabled {
+                setup_ipv6_fw_sysctl()?;
+            }
         }
 
         let (host_sock, netns_sock) = netlink_sockets;
 
+        if let Some(action) = data.ip_conflict_check {
+            check_ip_conflicts(host_sock, data, action)?;
+        }
+
         let container_veth_mac = create_interfaces(
             host_sock,
             netns_sock,
@@ -140,13 +538,28 @@ impl driver::NetworkDriver for Bridge<'_> {
             dns_server_ips: Some(Vec::<IpAddr>::new()),
             dns_search_domains: Some(Vec::<String>::new()),
             interfaces: Some(HashMap::new()),
+            gateway: None,
+            gateway_reachable: None,
+            warnings: None,
+            changed_sysctls: None,
+            netns_inode: None,
+            firewall_chains: None,
         };
         // interfaces map, but we only ever expect one, for response
         let mut interfaces: HashMap<String, types::NetInterface> = HashMap::new();
 
+        let mut net_addresses = data.ipam.net_addresses.clone();
+        // the gateway is not reachable through an adopted interface
+        if data.adopt_interface.is_some() {
+            for addr in net_addresses.iter_mut() {
+                addr.gateway = None;
+            }
+        }
+        response.gateway = core_utils::get_gateways(&net_addresses);
         let interface = types::NetInterface {
             mac_address: container_veth_mac,
-            subnets: Option::from(data.ipam.net_addresses.clone()),
+            subnets: Option::from(net_addresses),
+            stats: None,
         };
         // Add interface to interfaces (part of StatusBlock)
         interfaces.insert(data.container_interface_name.clone(), interface);
@@ -205,13 +618,25 @@ impl driver::NetworkDriver for Bridge<'_> {
                 let _ = response
                     .dns_server_ips
                     .insert(container_dns_servers.clone());
+            } else if data.host_dns {
+                let _ = response
+                    .dns_server_ips
+                    .insert(core_utils::get_host_nameservers(
+                        core_utils::HOST_RESOLV_CONF,
+                    ));
             }
             None
         };
+        core_utils::add_dns_search_domains(&mut response, &data.dns_search_domains);
+
+        raise_neigh_gc_thresh(&data.neigh_gc_thresh, data.ipam.ipv6_enabled)?;
+        if !data.icmp_redirects {
+            disable_icmp_redirects(&data.bridge_interface_name, data.ipam.ipv6_enabled)?;
+        }
 
         // if the network is internal block routing and do not setup firewall rules
         if self.info.network.internal {
-            CoreUtils::apply_sysctl_value(
+            CoreUtils::apply_host_sysctl_value(
                 format!(
                     "/proc/sys/net/ipv4/conf/{}/forwarding",
                     data.bridge_interface_name
@@ -219,7 +644,7 @@ impl driver::NetworkDriver for Bridge<'_> {
                 "0",
             )?;
             if data.ipam.ipv6_enabled {
-                CoreUtils::apply_sysctl_value(
+                CoreUtils::apply_host_sysctl_value(
                     format!(
                         "/proc/sys/net/ipv6/conf/{}/forwarding",
                         data.bridge_interface_name
@@ -228,12 +653,21 @@ impl driver::NetworkDriver for Bridge<'_> {
                 )?;
             }
             // return here to skip setting up firewall rules
-            return Ok((response, aardvark_entry));
+            return Ok((response, aardvark_entry, Vec::new()));
+        }
+        // the host does not route l2 networks so there are no firewall rules
+        if data.l2 {
+            return Ok((response, aardvark_entry, Vec::new()));
         }
 
-        self.setup_firewall(data)?;
+        // the rules are applied later by the setup-firewall command
+        let rules = if self.info.skip_firewall {
+            Vec::new()
+        } else {
+            self.apply_firewall(data)?
+        };
 
-        Ok((response, aardvark_entry))
+        Ok((response, aardvark_entry, rules))
     }
 
     fn teardown(
@@ -250,12 +684,29 @@ impl driver::NetworkDriver for Bridge<'_> {
                 .del_route(route)
                 .unwrap_or_else(|err| error_list.push(err))
         }
+        core_utils::remove_blackhole_routes(netns_sock, &self.info.network.options)
+            .unwrap_or_else(|err| error_list.push(err));
+
+        // the table routes are removed with the interface but the rules are not
+        let policy_routing = parse_option(&self.info.network.options, OPTION_POLICY_ROUTING)
+            .unwrap_or_else(|e| {
+                // just log we still try to do as much as possible for cleanup
+                error!("failed to parse {} option: {}", OPTION_POLICY_ROUTING, e);
+                None
+            })
+            .unwrap_or(false);
+        if !self.info.network.internal && policy_routing {
+            self.teardown_policy_routing(netns_sock)
+                .unwrap_or_else(|err| error_list.push(err));
+        }
 
         let complete_teardown = match remove_link(
             host_sock,
             netns_sock,
             &get_interface_name(self.info.network.network_interface.clone())?,
             &self.info.per_network_opts.interface_name,
+            self.info.per_network_opts.adopt_interface.as_deref(),
+            self.info.netns_host,
         ) {
             Ok(teardown) => teardown,
             Err(err) => {
@@ -264,7 +715,12 @@ impl driver::NetworkDriver for Bridge<'_> {
             }
         };
 
-        if self.info.network.internal {
+        let l2 = get_mode_option(&self.info.network.options).unwrap_or_else(|e| {
+            // just log we still try to do as much as possible for cleanup
+            error!("failed to parse {} option: {}", OPTION_MODE, e);
+            false
+        });
+        if self.info.network.internal || l2 {
             if !error_list.is_empty() {
                 return Err(NetavarkError::List(error_list));
             }
@@ -304,14 +760,31 @@ impl<'a> Bridge<'a> {
         &'a self,
         container_addresses: &Vec<IpNet>,
         nameservers: &'a Vec<IpAddr>,
-        isolate: IsolateOption,
+        firewall: &FirewallOptions,
     ) -> NetavarkResult<(SetupNetwork, PortForwardConfig)> {
         let id_network_hash =
             CoreUtils::create_network_hash(&self.info.network.name, MAX_HASH_SIZE);
         let sn = SetupNetwork {
             net: self.info.network.clone(),
             network_hash_name: id_network_hash.clone(),
-            isolation: isolate,
+            isolation: firewall.isolate,
+            snat_ip: firewall.snat_ip,
+            allowed: firewall.allowed.clone(),
+            mss_clamp: firewall.mss_clamp,
+            conntrack_zone: firewall.conntrack_zone,
+            conntrack_helpers: firewall.conntrack_helpers,
+            ipv6_nat: firewall.ipv6_nat,
+            random_fully: firewall.random_fully,
+            default_drop: firewall.default_drop,
+            egress_only: firewall.egress_only,
+            log_drops: firewall.log_drops,
+            conn_limit: firewall.conn_limit,
+            conn_rate_limit: firewall.conn_rate_limit,
+            dns_port: match firewall.dns_port {
+                Some(port) => Some(port),
+                None => self.info.network.dns_enabled.then_some(self.info.dns_port),
+            },
+            block_host_access: firewall.block_host_access,
         };
 
         let mut has_ipv4 = false;
@@ -343,6 +816,7 @@ impl<'a> Bridge<'a> {
         }
         let spf = PortForwardConfig {
             container_id: self.info.container_id.clone(),
+            container_name: self.info.container_name.clone(),
             port_mappings: self.info.port_mappings,
             network_name: self.info.network.name.clone(),
             network_hash_name: id_network_hash,
@@ -350,26 +824,26 @@ impl<'a> Bridge<'a> {
             subnet_v4: net_v4,
             container_ip_v6: addr_v6,
             subnet_v6: net_v6,
-            dns_port: self.info.dns_port,
+            dns_port: firewall.dns_port.unwrap_or(self.info.dns_port),
             dns_server_ips: nameservers,
         };
         Ok((sn, spf))
     }
 
-    fn setup_firewall(&self, data: &InternalData) -> NetavarkResult<()> {
+    fn apply_firewall(&self, data: &InternalData) -> NetavarkResult<Vec<FirewallRule>> {
         let (sn, spf) = self.get_firewall_conf(
             &data.ipam.container_addresses,
             &data.ipam.nameservers,
-            data.isolate,
+            &data.firewall,
         )?;
 
-        self.info.firewall.setup_network(sn)?;
+        let mut rules = self.info.firewall.setup_network(sn)?;
 
         if spf.port_mappings.is_some() {
             // Need to enable sysctl localnet so that traffic can pass
             // through localhost to containers
 
-            CoreUtils::apply_sysctl_value(
+            CoreUtils::apply_host_sysctl_value(
                 format!(
                     "net.ipv4.conf.{}.route_localnet",
                     data.bridge_interface_name
@@ -378,23 +852,168 @@ impl<'a> Bridge<'a> {
             )?;
         }
 
-        self.info.firewall.setup_port_forward(spf)?;
+        rules.extend(self.info.firewall.setup_port_forward(spf)?);
+        Ok(rules)
+    }
+
+    fn teardown_policy_routing(&self, netns: &mut netlink::Socket) -> NetavarkResult<()> {
+        let container_addresses = match &self.data {
+            Some(d) => d.ipam.container_addresses.clone(),
+            None => {
+                get_ipam_addresses(self.info.per_network_opts, self.info.network)?
+                    .container_addresses
+            }
+        };
+        let veth = netns
+            .get_link(netlink::LinkID::Name(
+                self.info.per_network_opts.interface_name.clone(),
+            ))
+            .wrap("get container veth")?;
+        let table = POLICY_ROUTING_TABLE_OFFSET + veth.header.index;
+
+        let mut error_list = NetavarkErrorList::new();
+        for addr in &container_addresses {
+            netns
+                .del_rule(&policy_rule(addr, table))
+                .unwrap_or_else(|err| error_list.push(err));
+        }
+        if !error_list.is_empty() {
+            return Err(NetavarkError::List(error_list));
+        }
         Ok(())
     }
 
     fn teardown_firewall(&self, complete_teardown: bool) -> NetavarkResult<()> {
         // we have to allocate the vecoros here in the top level to avoid
         // "borrow later used" problems
-        let (container_addresses, nameservers);
+        let (container_addresses, nameservers, firewall);
 
-        let (container_addresses_ref, nameservers_ref, isolate) = match &self.data {
-            Some(d) => (&d.ipam.container_addresses, &d.ipam.nameservers, d.isolate),
+        let (container_addresses_ref, nameservers_ref, firewall_ref) = match &self.data {
+            Some(d) => (
+                &d.ipam.container_addresses,
+                &d.ipam.nameservers,
+                &d.firewall,
+            ),
             None => {
                 let isolate = get_isolate_option(&self.info.network.options).unwrap_or_else(|e| {
                     // just log we still try to do as much as possible for cleanup
                     error!("failed to parse {} option: {}", OPTION_ISOLATE, e);
                     IsolateOption::Never
                 });
+                let snat_ip = parse_option(&self.info.network.options, OPTION_SNAT_IP)
+                    .unwrap_or_else(|e| {
+                        // just log we still try to do as much as possible for cleanup
+                        error!("failed to parse {} option: {}", OPTION_SNAT_IP, e);
+                        None
+                    });
+                let allowed = core_utils::get_allowed_networks(&self.info.network.options)
+                    .unwrap_or_else(|e| {
+                        // just log we still try to do as much as possible for cleanup
+                        error!("failed to parse allowed networks: {}", e);
+                        AllowedNetworks::default()
+                    });
+                let mtu = parse_option(&self.info.network.options, OPTION_MTU)
+                    .unwrap_or_else(|e| {
+                        // just log we still try to do as much as possible for cleanup
+                        error!("failed to parse {} option: {}", OPTION_MTU, e);
+                        None
+                    })
+                    .unwrap_or(0);
+                let mss_clamp = get_mss_clamp_option(&self.info.network.options, mtu)
+                    .unwrap_or_else(|e| {
+                        // just log we still try to do as much as possible for cleanup
+                        error!("failed to parse {} option: {}", OPTION_MSS_CLAMP, e);
+                        false
+                    });
+                let conntrack_zone =
+                    get_conntrack_zone_option(&self.info.network.options, &self.info.network.name)
+                        .unwrap_or_else(|e| {
+                            // just log we still try to do as much as possible for cleanup
+                            error!("failed to parse {} option: {}", OPTION_CONNTRACK_ZONE, e);
+                            None
+                        });
+                let conntrack_helpers =
+                    parse_option(&self.info.network.options, OPTION_CONNTRACK_HELPERS)
+                        .unwrap_or_else(|e| {
+                            // just log we still try to do as much as possible for cleanup
+                            error!("failed to parse {} option: {}", OPTION_CONNTRACK_HELPERS, e);
+                            None
+                        })
+                        .unwrap_or(false);
+                let ipv6_nat = parse_option(&self.info.network.options, OPTION_IPV6_NAT)
+                    .unwrap_or_else(|e| {
+                        // just log we still try to do as much as possible for cleanup
+                        error!("failed to parse {} option: {}", OPTION_IPV6_NAT, e);
+                        None
+                    })
+                    .unwrap_or(true);
+                let random_fully = get_random_fully_option(&self.info.network.options)
+                    .unwrap_or_else(|e| {
+                        // just log we still try to do as much as possible for cleanup
+                        error!("failed to parse {} option: {}", OPTION_RANDOM_FULLY, e);
+                        false
+                    });
+                let default_drop = get_default_policy_option(&self.info.network.options)
+                    .unwrap_or_else(|e| {
+                        // just log we still try to do as much as possible for cleanup
+                        error!("failed to parse {} option: {}", OPTION_DEFAULT_POLICY, e);
+                        false
+                    });
+                let egress_only = parse_option(&self.info.network.options, OPTION_EGRESS_ONLY)
+                    .unwrap_or_else(|e| {
+                        // just log we still try to do as much as possible for cleanup
+                        error!("failed to parse {} option: {}", OPTION_EGRESS_ONLY, e);
+                        None
+                    })
+                    .unwrap_or(false);
+                let log_drops = parse_option(&self.info.network.options, OPTION_LOG_DROPS)
+                    .unwrap_or_else(|e| {
+                        // just log we still try to do as much as possible for cleanup
+                        error!("failed to parse {} option: {}", OPTION_LOG_DROPS, e);
+                        None
+                    })
+                    .unwrap_or(false);
+                let (conn_limit, conn_rate_limit) =
+                    get_conn_limit_options(&self.info.network.options).unwrap_or_else(|e| {
+                        // just log we still try to do as much as possible for cleanup
+                        error!("failed to parse {} option: {}", OPTION_CONN_LIMIT, e);
+                        (None, None)
+                    });
+                let dns_port = get_dns_port_option(
+                    &self.info.network.options,
+                    self.info.network.dns_enabled,
+                    self.info.dns_port,
+                )
+                .unwrap_or_else(|e| {
+                    // just log we still try to do as much as possible for cleanup
+                    error!("failed to parse {} option: {}", OPTION_DNS_PORT, e);
+                    None
+                });
+                let block_host_access =
+                    parse_option(&self.info.network.options, OPTION_BLOCK_HOST_ACCESS)
+                        .unwrap_or_else(|e| {
+                            // just log we still try to do as much as possible for cleanup
+                            error!("failed to parse {} option: {}", OPTION_BLOCK_HOST_ACCESS, e);
+                            None
+                        })
+                        .unwrap_or(false);
+                firewall = FirewallOptions {
+                    isolate,
+                    snat_ip,
+                    allowed,
+                    mss_clamp,
+                    conntrack_zone,
+                    conntrack_helpers,
+                    ipv6_nat,
+                    random_fully,
+                    default_drop,
+                    egress_only,
+                    log_drops,
+                    conn_limit,
+                    conn_rate_limit,
+                    dns_port,
+                    block_host_access,
+                };
 
                 (container_addresses, nameservers) =
                     match get_ipam_addresses(self.info.per_network_opts, self.info.network) {
@@ -405,12 +1024,18 @@ impl<'a> Bridge<'a> {
                             (Vec::new(), Vec::new())
                         }
                     };
-                (&container_addresses, &nameservers, isolate)
+                (&container_addresses, &nameservers, &firewall)
             }
         };
 
         let (sn, spf) =
-            self.get_firewall_conf(container_addresses_ref, nameservers_ref, isolate)?;
+            self.get_firewall_conf(container_addresses_ref, nameservers_ref, firewall_ref)?;
+
+        // delete the rules setup installed first, the rules derived from the
+        // config below only catch the ones which were not recorded
+        self.info
+            .firewall
+            .remove_rules(self.info.firewall_rules, complete_teardown)?;
 
         let tn = TearDownNetwork {
             config: sn,
@@ -444,7 +1069,7 @@ fn setup_ipv4_fw_sysctl() -> NetavarkResult<()> {
     let mut result = Ok("".to_string());
 
     IPV4_FORWARD_ONCE.call_once(|| {
-        result = CoreUtils::apply_sysctl_value(IPV4_FORWARD, "1");
+        result = CoreUtils::apply_host_sysctl_value(IPV4_FORWARD, "1");
     });
 
     match result {
@@ -458,7 +1083,7 @@ fn setup_ipv6_fw_sysctl() -> NetavarkResult<()> {
     let mut result = Ok("".to_string());
 
     IPV6_FORWARD_ONCE.call_once(|| {
-        result = CoreUtils::apply_sysctl_value(IPV6_FORWARD, "1");
+        result = CoreUtils::apply_host_sysctl_value(IPV6_FORWARD, "1");
     });
 
     match result {
@@ -480,7 +1105,11 @@ fn create_interfaces(
     let bridge = match host.get_link(netlink::LinkID::Name(
         data.bridge_interface_name.to_string(),
     )) {
-        Ok(bridge) => check_link_is_bridge(bridge, &data.bridge_interface_name)?,
+        Ok(bridge) => {
+            let bridge = check_link_is_bridge(bridge, &data.bridge_interface_name)?;
+            check_existing_bridge(host, &bridge, data)?;
+            bridge
+        }
         Err(err) => match err.unwrap() {
             NetavarkError::Netlink(e) => {
                 if -e.raw_code() != libc::ENODEV {
@@ -488,12 +1117,34 @@ fn create_interfaces(
                     // for all other errors we want to return the error
                     return Err(err).wrap("get bridge interface");
                 }
+                check_bridge_addresses_unused(host, &data.bridge_addresses)?;
                 let mut create_link_opts = netlink::CreateLinkOptions::new(
                     data.bridge_interface_name.to_string(),
                     InfoKind::Bridge,
                 );
                 create_link_opts.mtu = data.mtu;
-                host.create_link(create_link_opts).wrap("create bridge")?;
+                let mut bridge_opts = Vec::new();
+                if data.vlan_filtering {
+                    bridge_opts.push(InfoBridge::VlanFiltering(1));
+                }
+                if let Some(pvid) = data.default_pvid {
+                    bridge_opts.push(InfoBridge::VlanDefaultPvid(pvid));
+                }
+                if let Some(mask) = data.group_fwd_mask {
+                    bridge_opts.push(InfoBridge::GroupFwdMask(mask));
+                }
+                if let Some(snooping) = data.multicast_snooping {
+                    bridge_opts.push(InfoBridge::MulticastSnooping(snooping as u8));
+                }
+                if !bridge_opts.is_empty() {
+                    create_link_opts.info_data = Some(InfoData::Bridge(bridge_opts));
+                }
+                core_utils::wrap_netlink(
+                    host.create_link(create_link_opts),
+                    "create bridge",
+                    &data.bridge_interface_name,
+                    "host",
+                )?;
 
                 if data.ipam.ipv6_enabled {
                     // Disable duplicate address detection if ipv6 enabled
@@ -504,8 +1155,8 @@ fn create_interfaces(
                     );
                     let br_accept_ra =
                         format!("net/ipv6/conf/{}/accept_ra", &data.bridge_interface_name);
-                    CoreUtils::apply_sysctl_value(br_accept_dad, "0")?;
-                    CoreUtils::apply_sysctl_value(br_accept_ra, "0")?;
+                    CoreUtils::apply_host_sysctl_value(br_accept_dad, "0")?;
+                    CoreUtils::apply_host_sysctl_value(br_accept_ra, "0")?;
                 }
 
                 let link = host
@@ -514,19 +1165,45 @@ fn create_interfaces(
                     ))
                     .wrap("get bridge interface")?;
 
-                for addr in &data.ipam.gateway_addresses {
-                    host.add_addr(link.header.index, addr)
-                        .wrap("add ip addr to bridge")?;
+                if let Some(alias) = &data.ifalias {
+                    core_utils::wrap_netlink(
+                        host.set_link_alias(link.header.index, alias.clone()),
+                        "set bridge alias",
+                        &data.bridge_interface_name,
+                        "host",
+                    )?;
                 }
 
-                host.set_up(netlink::LinkID::ID(link.header.index))
-                    .wrap("set bridge up")?;
+                for addr in data
+                    .ipam
+                    .gateway_addresses
+                    .iter()
+                    .chain(&data.bridge_addresses)
+                {
+                    core_utils::wrap_netlink(
+                        host.add_addr(link.header.index, addr),
+                        &format!("add ip addr {} to bridge", addr),
+                        &data.bridge_interface_name,
+                        "host",
+                    )?;
+                }
+
+                core_utils::wrap_netlink(
+                    host.set_up(netlink::LinkID::ID(link.header.index)),
+                    "set bridge up",
+                    &data.bridge_interface_name,
+                    "host",
+                )?;
                 link
             }
             _ => return Err(err),
         },
     };
 
+    if data.keep_carrier {
+        add_carrier_dummy(host, &data.bridge_interface_name, bridge.header.index)?;
+    }
+
     create_veth_pair(
         host,
         netns,
@@ -548,36 +1225,67 @@ fn create_veth_pair(
     hostns_fd: RawFd,
     netns_fd: RawFd,
 ) -> NetavarkResult<String> {
-    let mut peer_opts =
-        netlink::CreateLinkOptions::new(data.container_interface_name.to_string(), InfoKind::Veth);
-    peer_opts.mac = data.mac_address.clone().unwrap_or_default();
-    peer_opts.mtu = data.mtu;
-    peer_opts.netns = netns_fd;
-
-    let mut peer = LinkMessage::default();
-    netlink::parse_create_link_options(&mut peer, peer_opts);
-
-    let mut host_veth = netlink::CreateLinkOptions::new(String::from(""), InfoKind::Veth);
-    host_veth.mtu = data.mtu;
-    host_veth.primary_index = primary_index;
-    host_veth.info_data = Some(InfoData::Veth(VethInfo::Peer(peer)));
-
-    host.create_link(host_veth).map_err(|err| match err {
-        NetavarkError::Netlink(ref e) if -e.raw_code() == libc::EEXIST => NetavarkError::wrap(
-            format!(
-                "create veth pair: interface {} already exists on container namespace",
-                data.container_interface_name
-            ),
-            err,
-        ),
-        _ => NetavarkError::wrap("create veth pair", err),
-    })?;
-
-    let veth = netns
-        .get_link(netlink::LinkID::Name(
+    let veth = if let Some(name) = &data.adopt_interface {
+        core_utils::adopt_interface(
+            host,
+            netns,
+            name,
+            &data.container_interface_name,
+            hostns_fd,
+            netns_fd,
+        )?
+    } else {
+        let mut peer_opts = netlink::CreateLinkOptions::new(
             data.container_interface_name.to_string(),
-        ))
-        .wrap("get container veth")?;
+            InfoKind::Veth,
+        );
+        peer_opts.mac = data.mac_address.clone().unwrap_or_default();
+        peer_opts.mtu = data.mtu;
+        peer_opts.netns = netns_fd;
+        peer_opts.num_rx_queues = data.num_rx_queues;
+        peer_opts.num_tx_queues = data.num_tx_queues;
+        peer_opts.txqueuelen = data.txqueuelen;
+
+        let mut peer = LinkMessage::default();
+        netlink::parse_create_link_options(&mut peer, peer_opts);
+
+        let mut host_veth = netlink::CreateLinkOptions::new(String::from(""), InfoKind::Veth);
+        host_veth.mtu = data.mtu;
+        host_veth.primary_index = primary_index;
+        host_veth.num_rx_queues = data.num_rx_queues;
+        host_veth.num_tx_queues = data.num_tx_queues;
+        host_veth.info_data = Some(InfoData::Veth(VethInfo::Peer(peer)));
+
+        let attempts = core_utils::get_netns_move_attempts()?;
+        core_utils::retry_netns_move(&data.container_interface_name, attempts, || {
+            host.create_link(host_veth.clone())
+        })
+        .map_err(|err| match err {
+            NetavarkError::Netlink(ref e) if -e.raw_code() == libc::EEXIST => NetavarkError::wrap(
+                format!(
+                    "create veth pair: interface {} already exists on container namespace",
+                    data.container_interface_name
+                ),
+                err,
+            ),
+            _ => NetavarkError::wrap(
+                format!(
+                    "create veth pair on {} in container namespace",
+                    data.container_interface_name
+                ),
+                err,
+            ),
+        })?;
+
+        core_utils::wrap_netlink(
+            netns.get_link(netlink::LinkID::Name(
+                data.container_interface_name.to_string(),
+            )),
+            "get container veth",
+            &data.container_interface_name,
+            "container",
+        )?
+    };
 
     let mut mac = String::from("");
     let mut host_link = 0;
@@ -607,49 +1315,270 @@ fn create_veth_pair(
             );
             core_utils::CoreUtils::apply_sysctl_value(disable_dad_in_container, "0")?;
         }
+        // the sysctl belongs to the veth, it is gone once teardown removes it
+        if let Some(rp_filter) = data.rp_filter {
+            core_utils::CoreUtils::apply_sysctl_value(
+                format!(
+                    "/proc/sys/net/ipv4/conf/{}/rp_filter",
+                    &data.container_interface_name
+                ),
+                rp_filter.to_string(),
+            )?;
+        }
+        core_utils::apply_netns_sysctls(&data.sysctls)?;
         Ok::<(), NetavarkError>(())
     });
     // check the result and return error
     res?;
 
-    if data.ipam.ipv6_enabled {
-        let host_veth = host.get_link(netlink::LinkID::ID(host_link))?;
+    core_utils::set_netns_sysfs_values(
+        netns_fd,
+        &data.container_interface_name,
+        &data.veth_sysfs_values,
+    )?;
 
-        for nla in host_veth.nlas.into_iter() {
-            if let Nla::IfName(name) = nla {
-                //  Disable dad inside on the host too
-                let disable_dad_in_container =
-                    format!("/proc/sys/net/ipv6/conf/{}/accept_dad", name);
-                core_utils::CoreUtils::apply_sysctl_value(disable_dad_in_container, "0")?;
+    // an adopted interface has no host side on the bridge
+    if data.adopt_interface.is_none() {
+        if data.ipam.ipv6_enabled {
+            let host_veth = host.get_link(netlink::LinkID::ID(host_link))?;
+
+            for nla in host_veth.nlas.into_iter() {
+                if let Nla::IfName(name) = nla {
+                    //  Disable dad inside on the host too
+                    let disable_dad_in_container =
+                        format!("/proc/sys/net/ipv6/conf/{}/accept_dad", name);
+                    core_utils::CoreUtils::apply_host_sysctl_value(disable_dad_in_container, "0")?;
+                }
             }
         }
+
+        host.set_up(netlink::LinkID::ID(host_link))
+            .wrap("failed to set host veth up")?;
+
+        setup_port_vlans(host, data, host_link)?;
+
+        if let Some(flood) = data.mcast_flood {
+            host.set_bridge_port_mcast_flood(host_link, flood)
+                .wrap("set multicast flooding of host veth")?;
+        }
     }
 
-    host.set_up(netlink::LinkID::ID(host_link))
-        .wrap("failed to set host veth up")?;
+    if let Some(alias) = &data.ifalias {
+        core_utils::wrap_netlink(
+            netns.set_link_alias(veth.header.index, alias.clone()),
+            "set container veth alias",
+            &data.container_interface_name,
+            "container",
+        )?;
+    }
 
     for addr in &data.ipam.container_addresses {
-        netns
-            .add_addr(veth.header.index, addr)
-            .wrap("add ip addr to container veth")?;
+        let peer = data
+            .peer_address
+            .filter(|peer| peer.is_ipv6() == addr.addr().is_ipv6());
+        core_utils::wrap_netlink(
+            if let Some(peer) = peer {
+                netns.add_addr_with_peer(veth.header.index, addr, peer)
+            } else if data.no_subnet_route {
+                netns.add_addr_without_prefix_route(veth.header.index, addr)
+            } else {
+                netns.add_addr(veth.header.index, addr)
+            },
+            &format!("add ip addr {} to container veth", addr),
+            &data.container_interface_name,
+            "container",
+        )?;
     }
 
-    netns
-        .set_up(netlink::LinkID::ID(veth.header.index))
-        .wrap("set container veth up")?;
+    if let Some(qdisc) = &data.qdisc {
+        core_utils::wrap_netlink(
+            netns.set_root_qdisc(veth.header.index, qdisc),
+            &format!("set {} qdisc on container veth", qdisc),
+            &data.container_interface_name,
+            "container",
+        )?;
+    }
+
+    core_utils::wrap_netlink(
+        netns.set_up(netlink::LinkID::ID(veth.header.index)),
+        "set container veth up",
+        &data.container_interface_name,
+        "container",
+    )?;
+
+    // an adopted interface is not connected to the bridge, the routes via
+    // the gateway do not apply to it
+    if data.adopt_interface.is_none() {
+        if data.no_subnet_route {
+            core_utils::add_gateway_host_routes(
+                netns,
+                veth.header.index,
+                &data.ipam.gateway_addresses,
+            )?;
+        }
+
+        if !internal && !data.no_default_route {
+            core_utils::add_default_routes(netns, &data.ipam.gateway_addresses, data.metric)?;
+        }
 
-    if !internal && !data.no_default_route {
-        core_utils::add_default_routes(netns, &data.ipam.gateway_addresses, data.metric)?;
+        // add static routes
+        if data.route_table && !data.ipam.routes.is_empty() {
+            add_route_table_routes(netns, data)?;
+        } else {
+            for route in data.ipam.routes.iter() {
+                netns.add_route(route)?
+            }
+        }
+    }
+    for dest in &data.blackhole_routes {
+        netns.add_blackhole_route(dest)?;
     }
 
-    // add static routes
-    for route in data.ipam.routes.iter() {
-        netns.add_route(route)?
+    if data.policy_routing && !internal {
+        setup_policy_routing(netns, data, veth.header.index)?;
     }
 
     Ok(mac)
 }
 
+/// Add a routing table for the container interface and rules so that traffic
+/// from the interface addresses is routed via the gateway of this network.
+fn setup_policy_routing(
+    netns: &mut netlink::Socket,
+    data: &InternalData,
+    link_index: u32,
+) -> NetavarkResult<()> {
+    let table = POLICY_ROUTING_TABLE_OFFSET + link_index;
+    let mut default_v4 = false;
+    let mut default_v6 = false;
+    for addr in &data.ipam.container_addresses {
+        netns
+            .add_table_route(&netlink::TableRoute {
+                table,
+                dest: addr.trunc(),
+                gw: None,
+                oif: Some(link_index),
+            })
+            .wrap("add policy routing subnet route")?;
+
+        let default_added = match addr {
+            IpNet::V4(_) => &mut default_v4,
+            IpNet::V6(_) => &mut default_v6,
+        };
+        if !data.no_default_route && !*default_added {
+            let gw = data
+                .ipam
+                .gateway_addresses
+                .iter()
+                .find(|gw| gw.contains(&addr.addr()));
+            if let Some(gw) = gw {
+                let dest = match gw {
+                    IpNet::V4(_) => IpNet::V4(Default::default()),
+                    IpNet::V6(_) => IpNet::V6(Default::default()),
+                };
+                netns
+                    .add_table_route(&netlink::TableRoute {
+                        table,
+                        dest,
+                        gw: Some(gw.addr()),
+                        oif: Some(link_index),
+                    })
+                    .wrap("add policy routing default route")?;
+                *default_added = true;
+            }
+        }
+
+        netns
+            .add_rule(&policy_rule(addr, table))
+            .wrap("add policy routing rule")?;
+    }
+    Ok(())
+}
+
+/// Add the static routes to the routing table shared by the networks of the
+/// container, the rules looking it up are added for the ip families used.
+/// Like in the main table the gateway of a route can be on another interface.
+fn add_route_table_routes(netns: &mut netlink::Socket, data: &InternalData) -> NetavarkResult<()> {
+    let table = core_utils::allocate_route_table(netns)?;
+    for route in &data.ipam.routes {
+        let (dest, gw) = match route {
+            netlink::Route::Ipv4 { dest, gw, .. } => (IpNet::V4(*dest), IpAddr::V4(*gw)),
+            netlink::Route::Ipv6 { dest, gw, .. } => (IpNet::V6(*dest), IpAddr::V6(*gw)),
+        };
+        core_utils::add_route_table_rule(netns, table, gw.is_ipv6())?;
+        netns
+            .add_table_route(&netlink::TableRoute {
+                table,
+                dest,
+                gw: Some(gw),
+                oif: None,
+            })
+            .wrap("add route to the container routing table")?;
+    }
+    Ok(())
+}
+
+fn policy_rule(addr: &IpNet, table: u32) -> netlink::Rule {
+    netlink::Rule {
+        source: IpNet::from(addr.addr()),
+        table,
+        priority: POLICY_ROUTING_PRIORITY,
+    }
+}
+
+/// make sure the ip address is assigned to an interface on the host
+fn check_host_address(ip: IpAddr) -> NetavarkResult<()> {
+    let mut sock = netlink::Socket::new().wrap("host netlink socket")?;
+    let addresses = sock.dump_addresses().wrap("failed to get host addresses")?;
+    let octets = match ip {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    };
+    for addr in addresses {
+        for nla in addr.nlas {
+            if let address::Nla::Address(a) | address::Nla::Local(a) = nla {
+                if a == octets {
+                    return Ok(());
+                }
+            }
+        }
+    }
+    Err(NetavarkError::Message(format!(
+        "{} {} is not assigned to any host interface",
+        OPTION_SNAT_IP, ip
+    )))
+}
+
+/// program the pvid and tagged vlans on the container bridge port, without a
+/// pvid the port keeps the default pvid of the bridge
+fn setup_port_vlans(
+    host: &mut netlink::Socket,
+    data: &InternalData,
+    port_index: u32,
+) -> NetavarkResult<()> {
+    let bridge_pvid = data.default_pvid.unwrap_or(DEFAULT_BRIDGE_PVID);
+    if let Some(pvid) = data.pvid {
+        host.add_bridge_vlan(
+            port_index,
+            pvid,
+            BRIDGE_VLAN_INFO_PVID | BRIDGE_VLAN_INFO_UNTAGGED,
+        )
+        .wrap("set pvid on bridge port")?;
+        // The kernel adds the port to the default vlan of the bridge as
+        // untagged member, remove it so the container is only part of the
+        // requested vlans.
+        if pvid != bridge_pvid && !data.tagged_vlans.contains(&bridge_pvid) {
+            host.del_bridge_vlan(port_index, bridge_pvid)
+                .wrap("remove default vlan from bridge port")?;
+        }
+    }
+    for vid in &data.tagged_vlans {
+        host.add_bridge_vlan(port_index, *vid, 0)
+            .wrap(format!("add tagged vlan {} to bridge port", vid))?;
+    }
+    Ok(())
+}
+
 /// make sure the LinkMessage has the kind bridge
 fn check_link_is_bridge(msg: LinkMessage, br_name: &str) -> NetavarkResult<LinkMessage> {
     for nla in msg.nlas.iter() {
@@ -674,28 +1603,170 @@ fn check_link_is_bridge(msg: LinkMessage, br_name: &str) -> NetavarkResult<LinkM
     )))
 }
 
+/// The bridge options are only applied when the bridge is created, so an
+/// existing bridge must already have the vlan filtering, group_fwd_mask and
+/// bridge addresses the network asks for.
+fn check_existing_bridge(
+    host: &mut netlink::Socket,
+    bridge: &LinkMessage,
+    data: &InternalData,
+) -> NetavarkResult<()> {
+    let mut vlan_filtering = false;
+    let mut group_fwd_mask = 0;
+    for nla in bridge.nlas.iter() {
+        if let Nla::Info(info) = nla {
+            for inf in info.iter() {
+                if let Info::Data(InfoData::Bridge(opts)) = inf {
+                    for opt in opts.iter() {
+                        match opt {
+                            InfoBridge::VlanFiltering(v) => vlan_filtering = *v != 0,
+                            InfoBridge::GroupFwdMask(mask) => group_fwd_mask = *mask,
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+    let name = &data.bridge_interface_name;
+    if data.vlan_filtering && !vlan_filtering {
+        return Err(NetavarkError::Message(format!(
+            "bridge {} already exists without the {} option",
+            name, OPTION_VLAN_FILTERING
+        )));
+    }
+    if let Some(mask) = data.group_fwd_mask {
+        if mask != group_fwd_mask {
+            return Err(NetavarkError::Message(format!(
+                "bridge {} already exists with {} {:#x} instead of {:#x}",
+                name, OPTION_GROUP_FWD_MASK, group_fwd_mask, mask
+            )));
+        }
+    }
+    if data.bridge_addresses.is_empty() {
+        return Ok(());
+    }
+    let addresses = host
+        .dump_addresses()
+        .wrap("failed to get bridge addresses")?;
+    for addr in data.bridge_addresses.iter() {
+        let octets = match addr.addr() {
+            IpAddr::V4(v4) => v4.octets().to_vec(),
+            IpAddr::V6(v6) => v6.octets().to_vec(),
+        };
+        let assigned = addresses.iter().any(|msg| {
+            msg.header.index == bridge.header.index
+                && msg.header.prefix_len == addr.prefix_len()
+                && msg.nlas.iter().any(|nla| {
+                    matches!(nla, address::Nla::Address(a) | address::Nla::Local(a) if *a == octets)
+                })
+        });
+        if !assigned {
+            return Err(NetavarkError::Message(format!(
+                "bridge {} already exists without the {} {}",
+                name, OPTION_BRIDGE_ADDRESSES, addr
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The bridge addresses must not be in a subnet which is already routed on
+/// the host, e.g. the subnet of another network.
+fn check_bridge_addresses_unused(
+    host: &mut netlink::Socket,
+    addresses: &[IpNet],
+) -> NetavarkResult<()> {
+    if addresses.is_empty() {
+        return Ok(());
+    }
+    let subnets = core_utils::get_route_subnets(host)?;
+    for addr in addresses {
+        if let Some(subnet) = subnets.iter().find(|s| s.contains(&addr.addr())) {
+            return Err(NetavarkError::Message(format!(
+                "{} {} is in the subnet {} which is already used on the host",
+                OPTION_BRIDGE_ADDRESSES, addr, subnet
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Name of the dummy interface which keeps the carrier of the bridge up.
+fn carrier_dummy_name(br_name: &str) -> String {
+    format!("nvc-{}", CoreUtils::create_network_hash(br_name, 11))
+}
+
+/// Attach the carrier dummy to the bridge unless it already exists. A bridge
+/// only has carrier while one of its ports is up, the dummy always is.
+fn add_carrier_dummy(
+    host: &mut netlink::Socket,
+    br_name: &str,
+    bridge_index: u32,
+) -> NetavarkResult<()> {
+    let name = carrier_dummy_name(br_name);
+    match host.get_link(netlink::LinkID::Name(name.clone())) {
+        Ok(_) => return Ok(()),
+        Err(NetavarkError::Netlink(e)) if -e.raw_code() == libc::ENODEV => {}
+        Err(e) => return Err(e).wrap("get carrier dummy interface"),
+    }
+    let mut opts = netlink::CreateLinkOptions::new(name.clone(), InfoKind::Dummy);
+    opts.primary_index = bridge_index;
+    core_utils::wrap_netlink(
+        host.create_link(opts),
+        "create carrier dummy interface",
+        &name,
+        "host",
+    )?;
+    core_utils::wrap_netlink(
+        host.set_up(netlink::LinkID::Name(name.clone())),
+        "set carrier dummy interface up",
+        &name,
+        "host",
+    )
+}
+
 fn remove_link(
     host: &mut netlink::Socket,
     netns: &mut netlink::Socket,
     br_name: &str,
     container_veth_name: &str,
+    adopted: Option<&str>,
+    hostns_fd: RawFd,
 ) -> NetavarkResult<bool> {
-    netns
-        .del_link(netlink::LinkID::Name(container_veth_name.to_string()))
-        .wrap(format!(
-            "failed to delete container veth {}",
-            container_veth_name
-        ))?;
+    match adopted {
+        Some(name) => {
+            core_utils::release_adopted_interface(netns, container_veth_name, name, hostns_fd)?
+        }
+        None => netns
+            .del_link(netlink::LinkID::Name(container_veth_name.to_string()))
+            .wrap(format!(
+                "failed to delete container veth {}",
+                container_veth_name
+            ))?,
+    }
 
     let br = host
         .get_link(netlink::LinkID::Name(br_name.to_string()))
         .wrap("failed to get bridge interface")?;
 
-    let links = host
+    let dummy_name = carrier_dummy_name(br_name);
+    let mut links = host
         .dump_links(&mut vec![Nla::Master(br.header.index)])
         .wrap("failed to get connected bridge interfaces")?;
+    let port_count = links.len();
+    links.retain(|link| {
+        !link
+            .nlas
+            .iter()
+            .any(|nla| matches!(nla, Nla::IfName(name) if *name == dummy_name))
+    });
     // no connected interfaces on that bridge we can remove it
     if links.is_empty() {
+        if port_count > 0 {
+            host.del_link(netlink::LinkID::Name(dummy_name.clone()))
+                .wrap(format!("failed to delete carrier dummy {}", dummy_name))?;
+        }
         log::info!("removing bridge {}", br_name);
         host.del_link(netlink::LinkID::ID(br.header.index))
             .wrap(format!("failed to delete bridge {}", container_veth_name))?;
@@ -714,3 +1785,491 @@ fn get_isolate_option(opts: &Option<HashMap<String, String>>) -> NetavarkResult<
         _ => IsolateOption::Never,
     })
 }
+
+/// parse a veth queue count option, unset the kernel default is used
+fn get_queues_option(
+    opts: &Option<HashMap<String, String>>,
+    name: &str,
+) -> NetavarkResult<Option<u32>> {
+    let queues: u32 = match parse_option(opts, name)? {
+        Some(queues) => queues,
+        None => return Ok(None),
+    };
+    if !(1..=MAX_VETH_QUEUES).contains(&queues) {
+        return Err(NetavarkError::Message(format!(
+            "invalid {} {}, must be between 1 and {}",
+            name, queues, MAX_VETH_QUEUES
+        )));
+    }
+    Ok(Some(queues))
+}
+
+fn get_txqueuelen_option(opts: &Option<HashMap<String, String>>) -> NetavarkResult<u32> {
+    let len: u32 = match parse_option(opts, OPTION_TXQUEUELEN)? {
+        Some(len) => len,
+        None => return Ok(0),
+    };
+    if !(1..=MAX_TXQUEUELEN).contains(&len) {
+        return Err(NetavarkError::Message(format!(
+            "invalid {} {}, must be between 1 and {}",
+            OPTION_TXQUEUELEN, len, MAX_TXQUEUELEN
+        )));
+    }
+    Ok(len)
+}
+
+/// Parse the bridge_addresses option, a comma separated list of addresses with
+/// prefix length which are added to the bridge, e.g. for a management subnet.
+/// They must not be in the subnets of the network so they cannot collide with
+/// container addresses.
+fn get_bridge_addresses_option(network: &types::Network) -> NetavarkResult<Vec<IpNet>> {
+    let value: Option<String> = parse_option(&network.options, OPTION_BRIDGE_ADDRESSES)?;
+    let mut addresses: Vec<IpNet> = Vec::new();
+    for entry in value.iter().flat_map(|v| core_utils::split_option_list(v)) {
+        let addr: IpNet = match entry.parse() {
+            Ok(a) => a,
+            Err(e) => {
+                return Err(NetavarkError::Message(format!(
+                    "invalid {} entry {:?}: {}",
+                    OPTION_BRIDGE_ADDRESSES, entry, e
+                )))
+            }
+        };
+        if let Some(subnet) = network
+            .subnets
+            .iter()
+            .flatten()
+            .find(|s| s.subnet.contains(&addr.addr()))
+        {
+            return Err(NetavarkError::Message(format!(
+                "invalid {} entry {:?}: address is in the subnet {} of the network",
+                OPTION_BRIDGE_ADDRESSES, entry, subnet.subnet
+            )));
+        }
+        if !addresses.contains(&addr) {
+            addresses.push(addr);
+        }
+    }
+    Ok(addresses)
+}
+
+/// Parse the peer_address option, the container address of the same ip family
+/// is assigned with this point-to-point peer instead of a broadcast subnet. The
+/// container address must be a host address (/32, /128) or share a /31 (/127)
+/// with the peer.
+fn get_peer_address_option(
+    opts: &Option<HashMap<String, String>>,
+    container_addresses: &[IpNet],
+) -> NetavarkResult<Option<IpAddr>> {
+    let peer: IpAddr = match parse_option(opts, OPTION_PEER_ADDRESS)? {
+        Some(peer) => peer,
+        None => return Ok(None),
+    };
+    let mut found = false;
+    for addr in container_addresses
+        .iter()
+        .filter(|a| a.addr().is_ipv6() == peer.is_ipv6())
+    {
+        found = true;
+        let host_prefix = addr.max_prefix_len();
+        let valid = peer != addr.addr()
+            && (addr.prefix_len() == host_prefix
+                || (addr.prefix_len() == host_prefix - 1 && addr.contains(&peer)));
+        if !valid {
+            return Err(NetavarkError::Message(format!(
+                "invalid {} {}: container address {} must be a /{} or in the same /{} as the peer",
+                OPTION_PEER_ADDRESS,
+                peer,
+                addr,
+                host_prefix,
+                host_prefix - 1
+            )));
+        }
+    }
+    if !found {
+        return Err(NetavarkError::Message(format!(
+            "invalid {} {}: no container address of the same ip family",
+            OPTION_PEER_ADDRESS, peer
+        )));
+    }
+    Ok(Some(peer))
+}
+
+fn get_ip_conflict_check_option(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Option<ConflictAction>> {
+    let value: Option<String> = parse_option(opts, OPTION_IP_CONFLICT_CHECK)?;
+    match value.as_deref() {
+        None | Some("") | Some("off") => Ok(None),
+        Some("error") => Ok(Some(ConflictAction::Error)),
+        Some("warning") => Ok(Some(ConflictAction::Warn)),
+        Some(v) => Err(NetavarkError::Message(format!(
+            "invalid {} {:?}, must be error, warning or off",
+            OPTION_IP_CONFLICT_CHECK, v
+        ))),
+    }
+}
+
+/// Probe the ipv4 container addresses on the bridge before they are
+/// assigned. Without the bridge there is nobody else who could use them.
+fn check_ip_conflicts(
+    host: &mut netlink::Socket,
+    data: &InternalData,
+    action: ConflictAction,
+) -> NetavarkResult<()> {
+    let bridge = match host.get_link(netlink::LinkID::Name(
+        data.bridge_interface_name.to_string(),
+    )) {
+        Ok(bridge) => bridge,
+        Err(err) => match err.unwrap() {
+            NetavarkError::Netlink(e) if -e.raw_code() == libc::ENODEV => return Ok(()),
+            _ => return Err(err).wrap("get bridge interface"),
+        },
+    };
+    if bridge.header.flags & IFF_UP == 0 {
+        return Ok(());
+    }
+    let mac = bridge.nlas.iter().find_map(|nla| match nla {
+        Nla::Address(mac) => Some(mac.clone()),
+        _ => None,
+    });
+    let mac = match mac {
+        Some(mac) => mac,
+        None => return Ok(()),
+    };
+
+    for addr in &data.ipam.container_addresses {
+        let addr = match addr {
+            IpNet::V4(v4) => v4.addr(),
+            IpNet::V6(_) => continue,
+        };
+        let conflict = verify::arp_probe(bridge.header.index, &mac, addr, IP_CONFLICT_TIMEOUT)
+            .map_err(|e| {
+                NetavarkError::wrap(
+                    format!(
+                        "probe address {} on bridge {}",
+                        addr, data.bridge_interface_name
+                    ),
+                    e.into(),
+                )
+            })?;
+        if !conflict {
+            continue;
+        }
+        let msg = format!(
+            "ip address {} is already in use on bridge {}",
+            addr, data.bridge_interface_name
+        );
+        match action {
+            ConflictAction::Error => return Err(NetavarkError::Message(msg)),
+            ConflictAction::Warn => warnings::push(msg),
+        }
+    }
+    Ok(())
+}
+
+/// parse the neigh_gc_thresh options, the thresholds which are set must be
+/// positive and ascending like the kernel expects them
+fn get_neigh_gc_thresh_options(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<[Option<u32>; 3]> {
+    let mut thresh = [None; 3];
+    let mut previous: Option<(&str, u32)> = None;
+    for (i, name) in OPTION_NEIGH_GC_THRESH.iter().enumerate() {
+        let value: u32 = match parse_option(opts, name)? {
+            Some(v) => v,
+            None => continue,
+        };
+        if value == 0 {
+            return Err(NetavarkError::Message(format!(
+                "invalid {} 0, must be greater than 0",
+                name
+            )));
+        }
+        if let Some((prev_name, prev)) = previous {
+            if prev > value {
+                return Err(NetavarkError::Message(format!(
+                    "invalid {} {}, must not be lower than {} {}",
+                    name, value, prev_name, prev
+                )));
+            }
+        }
+        previous = Some((name, value));
+        thresh[i] = Some(value);
+    }
+    Ok(thresh)
+}
+
+/// The neighbor table size is not per interface, so the host wide default
+/// gc thresholds are raised to the given values. They are never lowered, the
+/// state restores the previous values once no network uses the options.
+fn raise_neigh_gc_thresh(thresh: &[Option<u32>; 3], ipv6: bool) -> NetavarkResult<()> {
+    let families: &[bool] = if ipv6 { &[false, true] } else { &[false] };
+    for family in families {
+        for (i, value) in thresh.iter().enumerate() {
+            let value = match value {
+                Some(v) => *v,
+                None => continue,
+            };
+            let name = core_utils::neigh_gc_thresh_sysctl(*family, i);
+            let current = sysctl::Ctl::new(&name)?.value_string()?;
+            let current: u32 = current.trim().parse().map_err(|e| {
+                NetavarkError::Message(format!("parse {} value {:?}: {}", name, current, e))
+            })?;
+            if current < value {
+                CoreUtils::apply_host_sysctl_value(&name, value.to_string())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Do not send or accept icmp redirects on the bridge, they could be used to
+/// misroute container traffic. The sysctls belong to the bridge so they are
+/// gone together with it on teardown.
+fn disable_icmp_redirects(bridge_name: &str, ipv6: bool) -> NetavarkResult<()> {
+    for name in ["accept_redirects", "send_redirects"] {
+        CoreUtils::apply_host_sysctl_value(
+            format!("/proc/sys/net/ipv4/conf/{}/{}", bridge_name, name),
+            "0",
+        )?;
+    }
+    if ipv6 {
+        CoreUtils::apply_host_sysctl_value(
+            format!("/proc/sys/net/ipv6/conf/{}/accept_redirects", bridge_name),
+            "0",
+        )?;
+    }
+    Ok(())
+}
+
+/// Parse the gro_flush_timeout and napi_defer_hard_irqs options, the values
+/// are written to sysfs of the container veth. The kernel rejects
+/// napi_defer_hard_irqs above i32::MAX.
+fn get_veth_sysfs_options(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Vec<(&'static str, String)>> {
+    let mut values = Vec::new();
+    let gro_flush_timeout: Option<u64> = parse_option(opts, OPTION_GRO_FLUSH_TIMEOUT)?;
+    if let Some(timeout) = gro_flush_timeout {
+        values.push((OPTION_GRO_FLUSH_TIMEOUT, timeout.to_string()));
+    }
+    let napi_defer_hard_irqs: Option<u32> = parse_option(opts, OPTION_NAPI_DEFER_HARD_IRQS)?;
+    if let Some(irqs) = napi_defer_hard_irqs {
+        if irqs > i32::MAX as u32 {
+            return Err(NetavarkError::Message(format!(
+                "invalid {} {}, must be at most {}",
+                OPTION_NAPI_DEFER_HARD_IRQS,
+                irqs,
+                i32::MAX
+            )));
+        }
+        values.push((OPTION_NAPI_DEFER_HARD_IRQS, irqs.to_string()));
+    }
+    Ok(values)
+}
+
+/// Parse the qdisc option, the name of the root qdisc of the container veth.
+fn get_qdisc_option(opts: &Option<HashMap<String, String>>) -> NetavarkResult<Option<String>> {
+    let qdisc: Option<String> = parse_option(opts, OPTION_QDISC)?;
+    match qdisc.as_deref() {
+        None | Some("") => Ok(None),
+        Some(name) if QDISCS.contains(&name) => Ok(qdisc),
+        Some(name) => Err(NetavarkError::Message(format!(
+            "invalid {} {:?}, must be one of {}",
+            OPTION_QDISC,
+            name,
+            QDISCS.join(", ")
+        ))),
+    }
+}
+
+/// Parse the group_fwd_mask option, decimal or hex with a 0x prefix.
+/// Bit n forwards frames sent to 01:80:C2:00:00:0n, e.g. 0x8 for 802.1X
+/// and 0x4000 for LLDP.
+fn get_group_fwd_mask_option(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Option<u16>> {
+    let value: String = match parse_option(opts, OPTION_GROUP_FWD_MASK)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    let mask = match parsed {
+        Ok(m) => m,
+        Err(e) => {
+            return Err(NetavarkError::Message(format!(
+                "invalid {} {:?}: {}",
+                OPTION_GROUP_FWD_MASK, value, e
+            )))
+        }
+    };
+    if mask & GROUP_FWD_MASK_RESTRICTED != 0 {
+        return Err(NetavarkError::Message(format!(
+            "invalid {} {:?}: STP, MAC pause and LACP frames (bits 0-2) cannot be forwarded",
+            OPTION_GROUP_FWD_MASK, value
+        )));
+    }
+    Ok(Some(mask))
+}
+
+/// parse the conntrack_zone option, "true" derives the zone from the network
+/// name so each network gets its own zone without configuring numbers
+fn get_conntrack_zone_option(
+    opts: &Option<HashMap<String, String>>,
+    network_name: &str,
+) -> NetavarkResult<Option<u16>> {
+    let value: String = match parse_option(opts, OPTION_CONNTRACK_ZONE)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    match value.as_str() {
+        "false" => Ok(None),
+        // setup replaces true with the zone allocated for the network, this
+        // is only reached without a recorded zone
+        "true" => Ok(Some(core_utils::conntrack_zone_from_name(network_name))),
+        zone => match zone.parse::<u16>() {
+            Ok(zone) if zone > 0 => Ok(Some(zone)),
+            _ => Err(NetavarkError::Message(format!(
+                "invalid {} \"{}\", must be true, false or a zone between 1 and 65535",
+                OPTION_CONNTRACK_ZONE, zone
+            ))),
+        },
+    }
+}
+
+/// parse the conn_limit and conn_rate_limit options, the limits are per
+/// container and apply to the connections it starts
+fn get_conn_limit_options(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<(Option<u32>, Option<u32>)> {
+    let conn_limit: Option<u32> = parse_option(opts, OPTION_CONN_LIMIT)?;
+    if conn_limit == Some(0) {
+        return Err(NetavarkError::Message(format!(
+            "invalid {} 0, must be at least 1",
+            OPTION_CONN_LIMIT
+        )));
+    }
+    let conn_rate_limit: Option<u32> = parse_option(opts, OPTION_CONN_RATE_LIMIT)?;
+    match conn_rate_limit {
+        Some(rate) if rate == 0 || rate > MAX_CONN_RATE_LIMIT => {
+            Err(NetavarkError::Message(format!(
+                "invalid {} {}, must be between 1 and {} connections per second",
+                OPTION_CONN_RATE_LIMIT, rate, MAX_CONN_RATE_LIMIT
+            )))
+        }
+        _ => Ok((conn_limit, conn_rate_limit)),
+    }
+}
+
+/// parse the dns_port option, the port a resolver run by the caller listens on
+/// at the gateway; the dns server of netavark always uses the global port
+fn get_dns_port_option(
+    opts: &Option<HashMap<String, String>>,
+    dns_enabled: bool,
+    netavark_dns_port: u16,
+) -> NetavarkResult<Option<u16>> {
+    match parse_option::<u16>(opts, OPTION_DNS_PORT)? {
+        Some(0) => Err(NetavarkError::Message(format!(
+            "invalid {} 0, must be between 1 and 65535",
+            OPTION_DNS_PORT
+        ))),
+        Some(port) if dns_enabled && port != netavark_dns_port => {
+            Err(NetavarkError::Message(format!(
+                "{} {} cannot be used with dns_enabled, the netavark dns server listens on port {}",
+                OPTION_DNS_PORT, port, netavark_dns_port
+            )))
+        }
+        port => Ok(port),
+    }
+}
+
+/// parse the rp_filter option, when unset containers with multiple networks
+/// use loose mode as their replies may leave through another interface
+fn get_rp_filter_option(
+    opts: &Option<HashMap<String, String>>,
+    network_count: usize,
+) -> NetavarkResult<Option<u8>> {
+    match parse_option::<u8>(opts, OPTION_RP_FILTER)? {
+        Some(mode) if mode > 2 => Err(NetavarkError::Message(format!(
+            "invalid {} mode {}, must be 0 (off), 1 (strict) or 2 (loose)",
+            OPTION_RP_FILTER, mode
+        ))),
+        Some(mode) => Ok(Some(mode)),
+        None if network_count > 1 => Ok(Some(2)),
+        None => Ok(None),
+    }
+}
+
+/// Whether setup creates dns rules for the network when the resolver listens
+/// on the given port, its options are checked like setup does. Managed non
+/// internal bridge networks with dns enabled get redirect rules, the ones with
+/// the dns_port option allow rules.
+pub fn has_dns_rules(network: &types::Network, dns_port: u16) -> NetavarkResult<bool> {
+    if network.driver != constants::DRIVER_BRIDGE
+        || network.internal
+        || get_mode_option(&network.options)?
+    {
+        return Ok(false);
+    }
+    let port_option = get_dns_port_option(&network.options, network.dns_enabled, dns_port)?;
+    Ok(network.dns_enabled || port_option.is_some())
+}
+
+/// parse the mode option, returns true when the bridge only switches
+/// between the containers
+fn get_mode_option(opts: &Option<HashMap<String, String>>) -> NetavarkResult<bool> {
+    let mode: Option<String> = parse_option(opts, OPTION_MODE)?;
+    match mode.as_deref() {
+        None | Some("") | Some("managed") => Ok(false),
+        Some("l2") => Ok(true),
+        Some(mode) => Err(NetavarkError::Message(format!(
+            "invalid bridge {} {:?}, must be managed or l2",
+            OPTION_MODE, mode
+        ))),
+    }
+}
+
+/// parse the default_policy option, returns true when forwarded connections
+/// into the network should be dropped by default
+fn get_default_policy_option(opts: &Option<HashMap<String, String>>) -> NetavarkResult<bool> {
+    let policy: Option<String> = parse_option(opts, OPTION_DEFAULT_POLICY)?;
+    match policy.as_deref() {
+        None | Some("") | Some("accept") => Ok(false),
+        Some("drop") => Ok(true),
+        Some(policy) => Err(NetavarkError::Message(format!(
+            "invalid {} {:?}, must be accept or drop",
+            OPTION_DEFAULT_POLICY, policy
+        ))),
+    }
+}
+
+/// parse the random_fully option, when unset the source ports are randomized
+/// if the kernel supports it
+fn get_random_fully_option(opts: &Option<HashMap<String, String>>) -> NetavarkResult<bool> {
+    let supported = core_utils::kernel_supports_random_fully();
+    match parse_option(opts, OPTION_RANDOM_FULLY)? {
+        Some(true) if !supported => {
+            warnings::push(format!(
+                "{} is not supported by the running kernel, source ports are not randomized",
+                OPTION_RANDOM_FULLY
+            ));
+            Ok(false)
+        }
+        Some(random_fully) => Ok(random_fully),
+        None => Ok(supported),
+    }
+}
+
+/// parse the mss_clamp option, when unset clamping is enabled for mtus
+/// below the ethernet default
+fn get_mss_clamp_option(opts: &Option<HashMap<String, String>>, mtu: u32) -> NetavarkResult<bool> {
+    let mss_clamp: Option<bool> = parse_option(opts, OPTION_MSS_CLAMP)?;
+    Ok(mss_clamp.unwrap_or(mtu != 0 && mtu < DEFAULT_MTU))
+}
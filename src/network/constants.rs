@@ -11,6 +11,7 @@ pub const IPAM_NONE: &str = "none";
 pub const DRIVER_BRIDGE: &str = "bridge";
 pub const DRIVER_IPVLAN: &str = "ipvlan";
 pub const DRIVER_MACVLAN: &str = "macvlan";
+pub const DRIVER_NONE: &str = "none";
 
 pub const OPTION_ISOLATE: &str = "isolate";
 pub const ISOLATE_OPTION_TRUE: &str = "true";
@@ -21,6 +22,72 @@ pub const OPTION_MODE: &str = "mode";
 pub const OPTION_METRIC: &str = "metric";
 pub const OPTION_NO_DEFAULT_ROUTE: &str = "no_default_route";
 pub const OPTION_BCLIM: &str = "bclim";
+pub const OPTION_VLAN_FILTERING: &str = "vlan_filtering";
+pub const OPTION_DEFAULT_PVID: &str = "default_pvid";
+pub const OPTION_SNAT_IP: &str = "snat_ip";
+pub const OPTION_DNS_SEARCH_DOMAINS: &str = "dns_search_domains";
+pub const OPTION_NO_SUBNET_ROUTE: &str = "no_subnet_route";
+pub const OPTION_POLICY_ROUTING: &str = "policy_routing";
+pub const OPTION_NUM_RX_QUEUES: &str = "num_rx_queues";
+pub const OPTION_NUM_TX_QUEUES: &str = "num_tx_queues";
+pub const OPTION_ALLOW_FROM: &str = "allow_from";
+pub const OPTION_ALLOW_TO: &str = "allow_to";
+pub const OPTION_GROUP_FWD_MASK: &str = "group_fwd_mask";
+pub const OPTION_MSS_CLAMP: &str = "mss_clamp";
+pub const OPTION_RP_FILTER: &str = "rp_filter";
+pub const OPTION_CONNTRACK_ZONE: &str = "conntrack_zone";
+pub const OPTION_CONNTRACK_HELPERS: &str = "conntrack_helpers";
+pub const OPTION_CARRIER_WAIT: &str = "carrier_wait";
+pub const OPTION_PARENT_WAIT: &str = "parent_wait";
+pub const OPTION_NO_LINK_UP: &str = "no_link_up";
+pub const OPTION_RESERVED_COUNT: &str = "reserved_count";
+pub const OPTION_EXCLUDE_IPS: &str = "exclude_ips";
+pub const OPTION_SYSCTLS: &str = "sysctls";
+pub const OPTION_IPV6_NAT: &str = "ipv6_nat";
+pub const OPTION_RANDOM_FULLY: &str = "random_fully";
+pub const OPTION_BRIDGE_ADDRESSES: &str = "bridge_addresses";
+pub const OPTION_IFALIAS: &str = "ifalias";
+pub const OPTION_KEEP_CARRIER: &str = "keep_carrier";
+pub const OPTION_MULTICAST_SNOOPING: &str = "multicast_snooping";
+pub const OPTION_MCAST_FLOOD: &str = "mcast_flood";
+pub const OPTION_IP_CONFLICT_CHECK: &str = "ip_conflict_check";
+pub const OPTION_DEFAULT_POLICY: &str = "default_policy";
+pub const OPTION_EGRESS_ONLY: &str = "egress_only";
+pub const OPTION_LOG_DROPS: &str = "log_drops";
+pub const OPTION_CONN_LIMIT: &str = "conn_limit";
+pub const OPTION_CONN_RATE_LIMIT: &str = "conn_rate_limit";
+pub const OPTION_DNS_PORT: &str = "dns_port";
+pub const OPTION_BLOCK_HOST_ACCESS: &str = "block_host_access";
+pub const OPTION_TXQUEUELEN: &str = "txqueuelen";
+pub const OPTION_QDISC: &str = "qdisc";
+pub const OPTION_ROUTE_TABLE: &str = "route_table";
+pub const OPTION_PARENT_NETNS: &str = "parent_netns";
+pub const OPTION_HOST_DNS: &str = "host_dns";
+pub const OPTION_GRO_FLUSH_TIMEOUT: &str = "gro_flush_timeout";
+pub const OPTION_NAPI_DEFER_HARD_IRQS: &str = "napi_defer_hard_irqs";
+pub const OPTION_PEER_ADDRESS: &str = "peer_address";
+pub const OPTION_ICMP_REDIRECTS: &str = "icmp_redirects";
+pub const OPTION_MAC_OUI: &str = "mac_oui";
+pub const OPTION_BLACKHOLE_ROUTES: &str = "blackhole_routes";
+pub const OPTION_NEIGH_GC_THRESH: [&str; 3] =
+    ["neigh_gc_thresh1", "neigh_gc_thresh2", "neigh_gc_thresh3"];
+
+// Docker network options translated for compatibility
+pub const DOCKER_OPTION_PREFIX: &str = "com.docker.network.";
+pub const DOCKER_OPTION_BRIDGE_NAME: &str = "com.docker.network.bridge.name";
+pub const DOCKER_OPTION_MTU: &str = "com.docker.network.driver.mtu";
+pub const DOCKER_OPTION_PARENT: &str = "parent";
+pub const DOCKER_OPTION_VLAN: &str = "vlan";
+
+/// upper bound in seconds for the carrier_wait option
+pub const MAX_CARRIER_WAIT: u32 = 60;
+
+/// upper bound in seconds for the parent_wait option
+pub const MAX_PARENT_WAIT: u32 = 60;
+
+/// The kernel limits the alias to IFALIASZ - 1 bytes including the nul
+/// byte the netlink library appends.
+pub const MAX_IFALIAS_LEN: usize = 254;
 
 /// 100 is the default metric for most Linux networking tools.
 pub const DEFAULT_METRIC: u32 = 100;
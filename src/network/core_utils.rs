@@ -1,15 +1,18 @@
 use crate::error::{ErrorWrap, NetavarkError, NetavarkResult};
-use crate::network::{constants, internal_types, types};
+use crate::network::{constants, internal_types, types, validation};
 use crate::wrap;
 use ipnet::IpNet;
 use log::debug;
 use netlink_packet_route::{
-    MACVLAN_MODE_BRIDGE, MACVLAN_MODE_PASSTHRU, MACVLAN_MODE_PRIVATE, MACVLAN_MODE_SOURCE,
-    MACVLAN_MODE_VEPA,
+    nlas::link::{Info, InfoKind, Nla as LinkNla},
+    rule::Nla as RuleNla,
+    LinkMessage, RuleMessage, AF_INET, AF_INET6, MACVLAN_MODE_BRIDGE, MACVLAN_MODE_PASSTHRU,
+    MACVLAN_MODE_PRIVATE, MACVLAN_MODE_SOURCE, MACVLAN_MODE_VEPA, RT_TABLE_MAIN,
 };
+use nix::mount::{mount, MsFlags};
 use nix::sched;
 use sha2::{Digest, Sha512};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::fmt::Display;
 use std::fs::File;
@@ -19,6 +22,8 @@ use std::net::Ipv4Addr;
 use std::net::Ipv6Addr;
 use std::os::unix::prelude::*;
 use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
 use sysctl::{Sysctl, SysctlError};
 
 use super::netlink;
@@ -68,6 +73,653 @@ where
     Ok(Some(val))
 }
 
+/// Parse the comma separated dns search domains network option.
+pub fn get_dns_search_domains(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Vec<String>> {
+    let domains: Option<String> = parse_option(opts, constants::OPTION_DNS_SEARCH_DOMAINS)?;
+    let mut result: Vec<String> = Vec::new();
+    for domain in domains.iter().flat_map(|d| d.split(',')) {
+        let domain = domain.trim();
+        if domain.is_empty() {
+            continue;
+        }
+        validation::validate_dns_name(domain)?;
+        if !result.iter().any(|d| d == domain) {
+            result.push(domain.to_string());
+        }
+    }
+    Ok(result)
+}
+
+/// Parse the ifalias network option, an empty alias is the same as none.
+pub fn get_ifalias_option(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Option<String>> {
+    let alias: Option<String> = parse_option(opts, constants::OPTION_IFALIAS)?;
+    match alias {
+        Some(alias) if alias.len() > constants::MAX_IFALIAS_LEN => {
+            Err(NetavarkError::Message(format!(
+                "invalid {} of {} bytes, must be at most {} bytes",
+                constants::OPTION_IFALIAS,
+                alias.len(),
+                constants::MAX_IFALIAS_LEN
+            )))
+        }
+        Some(alias) if alias.is_empty() => Ok(None),
+        alias => Ok(alias),
+    }
+}
+
+/// Get the mac address of the container interface. The static mac is used
+/// when set, it must be within the OUI of the mac_oui network option if that
+/// is given. Otherwise with the mac_oui option the address is derived from the
+/// network name and container id so it is stable across restarts. None lets
+/// the kernel pick a random address.
+pub fn get_container_mac(
+    opts: &Option<HashMap<String, String>>,
+    static_mac: &Option<String>,
+    network_name: &str,
+    container_id: &str,
+) -> NetavarkResult<Option<Vec<u8>>> {
+    let oui = get_mac_oui_option(opts)?;
+    match (static_mac, oui) {
+        (Some(mac), oui) => {
+            let mac = CoreUtils::decode_address_from_hex(mac)?;
+            if let Some(oui) = oui {
+                if !mac.starts_with(&oui) {
+                    return Err(NetavarkError::Message(format!(
+                        "static mac {} is not within the {} {}",
+                        CoreUtils::encode_address_to_hex(&mac),
+                        constants::OPTION_MAC_OUI,
+                        CoreUtils::encode_address_to_hex(&oui)
+                    )));
+                }
+            }
+            Ok(Some(mac))
+        }
+        (None, Some(oui)) => Ok(Some(generate_mac(oui, network_name, container_id))),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Parse the mac_oui network option, three hex bytes of a unicast address.
+fn get_mac_oui_option(opts: &Option<HashMap<String, String>>) -> NetavarkResult<Option<[u8; 3]>> {
+    let oui: String = match parse_option(opts, constants::OPTION_MAC_OUI)? {
+        Some(oui) => oui,
+        None => return Ok(None),
+    };
+    let invalid = || {
+        NetavarkError::Message(format!(
+            "invalid {} {:?}, must be three hex bytes of a unicast address like 02:42:ac",
+            constants::OPTION_MAC_OUI,
+            oui
+        ))
+    };
+    let bytes: Vec<u8> = oui
+        .split([':', '-'])
+        .map(|b| u8::from_str_radix(b, 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    match bytes[..] {
+        [a, b, c] if a & 1 == 0 => Ok(Some([a, b, c])),
+        _ => Err(invalid()),
+    }
+}
+
+/// Generate a mac address within the given OUI from a hash of the network
+/// name and container id, the same inputs always give the same address.
+pub fn generate_mac(oui: [u8; 3], network_name: &str, container_id: &str) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    hasher.update(format!("{}/{}", network_name, container_id).as_bytes());
+    let hash = hasher.finalize();
+    let mut mac = oui.to_vec();
+    mac.extend_from_slice(&hash[..3]);
+    mac
+}
+
+/// Parse the blackhole_routes network option, a comma separated list of
+/// subnets whose traffic is dropped inside the container.
+pub fn get_blackhole_routes_option(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Vec<IpNet>> {
+    let value: String = match parse_option(opts, constants::OPTION_BLACKHOLE_ROUTES)? {
+        Some(v) => v,
+        None => return Ok(Vec::new()),
+    };
+    split_option_list(&value)
+        .map(|entry| {
+            let net: IpNet = entry.parse().map_err(|e| {
+                NetavarkError::Message(format!(
+                    "invalid {} entry {:?}: {}",
+                    constants::OPTION_BLACKHOLE_ROUTES,
+                    entry,
+                    e
+                ))
+            })?;
+            if net != net.trunc() {
+                return Err(NetavarkError::Message(format!(
+                    "invalid {} entry {:?}: host bits are set, use {}",
+                    constants::OPTION_BLACKHOLE_ROUTES,
+                    entry,
+                    net.trunc()
+                )));
+            }
+            Ok(net)
+        })
+        .collect()
+}
+
+/// Values a namespaced sysctl accepts.
+#[derive(Clone, Copy)]
+enum SysctlValue {
+    Number,
+    /// two numbers separated by whitespace
+    Range,
+}
+
+/// Namespaced sysctls which can be set in the container. They only change
+/// the behaviour of the container namespace and cannot affect other networks.
+const NETNS_SYSCTLS: [(&str, SysctlValue); 14] = [
+    ("net.core.somaxconn", SysctlValue::Number),
+    ("net.ipv4.ip_local_port_range", SysctlValue::Range),
+    ("net.ipv4.ip_unprivileged_port_start", SysctlValue::Number),
+    ("net.ipv4.ping_group_range", SysctlValue::Range),
+    ("net.ipv4.tcp_fastopen", SysctlValue::Number),
+    ("net.ipv4.tcp_fin_timeout", SysctlValue::Number),
+    ("net.ipv4.tcp_keepalive_intvl", SysctlValue::Number),
+    ("net.ipv4.tcp_keepalive_probes", SysctlValue::Number),
+    ("net.ipv4.tcp_keepalive_time", SysctlValue::Number),
+    ("net.ipv4.tcp_mtu_probing", SysctlValue::Number),
+    ("net.ipv4.tcp_retries2", SysctlValue::Number),
+    ("net.ipv4.tcp_syn_retries", SysctlValue::Number),
+    ("net.ipv4.tcp_synack_retries", SysctlValue::Number),
+    ("net.ipv4.tcp_tw_reuse", SysctlValue::Number),
+];
+
+/// Check that the namespaced sysctl may be set to the value.
+fn validate_netns_sysctl(key: &str, val: &str) -> Result<(), String> {
+    if !key.starts_with("net.") {
+        return Err(format!(
+            "{} is not allowed, only net.* sysctls can be set",
+            key
+        ));
+    }
+    let kind = match NETNS_SYSCTLS.iter().find(|(name, _)| *name == key) {
+        Some((_, kind)) => *kind,
+        None => {
+            let names: Vec<&str> = NETNS_SYSCTLS.iter().map(|(name, _)| *name).collect();
+            return Err(format!(
+                "{} is not allowed, must be one of {}",
+                key,
+                names.join(", ")
+            ));
+        }
+    };
+    let numbers: Vec<&str> = val.split_whitespace().collect();
+    let count = match kind {
+        SysctlValue::Number => 1,
+        SysctlValue::Range => 2,
+    };
+    if numbers.len() != count {
+        return Err(match kind {
+            SysctlValue::Number => "value must be a number".to_string(),
+            SysctlValue::Range => "value must be two numbers separated by a space".to_string(),
+        });
+    }
+    for n in numbers {
+        if let Err(e) = n.parse::<u32>() {
+            return Err(format!("value must be a number: {}", e));
+        }
+    }
+    Ok(())
+}
+
+/// Get the sysctls for the container namespace, the comma separated
+/// key=value entries of the sysctls network option merged with the sysctls
+/// of the per network options, which take precedence.
+pub fn get_netns_sysctls(
+    opts: &Option<HashMap<String, String>>,
+    sysctls: &Option<HashMap<String, String>>,
+) -> NetavarkResult<Vec<(String, String)>> {
+    let invalid = |key: &str, val: &str, msg: String| {
+        NetavarkError::Message(format!(
+            "invalid {} entry \"{}={}\": {}",
+            constants::OPTION_SYSCTLS,
+            key,
+            val,
+            msg
+        ))
+    };
+
+    let mut merged = BTreeMap::new();
+    let value: Option<String> = parse_option(opts, constants::OPTION_SYSCTLS)?;
+    for entry in split_option_list(value.as_deref().unwrap_or_default()) {
+        match entry.split_once('=') {
+            Some((key, val)) => merged.insert(key.trim(), val.trim()),
+            None => {
+                return Err(NetavarkError::Message(format!(
+                    "invalid {} entry {:?}: must be key=value",
+                    constants::OPTION_SYSCTLS,
+                    entry
+                )))
+            }
+        };
+    }
+    for (key, val) in sysctls.iter().flatten() {
+        merged.insert(key.as_str(), val.as_str());
+    }
+
+    merged
+        .into_iter()
+        .map(|(key, val)| {
+            validate_netns_sysctl(key, val).map_err(|msg| invalid(key, val, msg))?;
+            Ok((key.to_string(), val.to_string()))
+        })
+        .collect()
+}
+
+/// Set the sysctls of get_netns_sysctls, must be called in the container namespace.
+pub fn apply_netns_sysctls(sysctls: &[(String, String)]) -> NetavarkResult<()> {
+    for (key, val) in sysctls {
+        CoreUtils::apply_sysctl_value(format!("/proc/sys/{}", key.replace('.', "/")), val)?;
+    }
+    Ok(())
+}
+
+/// Remove the blackhole routes of the network option, routes which no longer
+/// exist are ignored.
+pub fn remove_blackhole_routes(
+    netns: &mut netlink::Socket,
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<()> {
+    for dest in get_blackhole_routes_option(opts)? {
+        match netns.del_blackhole_route(&dest) {
+            Ok(_) => {}
+            Err(NetavarkError::Netlink(e)) if -e.raw_code() == libc::ESRCH => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// first table id the route_table option allocates in the container namespace
+const ROUTE_TABLE_START: u32 = 2000;
+/// priority of the rules looking up the route_table table, after the rules
+/// of the policy_routing option and before the main table
+pub const ROUTE_TABLE_PRIORITY: u32 = 200;
+
+/// Path of the host wide gc threshold sysctl of the neighbor table, index 0
+/// is gc_thresh1.
+pub fn neigh_gc_thresh_sysctl(ipv6: bool, index: usize) -> String {
+    let family = if ipv6 { "ipv6" } else { "ipv4" };
+    format!(
+        "/proc/sys/net/{}/neigh/default/gc_thresh{}",
+        family,
+        index + 1
+    )
+}
+
+/// Parse the route_table network option, the static routes of the network are
+/// added to a routing table of the container instead of the main table.
+pub fn get_route_table_option(opts: &Option<HashMap<String, String>>) -> NetavarkResult<bool> {
+    Ok(parse_option(opts, constants::OPTION_ROUTE_TABLE)?.unwrap_or(false))
+}
+
+fn route_table_rule(table: u32, ipv6: bool) -> netlink::Rule {
+    let source = if ipv6 {
+        IpNet::V6(Default::default())
+    } else {
+        IpNet::V4(Default::default())
+    };
+    netlink::Rule {
+        source,
+        table,
+        priority: ROUTE_TABLE_PRIORITY,
+    }
+}
+
+fn rule_table(rule: &RuleMessage) -> u32 {
+    rule.nlas
+        .iter()
+        .find_map(|nla| match nla {
+            RuleNla::Table(table) => Some(*table),
+            _ => None,
+        })
+        .unwrap_or(rule.header.table as u32)
+}
+
+fn rule_priority(rule: &RuleMessage) -> Option<u32> {
+    rule.nlas.iter().find_map(|nla| match nla {
+        RuleNla::Priority(priority) => Some(*priority),
+        _ => None,
+    })
+}
+
+/// Get the container routing table of the route_table option, it is the
+/// table the rules with ROUTE_TABLE_PRIORITY look up.
+pub fn find_route_table(netns: &mut netlink::Socket) -> NetavarkResult<Option<u32>> {
+    Ok(netns
+        .dump_rules()?
+        .iter()
+        .find(|rule| rule_priority(rule) == Some(ROUTE_TABLE_PRIORITY))
+        .map(rule_table))
+}
+
+/// Get the container routing table of the route_table option, all networks
+/// of the container share it. When the namespace has none yet the first table
+/// id which is not used by a route or rule is allocated, nothing is created
+/// until add_route_table_rule is called.
+pub fn allocate_route_table(netns: &mut netlink::Socket) -> NetavarkResult<u32> {
+    if let Some(table) = find_route_table(netns)? {
+        return Ok(table);
+    }
+    let mut used: HashSet<u32> = netns.dump_rules()?.iter().map(rule_table).collect();
+    for route in netns.dump_routes()? {
+        let table = route
+            .nlas
+            .iter()
+            .find_map(|nla| match nla {
+                netlink_packet_route::route::Nla::Table(table) => Some(*table),
+                _ => None,
+            })
+            .unwrap_or(route.header.table as u32);
+        used.insert(table);
+    }
+    match (ROUTE_TABLE_START..u32::MAX).find(|table| !used.contains(table)) {
+        Some(table) => Ok(table),
+        None => Err(NetavarkError::msg("no free routing table in the container")),
+    }
+}
+
+/// The conntrack zone derived from the network name, used for the
+/// conntrack_zone=true option when no zone was recorded for the network.
+pub fn conntrack_zone_from_name(network_name: &str) -> u16 {
+    // the hash is hex so it always fits, zone 0 is the default
+    // zone shared by everything else
+    let hash = CoreUtils::create_network_hash(network_name, 4);
+    u16::from_str_radix(&hash, 16).unwrap_or_default().max(1)
+}
+
+/// Add the rule looking up the container routing table for the ip family,
+/// an existing rule is kept.
+pub fn add_route_table_rule(
+    netns: &mut netlink::Socket,
+    table: u32,
+    ipv6: bool,
+) -> NetavarkResult<()> {
+    let family = if ipv6 { AF_INET6 } else { AF_INET } as u8;
+    let exists = netns.dump_rules()?.iter().any(|rule| {
+        rule.header.family == family && rule_priority(rule) == Some(ROUTE_TABLE_PRIORITY)
+    });
+    if exists {
+        return Ok(());
+    }
+    netns
+        .add_rule(&route_table_rule(table, ipv6))
+        .wrap("add route table rule")
+}
+
+/// Remove the rules of the container routing table, the routes in it are
+/// gone together with the interfaces. Rules which no longer exist are ignored.
+pub fn remove_route_table_rules(netns: &mut netlink::Socket, table: u32) -> NetavarkResult<()> {
+    for ipv6 in [false, true] {
+        match netns.del_rule(&route_table_rule(table, ipv6)) {
+            Ok(_) => {}
+            Err(NetavarkError::Netlink(e)) if -e.raw_code() == libc::ENOENT => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Get the major and minor version of a kernel release like 6.1.0-13-amd64.
+pub fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Fully randomized source ports for nat were added in linux 3.13.
+pub fn kernel_supports_random_fully() -> bool {
+    match nix::sys::utsname::uname() {
+        Ok(uts) => matches!(
+            parse_kernel_version(&uts.release().to_string_lossy()),
+            Some(version) if version >= (3, 13)
+        ),
+        Err(e) => {
+            debug!("failed to get kernel version: {}", e);
+            false
+        }
+    }
+}
+
+/// Split a comma separated network option into its non empty entries.
+pub fn split_option_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(|v| v.trim()).filter(|v| !v.is_empty())
+}
+
+/// Parse the allow_from and allow_to network options. Network names must
+/// already be replaced by their subnets, see state::resolve_allowed_networks.
+pub fn get_allowed_networks(
+    opts: &Option<HashMap<String, String>>,
+) -> NetavarkResult<internal_types::AllowedNetworks> {
+    let parse = |name: &str| -> NetavarkResult<Vec<IpNet>> {
+        let value: Option<String> = parse_option(opts, name)?;
+        let mut result: Vec<IpNet> = Vec::new();
+        for entry in value.iter().flat_map(|v| split_option_list(v)) {
+            let net: IpNet = match entry.parse() {
+                Ok(n) => n,
+                Err(_) => {
+                    return Err(NetavarkError::Message(format!(
+                        "invalid {} entry {:?}: unknown network",
+                        name, entry
+                    )))
+                }
+            };
+            // use the network address, iptables would do the same
+            let net = net.trunc();
+            if !result.contains(&net) {
+                result.push(net);
+            }
+        }
+        Ok(result)
+    };
+    Ok(internal_types::AllowedNetworks {
+        from: parse(constants::OPTION_ALLOW_FROM)?,
+        to: parse(constants::OPTION_ALLOW_TO)?,
+    })
+}
+
+/// Add the domains to the dns search domains of the status block, skipping duplicates.
+pub fn add_dns_search_domains(status: &mut types::StatusBlock, domains: &[String]) {
+    let search = status.dns_search_domains.get_or_insert_with(Vec::new);
+    for domain in domains {
+        if !search.contains(domain) {
+            search.push(domain.clone());
+        }
+    }
+}
+
+/// Get the gateways of the assigned addresses without duplicates, None if
+/// there is no gateway.
+pub fn get_gateways(addresses: &[types::NetAddress]) -> Option<Vec<IpAddr>> {
+    let mut gateways = Vec::new();
+    for gw in addresses.iter().filter_map(|a| a.gateway) {
+        if !gateways.contains(&gw) {
+            gateways.push(gw);
+        }
+    }
+    if gateways.is_empty() {
+        None
+    } else {
+        Some(gateways)
+    }
+}
+
+/// Get the first address of the subnet which is not in use. The network
+/// address, the ipv4 broadcast address and the gateway are never returned,
+/// neither are the first `reserved` addresses after them.
+/// If the subnet has a lease range only addresses in that range are used.
+pub fn get_free_ip(
+    subnet: &types::Subnet,
+    used: &HashSet<IpAddr>,
+    reserved: u32,
+) -> NetavarkResult<Option<IpAddr>> {
+    let parse = |ip: &Option<String>| -> NetavarkResult<Option<IpAddr>> {
+        match ip {
+            Some(ip) => match ip.parse() {
+                Ok(ip) => Ok(Some(ip)),
+                Err(e) => Err(NetavarkError::Message(format!(
+                    "invalid lease range ip {}: {}",
+                    ip, e
+                ))),
+            },
+            None => Ok(None),
+        }
+    };
+    let (start, end) = match &subnet.lease_range {
+        Some(range) => (parse(&range.start_ip)?, parse(&range.end_ip)?),
+        None => (None, None),
+    };
+
+    let network = subnet.subnet.network();
+    let mut hosts = subnet
+        .subnet
+        .hosts()
+        .filter(|ip| *ip != network && Some(*ip) != subnet.gateway)
+        .skip(reserved as usize)
+        .peekable();
+    if reserved > 0 && hosts.peek().is_none() {
+        return Err(NetavarkError::Message(format!(
+            "invalid {} {}: no addresses left in subnet {}",
+            constants::OPTION_RESERVED_COUNT,
+            reserved,
+            subnet.subnet
+        )));
+    }
+    Ok(hosts.find(|ip| {
+        !used.contains(ip)
+            && !matches!(start, Some(start) if *ip < start)
+            && !matches!(end, Some(end) if *ip > end)
+    }))
+}
+
+/// Pool from which bridge networks without subnets get their subnet.
+pub const DEFAULT_SUBNET_POOL_ENV: &str = "NETAVARK_DEFAULT_SUBNET_POOL";
+/// Prefix length of the subnets taken from the pool.
+pub const DEFAULT_SUBNET_SIZE_ENV: &str = "NETAVARK_DEFAULT_SUBNET_SIZE";
+const DEFAULT_SUBNET_POOL: &str = "10.88.0.0/16";
+const DEFAULT_SUBNET_SIZE: u8 = 24;
+
+/// Get the pool and the prefix length of the subnets for bridge networks
+/// without subnets. The subnets need room for a gateway and a container.
+pub fn get_default_subnet_pool() -> NetavarkResult<(IpNet, u8)> {
+    let value =
+        env::var(DEFAULT_SUBNET_POOL_ENV).unwrap_or_else(|_| DEFAULT_SUBNET_POOL.to_string());
+    let pool = match value.parse::<IpNet>() {
+        Ok(pool) => pool.trunc(),
+        Err(e) => {
+            return Err(NetavarkError::Message(format!(
+                "invalid {} value {:?}: {}",
+                DEFAULT_SUBNET_POOL_ENV, value, e
+            )))
+        }
+    };
+    let max_size = pool.max_prefix_len() - 2;
+    let size = match env::var(DEFAULT_SUBNET_SIZE_ENV) {
+        Ok(value) => match value.parse::<u8>() {
+            Ok(size) if size >= pool.prefix_len() && size <= max_size => size,
+            _ => {
+                return Err(NetavarkError::Message(format!(
+                    "invalid {} value {:?}, must be a prefix length between {} and {}",
+                    DEFAULT_SUBNET_SIZE_ENV,
+                    value,
+                    pool.prefix_len(),
+                    max_size
+                )))
+            }
+        },
+        Err(_) => DEFAULT_SUBNET_SIZE.clamp(pool.prefix_len(), max_size),
+    };
+    Ok((pool, size))
+}
+
+/// Get the first subnet of the pool with the given prefix length which does
+/// not overlap any of the used subnets.
+pub fn get_free_subnet(pool: IpNet, size: u8, used: &[IpNet]) -> Option<IpNet> {
+    pool.subnets(size).ok()?.find(|subnet| {
+        !used
+            .iter()
+            .any(|u| u.contains(&subnet.network()) || subnet.contains(&u.network()))
+    })
+}
+
+/// Get the destinations of the routes in the main table, except the default
+/// routes, so picked subnets do not collide with networks the host reaches.
+pub fn get_route_subnets(sock: &mut netlink::Socket) -> NetavarkResult<Vec<IpNet>> {
+    let mut subnets = Vec::new();
+    for route in sock.dump_routes()? {
+        let prefix_len = route.header.destination_prefix_length;
+        if prefix_len == 0 {
+            continue;
+        }
+        for nla in route.nlas {
+            let bytes = match nla {
+                netlink_packet_route::route::Nla::Destination(bytes) => bytes,
+                _ => continue,
+            };
+            let addr = if let Ok(v4) = <[u8; 4]>::try_from(bytes.as_slice()) {
+                IpAddr::from(v4)
+            } else if let Ok(v6) = <[u8; 16]>::try_from(bytes.as_slice()) {
+                IpAddr::from(v6)
+            } else {
+                continue;
+            };
+            if let Ok(subnet) = IpNet::new(addr, prefix_len) {
+                subnets.push(subnet.trunc());
+            }
+        }
+    }
+    Ok(subnets)
+}
+
+/// Parse the exclude_ips network option, these addresses are never allocated.
+/// Every address must be in one of the subnets of the network.
+pub fn get_exclude_ips_option(network: &types::Network) -> NetavarkResult<HashSet<IpAddr>> {
+    let value: String = match parse_option(&network.options, constants::OPTION_EXCLUDE_IPS)? {
+        Some(v) => v,
+        None => return Ok(HashSet::new()),
+    };
+    split_option_list(&value)
+        .map(|entry| {
+            let ip: IpAddr = entry.parse().map_err(|e| {
+                NetavarkError::Message(format!(
+                    "invalid {} entry {:?}: {}",
+                    constants::OPTION_EXCLUDE_IPS,
+                    entry,
+                    e
+                ))
+            })?;
+            if !network
+                .subnets
+                .iter()
+                .flatten()
+                .any(|s| s.subnet.contains(&ip))
+            {
+                return Err(NetavarkError::Message(format!(
+                    "invalid {} entry {:?}: not in a subnet of network {}",
+                    constants::OPTION_EXCLUDE_IPS,
+                    entry,
+                    network.name
+                )));
+            }
+            Ok(ip)
+        })
+        .collect()
+}
+
 pub fn get_ipam_addresses<'a>(
     per_network_opts: &'a types::PerNetworkOptions,
     network: &'a types::Network,
@@ -92,15 +744,17 @@ pub fn get_ipam_addresses<'a>(
             // nameservers which can be configured for this container
             let mut nameservers: Vec<IpAddr> = Vec::new();
 
-            let static_ips = match per_network_opts.static_ips.as_ref() {
-                None => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "no static ips provided",
-                    ))
-                }
-                Some(i) => i,
-            };
+            let static_ips = per_network_opts.static_ips.as_deref().unwrap_or_default();
+            let static_ips_by_subnet = per_network_opts
+                .static_ips_by_subnet
+                .as_ref()
+                .filter(|ips| !ips.is_empty());
+            if static_ips.is_empty() && static_ips_by_subnet.is_none() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "no static ips provided",
+                ));
+            }
 
             // prepare a vector of static aps with appropriate cidr
             for (idx, subnet) in network.subnets.iter().flatten().enumerate() {
@@ -127,9 +781,29 @@ pub fn get_ipam_addresses<'a>(
                     ipv6_enabled = true;
                 }
 
+                let static_ip = match static_ips_by_subnet {
+                    Some(ips) => ips.get(&subnet.subnet),
+                    None => static_ips.get(idx),
+                };
+                let static_ip = match static_ip {
+                    Some(ip) => *ip,
+                    None => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::Other,
+                            format!("no static ip provided for subnet {}", subnet.subnet),
+                        ))
+                    }
+                };
+                if static_ips_by_subnet.is_some() && !subnet.subnet.contains(&static_ip) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        format!("static ip {} is not in subnet {}", static_ip, subnet.subnet),
+                    ));
+                }
+
                 // Build up response information
                 let container_address: ipnet::IpNet =
-                    match format!("{}/{}", static_ips[idx], subnet_mask_cidr).parse() {
+                    match format!("{}/{}", static_ip, subnet_mask_cidr).parse() {
                         Ok(i) => i,
                         Err(e) => {
                             return Err(Error::new(std::io::ErrorKind::Other, e));
@@ -287,6 +961,357 @@ impl CoreUtils {
         }
         ctl.set_value_string(val)
     }
+
+    /// Set a sysctl of the host like apply_sysctl_value, a change of the value
+    /// is recorded so setup can report it.
+    pub fn apply_host_sysctl_value(
+        ns_value: impl AsRef<str>,
+        val: impl AsRef<str>,
+    ) -> Result<String, SysctlError> {
+        let ctl = sysctl::Ctl::new(ns_value.as_ref())?;
+        let old_value = ctl.value_string()?;
+        let result = Self::apply_sysctl_value(ns_value, &val)?;
+        if old_value != val.as_ref() {
+            if let Ok(mut changes) = SYSCTL_CHANGES.lock() {
+                changes.push(types::SysctlChange {
+                    name: ctl.name()?,
+                    old_value,
+                    new_value: val.as_ref().to_string(),
+                });
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// host sysctls changed by apply_host_sysctl_value since the last take
+static SYSCTL_CHANGES: Mutex<Vec<types::SysctlChange>> = Mutex::new(Vec::new());
+
+/// Return the recorded host sysctl changes and clear them.
+pub fn take_sysctl_changes() -> Vec<types::SysctlChange> {
+    match SYSCTL_CHANGES.lock() {
+        Ok(mut changes) => std::mem::take(&mut *changes),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// sysctl enabling the automatic conntrack helper assignment, it only exists
+/// on kernels before 6.0 and is off by default since 4.7
+pub const CONNTRACK_HELPER_SYSCTL: &str = "/proc/sys/net/netfilter/nf_conntrack_helper";
+
+/// iptables has no way to keep the kernel from assigning a helper to a
+/// connection, CT --helper can only assign one. So all we can do is warn
+/// when the automatic assignment is enabled on the host.
+pub fn conntrack_helper_warning(sysctl_path: &str) -> Option<String> {
+    match std::fs::read_to_string(sysctl_path) {
+        Ok(value) if value.trim() == "1" => Some(
+            "automatic conntrack helper assignment is enabled (net.netfilter.nf_conntrack_helper=1), \
+             helpers like ftp and sip are applied to container traffic"
+                .to_string(),
+        ),
+        _ => None,
+    }
+}
+
+pub const HOST_RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// Parse the host_dns network option, it is ignored for internal networks as
+/// the host nameservers cannot be reached from them.
+pub fn get_host_dns_option(network: &types::Network) -> NetavarkResult<bool> {
+    let host_dns: bool =
+        parse_option(&network.options, constants::OPTION_HOST_DNS)?.unwrap_or(false);
+    Ok(host_dns && !network.internal)
+}
+
+/// Get the nameservers of the host resolv.conf for networks with the host_dns
+/// option. Loopback servers, e.g. the stub resolver of systemd-resolved, cannot
+/// be reached from the container and are skipped like malformed entries.
+/// Problems reading the file are only warnings, the container still works
+/// without nameservers.
+pub fn get_host_nameservers(path: &str) -> Vec<IpAddr> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            crate::warnings::push(format!(
+                "failed to read host nameservers from {}: {}",
+                path, e
+            ));
+            return Vec::new();
+        }
+    };
+    let mut servers = Vec::new();
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        if fields.next() != Some("nameserver") {
+            continue;
+        }
+        match fields.next().map(|f| f.parse::<IpAddr>()) {
+            Some(Ok(ip)) if !ip.is_loopback() && !ip.is_unspecified() => {
+                if !servers.contains(&ip) {
+                    servers.push(ip);
+                }
+            }
+            _ => debug!("skipping host nameserver line {:?}", line),
+        }
+    }
+    if servers.is_empty() {
+        crate::warnings::push(format!(
+            "no nameservers usable from the container found in {}",
+            path
+        ));
+    }
+    servers
+}
+
+/// Write sysfs attributes of an interface in the container namespace, e.g.
+/// gro_flush_timeout. The sysfs mounted on the host only shows the host
+/// interfaces, so a thread joins the namespace and mounts its own sysfs in a
+/// private mount namespace which is gone when the thread exits. Attributes the
+/// kernel does not have are skipped with a warning.
+pub fn set_netns_sysfs_values(
+    netns_fd: RawFd,
+    if_name: &str,
+    values: &[(&str, String)],
+) -> NetavarkResult<()> {
+    if values.is_empty() {
+        return Ok(());
+    }
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            join_netns(netns_fd)?;
+            sched::unshare(sched::CloneFlags::CLONE_NEWNS).map_err(|e| {
+                NetavarkError::wrap("unshare mount namespace", io::Error::from(e).into())
+            })?;
+            // do not propagate our sysfs mount back to the host
+            mount::<str, str, str, str>(
+                None,
+                "/",
+                None,
+                MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+                None,
+            )
+            .map_err(|e| NetavarkError::wrap("make mounts private", io::Error::from(e).into()))?;
+            mount::<str, str, str, str>(
+                Some("sysfs"),
+                "/sys",
+                Some("sysfs"),
+                MsFlags::empty(),
+                None,
+            )
+            .map_err(|e| NetavarkError::wrap("mount sysfs", io::Error::from(e).into()))?;
+            for (name, value) in values {
+                let path = format!("/sys/class/net/{}/{}", if_name, name);
+                debug!("Setting {} to {}", path, value);
+                match std::fs::write(&path, value) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        crate::warnings::push(format!(
+                            "{} is not supported by the kernel, not setting it on {}",
+                            name, if_name
+                        ));
+                    }
+                    Err(e) => return Err(NetavarkError::wrap(format!("set {}", path), e.into())),
+                }
+            }
+            Ok(())
+        })
+        .join()
+        .unwrap_or_else(|_| Err(NetavarkError::msg("sysfs thread panicked")))
+    })
+}
+
+/// Number of attempts to move an interface into the container namespace.
+pub const NETNS_MOVE_ATTEMPTS_ENV: &str = "NETAVARK_NETNS_MOVE_ATTEMPTS";
+const DEFAULT_NETNS_MOVE_ATTEMPTS: u32 = 3;
+/// Delay before the first retry, it grows with each attempt.
+const NETNS_MOVE_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+pub fn get_netns_move_attempts() -> NetavarkResult<u32> {
+    match env::var(NETNS_MOVE_ATTEMPTS_ENV) {
+        Ok(value) => match value.parse::<u32>() {
+            Ok(attempts) if attempts > 0 => Ok(attempts),
+            _ => Err(NetavarkError::Message(format!(
+                "invalid {} value {:?}, must be a number greater than 0",
+                NETNS_MOVE_ATTEMPTS_ENV, value
+            ))),
+        },
+        Err(_) => Ok(DEFAULT_NETNS_MOVE_ATTEMPTS),
+    }
+}
+
+/// Run an operation moving the interface into the container namespace, i.e.
+/// creating a link with IFLA_NET_NS_FD. The kernel returns EBUSY while the
+/// interface is still being set up, so the operation is retried for up to
+/// attempts times. Other errors are returned right away.
+pub fn retry_netns_move<T>(
+    if_name: &str,
+    attempts: u32,
+    mut op: impl FnMut() -> NetavarkResult<T>,
+) -> NetavarkResult<T> {
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(NetavarkError::Netlink(ref e))
+                if -e.raw_code() == libc::EBUSY && attempt < attempts =>
+            {
+                debug!(
+                    "moving {} into the container namespace returned EBUSY, attempt {} of {}",
+                    if_name, attempt, attempts
+                );
+                std::thread::sleep(NETNS_MOVE_RETRY_DELAY * attempt);
+                attempt += 1;
+            }
+            Err(NetavarkError::Netlink(e)) if -e.raw_code() == libc::EBUSY && attempt > 1 => {
+                return Err(NetavarkError::wrap(
+                    format!(
+                        "move {} into the container namespace: still busy after {} attempts",
+                        if_name, attempt
+                    ),
+                    NetavarkError::Netlink(e),
+                ))
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Check that the host interface can be adopted by a container. It must not
+/// be a bridge, a port of a bridge or bond, or a veth whose peer is in another
+/// namespace, i.e. the host side of the interface of another container.
+pub fn check_adoptable_interface(link: &LinkMessage, name: &str) -> NetavarkResult<()> {
+    let mut kind = None;
+    for nla in link.nlas.iter() {
+        match nla {
+            LinkNla::Master(_) => {
+                return Err(NetavarkError::Message(format!(
+                    "interface {} can not be adopted, it is attached to another interface",
+                    name
+                )))
+            }
+            LinkNla::NetnsId(_) => {
+                return Err(NetavarkError::Message(format!(
+                    "interface {} can not be adopted, it is connected to another namespace",
+                    name
+                )))
+            }
+            LinkNla::Info(info) => {
+                for inf in info.iter() {
+                    if let Info::Kind(k) = inf {
+                        kind = Some(k);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if kind == Some(&InfoKind::Bridge) {
+        return Err(NetavarkError::Message(format!(
+            "interface {} can not be adopted, it is a bridge",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Move the existing host interface into the container namespace and rename
+/// it to the container interface name, returns the link in the namespace.
+/// If the rename fails the interface is moved back to the host.
+pub fn adopt_interface(
+    host: &mut netlink::Socket,
+    netns: &mut netlink::Socket,
+    name: &str,
+    container_if_name: &str,
+    hostns_fd: RawFd,
+    netns_fd: RawFd,
+) -> NetavarkResult<LinkMessage> {
+    let link = match host.get_link(netlink::LinkID::Name(name.to_string())) {
+        Ok(link) => link,
+        Err(NetavarkError::Netlink(e)) if -e.raw_code() == libc::ENODEV => {
+            return Err(NetavarkError::Message(format!(
+                "interface {} to adopt does not exist",
+                name
+            )))
+        }
+        Err(err) => return Err(err).wrap(format!("get interface {} to adopt", name)),
+    };
+    check_adoptable_interface(&link, name)?;
+
+    // moving the interface sets it down, it must be down to be renamed
+    wrap_netlink(
+        host.set_link_ns(link.header.index, netns_fd),
+        "move adopted interface",
+        name,
+        "host",
+    )?;
+    let link = wrap_netlink(
+        netns.get_link(netlink::LinkID::Name(name.to_string())),
+        "get adopted interface",
+        name,
+        "container",
+    )?;
+    if let Err(err) = netns.set_link_name(link.header.index, container_if_name.to_string()) {
+        if let Err(e) = netns.set_link_ns(link.header.index, hostns_fd) {
+            log::error!("failed to move adopted interface {} back: {}", name, e);
+        }
+        return Err(NetavarkError::wrap(
+            format!("rename adopted interface {} to {}", name, container_if_name),
+            err,
+        ));
+    }
+    wrap_netlink(
+        netns.get_link(netlink::LinkID::ID(link.header.index)),
+        "get adopted interface",
+        container_if_name,
+        "container",
+    )
+}
+
+/// Rename the adopted interface back to its host name and move it back into
+/// the host namespace. The kernel removes the container addresses and routes.
+pub fn release_adopted_interface(
+    netns: &mut netlink::Socket,
+    container_if_name: &str,
+    name: &str,
+    hostns_fd: RawFd,
+) -> NetavarkResult<()> {
+    let link = netns
+        .get_link(netlink::LinkID::Name(container_if_name.to_string()))
+        .wrap(format!(
+            "failed to get adopted interface {}",
+            container_if_name
+        ))?;
+    netns
+        .set_down(netlink::LinkID::ID(link.header.index))
+        .wrap(format!(
+            "failed to set adopted interface {} down",
+            container_if_name
+        ))?;
+    netns
+        .set_link_name(link.header.index, name.to_string())
+        .wrap(format!(
+            "failed to rename adopted interface {} to {}",
+            container_if_name, name
+        ))?;
+    netns
+        .set_link_ns(link.header.index, hostns_fd)
+        .wrap(format!(
+            "failed to move adopted interface {} to the host",
+            name
+        ))
+}
+
+/// Add the netlink operation and the interface and namespace it was done on to
+/// the error, e.g. "add ip addr to container veth on eth0 in container namespace".
+pub fn wrap_netlink<T>(
+    result: NetavarkResult<T>,
+    op: &str,
+    interface: &str,
+    namespace: &str,
+) -> NetavarkResult<T> {
+    result.wrap(format!(
+        "{} on {} in {} namespace",
+        op, interface, namespace
+    ))
 }
 
 pub fn join_netns(fd: RawFd) -> NetavarkResult<()> {
@@ -350,6 +1375,22 @@ pub fn open_netlink_sockets(
     ))
 }
 
+/// Open the network namespace at the path and a netlink socket in it.
+pub fn open_netns(netns_path: &str, hostns_fd: RawFd) -> NetavarkResult<NamespaceOptions> {
+    let (file, fd) = open_netlink_socket(netns_path)?;
+    exec_netns!(
+        hostns_fd,
+        fd,
+        res,
+        netlink::Socket::new().wrap(format!("netlink socket in {}", netns_path))
+    );
+    Ok(NamespaceOptions {
+        file,
+        fd,
+        netlink: res?,
+    })
+}
+
 fn open_netlink_socket(netns_path: &str) -> NetavarkResult<(File, RawFd)> {
     let ns = wrap!(File::open(netns_path), format!("open {}", netns_path))?;
     let ns_fd = ns.as_raw_fd();
@@ -396,6 +1437,56 @@ pub fn add_default_routes(
     Ok(())
 }
 
+/// Make sure the container is still routable without the connected subnet
+/// routes, we need a gateway for each address to route everything via it.
+pub fn validate_no_subnet_route(
+    network: &types::Network,
+    ipam: &internal_types::IPAMAddresses,
+    no_default_route: bool,
+) -> NetavarkResult<()> {
+    if network.internal || no_default_route {
+        return Err(NetavarkError::msg(format!(
+            "{} requires a default route, it cannot be used with internal networks or {}",
+            constants::OPTION_NO_SUBNET_ROUTE,
+            constants::OPTION_NO_DEFAULT_ROUTE
+        )));
+    }
+    for addr in &ipam.container_addresses {
+        if !ipam
+            .gateway_addresses
+            .iter()
+            .any(|gw| gw.contains(&addr.addr()))
+        {
+            return Err(NetavarkError::Message(format!(
+                "{} requires a gateway for the subnet of {}",
+                constants::OPTION_NO_SUBNET_ROUTE,
+                addr
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Add link scoped host routes for the gateways, they are needed to reach
+/// the gateways when the addresses were added without subnet route.
+pub fn add_gateway_host_routes(
+    sock: &mut netlink::Socket,
+    link_index: u32,
+    gws: &[ipnet::IpNet],
+) -> NetavarkResult<()> {
+    for gw in gws {
+        let dest = ipnet::IpNet::from(gw.addr());
+        sock.add_table_route(&netlink::TableRoute {
+            table: RT_TABLE_MAIN as u32,
+            dest,
+            gw: None,
+            oif: Some(link_index),
+        })
+        .wrap(format!("add host route to gateway {}", gw.addr()))?;
+    }
+    Ok(())
+}
+
 pub fn create_route_list(
     routes: &Option<Vec<types::Route>>,
 ) -> NetavarkResult<Vec<netlink::Route>> {
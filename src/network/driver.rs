@@ -1,7 +1,7 @@
 use crate::{
     dns::aardvark::AardvarkEntry,
     error::{NetavarkError, NetavarkResult},
-    firewall::FirewallDriver,
+    firewall::{FirewallDriver, FirewallRule},
 };
 
 use std::{net::IpAddr, path::Path};
@@ -9,6 +9,7 @@ use std::{net::IpAddr, path::Path};
 use super::{
     bridge::Bridge,
     constants, netlink,
+    none::NoneDriver,
     plugin::PluginDriver,
     types::{Network, PerNetworkOptions, PortMapping, StatusBlock},
     vlan::Vlan,
@@ -18,6 +19,8 @@ use std::os::unix::io::RawFd;
 
 pub struct DriverInfo<'a> {
     pub firewall: &'a dyn FirewallDriver,
+    /// rules setup installed for this network, recorded in the state
+    pub firewall_rules: &'a [FirewallRule],
     pub container_id: &'a String,
     pub container_name: &'a String,
     pub container_dns_servers: &'a Option<Vec<IpAddr>>,
@@ -26,18 +29,24 @@ pub struct DriverInfo<'a> {
     pub netns_path: &'a str,
     pub network: &'a Network,
     pub per_network_opts: &'a PerNetworkOptions,
+    /// number of networks the container is connected to
+    pub network_count: usize,
     pub port_mappings: &'a Option<Vec<PortMapping>>,
     pub dns_port: u16,
+    /// setup creates no firewall rules, the setup-firewall command applies
+    /// them later
+    pub skip_firewall: bool,
 }
 
 pub trait NetworkDriver {
     /// validate the driver options
     fn validate(&mut self) -> NetavarkResult<()>;
-    /// setup the network interfaces/firewall rules for this driver
+    /// setup the network interfaces/firewall rules for this driver, returns
+    /// the installed firewall rules so setup can record them
     fn setup(
         &self,
         netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
-    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry>)>;
+    ) -> NetavarkResult<(StatusBlock, Option<AardvarkEntry>, Vec<FirewallRule>)>;
     /// teardown the network interfaces/firewall rules for this driver
     fn teardown(
         &self,
@@ -46,6 +55,18 @@ pub trait NetworkDriver {
 
     /// return the network name
     fn network_name(&self) -> String;
+
+    /// apply the firewall rules setup skipped with skip_firewall and return
+    /// them, validate() must be called first
+    fn setup_firewall(&self) -> NetavarkResult<Vec<FirewallRule>> {
+        Ok(Vec::new())
+    }
+
+    /// render the firewall rules setup would create for this driver,
+    /// validate() must be called first
+    fn render_firewall(&self) -> NetavarkResult<Vec<String>> {
+        Ok(Vec::new())
+    }
 }
 
 pub fn get_network_driver<'a>(
@@ -55,6 +76,7 @@ pub fn get_network_driver<'a>(
     match info.network.driver.as_str() {
         constants::DRIVER_BRIDGE => Ok(Box::new(Bridge::new(info))),
         constants::DRIVER_IPVLAN | constants::DRIVER_MACVLAN => Ok(Box::new(Vlan::new(info))),
+        constants::DRIVER_NONE => Ok(Box::new(NoneDriver::new(info))),
 
         name => {
             if let Some(dirs) = plugins_directories {
@@ -1,5 +1,6 @@
 use super::netlink;
 use crate::network::types;
+use ipnet::IpNet;
 use std::net::IpAddr;
 
 /// Teardown contains options for tearing down behind a container
@@ -19,6 +20,55 @@ pub struct SetupNetwork {
     pub network_hash_name: String,
     /// isolation determines whether the network can communicate with others outside of its interface
     pub isolation: IsolateOption,
+    /// source address used for outgoing traffic instead of masquerading,
+    /// only used for the subnets of the same ip family
+    pub snat_ip: Option<IpAddr>,
+    /// subnets which are allowed to talk to the network despite isolation
+    pub allowed: AllowedNetworks,
+    /// clamp the tcp mss of forwarded connections to the path mtu
+    pub mss_clamp: bool,
+    /// conntrack zone for the traffic coming from the network interface
+    pub conntrack_zone: Option<u16>,
+    /// allow conntrack helpers for the traffic coming from the network
+    /// interface, otherwise it gets a conntrack template without helper
+    pub conntrack_helpers: bool,
+    /// masquerade the ipv6 subnets, by default they are routed
+    pub ipv6_nat: bool,
+    /// fully randomize the source ports of masqueraded and snat connections
+    pub random_fully: bool,
+    /// drop forwarded connections into the network which are not replies,
+    /// published ports or ICMP
+    pub default_drop: bool,
+    /// only accept replies to connections started by the containers
+    pub egress_only: bool,
+    /// log the packets dropped by default_drop or egress_only
+    pub log_drops: bool,
+    /// maximum number of concurrent connections started by each container
+    pub conn_limit: Option<u32>,
+    /// maximum number of new connections per second started by each container
+    pub conn_rate_limit: Option<u32>,
+    /// port of the dns resolver on the gateway the containers may query, set
+    /// when dns is enabled for the network
+    pub dns_port: Option<u16>,
+    /// drop new connections from the network to the host addresses other than
+    /// the gateway
+    pub block_host_access: bool,
+}
+
+/// AllowedNetworks contains the subnets set with the allow_from and
+/// allow_to network options.
+#[derive(Clone, Debug, Default)]
+pub struct AllowedNetworks {
+    /// subnets which can connect to the network
+    pub from: Vec<IpNet>,
+    /// subnets the network can connect to
+    pub to: Vec<IpNet>,
+}
+
+impl AllowedNetworks {
+    pub fn is_empty(&self) -> bool {
+        self.from.is_empty() && self.to.is_empty()
+    }
 }
 
 #[derive(Debug)]
@@ -31,6 +81,8 @@ pub struct TearDownNetwork {
 pub struct PortForwardConfig<'a> {
     /// id of container
     pub container_id: String,
+    /// name of container, only used to make the rules readable
+    pub container_name: String,
     /// port mappings
     pub port_mappings: &'a Option<Vec<types::PortMapping>>,
     /// name of network
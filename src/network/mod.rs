@@ -1,8 +1,11 @@
 pub mod types;
 pub mod validation;
 use std::{
-    fs::File,
+    collections::HashMap,
+    fs::{self, File},
     io::{self, BufReader},
+    os::unix::fs::MetadataExt,
+    path::Path,
 };
 
 use crate::{
@@ -16,19 +19,224 @@ pub mod driver;
 pub mod internal_types;
 mod macvlan_dhcp;
 pub mod netlink;
+pub mod none;
 pub mod plugin;
+pub mod state;
+pub mod verify;
 pub mod vlan;
 
 impl types::NetworkOptions {
     pub fn load(path: Option<String>) -> NetavarkResult<types::NetworkOptions> {
-        wrap!(Self::load_inner(path), "failed to load network options")
+        let mut opts = wrap!(Self::load_inner(path), "failed to load network options")?;
+        opts.apply_docker_options();
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("loaded network options:\n{}", opts.redacted_json());
+        }
+        Ok(opts)
+    }
+
+    /// Pretty printed json of the options for logging, the values of all
+    /// keys which look like they contain secrets are replaced.
+    pub fn redacted_json(&self) -> String {
+        let mut value = match serde_json::to_value(self) {
+            Ok(value) => value,
+            Err(e) => return format!("failed to serialize network options: {}", e),
+        };
+        redact_secrets(&mut value);
+        serde_json::to_string_pretty(&value).unwrap_or_default()
+    }
+
+    /// Fill in the values netavark uses for unset fields so the options
+    /// show the effective configuration: the host-local ipam driver and a
+    /// range of one port for port mappings.
+    pub fn apply_defaults(&mut self) {
+        for network in self.network_info.values_mut() {
+            network
+                .ipam_options
+                .get_or_insert_with(HashMap::new)
+                .entry("driver".to_string())
+                .or_insert_with(|| constants::IPAM_HOSTLOCAL.to_string());
+        }
+        for port in self.port_mappings.iter_mut().flatten() {
+            if port.range == 0 {
+                port.range = 1;
+            }
+        }
+    }
+
+    /// Translate the supported Docker network options into the netavark
+    /// fields and options, settings given in the netavark form take
+    /// precedence. Other Docker options are ignored.
+    pub fn apply_docker_options(&mut self) {
+        for network in self.network_info.values_mut() {
+            let options = match network.options.as_mut() {
+                Some(o) => o,
+                None => continue,
+            };
+            // bridge name for the bridge driver, parent interface for macvlan and ipvlan
+            let interface_option = match network.driver.as_str() {
+                constants::DRIVER_BRIDGE => Some(constants::DOCKER_OPTION_BRIDGE_NAME),
+                constants::DRIVER_MACVLAN | constants::DRIVER_IPVLAN => {
+                    Some(constants::DOCKER_OPTION_PARENT)
+                }
+                _ => None,
+            };
+            if let Some(name) = interface_option.and_then(|o| options.remove(o)) {
+                if network.network_interface.is_none() {
+                    network.network_interface = Some(name);
+                }
+            }
+            if let Some(vlan) = options.remove(constants::DOCKER_OPTION_VLAN) {
+                match network.driver.as_str() {
+                    // put the untagged traffic of the containers into the vlan
+                    constants::DRIVER_BRIDGE => {
+                        options
+                            .entry(constants::OPTION_VLAN_FILTERING.to_string())
+                            .or_insert_with(|| "true".to_string());
+                        options
+                            .entry(constants::OPTION_DEFAULT_PVID.to_string())
+                            .or_insert(vlan);
+                    }
+                    // use the vlan interface of the parent like docker does
+                    constants::DRIVER_MACVLAN | constants::DRIVER_IPVLAN => {
+                        match network.network_interface.as_mut() {
+                            Some(parent) if !parent.contains('.') => {
+                                *parent = format!("{}.{}", parent, vlan)
+                            }
+                            _ => log::debug!(
+                                "ignoring docker vlan option of network {} without a parent interface",
+                                network.name
+                            ),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(mtu) = options.remove(constants::DOCKER_OPTION_MTU) {
+                options
+                    .entry(constants::OPTION_MTU.to_string())
+                    .or_insert(mtu);
+            }
+            for key in options.keys() {
+                if key.starts_with(constants::DOCKER_OPTION_PREFIX) {
+                    log::debug!(
+                        "ignoring unsupported docker option {} of network {}",
+                        key,
+                        network.name
+                    );
+                }
+            }
+        }
+    }
+
+    /// Only keep the given network so the container can be disconnected from
+    /// it while staying connected to the other networks.
+    pub fn retain_network(&mut self, name: &str) -> NetavarkResult<()> {
+        if !self.networks.contains_key(name) || !self.network_info.contains_key(name) {
+            return Err(NetavarkError::Message(format!(
+                "network {} is not configured for the container",
+                name
+            )));
+        }
+        self.networks.retain(|n, _| n == name);
+        self.network_info.retain(|n, _| n == name);
+        Ok(())
+    }
+
+    /// Get the container id for each network, the map key is the network name.
+    /// When no container id is set a deterministic one is derived from the
+    /// network namespace at `netns_path`, the network name and interface name,
+    /// so two containers without id never share addresses or state. Commands
+    /// which do not touch a namespace pass None. Port forwarding needs the real
+    /// id so this errors if port mappings are given without a container id.
+    pub fn get_container_ids(
+        &self,
+        netns_path: Option<&str>,
+    ) -> NetavarkResult<HashMap<String, String>> {
+        let mut ids = HashMap::with_capacity(self.networks.len());
+        match &self.container_id {
+            Some(id) => {
+                for net_name in self.networks.keys() {
+                    ids.insert(net_name.clone(), id.clone());
+                }
+            }
+            None => {
+                if matches!(&self.port_mappings, Some(ports) if !ports.is_empty()) {
+                    return Err(NetavarkError::msg(
+                        "container_id must be set when port mappings are given",
+                    ));
+                }
+                // a namespace keeps its device and inode until it is deleted,
+                // so setup and teardown of the same container agree on the id
+                let netns = match netns_path {
+                    Some(path) => {
+                        let meta = wrap!(fs::metadata(path), format!("stat netns {:?}", path))?;
+                        format!("{}:{}/", meta.dev(), meta.ino())
+                    }
+                    None => String::new(),
+                };
+                for (net_name, opts) in self.networks.iter() {
+                    let id = core_utils::CoreUtils::create_network_hash(
+                        &format!("{}{}/{}", netns, net_name, opts.interface_name),
+                        64,
+                    )
+                    .to_lowercase();
+                    ids.insert(net_name.clone(), id);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Name of the container for log messages, falls back to the container id.
+    pub fn display_name(&self) -> &str {
+        if !self.container_name.is_empty() {
+            return &self.container_name;
+        }
+        self.container_id.as_deref().unwrap_or_default()
     }
 
     fn load_inner(path: Option<String>) -> Result<types::NetworkOptions, io::Error> {
         let opts = match path {
-            Some(path) => serde_json::from_reader(BufReader::new(File::open(path)?)),
+            Some(path) => {
+                let reader = BufReader::new(File::open(&path)?);
+                // podman always passes json, yaml is for hand written configs
+                if is_yaml_path(&path) {
+                    return serde_yaml::from_reader(reader)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                serde_json::from_reader(reader)
+            }
             None => serde_json::from_reader(io::stdin()),
         }?;
         Ok(opts)
     }
 }
+
+/// parts of option keys whose values must not be logged
+const SECRET_KEY_PARTS: &[&str] = &["password", "secret", "token"];
+const REDACTED: &str = "<redacted>";
+
+fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_lowercase();
+                if SECRET_KEY_PARTS.iter().any(|part| key.contains(part)) {
+                    *value = serde_json::Value::String(REDACTED.to_string());
+                } else {
+                    redact_secrets(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+fn is_yaml_path(path: &str) -> bool {
+    matches!(
+        Path::new(path).extension().and_then(|ext| ext.to_str()),
+        Some("yaml") | Some("yml")
+    )
+}
@@ -1,5 +1,5 @@
 use std::{
-    net::{Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     os::unix::prelude::RawFd,
 };
 
@@ -11,15 +11,25 @@ use crate::{
 use log::{info, trace};
 use netlink_packet_core::{
     NetlinkHeader, NetlinkMessage, NetlinkPayload, NLM_F_ACK, NLM_F_CREATE, NLM_F_DUMP, NLM_F_EXCL,
-    NLM_F_REQUEST,
+    NLM_F_REPLACE, NLM_F_REQUEST,
 };
 use netlink_packet_route::{
-    nlas::link::{Info, InfoData, InfoKind, Nla},
-    AddressMessage, LinkMessage, RouteMessage, RtnlMessage, AF_INET, AF_INET6, IFF_UP, RTN_UNICAST,
-    RTPROT_STATIC, RTPROT_UNSPEC, RT_SCOPE_UNIVERSE, RT_TABLE_MAIN,
+    nlas::link::{AfSpecBridge, BridgeVlanInfo, Info, InfoData, InfoKind, Nla},
+    tc::constants::TC_H_ROOT,
+    AddressMessage, LinkMessage, RouteMessage, RtnlMessage, RuleMessage, TcMessage, AF_BRIDGE,
+    AF_INET, AF_INET6, FR_ACT_TO_TBL, IFA_F_NOPREFIXROUTE, IFF_UP, IFLA_PROTINFO, RTN_BLACKHOLE,
+    RTN_UNICAST, RTPROT_STATIC, RTPROT_UNSPEC, RT_SCOPE_LINK, RT_SCOPE_UNIVERSE, RT_TABLE_MAIN,
+    RT_TABLE_UNSPEC,
+};
+use netlink_packet_utils::{
+    nla::{DefaultNla, NLA_F_NESTED},
+    Emitable,
 };
 use netlink_sys::{protocols::NETLINK_ROUTE, SocketAddr};
 
+/// IFLA_BRPORT_MCAST_FLOOD from linux/if_link.h
+const IFLA_BRPORT_MCAST_FLOOD: u16 = 27;
+
 pub struct Socket {
     socket: netlink_sys::Socket,
     sequence_number: u32,
@@ -37,6 +47,11 @@ pub struct CreateLinkOptions {
     pub link: u32,
     pub mac: Vec<u8>,
     pub netns: RawFd,
+    /// queue counts, None keeps the kernel default
+    pub num_rx_queues: Option<u32>,
+    pub num_tx_queues: Option<u32>,
+    /// transmit queue length, 0 keeps the kernel default
+    pub txqueuelen: u32,
 }
 
 pub enum LinkID {
@@ -57,6 +72,33 @@ pub enum Route {
     },
 }
 
+/// Route in the given routing table via the output interface.
+/// Without gateway the route is a link scoped route on the output interface,
+/// without output interface the kernel picks the one the gateway is on.
+pub struct TableRoute {
+    pub table: u32,
+    pub dest: ipnet::IpNet,
+    pub gw: Option<IpAddr>,
+    pub oif: Option<u32>,
+}
+
+/// Policy routing rule which looks up the given table for traffic from source.
+pub struct Rule {
+    pub source: ipnet::IpNet,
+    pub table: u32,
+    pub priority: u32,
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "(from: {}, table: {}, priority: {})",
+            self.source, self.table, self.priority
+        )
+    }
+}
+
 impl std::fmt::Display for Route {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let (dest, gw, metric) = match self {
@@ -161,6 +203,16 @@ impl Socket {
         Ok(())
     }
 
+    pub fn set_link_alias(&mut self, id: u32, alias: String) -> NetavarkResult<()> {
+        let mut msg = LinkMessage::default();
+        msg.header.index = id;
+        msg.nlas.push(Nla::IfAlias(alias));
+        let result = self.make_netlink_request(RtnlMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
     pub fn del_link(&mut self, id: LinkID) -> NetavarkResult<()> {
         let mut msg = LinkMessage::default();
 
@@ -174,6 +226,66 @@ impl Socket {
         Ok(())
     }
 
+    /// add the vlan to the bridge port, flags are the BRIDGE_VLAN_INFO_* flags
+    pub fn add_bridge_vlan(&mut self, link_id: u32, vid: u16, flags: u16) -> NetavarkResult<()> {
+        let msg = Self::create_bridge_vlan_msg(link_id, vid, flags);
+        info!("Adding vlan {} to bridge port {}", vid, link_id);
+
+        let result = self.make_netlink_request(RtnlMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    /// remove the vlan from the bridge port
+    pub fn del_bridge_vlan(&mut self, link_id: u32, vid: u16) -> NetavarkResult<()> {
+        let msg = Self::create_bridge_vlan_msg(link_id, vid, 0);
+        info!("Removing vlan {} from bridge port {}", vid, link_id);
+
+        let result = self.make_netlink_request(RtnlMessage::DelLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    /// enable or disable flooding of unknown multicast traffic to the bridge port
+    pub fn set_bridge_port_mcast_flood(&mut self, link_id: u32, flood: bool) -> NetavarkResult<()> {
+        let mut msg = LinkMessage::default();
+        msg.header.interface_family = AF_BRIDGE as u8;
+        msg.header.index = link_id;
+
+        // the port attributes are nested in IFLA_PROTINFO, the crate has no type for them
+        let flag = DefaultNla::new(IFLA_BRPORT_MCAST_FLOOD, vec![flood as u8]);
+        let mut port_attrs = vec![0; flag.buffer_len()];
+        flag.emit(&mut port_attrs);
+        msg.nlas.push(Nla::Other(DefaultNla::new(
+            IFLA_PROTINFO | NLA_F_NESTED,
+            port_attrs,
+        )));
+        info!(
+            "Setting multicast flooding of bridge port {} to {}",
+            link_id, flood
+        );
+
+        let result = self.make_netlink_request(RtnlMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    fn create_bridge_vlan_msg(link_id: u32, vid: u16, flags: u16) -> LinkMessage {
+        let mut msg = LinkMessage::default();
+        msg.header.interface_family = AF_BRIDGE as u8;
+        msg.header.index = link_id;
+
+        let mut info = BridgeVlanInfo::default();
+        info.flags = flags;
+        info.vid = vid;
+        msg.nlas
+            .push(Nla::AfSpecBridge(vec![AfSpecBridge::VlanInfo(info)]));
+        msg
+    }
+
     pub fn set_link_ns(&mut self, link_id: u32, netns_fd: i32) -> NetavarkResult<()> {
         let mut msg = LinkMessage::default();
         msg.header.index = link_id;
@@ -210,6 +322,42 @@ impl Socket {
 
     pub fn add_addr(&mut self, link_id: u32, addr: &ipnet::IpNet) -> NetavarkResult<()> {
         let msg = Self::create_addr_msg(link_id, addr);
+        self.new_addr(msg, addr)
+    }
+
+    /// add the address without the connected route for its subnet
+    pub fn add_addr_without_prefix_route(
+        &mut self,
+        link_id: u32,
+        addr: &ipnet::IpNet,
+    ) -> NetavarkResult<()> {
+        let mut msg = Self::create_addr_msg(link_id, addr);
+        msg.nlas.push(netlink_packet_route::address::Nla::Flags(
+            IFA_F_NOPREFIXROUTE,
+        ));
+        self.new_addr(msg, addr)
+    }
+
+    /// add the address with a point-to-point peer instead of a broadcast address
+    pub fn add_addr_with_peer(
+        &mut self,
+        link_id: u32,
+        addr: &ipnet::IpNet,
+        peer: IpAddr,
+    ) -> NetavarkResult<()> {
+        let mut msg = Self::create_addr_msg(link_id, addr);
+        msg.nlas
+            .retain(|nla| !matches!(nla, netlink_packet_route::address::Nla::Broadcast(_)));
+        let peer_vec = match peer {
+            IpAddr::V4(v4) => v4.octets().to_vec(),
+            IpAddr::V6(v6) => v6.octets().to_vec(),
+        };
+        msg.nlas
+            .push(netlink_packet_route::address::Nla::Address(peer_vec));
+        self.new_addr(msg, addr)
+    }
+
+    fn new_addr(&mut self, msg: AddressMessage, addr: &ipnet::IpNet) -> NetavarkResult<()> {
         let result = match self.make_netlink_request(
             RtnlMessage::NewAddress(msg),
             NLM_F_ACK | NLM_F_EXCL | NLM_F_CREATE,
@@ -302,6 +450,152 @@ impl Socket {
         Ok(())
     }
 
+    fn create_blackhole_route_msg(dest: &ipnet::IpNet) -> RouteMessage {
+        let mut msg = RouteMessage::default();
+        msg.header.table = RT_TABLE_MAIN;
+        msg.header.protocol = RTPROT_STATIC;
+        msg.header.scope = RT_SCOPE_UNIVERSE;
+        msg.header.kind = RTN_BLACKHOLE;
+        msg.header.address_family = match dest {
+            ipnet::IpNet::V4(_) => AF_INET as u8,
+            ipnet::IpNet::V6(_) => AF_INET6 as u8,
+        };
+        msg.header.destination_prefix_length = dest.prefix_len();
+        msg.nlas
+            .push(netlink_packet_route::route::Nla::Destination(ip_to_vec(
+                dest.network(),
+            )));
+        msg
+    }
+
+    /// add a blackhole route, traffic to dest is dropped without leaving the
+    /// namespace, an existing route to dest is replaced
+    pub fn add_blackhole_route(&mut self, dest: &ipnet::IpNet) -> NetavarkResult<()> {
+        let msg = Self::create_blackhole_route_msg(dest);
+        info!("Adding blackhole route {}", dest);
+
+        let result = self.make_netlink_request(
+            RtnlMessage::NewRoute(msg),
+            NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE,
+        )?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    /// replace the root qdisc of the link with the given kind, using the
+    /// default parameters of the kernel
+    pub fn set_root_qdisc(&mut self, link_id: u32, kind: &str) -> NetavarkResult<()> {
+        let mut msg = TcMessage::with_index(link_id as i32);
+        msg.header.parent = TC_H_ROOT;
+        msg.nlas
+            .push(netlink_packet_route::tc::Nla::Kind(kind.to_string()));
+        info!("Setting root qdisc {} on link {}", kind, link_id);
+
+        let result = self.make_netlink_request(
+            RtnlMessage::NewQueueDiscipline(msg),
+            NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE,
+        )?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    pub fn del_blackhole_route(&mut self, dest: &ipnet::IpNet) -> NetavarkResult<()> {
+        let msg = Self::create_blackhole_route_msg(dest);
+        info!("Deleting blackhole route {}", dest);
+
+        let result = self.make_netlink_request(RtnlMessage::DelRoute(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    /// add a route to a custom routing table
+    pub fn add_table_route(&mut self, route: &TableRoute) -> NetavarkResult<()> {
+        let mut msg = RouteMessage::default();
+
+        // the header field only fits the reserved tables, use the nla instead
+        msg.header.table = RT_TABLE_UNSPEC;
+        msg.header.protocol = RTPROT_STATIC;
+        msg.header.kind = RTN_UNICAST;
+        msg.header.address_family = match route.dest {
+            ipnet::IpNet::V4(_) => AF_INET as u8,
+            ipnet::IpNet::V6(_) => AF_INET6 as u8,
+        };
+        msg.header.destination_prefix_length = route.dest.prefix_len();
+        msg.nlas
+            .push(netlink_packet_route::route::Nla::Destination(ip_to_vec(
+                route.dest.network(),
+            )));
+        match route.gw {
+            Some(gw) => {
+                msg.header.scope = RT_SCOPE_UNIVERSE;
+                msg.nlas
+                    .push(netlink_packet_route::route::Nla::Gateway(ip_to_vec(gw)));
+            }
+            None => msg.header.scope = RT_SCOPE_LINK,
+        }
+        if let Some(oif) = route.oif {
+            msg.nlas.push(netlink_packet_route::route::Nla::Oif(oif));
+        }
+        msg.nlas
+            .push(netlink_packet_route::route::Nla::Table(route.table));
+
+        info!(
+            "Adding route to {} via {:?} in table {}",
+            route.dest, route.gw, route.table
+        );
+        let result =
+            self.make_netlink_request(RtnlMessage::NewRoute(msg), NLM_F_ACK | NLM_F_CREATE)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    fn create_rule_msg(rule: &Rule) -> RuleMessage {
+        let mut msg = RuleMessage::default();
+        msg.header.family = match rule.source {
+            ipnet::IpNet::V4(_) => AF_INET as u8,
+            ipnet::IpNet::V6(_) => AF_INET6 as u8,
+        };
+        msg.header.src_len = rule.source.prefix_len();
+        msg.header.table = RT_TABLE_UNSPEC;
+        msg.header.action = FR_ACT_TO_TBL;
+        msg.nlas
+            .push(netlink_packet_route::rule::Nla::Source(ip_to_vec(
+                rule.source.network(),
+            )));
+        msg.nlas
+            .push(netlink_packet_route::rule::Nla::Table(rule.table));
+        msg.nlas
+            .push(netlink_packet_route::rule::Nla::Priority(rule.priority));
+        msg
+    }
+
+    pub fn add_rule(&mut self, rule: &Rule) -> NetavarkResult<()> {
+        let msg = Self::create_rule_msg(rule);
+        info!("Adding rule {}", rule);
+
+        let result = self.make_netlink_request(
+            RtnlMessage::NewRule(msg),
+            NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL,
+        )?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
+    pub fn del_rule(&mut self, rule: &Rule) -> NetavarkResult<()> {
+        let msg = Self::create_rule_msg(rule);
+        info!("Deleting rule {}", rule);
+
+        let result = self.make_netlink_request(RtnlMessage::DelRule(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
     pub fn dump_routes(&mut self) -> NetavarkResult<Vec<RouteMessage>> {
         let mut msg = RouteMessage::default();
 
@@ -329,6 +623,28 @@ impl Socket {
         Ok(routes)
     }
 
+    pub fn dump_rules(&mut self) -> NetavarkResult<Vec<RuleMessage>> {
+        let msg = RuleMessage::default();
+
+        let results =
+            self.make_netlink_request(RtnlMessage::GetRule(msg), NLM_F_DUMP | NLM_F_ACK)?;
+
+        let mut rules = Vec::with_capacity(results.len());
+
+        for res in results {
+            match res {
+                RtnlMessage::NewRule(m) => rules.push(m),
+                m => {
+                    return Err(NetavarkError::Message(format!(
+                        "unexpected netlink message type: {}",
+                        m.message_type()
+                    )))
+                }
+            };
+        }
+        Ok(rules)
+    }
+
     pub fn dump_links(&mut self, nlas: &mut Vec<Nla>) -> NetavarkResult<Vec<LinkMessage>> {
         let mut msg = LinkMessage::default();
         msg.nlas.append(nlas);
@@ -394,6 +710,22 @@ impl Socket {
         Ok(())
     }
 
+    pub fn set_down(&mut self, id: LinkID) -> NetavarkResult<()> {
+        let mut msg = LinkMessage::default();
+
+        match id {
+            LinkID::ID(id) => msg.header.index = id,
+            LinkID::Name(name) => msg.nlas.push(Nla::IfName(name)),
+        }
+
+        msg.header.change_mask |= IFF_UP;
+
+        let result = self.make_netlink_request(RtnlMessage::SetLink(msg), NLM_F_ACK)?;
+        expect_netlink_result!(result, 0);
+
+        Ok(())
+    }
+
     fn make_netlink_request(
         &mut self,
         msg: RtnlMessage,
@@ -497,6 +829,9 @@ impl CreateLinkOptions {
             mac: vec![],
             // 0 is a valid fd, so use -1 by default
             netns: -1,
+            num_rx_queues: None,
+            num_tx_queues: None,
+            txqueuelen: 0,
         }
     }
 }
@@ -538,4 +873,22 @@ pub fn parse_create_link_options(msg: &mut LinkMessage, options: CreateLinkOptio
     if options.netns > -1 {
         msg.nlas.push(Nla::NetNsFd(options.netns));
     }
+
+    // add queues
+    if let Some(queues) = options.num_rx_queues {
+        msg.nlas.push(Nla::NumRxQueues(queues));
+    }
+    if let Some(queues) = options.num_tx_queues {
+        msg.nlas.push(Nla::NumTxQueues(queues));
+    }
+    if options.txqueuelen != 0 {
+        msg.nlas.push(Nla::TxQueueLen(options.txqueuelen));
+    }
+}
+
+fn ip_to_vec(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    }
 }
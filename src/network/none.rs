@@ -0,0 +1,65 @@
+//! The none driver is used for containers which manage their own networking,
+//! the network is only reported in the response.
+use std::collections::HashMap;
+
+use log::debug;
+
+use crate::{dns::aardvark::AardvarkEntry, error::NetavarkResult, firewall::FirewallRule};
+
+use super::{
+    driver::{DriverInfo, NetworkDriver},
+    netlink, types,
+};
+
+pub struct NoneDriver<'a> {
+    info: DriverInfo<'a>,
+}
+
+impl<'a> NoneDriver<'a> {
+    pub fn new(info: DriverInfo<'a>) -> Self {
+        NoneDriver { info }
+    }
+}
+
+impl NetworkDriver for NoneDriver<'_> {
+    fn network_name(&self) -> String {
+        self.info.network.name.clone()
+    }
+
+    fn validate(&mut self) -> NetavarkResult<()> {
+        Ok(())
+    }
+
+    fn setup(
+        &self,
+        _netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
+    ) -> NetavarkResult<(
+        types::StatusBlock,
+        Option<AardvarkEntry<'_>>,
+        Vec<FirewallRule>,
+    )> {
+        debug!(
+            "network {} uses the none driver, nothing to set up",
+            self.info.network.name
+        );
+        let response = types::StatusBlock {
+            dns_server_ips: None,
+            dns_search_domains: None,
+            interfaces: Some(HashMap::new()),
+            gateway: None,
+            gateway_reachable: None,
+            warnings: None,
+            changed_sysctls: None,
+            netns_inode: None,
+            firewall_chains: None,
+        };
+        Ok((response, None, Vec::new()))
+    }
+
+    fn teardown(
+        &self,
+        _netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
+    ) -> NetavarkResult<()> {
+        Ok(())
+    }
+}
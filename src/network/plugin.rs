@@ -7,6 +7,7 @@ use std::{
 use crate::{
     dns::aardvark::AardvarkEntry,
     error::{ErrorWrap, JsonError, NetavarkError, NetavarkResult},
+    firewall::FirewallRule,
     wrap,
 };
 
@@ -37,14 +38,14 @@ impl NetworkDriver for PluginDriver<'_> {
     fn setup(
         &self,
         _netlink_sockets: (&mut super::netlink::Socket, &mut super::netlink::Socket),
-    ) -> NetavarkResult<(types::StatusBlock, Option<AardvarkEntry>)> {
+    ) -> NetavarkResult<(types::StatusBlock, Option<AardvarkEntry>, Vec<FirewallRule>)> {
         let result = self.exec_plugin(true, self.info.netns_path).wrap(format!(
             "plugin {:?} failed",
             &self.path.file_name().unwrap_or_default()
         ))?;
         // The unwrap should be safe, only if the exec_plugin has a bug this
         // could fail, in which case the test should catch it.
-        Ok((result.unwrap(), None))
+        Ok((result.unwrap(), None, Vec::new()))
     }
 
     fn teardown(
@@ -0,0 +1,1272 @@
+//! Persistent netavark state stored in the config directory.
+//!
+//! The state file is shared between all netavark processes, callers must
+//! hold the lock from [`StateFile::open`] while they read and modify it.
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Write},
+    net::IpAddr,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use fs2::FileExt;
+use ipnet::IpNet;
+use log::debug;
+use nix::unistd::{access, AccessFlags};
+use sysctl::Sysctl;
+
+use crate::dhcp_proxy::proxy_conf::get_run_dir;
+use crate::error::{ErrorWrap, NetavarkError, NetavarkResult};
+use crate::firewall::iptables::port_forward_chain_name;
+use crate::firewall::{FirewallChains, FirewallRule};
+use crate::wrap;
+
+use super::{constants, core_utils, netlink, types};
+
+const STATE_FILE: &str = "netavark-state.json";
+const STATE_LOCK_FILE: &str = "netavark-state.lock";
+const NETNS_LOCK_DIR: &str = "netavark-netns-locks";
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct State {
+    /// ip address allocations, maps network name -> address -> container id
+    #[serde(default)]
+    pub ipam: BTreeMap<String, BTreeMap<IpAddr, String>>,
+    /// subnets of all networks which were set up, maps network name -> subnets
+    #[serde(default)]
+    pub networks: BTreeMap<String, Vec<IpNet>>,
+    /// port forwarding chains of the containers, maps chain name -> owner
+    #[serde(default)]
+    pub port_forward_chains: BTreeMap<String, PortForwardChain>,
+    /// veth pairs of the containers, maps container id -> network name -> pair
+    #[serde(default)]
+    pub veth_pairs: BTreeMap<String, BTreeMap<String, VethPair>>,
+    /// network namespaces of the containers with veth pairs, maps container id
+    /// -> namespace, pairs whose namespace is gone are orphaned
+    #[serde(default)]
+    pub veth_netns: BTreeMap<String, NetnsId>,
+    /// firewall rules installed by setup, maps container id -> network name -> rules
+    #[serde(default)]
+    pub firewall_rules: BTreeMap<String, BTreeMap<String, Vec<FirewallRule>>>,
+    /// netavark chains of the installed firewall rules, maps container id ->
+    /// network name -> chains
+    #[serde(default)]
+    pub firewall_chains: BTreeMap<String, BTreeMap<String, FirewallChains>>,
+    /// container routing tables of the route_table option, maps container id
+    /// -> network name -> table id
+    #[serde(default)]
+    pub route_tables: BTreeMap<String, BTreeMap<String, u32>>,
+    /// conntrack zones of the networks with the conntrack_zone option, maps
+    /// network name -> zone
+    #[serde(default)]
+    pub conntrack_zones: BTreeMap<String, u16>,
+    /// host neighbor gc thresholds before the neigh_gc_thresh options raised
+    /// them, maps sysctl path -> value
+    #[serde(default)]
+    pub neigh_gc_thresh: BTreeMap<String, String>,
+    /// containers of the networks with neigh_gc_thresh options, maps network
+    /// name -> container ids
+    #[serde(default)]
+    pub neigh_gc_thresh_users: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// The container and network namespace a port forwarding chain belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortForwardChain {
+    pub container_id: String,
+    pub network: String,
+    pub netns_path: String,
+    /// device and inode of the namespace, the path might be reused
+    pub netns_dev: u64,
+    pub netns_ino: u64,
+}
+
+/// A network namespace, the device and inode identify it as the path might be
+/// reused.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetnsId {
+    pub path: String,
+    pub dev: u64,
+    pub ino: u64,
+}
+
+/// The host and container side of the veth pair connecting a container to a
+/// bridge network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VethPair {
+    pub host_interface: String,
+    pub host_index: u32,
+    pub container_interface: String,
+    pub container_index: u32,
+}
+
+/// The locked state file, the lock is released on drop.
+pub struct StateFile {
+    path: PathBuf,
+    _lock: File,
+    pub state: State,
+}
+
+impl StateFile {
+    /// Lock and read the state file in the given config directory.
+    pub fn open(config_dir: &str) -> NetavarkResult<StateFile> {
+        let dir = Path::new(config_dir);
+        wrap!(fs::create_dir_all(dir), "create config directory")?;
+
+        let lock = lock_file(&dir.join(STATE_LOCK_FILE))?;
+
+        let path = dir.join(STATE_FILE);
+        let state = match File::open(&path) {
+            Ok(file) => wrap!(
+                serde_json::from_reader(BufReader::new(file)),
+                format!("parse state file {:?}", &path)
+            )?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => State::default(),
+            Err(e) => return Err(NetavarkError::wrap("read state file", e.into())),
+        };
+
+        Ok(StateFile {
+            path,
+            _lock: lock,
+            state,
+        })
+    }
+
+    /// Write the state back, the file is replaced atomically.
+    pub fn save(&self) -> NetavarkResult<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = wrap!(File::create(&tmp_path), "create state file")?;
+        serde_json::to_writer(&mut file, &self.state)?;
+        file.flush()?;
+        file.sync_all()?;
+        wrap!(fs::rename(&tmp_path, &self.path), "write state file")?;
+        Ok(())
+    }
+}
+
+/// Make sure the config directory exists and is writable before anything is
+/// set up, so a read-only filesystem is reported with the directory netavark
+/// needs instead of failing on the first write.
+pub fn check_config_dir(config_dir: &Option<String>) -> NetavarkResult<()> {
+    let dir = match config_dir {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let res = fs::create_dir_all(dir)
+        .map_err(|e| e.to_string())
+        .and_then(|_| access(dir.as_str(), AccessFlags::W_OK).map_err(|e| e.desc().to_string()));
+    match res {
+        Ok(()) => Ok(()),
+        Err(e) => Err(NetavarkError::Message(format!(
+            "config directory {} must be writable to store the netavark state, use --config to set another directory: {}",
+            dir, e
+        ))),
+    }
+}
+
+/// Read the state file of the config directory without locking it or
+/// creating anything, a missing file is an empty state.
+pub fn read_state(config_dir: &str) -> NetavarkResult<State> {
+    let path = Path::new(config_dir).join(STATE_FILE);
+    match File::open(&path) {
+        Ok(file) => Ok(wrap!(
+            serde_json::from_reader(BufReader::new(file)),
+            format!("parse state file {:?}", &path)
+        )?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(State::default()),
+        Err(e) => Err(NetavarkError::wrap("read state file", e.into())),
+    }
+}
+
+fn open_lock_file(path: &Path) -> NetavarkResult<File> {
+    wrap!(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path),
+        format!("open lock file {:?}", path)
+    )
+}
+
+fn lock_file(path: &Path) -> NetavarkResult<File> {
+    let lock = open_lock_file(path)?;
+    wrap!(lock.lock_exclusive(), format!("lock file {:?}", path))?;
+    Ok(lock)
+}
+
+/// Lock of a network namespace from [`lock_netns`], released when dropped.
+pub struct NetnsLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl NetnsLock {
+    /// Release the lock and delete its file, used on teardown so the lock
+    /// directory does not grow with every container.
+    pub fn remove(self) -> NetavarkResult<()> {
+        // delete while still holding the lock, waiting processes notice that
+        // their file was unlinked and create a new one
+        wrap!(
+            fs::remove_file(&self.path),
+            format!("remove lock file {:?}", &self.path)
+        )?;
+        drop(self.file);
+        Ok(())
+    }
+}
+
+/// Directory of the netns lock files, rootless callers cannot write the run
+/// directory so they use $XDG_RUNTIME_DIR when no config directory is given.
+fn netns_lock_dir(config_dir: &Option<String>, rootless: bool) -> PathBuf {
+    if let Some(dir) = config_dir {
+        return Path::new(dir).join(NETNS_LOCK_DIR);
+    }
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if rootless && !dir.is_empty() => Path::new(&dir).join(NETNS_LOCK_DIR),
+        _ => Path::new(&get_run_dir(None)).join(NETNS_LOCK_DIR),
+    }
+}
+
+/// Lock the given network namespace so concurrent setups of the same
+/// namespace run one after the other. The lock is keyed by the namespace
+/// inode, so different paths to the same namespace share a lock. The lock
+/// files are stored in the config directory, or in the run directory when no
+/// config directory is given, $XDG_RUNTIME_DIR for rootless callers.
+pub fn lock_netns(
+    config_dir: &Option<String>,
+    netns_path: &str,
+    rootless: bool,
+) -> NetavarkResult<NetnsLock> {
+    let dir = netns_lock_dir(config_dir, rootless);
+    wrap!(
+        fs::create_dir_all(&dir),
+        format!("create netns lock directory {:?}", &dir)
+    )?;
+
+    let meta = wrap!(
+        fs::metadata(netns_path),
+        format!("stat netns {:?}", netns_path)
+    )?;
+    let path = dir.join(format!("{}-{}.lock", meta.dev(), meta.ino()));
+    loop {
+        let lock = open_lock_file(&path)?;
+        if lock.try_lock_exclusive().is_err() {
+            debug!(
+                "waiting for other netavark process to finish setting up netns {}",
+                netns_path
+            );
+            wrap!(lock.lock_exclusive(), format!("lock file {:?}", &path))?;
+        }
+        // a teardown might have deleted the file while we were waiting
+        let locked = wrap!(lock.metadata(), format!("stat lock file {:?}", &path))?;
+        match fs::metadata(&path) {
+            Ok(current) if current.dev() == locked.dev() && current.ino() == locked.ino() => {
+                return Ok(NetnsLock { file: lock, path })
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(NetavarkError::wrap(
+                    format!("stat lock file {:?}", &path),
+                    e.into(),
+                ))
+            }
+        }
+    }
+}
+
+/// Device and inode of the network namespaces used by processes.
+fn used_netns() -> HashSet<(u64, u64)> {
+    let mut used = HashSet::new();
+    if let Ok(entries) = fs::read_dir("/proc") {
+        for entry in entries.flatten() {
+            // processes might exit while we look at them, just skip them
+            if let Ok(meta) = fs::metadata(entry.path().join("ns/net")) {
+                used.insert((meta.dev(), meta.ino()));
+            }
+        }
+    }
+    used
+}
+
+fn netns_exists(path: &str, dev: u64, ino: u64, used: &HashSet<(u64, u64)>) -> bool {
+    let mounted = matches!(fs::metadata(path), Ok(meta) if meta.dev() == dev && meta.ino() == ino);
+    mounted || used.contains(&(dev, ino))
+}
+
+impl State {
+    /// Split the port forwarding chains into the chains of containers whose
+    /// network namespace no longer exists and the chains still in use. A
+    /// namespace exists while it is mounted at its path or used by a process.
+    pub fn partition_port_forward_chains(&self) -> (HashSet<String>, HashSet<String>) {
+        let used = used_netns();
+        let mut stale = HashSet::new();
+        let mut known = HashSet::new();
+        for (name, chain) in &self.port_forward_chains {
+            if netns_exists(&chain.netns_path, chain.netns_dev, chain.netns_ino, &used) {
+                known.insert(name.clone());
+            } else {
+                stale.insert(name.clone());
+            }
+        }
+        (stale, known)
+    }
+
+    /// Allocate one address per subnet of the network for the container.
+    /// Existing allocations of the container are returned as is.
+    /// The addresses are returned in the order of the network subnets.
+    pub fn allocate_ips(
+        &mut self,
+        network: &types::Network,
+        container_id: &str,
+    ) -> NetavarkResult<Vec<IpAddr>> {
+        let existing = self.get_ips(network, container_id);
+        let reserved: u32 =
+            core_utils::parse_option(&network.options, constants::OPTION_RESERVED_COUNT)?
+                .unwrap_or(0);
+        let excluded = core_utils::get_exclude_ips_option(network)?;
+        let allocations = self.ipam.entry(network.name.clone()).or_default();
+
+        let mut ips = Vec::new();
+        for (subnet, existing_ip) in network.subnets.iter().flatten().zip(existing) {
+            let ip = match existing_ip {
+                Some(ip) => ip,
+                None => {
+                    let used: HashSet<IpAddr> =
+                        allocations.keys().chain(excluded.iter()).copied().collect();
+                    let ip =
+                        core_utils::get_free_ip(subnet, &used, reserved)?.ok_or_else(|| {
+                            NetavarkError::Message(format!(
+                                "no free ip address left in subnet {} of network {}",
+                                subnet.subnet, network.name
+                            ))
+                        })?;
+                    allocations.insert(ip, container_id.to_string());
+                    ip
+                }
+            };
+            ips.push(ip);
+        }
+        Ok(ips)
+    }
+
+    /// Get the addresses allocated to the container for each subnet of the network.
+    pub fn get_ips(&self, network: &types::Network, container_id: &str) -> Vec<Option<IpAddr>> {
+        let allocations = self.ipam.get(&network.name);
+        network
+            .subnets
+            .iter()
+            .flatten()
+            .map(|subnet| {
+                allocations.and_then(|a| {
+                    a.iter()
+                        .find(|(ip, id)| *id == container_id && subnet.subnet.contains(*ip))
+                        .map(|(ip, _)| *ip)
+                })
+            })
+            .collect()
+    }
+
+    /// Release all addresses of the container in the network.
+    pub fn release_ips(&mut self, network_name: &str, container_id: &str) {
+        if let Some(allocations) = self.ipam.get_mut(network_name) {
+            allocations.retain(|_, id| id != container_id);
+            if allocations.is_empty() {
+                self.ipam.remove(network_name);
+            }
+        }
+    }
+}
+
+fn is_host_local_bridge(network: &types::Network) -> bool {
+    network.driver == constants::DRIVER_BRIDGE
+        && matches!(
+            network
+                .ipam_options
+                .as_ref()
+                .and_then(|map| map.get("driver"))
+                .map(|d| d.as_str()),
+            None | Some(constants::IPAM_HOSTLOCAL)
+        )
+}
+
+/// Networks which get their addresses allocated by netavark, these are
+/// host-local bridge networks where the caller did not set static ips.
+fn needs_allocation(network: &types::Network, opts: &types::PerNetworkOptions) -> bool {
+    is_host_local_bridge(network)
+        && !matches!(&opts.static_ips, Some(ips) if !ips.is_empty())
+        && !matches!(&opts.static_ips_by_subnet, Some(ips) if !ips.is_empty())
+}
+
+/// Names of the networks which need allocated addresses.
+fn allocated_networks(opts: &types::NetworkOptions) -> Vec<String> {
+    opts.network_info
+        .iter()
+        .filter(|(name, network)| {
+            matches!(opts.networks.get(*name), Some(per_net) if needs_allocation(network, per_net))
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Names of the host-local bridge networks without subnets, they get a
+/// subnet of the default subnet pool.
+fn default_subnet_networks(opts: &types::NetworkOptions) -> Vec<String> {
+    opts.network_info
+        .iter()
+        .filter(|(_, network)| {
+            is_host_local_bridge(network) && network.subnets.as_ref().map_or(true, |s| s.is_empty())
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+fn set_default_subnet(network: &mut types::Network, subnet: IpNet) {
+    network.subnets = Some(vec![types::Subnet {
+        gateway: subnet.hosts().next(),
+        lease_range: None,
+        subnet,
+    }]);
+}
+
+// give the networks the subnet recorded for them or the first free subnet of
+// the pool which does not overlap the used subnets
+fn pick_default_subnets(
+    state: &mut State,
+    opts: &mut types::NetworkOptions,
+    networks: &[String],
+    mut used: Vec<IpNet>,
+) -> NetavarkResult<()> {
+    let (pool, size) = core_utils::get_default_subnet_pool()?;
+    used.extend(
+        state
+            .networks
+            .iter()
+            .filter(|(name, _)| !networks.contains(name))
+            .flat_map(|(_, subnets)| subnets.iter().copied()),
+    );
+    used.extend(
+        opts.network_info
+            .values()
+            .flat_map(|n| n.subnets.iter().flatten().map(|s| s.subnet)),
+    );
+    for name in networks {
+        let subnet = match state.networks.get(name).and_then(|s| s.first()) {
+            Some(subnet) => *subnet,
+            None => core_utils::get_free_subnet(pool, size, &used).ok_or_else(|| {
+                NetavarkError::Message(format!(
+                    "no free /{} subnet left in the default subnet pool {} for network {}",
+                    size, pool, name
+                ))
+            })?,
+        };
+        debug!("Using subnet {} for network {}", subnet, name);
+        used.push(subnet);
+        state.networks.insert(name.clone(), vec![subnet]);
+        if let Some(network) = opts.network_info.get_mut(name) {
+            set_default_subnet(network, subnet);
+        }
+    }
+    Ok(())
+}
+
+/// Give the bridge networks without subnets a subnet of the default pool.
+/// The subnet is recorded so the network keeps it, new subnets do not
+/// overlap the routes of the host or the subnets of other networks.
+pub fn assign_default_subnets(
+    opts: &mut types::NetworkOptions,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let networks = default_subnet_networks(opts);
+    if networks.is_empty() {
+        return Ok(());
+    }
+    let mut state = open_state(config_dir)?;
+    let picked = networks
+        .iter()
+        .all(|name| matches!(state.state.networks.get(name), Some(s) if !s.is_empty()));
+    let routes = if picked {
+        Vec::new()
+    } else {
+        let mut host = netlink::Socket::new().wrap("open host netlink socket")?;
+        core_utils::get_route_subnets(&mut host)?
+    };
+    pick_default_subnets(&mut state.state, opts, &networks, routes)?;
+    state.save()
+}
+
+/// Like assign_default_subnets but from an empty state without looking at
+/// the host, the state file is not touched. This is used to validate a config.
+pub fn preview_default_subnets(opts: &mut types::NetworkOptions) -> NetavarkResult<()> {
+    let networks = default_subnet_networks(opts);
+    pick_default_subnets(&mut State::default(), opts, &networks, Vec::new())
+}
+
+/// Set the recorded subnets for the bridge networks without subnets.
+pub fn lookup_default_subnets(
+    opts: &mut types::NetworkOptions,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let networks = default_subnet_networks(opts);
+    if networks.is_empty() {
+        return Ok(());
+    }
+    let state = open_state(config_dir)?;
+    for name in networks {
+        if let Some(subnet) = state.state.networks.get(&name).and_then(|s| s.first()) {
+            if let Some(network) = opts.network_info.get_mut(&name) {
+                set_default_subnet(network, *subnet);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Bridge networks with the conntrack_zone option, with the zone when it is
+/// set to a number or None for true.
+fn conntrack_zone_networks(opts: &types::NetworkOptions) -> Vec<(String, Option<u16>)> {
+    opts.network_info
+        .iter()
+        .filter(|(_, network)| network.driver == constants::DRIVER_BRIDGE)
+        .filter_map(|(name, network)| {
+            let value = network
+                .options
+                .as_ref()?
+                .get(constants::OPTION_CONNTRACK_ZONE)?;
+            match value.as_str() {
+                "true" => Some((name.clone(), None)),
+                // invalid values are reported by the bridge driver
+                zone => zone.parse().ok().map(|zone| (name.clone(), Some(zone))),
+            }
+        })
+        .collect()
+}
+
+fn set_conntrack_zone(opts: &mut types::NetworkOptions, name: &str, zone: u16) {
+    if let Some(options) = opts
+        .network_info
+        .get_mut(name)
+        .and_then(|network| network.options.as_mut())
+    {
+        options.insert(
+            constants::OPTION_CONNTRACK_ZONE.to_string(),
+            zone.to_string(),
+        );
+    }
+}
+
+/// Replace conntrack_zone=true with the zone recorded for the network, a new
+/// network gets the zone derived from its name unless another network has it
+/// already, then the next free zone. Zones given as numbers are recorded as
+/// well so no network gets them allocated.
+pub fn assign_conntrack_zones(
+    opts: &mut types::NetworkOptions,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let networks = conntrack_zone_networks(opts);
+    if networks.is_empty() {
+        return Ok(());
+    }
+    let mut state = open_state(config_dir)?;
+    let zones = &mut state.state.conntrack_zones;
+    for (name, zone) in &networks {
+        if let Some(zone) = zone {
+            zones.insert(name.clone(), *zone);
+        }
+    }
+    for (name, zone) in networks {
+        if zone.is_some() {
+            continue;
+        }
+        let zone = match zones.get(&name) {
+            Some(zone) => *zone,
+            None => {
+                let used: HashSet<u16> = zones.values().copied().collect();
+                let zone = std::iter::once(core_utils::conntrack_zone_from_name(&name))
+                    .chain(1..=u16::MAX)
+                    .find(|zone| !used.contains(zone))
+                    .ok_or_else(|| NetavarkError::msg("no free conntrack zone"))?;
+                zones.insert(name.clone(), zone);
+                zone
+            }
+        };
+        set_conntrack_zone(opts, &name, zone);
+    }
+    state.save()
+}
+
+/// Replace conntrack_zone=true with the zone recorded for the network.
+pub fn lookup_conntrack_zones(
+    opts: &mut types::NetworkOptions,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let networks = conntrack_zone_networks(opts);
+    if !networks.iter().any(|(_, zone)| zone.is_none()) {
+        return Ok(());
+    }
+    let state = open_state(config_dir)?;
+    for (name, zone) in networks {
+        if zone.is_some() {
+            continue;
+        }
+        if let Some(zone) = state.state.conntrack_zones.get(&name) {
+            set_conntrack_zone(opts, &name, *zone);
+        }
+    }
+    Ok(())
+}
+
+/// The gc threshold sysctls the neigh_gc_thresh options of the bridge
+/// networks set, maps network name -> sysctl paths.
+fn neigh_gc_thresh_networks(opts: &types::NetworkOptions) -> Vec<(String, Vec<String>)> {
+    opts.network_info
+        .iter()
+        .filter(|(_, network)| network.driver == constants::DRIVER_BRIDGE)
+        .filter_map(|(name, network)| {
+            let options = network.options.as_ref()?;
+            let families: &[bool] = if network.ipv6_enabled {
+                &[false, true]
+            } else {
+                &[false]
+            };
+            let paths: Vec<String> = families
+                .iter()
+                .flat_map(|ipv6| {
+                    constants::OPTION_NEIGH_GC_THRESH
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, option)| options.contains_key(**option))
+                        .map(|(i, _)| core_utils::neigh_gc_thresh_sysctl(*ipv6, i))
+                })
+                .collect();
+            if paths.is_empty() {
+                None
+            } else {
+                Some((name.clone(), paths))
+            }
+        })
+        .collect()
+}
+
+/// Remember the neighbor gc thresholds before the neigh_gc_thresh options of
+/// the container networks raise them. Values recorded by an earlier network
+/// are kept, they are the ones before netavark changed them.
+pub fn record_neigh_gc_thresh(
+    opts: &types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let networks = neigh_gc_thresh_networks(opts);
+    let dir = match config_dir {
+        Some(dir) if !networks.is_empty() => dir,
+        _ => return Ok(()),
+    };
+    let mut state = StateFile::open(dir)?;
+    for (network, paths) in networks {
+        for path in paths {
+            if let Entry::Vacant(entry) = state.state.neigh_gc_thresh.entry(path) {
+                let value = sysctl::Ctl::new(entry.key())?.value_string()?;
+                entry.insert(value.trim().to_string());
+            }
+        }
+        state
+            .state
+            .neigh_gc_thresh_users
+            .entry(network.clone())
+            .or_default()
+            .insert(container_ids[&network].clone());
+    }
+    state.save()
+}
+
+/// Forget the containers of the networks with neigh_gc_thresh options, once
+/// no network uses them anymore the recorded thresholds are restored.
+pub fn release_neigh_gc_thresh(
+    opts: &types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let networks = neigh_gc_thresh_networks(opts);
+    let dir = match config_dir {
+        Some(dir) if !networks.is_empty() => dir,
+        _ => return Ok(()),
+    };
+    let mut state = StateFile::open(dir)?;
+    for (network, _) in networks {
+        if let Some(users) = state.state.neigh_gc_thresh_users.get_mut(&network) {
+            users.remove(&container_ids[&network]);
+            if users.is_empty() {
+                state.state.neigh_gc_thresh_users.remove(&network);
+            }
+        }
+    }
+    if state.state.neigh_gc_thresh_users.is_empty() {
+        for (path, value) in std::mem::take(&mut state.state.neigh_gc_thresh) {
+            debug!("Restoring {} to {}", path, value);
+            core_utils::CoreUtils::apply_host_sysctl_value(&path, &value)?;
+        }
+    }
+    state.save()
+}
+
+fn open_state(config_dir: &Option<String>) -> NetavarkResult<StateFile> {
+    match config_dir {
+        Some(dir) => StateFile::open(dir),
+        None => Err(NetavarkError::msg(
+            "ip address allocation requires --config to be specified",
+        )),
+    }
+}
+
+/// Allocate addresses for all networks without static ips and set them as
+/// static ips. Returns the names of these networks.
+pub fn allocate_static_ips(
+    opts: &mut types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) -> NetavarkResult<Vec<String>> {
+    let networks = allocated_networks(opts);
+    if networks.is_empty() {
+        return Ok(networks);
+    }
+    let mut state = open_state(config_dir)?;
+    assign_static_ips(&mut state.state, opts, container_ids, &networks)?;
+    state.save()?;
+    Ok(networks)
+}
+
+/// Allocate addresses for all networks without static ips from an empty
+/// state and set them as static ips, the state file is not touched.
+/// This is used to validate a config without a config directory.
+pub fn preview_static_ips(
+    opts: &mut types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+) -> NetavarkResult<()> {
+    let networks = allocated_networks(opts);
+    assign_static_ips(&mut State::default(), opts, container_ids, &networks)
+}
+
+fn assign_static_ips(
+    state: &mut State,
+    opts: &mut types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+    networks: &[String],
+) -> NetavarkResult<()> {
+    for name in networks {
+        let ips = state.allocate_ips(&opts.network_info[name], &container_ids[name])?;
+        if let Some(per_net) = opts.networks.get_mut(name) {
+            per_net.static_ips = Some(ips);
+        }
+    }
+    Ok(())
+}
+
+/// Set the previously allocated addresses as static ips for all networks
+/// without static ips. Returns the names of these networks.
+pub fn lookup_static_ips(
+    opts: &mut types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) -> NetavarkResult<Vec<String>> {
+    let networks = allocated_networks(opts);
+    if networks.is_empty() {
+        return Ok(networks);
+    }
+    let state = open_state(config_dir)?;
+    for name in networks.iter() {
+        let ips = state
+            .state
+            .get_ips(&opts.network_info[name], &container_ids[name]);
+        // only set them if we have an address for every subnet,
+        // otherwise keep the original error from the driver
+        if let Some(ips) = ips.into_iter().collect::<Option<Vec<_>>>() {
+            if let Some(per_net) = opts.networks.get_mut(name) {
+                per_net.static_ips = Some(ips);
+            }
+        }
+    }
+    Ok(networks)
+}
+
+/// Release the allocated addresses for the given networks.
+pub fn release_static_ips(
+    networks: &[String],
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let mut state = open_state(config_dir)?;
+    for name in networks {
+        if let Some(id) = container_ids.get(name) {
+            state.state.release_ips(name, id);
+        }
+    }
+    state.save()
+}
+
+/// Remember the subnets of the networks so other networks can refer to
+/// them by name in the allow_from and allow_to options.
+pub fn record_network_subnets(
+    opts: &types::NetworkOptions,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let dir = match config_dir {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let mut state = StateFile::open(dir)?;
+    let mut changed = false;
+    for (name, network) in &opts.network_info {
+        let subnets: Vec<IpNet> = network.subnets.iter().flatten().map(|s| s.subnet).collect();
+        if state.state.networks.get(name) != Some(&subnets) {
+            state.state.networks.insert(name.clone(), subnets);
+            changed = true;
+        }
+    }
+    if changed {
+        state.save()?;
+    }
+    Ok(())
+}
+
+/// Replace the network names in the allow_from and allow_to options with
+/// the subnets of these networks. Names are looked up in the given networks
+/// first and then in the networks recorded in the state.
+pub fn resolve_allowed_networks(
+    opts: &mut types::NetworkOptions,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    const OPTIONS: [&str; 2] = [constants::OPTION_ALLOW_FROM, constants::OPTION_ALLOW_TO];
+    let has_names = opts.network_info.values().any(|network| {
+        OPTIONS.iter().any(|name| {
+            matches!(network.options.as_ref().and_then(|o| o.get(*name)),
+                Some(value) if core_utils::split_option_list(value).any(|e| e.parse::<IpNet>().is_err()))
+        })
+    });
+    if !has_names {
+        return Ok(());
+    }
+
+    let mut known = match config_dir {
+        Some(dir) => StateFile::open(dir)?.state.networks,
+        None => BTreeMap::new(),
+    };
+    for (name, network) in &opts.network_info {
+        known.insert(
+            name.clone(),
+            network.subnets.iter().flatten().map(|s| s.subnet).collect(),
+        );
+    }
+
+    for network in opts.network_info.values_mut() {
+        let options = match network.options.as_mut() {
+            Some(o) => o,
+            None => continue,
+        };
+        for name in OPTIONS {
+            let value = match options.get(name) {
+                Some(v) => v,
+                None => continue,
+            };
+            let mut resolved: Vec<String> = Vec::new();
+            for entry in core_utils::split_option_list(value) {
+                if entry.parse::<IpNet>().is_ok() {
+                    resolved.push(entry.to_string());
+                    continue;
+                }
+                match known.get(entry) {
+                    Some(subnets) => resolved.extend(subnets.iter().map(|s| s.to_string())),
+                    None => {
+                        return Err(NetavarkError::Message(format!(
+                            "invalid {} entry {:?}: unknown network",
+                            name, entry
+                        )))
+                    }
+                }
+            }
+            options.insert(name.to_string(), resolved.join(","));
+        }
+    }
+    Ok(())
+}
+
+/// Names of the port forwarding chains setup creates for the configuration.
+fn port_forward_chain_names(
+    opts: &types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+) -> Vec<(String, String)> {
+    if opts.port_mappings.iter().flatten().next().is_none() {
+        return Vec::new();
+    }
+    opts.network_info
+        .iter()
+        .filter(|(_, network)| network.driver == constants::DRIVER_BRIDGE)
+        .filter_map(|(name, _)| {
+            let id = container_ids.get(name)?;
+            Some((port_forward_chain_name(name, id), name.clone()))
+        })
+        .collect()
+}
+
+/// Remember the port forwarding chains of the container so gc can find
+/// them once the network namespace is gone.
+pub fn record_port_forward_chains(
+    opts: &types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+    netns_path: &str,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let chains = port_forward_chain_names(opts, container_ids);
+    let dir = match config_dir {
+        Some(dir) if !chains.is_empty() => dir,
+        _ => return Ok(()),
+    };
+    let meta = wrap!(
+        fs::metadata(netns_path),
+        format!("stat netns {:?}", netns_path)
+    )?;
+    let mut state = StateFile::open(dir)?;
+    for (chain, network) in chains {
+        let entry = PortForwardChain {
+            container_id: container_ids[&network].clone(),
+            network,
+            netns_path: netns_path.to_string(),
+            netns_dev: meta.dev(),
+            netns_ino: meta.ino(),
+        };
+        state.state.port_forward_chains.insert(chain, entry);
+    }
+    state.save()
+}
+
+/// Forget the port forwarding chains of the container after teardown.
+pub fn forget_port_forward_chains(
+    opts: &types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let chains = port_forward_chain_names(opts, container_ids);
+    let dir = match config_dir {
+        Some(dir) if !chains.is_empty() => dir,
+        _ => return Ok(()),
+    };
+    let mut state = StateFile::open(dir)?;
+    let mut changed = false;
+    for (chain, _) in chains {
+        changed |= state.state.port_forward_chains.remove(&chain).is_some();
+    }
+    if changed {
+        state.save()?;
+    }
+    Ok(())
+}
+
+/// Remember the veth pairs created by setup, the map key is the network name.
+pub fn record_veth_pairs(
+    pairs: &HashMap<String, VethPair>,
+    container_ids: &HashMap<String, String>,
+    netns_path: &str,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let dir = match config_dir {
+        Some(dir) if !pairs.is_empty() => dir,
+        _ => return Ok(()),
+    };
+    let meta = wrap!(
+        fs::metadata(netns_path),
+        format!("stat netns {:?}", netns_path)
+    )?;
+    let netns = NetnsId {
+        path: netns_path.to_string(),
+        dev: meta.dev(),
+        ino: meta.ino(),
+    };
+    let mut state = StateFile::open(dir)?;
+    for (network, pair) in pairs {
+        let id = &container_ids[network];
+        state
+            .state
+            .veth_pairs
+            .entry(id.clone())
+            .or_default()
+            .insert(network.clone(), pair.clone());
+        state.state.veth_netns.insert(id.clone(), netns.clone());
+    }
+    state.save()
+}
+
+/// Forget the veth pairs of the container networks after teardown.
+pub fn forget_veth_pairs(
+    opts: &types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let bridges = opts
+        .network_info
+        .values()
+        .any(|n| n.driver == constants::DRIVER_BRIDGE);
+    let dir = match config_dir {
+        Some(dir) if bridges => dir,
+        _ => return Ok(()),
+    };
+    let mut state = StateFile::open(dir)?;
+    let mut changed = false;
+    for name in opts.network_info.keys() {
+        let id = match container_ids.get(name) {
+            Some(id) => id,
+            None => continue,
+        };
+        if let Some(pairs) = state.state.veth_pairs.get_mut(id) {
+            changed |= pairs.remove(name).is_some();
+            if pairs.is_empty() {
+                state.state.veth_pairs.remove(id);
+                state.state.veth_netns.remove(id);
+            }
+        }
+    }
+    if changed {
+        state.save()?;
+    }
+    Ok(())
+}
+
+/// Delete the host side of the recorded veth pairs whose container namespace no
+/// longer exists, e.g. after a crash before teardown, and forget them. Only an
+/// interface with the recorded name and index is deleted. Returns the names of
+/// the deleted interfaces.
+pub fn remove_orphaned_veths(config_dir: &Option<String>) -> NetavarkResult<Vec<String>> {
+    let mut state = match config_dir {
+        Some(dir) => StateFile::open(dir)?,
+        None => {
+            return Err(NetavarkError::msg(
+                "removing orphaned veths requires --config to be specified",
+            ))
+        }
+    };
+    let used = used_netns();
+    let orphaned: Vec<String> = state
+        .state
+        .veth_netns
+        .iter()
+        .filter(|(_, ns)| !netns_exists(&ns.path, ns.dev, ns.ino, &used))
+        .map(|(id, _)| id.clone())
+        .collect();
+    if orphaned.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut host = netlink::Socket::new().wrap("open host netlink socket")?;
+    let mut removed = Vec::new();
+    for id in orphaned {
+        for pair in state
+            .state
+            .veth_pairs
+            .remove(&id)
+            .unwrap_or_default()
+            .values()
+        {
+            let name = match host.get_link(netlink::LinkID::ID(pair.host_index)) {
+                Ok(link) => link.nlas.into_iter().find_map(|nla| match nla {
+                    netlink_packet_route::nlas::link::Nla::IfName(name) => Some(name),
+                    _ => None,
+                }),
+                // the interface is already gone
+                Err(_) => None,
+            };
+            if name.as_deref() != Some(pair.host_interface.as_str()) {
+                continue;
+            }
+            debug!(
+                "Removing veth {} of container {} whose namespace is gone",
+                pair.host_interface, id
+            );
+            host.del_link(netlink::LinkID::ID(pair.host_index))
+                .wrap(format!("delete orphaned veth {}", pair.host_interface))?;
+            removed.push(pair.host_interface.clone());
+        }
+        state.state.veth_netns.remove(&id);
+    }
+    state.save()?;
+    Ok(removed)
+}
+
+/// Remember the container routing tables used by the networks, the map key is
+/// the network name.
+pub fn record_route_tables(
+    tables: &HashMap<String, u32>,
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let dir = match config_dir {
+        Some(dir) if !tables.is_empty() => dir,
+        _ => return Ok(()),
+    };
+    let mut state = StateFile::open(dir)?;
+    for (network, table) in tables {
+        state
+            .state
+            .route_tables
+            .entry(container_ids[network].clone())
+            .or_default()
+            .insert(network.clone(), *table);
+    }
+    state.save()
+}
+
+/// Forget the routing tables of the container networks after teardown and
+/// return the tables no other network of their container uses anymore.
+pub fn forget_route_tables(
+    opts: &types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) -> NetavarkResult<Vec<u32>> {
+    let dir = match config_dir {
+        Some(dir) => dir,
+        None => return Ok(Vec::new()),
+    };
+    let mut state = StateFile::open(dir)?;
+    let mut unused = Vec::new();
+    let mut changed = false;
+    for name in opts.network_info.keys() {
+        let id = match container_ids.get(name) {
+            Some(id) => id,
+            None => continue,
+        };
+        if let Some(networks) = state.state.route_tables.get_mut(id) {
+            if let Some(table) = networks.remove(name) {
+                changed = true;
+                if !networks.values().any(|t| *t == table) && !unused.contains(&table) {
+                    unused.push(table);
+                }
+            }
+            if networks.is_empty() {
+                state.state.route_tables.remove(id);
+            }
+        }
+    }
+    if changed {
+        state.save()?;
+    }
+    Ok(unused)
+}
+
+/// Remember the firewall rules setup installed and their chains, the map key
+/// is the network name.
+pub fn record_firewall_rules(
+    rules: &HashMap<String, Vec<FirewallRule>>,
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let dir = match config_dir {
+        Some(dir) if !rules.is_empty() => dir,
+        _ => return Ok(()),
+    };
+    let mut state = StateFile::open(dir)?;
+    for (network, rules) in rules {
+        state
+            .state
+            .firewall_chains
+            .entry(container_ids[network].clone())
+            .or_default()
+            .insert(network.clone(), FirewallChains::from_rules(rules));
+        state
+            .state
+            .firewall_rules
+            .entry(container_ids[network].clone())
+            .or_default()
+            .insert(network.clone(), rules.clone());
+    }
+    state.save()
+}
+
+/// Replace the recorded firewall rules of the network containing the given
+/// comment with the new rules, for every container with recorded rules.
+pub fn replace_firewall_rules(
+    network: &str,
+    comment: &str,
+    rules: &[FirewallRule],
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let dir = match config_dir {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let mut state = StateFile::open(dir)?;
+    let quoted = format!("--comment '{}'", comment);
+    for recorded in state
+        .state
+        .firewall_rules
+        .values_mut()
+        .filter_map(|networks| networks.get_mut(network))
+    {
+        recorded.retain(|rule| !rule.rule.contains(&quoted));
+        recorded.extend(rules.iter().cloned());
+    }
+    state.save()
+}
+
+/// Get the recorded firewall rules of the container networks, the map key is
+/// the network name. Networks without recorded rules are missing.
+pub fn lookup_firewall_rules(
+    opts: &types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) -> NetavarkResult<HashMap<String, Vec<FirewallRule>>> {
+    let dir = match config_dir {
+        Some(dir) => dir,
+        None => return Ok(HashMap::new()),
+    };
+    let state = StateFile::open(dir)?;
+    let mut rules = HashMap::new();
+    for name in opts.network_info.keys() {
+        let recorded = container_ids
+            .get(name)
+            .and_then(|id| state.state.firewall_rules.get(id))
+            .and_then(|networks| networks.get(name));
+        if let Some(recorded) = recorded {
+            rules.insert(name.clone(), recorded.clone());
+        }
+    }
+    Ok(rules)
+}
+
+/// Forget the recorded firewall rules and chains of the container networks
+/// after teardown.
+pub fn forget_firewall_rules(
+    opts: &types::NetworkOptions,
+    container_ids: &HashMap<String, String>,
+    config_dir: &Option<String>,
+) -> NetavarkResult<()> {
+    let dir = match config_dir {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let mut state = StateFile::open(dir)?;
+    let mut changed = false;
+    for name in opts.network_info.keys() {
+        let id = match container_ids.get(name) {
+            Some(id) => id,
+            None => continue,
+        };
+        if let Some(networks) = state.state.firewall_rules.get_mut(id) {
+            changed |= networks.remove(name).is_some();
+            if networks.is_empty() {
+                state.state.firewall_rules.remove(id);
+            }
+        }
+        if let Some(networks) = state.state.firewall_chains.get_mut(id) {
+            changed |= networks.remove(name).is_some();
+            if networks.is_empty() {
+                state.state.firewall_chains.remove(id);
+            }
+        }
+    }
+    if changed {
+        state.save()?;
+    }
+    Ok(())
+}
@@ -1,8 +1,11 @@
 // Crate contains the types which are accepted by netavark.
 
+use crate::firewall::FirewallChains;
 use ipnet::IpNet;
+use serde::de::{Deserialize, Deserializer, Error};
 use std::collections::HashMap;
 use std::net::IpAddr;
+use std::str::FromStr;
 
 // Network describes the Network attributes.
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -57,17 +60,42 @@ pub struct Network {
     /// Network DNS servers for aardvark-dns.
     #[serde(rename = "network_dns_servers")]
     pub network_dns_servers: Option<Vec<IpAddr>>,
+
+    /// Extra firewall rules appended after the rules netavark creates for
+    /// this network. Only supported by the bridge driver with iptables.
+    #[serde(
+        rename = "extra_rules",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub extra_rules: Option<Vec<ExtraRule>>,
+}
+
+/// ExtraRule is a raw iptables rule added to one of the network chains.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExtraRule {
+    /// Chain of the rule, "forward" for the NETAVARK_FORWARD filter chain
+    /// or "nat" for the nat chain of the network.
+    #[serde(rename = "chain")]
+    pub chain: String,
+
+    /// The rule matches and target, e.g. "-p tcp --dport 22 -j DROP".
+    #[serde(rename = "rule")]
+    pub rule: String,
 }
 
 /// NetworkOptions for a given container.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NetworkOptions {
     /// The container id, used for iptables comments and ipam allocation.
+    /// If unset, an id is derived from the network name and interface name.
+    /// It is required when port mappings are set.
     #[serde(rename = "container_id")]
-    pub container_id: String,
+    pub container_id: Option<String>,
 
-    /// The container name, used as dns name.
-    #[serde(rename = "container_name")]
+    /// The container name, used as dns name and to make firewall rule
+    /// comments and log messages readable. Optional.
+    #[serde(rename = "container_name", default)]
     pub container_name: String,
 
     /// The options used to create the interfaces with.
@@ -82,8 +110,13 @@ pub struct NetworkOptions {
     #[serde(rename = "network_info")]
     pub network_info: HashMap<String, Network>,
 
-    /// The port mappings for this container.
-    #[serde(rename = "port_mappings")]
+    /// The port mappings for this container. Each mapping is either an
+    /// object or a string in the Docker -p format, e.g. "0.0.0.0:8080:80/tcp".
+    #[serde(
+        rename = "port_mappings",
+        default,
+        deserialize_with = "deserialize_port_mappings"
+    )]
     pub port_mappings: Option<Vec<PortMapping>>,
 
     /// Custom DNS servers for aardvark-dns.
@@ -106,12 +139,43 @@ pub struct PerNetworkOptions {
     pub interface_name: String,
 
     /// StaticIPs for this container.
+    /// The ips are assigned to the subnets of the network in order.
     #[serde(rename = "static_ips")]
     pub static_ips: Option<Vec<IpAddr>>,
 
+    /// StaticIPs for this container keyed by the subnet of the network
+    /// they belong to. Takes precedence over "static_ips" when set.
+    #[serde(
+        rename = "static_ips_by_subnet",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub static_ips_by_subnet: Option<HashMap<IpNet, IpAddr>>,
+
     /// MAC address for the container interface.
     #[serde(rename = "static_mac")]
     pub static_mac: Option<String>,
+
+    /// PVID for the container port on the bridge, untagged traffic of the
+    /// container is assigned to this vlan. Requires the vlan_filtering
+    /// network option.
+    #[serde(rename = "pvid")]
+    pub pvid: Option<u16>,
+
+    /// Tagged vlans the container port on the bridge is a member of.
+    /// Requires the vlan_filtering network option.
+    #[serde(rename = "tagged_vlans")]
+    pub tagged_vlans: Option<Vec<u16>>,
+
+    /// Existing host interface that is moved into the container namespace and
+    /// renamed to the interface name instead of creating a new veth pair.
+    /// On teardown it is renamed back and moved back to the host.
+    #[serde(rename = "adopt_interface", skip_serializing_if = "Option::is_none")]
+    pub adopt_interface: Option<String>,
+
+    /// Sysctls set in the container namespace after the interface setup,
+    /// they take precedence over the sysctls network option.
+    #[serde(rename = "sysctls", skip_serializing_if = "Option::is_none")]
+    pub sysctls: Option<HashMap<String, String>>,
 }
 
 /// PortMapping is one or more ports that will be mapped into the container.
@@ -149,6 +213,111 @@ pub struct PortMapping {
     /// 65536.
     #[serde(rename = "range")]
     pub range: u16,
+
+    /// AllowedSources restricts the port to connections coming from the
+    /// given source subnets. Connections from all other sources are not
+    /// forwarded to the container.
+    /// If unset or empty, connections from all sources are forwarded.
+    #[serde(rename = "allowed_sources")]
+    pub allowed_sources: Option<Vec<IpNet>>,
+}
+
+const PORT_PROTOCOLS: [&str; 3] = ["tcp", "udp", "sctp"];
+
+/// Parse a port mapping in the Docker -p format
+/// `[HOST_IP:]HOST_PORT:CONTAINER_PORT[/PROTOCOL]`. The ports can be ranges
+/// of the same length like `8080-8081:80-81`, ipv6 host addresses must be in
+/// brackets and the protocol defaults to tcp.
+impl FromStr for PortMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |msg: &str| format!("invalid port mapping {:?}: {}", s, msg);
+
+        let (spec, protocol) = s.rsplit_once('/').unwrap_or((s, "tcp"));
+        if protocol
+            .split(',')
+            .any(|proto| !PORT_PROTOCOLS.contains(&proto))
+        {
+            return Err(invalid(&format!(
+                "unknown protocol {:?}, must be tcp, udp or sctp",
+                protocol
+            )));
+        }
+
+        let (host_ip, ports) = match spec.strip_prefix('[') {
+            Some(rest) => rest
+                .split_once("]:")
+                .ok_or_else(|| invalid("missing closing bracket of the host ip"))?,
+            None => match spec.matches(':').count() {
+                0 | 1 => ("", spec),
+                2 => spec.split_once(':').unwrap_or_default(),
+                _ => return Err(invalid("ipv6 host ips must be in brackets")),
+            },
+        };
+        if !host_ip.is_empty() && host_ip.parse::<IpAddr>().is_err() {
+            return Err(invalid(&format!("invalid host ip {:?}", host_ip)));
+        }
+
+        let (host_ports, container_ports) = match ports.split_once(':') {
+            Some((host, container)) if !host.is_empty() => (host, container),
+            _ => return Err(invalid("a host port is required")),
+        };
+        let (host_port, host_end) = parse_port_range(host_ports).map_err(|e| invalid(&e))?;
+        let (container_port, container_end) =
+            parse_port_range(container_ports).map_err(|e| invalid(&e))?;
+        if host_end - host_port != container_end - container_port {
+            return Err(invalid(
+                "host and container port ranges must have the same length",
+            ));
+        }
+
+        Ok(PortMapping {
+            container_port,
+            host_ip: host_ip.to_string(),
+            host_port,
+            protocol: protocol.to_string(),
+            range: host_end - host_port + 1,
+            allowed_sources: None,
+        })
+    }
+}
+
+// parse a port or a range of ports like 8080-8081, returns the first and last port
+fn parse_port_range(ports: &str) -> Result<(u16, u16), String> {
+    let parse = |port: &str| match port.parse::<u16>() {
+        Ok(p) if p > 0 => Ok(p),
+        _ => Err(format!("invalid port {:?}", port)),
+    };
+    let (start, end) = match ports.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(ports)?, parse(ports)?),
+    };
+    if start > end {
+        return Err(format!("invalid port range {:?}", ports));
+    }
+    Ok((start, end))
+}
+
+// port mappings are given as objects or as strings in the Docker -p format
+fn deserialize_port_mappings<'de, D>(deserializer: D) -> Result<Option<Vec<PortMapping>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values: Option<Vec<serde_json::Value>> = Option::deserialize(deserializer)?;
+    let values = match values {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let mut ports = Vec::with_capacity(values.len());
+    for value in values {
+        let port = match value {
+            serde_json::Value::String(s) => s.parse().map_err(D::Error::custom)?,
+            value => serde_json::from_value(value).map_err(D::Error::custom)?,
+        };
+        ports.push(port);
+    }
+    Ok(Some(ports))
 }
 
 /// StatusBlock contains the network information about a container
@@ -170,6 +339,35 @@ pub struct StatusBlock {
     /// The map key is the interface name.
     #[serde(rename = "interfaces")]
     pub interfaces: Option<HashMap<String, NetInterface>>,
+
+    /// Gateway contains the gateways of the network the container uses,
+    /// one per subnet with a gateway.
+    #[serde(rename = "gateway", skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<Vec<IpAddr>>,
+
+    /// GatewayReachable is the result of the gateway reachability check.
+    /// Only set when setup was called with --verify.
+    #[serde(rename = "gateway_reachable", skip_serializing_if = "Option::is_none")]
+    pub gateway_reachable: Option<bool>,
+
+    /// Warnings contains non fatal issues found while setting up the network.
+    #[serde(rename = "warnings", skip_serializing_if = "Option::is_none")]
+    pub warnings: Option<Vec<String>>,
+
+    /// ChangedSysctls contains the host sysctls which were modified while
+    /// setting up the network.
+    #[serde(rename = "changed_sysctls", skip_serializing_if = "Option::is_none")]
+    pub changed_sysctls: Option<Vec<SysctlChange>>,
+
+    /// NetnsInode is the inode number of the container network namespace,
+    /// it identifies the namespace for other tools.
+    #[serde(rename = "netns_inode", skip_serializing_if = "Option::is_none")]
+    pub netns_inode: Option<u64>,
+
+    /// FirewallChains contains the netavark chains the firewall rules of the
+    /// network are in. Only set when setup was called with --verbose.
+    #[serde(rename = "firewall_chains", skip_serializing_if = "Option::is_none")]
+    pub firewall_chains: Option<FirewallChains>,
 }
 
 /// NetInterface contains the settings for a given network interface.
@@ -182,6 +380,31 @@ pub struct NetInterface {
     /// Subnets list of assigned subnets with their gateway.
     #[serde(rename = "subnets")]
     pub subnets: Option<Vec<NetAddress>>,
+
+    /// Interface counters right after setup, only set with setup --stats.
+    #[serde(rename = "stats", skip_serializing_if = "Option::is_none")]
+    pub stats: Option<InterfaceStats>,
+}
+
+/// SysctlChange contains a host sysctl netavark modified.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SysctlChange {
+    pub name: String,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+/// InterfaceStats contains the packet counters of an interface.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct InterfaceStats {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
 }
 
 /// NetAddress contains the ip address, subnet and gateway.
@@ -1,10 +1,391 @@
-use crate::error::NetavarkResult;
+use crate::error::{NetavarkError, NetavarkResult};
+use crate::network::constants;
+use crate::network::types::{ExtraRule, Network, PerNetworkOptions, PortMapping};
+use ipnet::IpNet;
 use log::debug;
+use nix::sys::statfs;
 use std::fs::File;
+use std::io;
+use std::net::IpAddr;
+use std::os::unix::io::AsRawFd;
 
+/// chains extra rules can be added to
+pub const EXTRA_RULE_CHAIN_FORWARD: &str = "forward";
+pub const EXTRA_RULE_CHAIN_NAT: &str = "nat";
+
+/// iptables options which would operate on something else than the rule
+const IPTABLES_COMMAND_OPTIONS: &[(&str, &str)] = &[
+    ("-A", "--append"),
+    ("-C", "--check"),
+    ("-D", "--delete"),
+    ("-E", "--rename-chain"),
+    ("-F", "--flush"),
+    ("-I", "--insert"),
+    ("-L", "--list"),
+    ("-N", "--new-chain"),
+    ("-P", "--policy"),
+    ("-R", "--replace"),
+    ("-S", "--list-rules"),
+    ("-X", "--delete-chain"),
+    ("-Z", "--zero"),
+    ("-t", "--table"),
+    ("-g", "--goto"),
+    ("-w", "--wait"),
+    ("-W", "--wait-interval"),
+    ("-M", "--modprobe"),
+];
+const IPTABLES_JUMP_OPTION: (&str, &str) = ("-j", "--jump");
+
+/// ioctl returning the CLONE_NEW* type of a namespace file, _IO(0xb7, 0x3)
+const NS_GET_NSTYPE: u32 = 0xb703;
+
+/// Check that the path exists and is a network namespace file.
 pub fn ns_checks(file: &str) -> NetavarkResult<()> {
     debug!("{:?}", "Validating network namespace...");
-    // TODO check for FS_MAGIC
-    let _ = File::open(file)?.metadata()?;
+    let ns = match File::open(file) {
+        Ok(ns) => ns,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(NetavarkError::Message(format!(
+                "namespace path {:?} does not exist",
+                file
+            )))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let fs = statfs::fstatfs(&ns).map_err(io::Error::from)?;
+    if fs.filesystem_type() != statfs::NSFS_MAGIC {
+        return Err(NetavarkError::Message(format!(
+            "{:?} is not a namespace file",
+            file
+        )));
+    }
+    let ns_type = unsafe { libc::ioctl(ns.as_raw_fd(), NS_GET_NSTYPE as _) };
+    if ns_type < 0 {
+        return Err(NetavarkError::wrap(
+            "get namespace type",
+            io::Error::last_os_error().into(),
+        ));
+    }
+    if ns_type != libc::CLONE_NEWNET {
+        return Err(NetavarkError::Message(format!(
+            "{:?} is a {} namespace, not a network namespace",
+            file,
+            ns_type_name(ns_type)
+        )));
+    }
+    Ok(())
+}
+
+fn ns_type_name(ns_type: i32) -> &'static str {
+    match ns_type {
+        libc::CLONE_NEWNS => "mount",
+        libc::CLONE_NEWUTS => "uts",
+        libc::CLONE_NEWIPC => "ipc",
+        libc::CLONE_NEWUSER => "user",
+        libc::CLONE_NEWPID => "pid",
+        libc::CLONE_NEWCGROUP => "cgroup",
+        _ => "unknown",
+    }
+}
+
+/// Check that the name is a syntactically valid dns name, e.g. for search domains.
+pub fn validate_dns_name(name: &str) -> NetavarkResult<()> {
+    let err = |reason: &str| {
+        Err(NetavarkError::Message(format!(
+            "invalid dns name \"{}\": {}",
+            name, reason
+        )))
+    };
+    // a single trailing dot marks a fully qualified name
+    let trimmed = name.strip_suffix('.').unwrap_or(name);
+    if trimmed.is_empty() {
+        return err("name is empty");
+    }
+    if trimmed.len() > 253 {
+        return err("name is longer than 253 characters");
+    }
+    for label in trimmed.split('.') {
+        if label.is_empty() {
+            return err("empty label");
+        }
+        if label.len() > 63 {
+            return err("label is longer than 63 characters");
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return err("label must not start or end with a hyphen");
+        }
+        if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return err("label must only contain letters, digits and hyphens");
+        }
+    }
     Ok(())
 }
+
+/// Check the subnets, static ips and aliases of the network. The drivers
+/// trust the caller with these, so they are only checked before setup with
+/// the validate command. All problems are returned.
+pub fn validate_network_addresses(
+    network: &Network,
+    per_network_opts: &PerNetworkOptions,
+) -> Vec<NetavarkError> {
+    let mut problems = Vec::new();
+    let subnets: Vec<_> = network.subnets.iter().flatten().collect();
+    for subnet in &subnets {
+        if let Some(gw) = subnet.gateway {
+            if !subnet.subnet.contains(&gw) || gw == subnet.subnet.network() {
+                problems.push(NetavarkError::Message(format!(
+                    "gateway {} is not a host address of subnet {}",
+                    gw, subnet.subnet
+                )));
+            }
+        }
+    }
+
+    problems.extend(
+        static_ip_mismatches(network, per_network_opts)
+            .into_iter()
+            .map(NetavarkError::Message),
+    );
+
+    if network.dns_enabled {
+        for alias in per_network_opts.aliases.iter().flatten() {
+            if let Err(e) = validate_dns_name(alias) {
+                problems.push(e);
+            }
+        }
+    }
+    problems
+}
+
+/// Describe every static ip which does not match its subnet. With host-local
+/// ipam the static ips are assigned to the subnets in order, so each of them
+/// must be in the subnet at its position and in no other subnet, and every
+/// subnet needs one. Other ipam drivers do not use the static ips.
+pub fn static_ip_mismatches(
+    network: &Network,
+    per_network_opts: &PerNetworkOptions,
+) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    let host_local = matches!(
+        network
+            .ipam_options
+            .as_ref()
+            .and_then(|map| map.get("driver"))
+            .map(|d| d.as_str()),
+        None | Some(constants::IPAM_HOSTLOCAL)
+    );
+    let by_subnet = per_network_opts
+        .static_ips_by_subnet
+        .as_ref()
+        .map_or(false, |ips| !ips.is_empty());
+    let static_ips = match &per_network_opts.static_ips {
+        Some(ips) if host_local && !by_subnet => ips,
+        _ => return mismatches,
+    };
+    let subnets: Vec<_> = network.subnets.iter().flatten().collect();
+    for (idx, ip) in static_ips.iter().enumerate() {
+        let containing: Vec<String> = subnets
+            .iter()
+            .filter(|s| s.subnet.contains(ip))
+            .map(|s| s.subnet.to_string())
+            .collect();
+        match subnets.get(idx) {
+            _ if containing.len() > 1 => mismatches.push(format!(
+                "static ip {} is in more than one subnet: {}",
+                ip,
+                containing.join(", ")
+            )),
+            Some(subnet) if !subnet.subnet.contains(ip) => mismatches.push(format!(
+                "static ip {} is not in subnet {}",
+                ip, subnet.subnet
+            )),
+            Some(_) => {}
+            None => mismatches.push(format!("static ip {} has no matching subnet", ip)),
+        }
+    }
+    for subnet in subnets.iter().skip(static_ips.len()) {
+        mismatches.push(format!(
+            "no static ip provided for subnet {}",
+            subnet.subnet
+        ));
+    }
+    mismatches
+}
+
+/// Check that no two port mappings forward the same host port, protocol and
+/// host ip, their DNAT rules would conflict. Ranges overlapping other ranges
+/// or single ports are conflicts as well. An empty host ip binds all
+/// addresses, an unspecified one all addresses of its ip family.
+pub fn validate_port_mappings(port_mappings: &[PortMapping]) -> NetavarkResult<()> {
+    for (idx, first) in port_mappings.iter().enumerate() {
+        for second in &port_mappings[idx + 1..] {
+            if !host_ips_overlap(&first.host_ip, &second.host_ip) {
+                continue;
+            }
+            let (first_start, first_end) = host_port_range(first);
+            let (second_start, second_end) = host_port_range(second);
+            if first_start > second_end || second_start > first_end {
+                continue;
+            }
+            let protocols: Vec<&str> = first.protocol.split(',').map(str::trim).collect();
+            if let Some(protocol) = second
+                .protocol
+                .split(',')
+                .map(str::trim)
+                .find(|p| protocols.contains(p))
+            {
+                return Err(NetavarkError::Message(format!(
+                    "port mappings {} and {} overlap on host port {}/{}",
+                    describe_port_mapping(first),
+                    describe_port_mapping(second),
+                    first_start.max(second_start),
+                    protocol
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// First and last host port of the mapping, a range of 0 is a single port.
+fn host_port_range(mapping: &PortMapping) -> (u32, u32) {
+    let start = u32::from(mapping.host_port);
+    (start, start + u32::from(mapping.range.max(1)) - 1)
+}
+
+fn host_ips_overlap(first: &str, second: &str) -> bool {
+    match (first.parse::<IpAddr>(), second.parse::<IpAddr>()) {
+        (Ok(a), Ok(b)) => {
+            a == b || (a.is_ipv4() == b.is_ipv4() && (a.is_unspecified() || b.is_unspecified()))
+        }
+        // empty, all addresses
+        _ => true,
+    }
+}
+
+/// Describe the mapping in the Docker format it can be given in.
+fn describe_port_mapping(mapping: &PortMapping) -> String {
+    let ports = |start: u16| match mapping.range {
+        0 | 1 => start.to_string(),
+        range => format!("{}-{}", start, u32::from(start) + u32::from(range) - 1),
+    };
+    let host_ip = match mapping.host_ip.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:", ip),
+        Ok(ip) => format!("{}:", ip),
+        Err(_) => String::new(),
+    };
+    format!(
+        "{}{}:{}/{}",
+        host_ip,
+        ports(mapping.host_port),
+        ports(mapping.container_port),
+        mapping.protocol
+    )
+}
+
+/// Check that the extra rule only uses the allowed network chains and
+/// targets, so it cannot jump to any other chain.
+pub fn validate_extra_rule(extra: &ExtraRule) -> NetavarkResult<()> {
+    let err = |reason: String| {
+        Err(NetavarkError::Message(format!(
+            "invalid extra rule \"{}\": {}",
+            extra.rule, reason
+        )))
+    };
+    let targets: &[&str] = match extra.chain.as_str() {
+        EXTRA_RULE_CHAIN_FORWARD => &["ACCEPT", "DROP", "REJECT", "RETURN", "LOG"],
+        EXTRA_RULE_CHAIN_NAT => &["ACCEPT", "RETURN", "LOG"],
+        chain => {
+            return err(format!(
+                "unknown chain \"{}\", must be {} or {}",
+                chain, EXTRA_RULE_CHAIN_FORWARD, EXTRA_RULE_CHAIN_NAT
+            ))
+        }
+    };
+    if extra.rule.trim().is_empty() {
+        return err("rule is empty".to_string());
+    }
+    if extra.rule.chars().any(|c| c.is_control()) {
+        return err("rule must not contain control characters".to_string());
+    }
+
+    let mut tokens = extra.rule.split_whitespace();
+    while let Some(token) = tokens.next() {
+        let (option, value) = match iptables_option(token) {
+            Some(option) => option,
+            None => continue,
+        };
+        if IPTABLES_COMMAND_OPTIONS.contains(&option) {
+            return err(format!("option {} is not allowed", token));
+        }
+        if option == IPTABLES_JUMP_OPTION {
+            match value.or_else(|| tokens.next()) {
+                Some(target) if targets.contains(&target) => {}
+                Some(target) => {
+                    return err(format!(
+                        "target {} is not allowed, must be one of {}",
+                        target,
+                        targets.join(", ")
+                    ))
+                }
+                None => return err(format!("missing target after {}", token)),
+            }
+        }
+    }
+    extra_rule_is_ipv6(&extra.rule)?;
+    Ok(())
+}
+
+/// Get the command or jump option a token of a rule stands for together with
+/// its attached value. Like iptables a short option can carry its value, e.g.
+/// -jDROP, and a long option can be abbreviated and carry its value after a =,
+/// e.g. --jum=DROP. An abbreviation of one of the options is always treated
+/// as that option, even when iptables would reject it as ambiguous.
+fn iptables_option(token: &str) -> Option<((&'static str, &'static str), Option<&str>)> {
+    let mut options = IPTABLES_COMMAND_OPTIONS
+        .iter()
+        .chain(std::iter::once(&IPTABLES_JUMP_OPTION));
+    if let Some(long) = token.strip_prefix("--") {
+        let (name, value) = match long.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (long, None),
+        };
+        if name.is_empty() {
+            return None;
+        }
+        options
+            .filter(|(_, l)| l[2..].starts_with(name))
+            // an exact match wins over the longer options it abbreviates
+            .min_by_key(|(_, l)| l.len())
+            .map(|option| (*option, value.filter(|v| !v.is_empty())))
+    } else {
+        let short = token.get(..2)?;
+        let value = Some(&token[2..]).filter(|v| !v.is_empty());
+        options
+            .find(|(s, _)| *s == short)
+            .map(|option| (*option, value))
+    }
+}
+
+/// Get the ip family of the addresses in the rule, None if the rule does
+/// not contain any address and can be used for both families.
+pub fn extra_rule_is_ipv6(rule: &str) -> NetavarkResult<Option<bool>> {
+    let mut family = None;
+    for token in rule.split_whitespace() {
+        let is_ipv6 = match (token.parse::<IpAddr>(), token.parse::<IpNet>()) {
+            (Ok(ip), _) => ip.is_ipv6(),
+            (_, Ok(net)) => net.addr().is_ipv6(),
+            _ => continue,
+        };
+        match family {
+            Some(f) if f != is_ipv6 => {
+                return Err(NetavarkError::Message(format!(
+                    "invalid extra rule \"{}\": rule mixes ipv4 and ipv6 addresses",
+                    rule
+                )))
+            }
+            _ => family = Some(is_ipv6),
+        }
+    }
+    Ok(family)
+}
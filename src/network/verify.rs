@@ -0,0 +1,334 @@
+//! Minimal reachability check used by setup --verify and the arp probe
+//! used to detect address conflicts
+use std::{
+    io, mem,
+    net::{IpAddr, Ipv4Addr},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    time::{Duration, Instant},
+};
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+const ETH_P_ARP: u16 = 0x0806;
+const ETH_P_IP: u16 = 0x0800;
+const ARPHRD_ETHER: u16 = 1;
+const ARP_REQUEST: u16 = 1;
+const ARP_PACKET_LEN: usize = 28;
+
+/// Send a single ICMP echo request to the given address and wait for the reply.
+/// This must be called from within the container network namespace.
+pub fn ping(addr: IpAddr, timeout: Duration) -> io::Result<()> {
+    let (family, proto, request, reply) = match addr {
+        IpAddr::V4(_) => (
+            libc::AF_INET,
+            libc::IPPROTO_ICMP,
+            ICMP_ECHO_REQUEST,
+            ICMP_ECHO_REPLY,
+        ),
+        IpAddr::V6(_) => (
+            libc::AF_INET6,
+            libc::IPPROTO_ICMPV6,
+            ICMPV6_ECHO_REQUEST,
+            ICMPV6_ECHO_REPLY,
+        ),
+    };
+
+    let fd = unsafe { libc::socket(family, libc::SOCK_RAW | libc::SOCK_CLOEXEC, proto) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+    set_recv_timeout(&sock, timeout)?;
+
+    let id = (std::process::id() & 0xffff) as u16;
+    let mut packet = [0u8; 16];
+    packet[0] = request;
+    packet[4..6].copy_from_slice(&id.to_be_bytes());
+    packet[6..8].copy_from_slice(&1u16.to_be_bytes());
+    packet[8..].copy_from_slice(b"netavark");
+    // the kernel fills in the checksum for icmpv6 raw sockets
+    if addr.is_ipv4() {
+        let sum = checksum(&packet);
+        packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    }
+
+    send_to(&sock, &packet, addr)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 1500];
+    while Instant::now() < deadline {
+        let n = unsafe {
+            libc::recv(
+                sock.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut data = &buf[..n as usize];
+        // ipv4 raw sockets also return the ip header
+        if addr.is_ipv4() {
+            if data.is_empty() {
+                continue;
+            }
+            let header_len = ((data[0] & 0x0f) as usize) * 4;
+            if data.len() < header_len {
+                continue;
+            }
+            data = &data[header_len..];
+        }
+        if data.len() >= 8 && data[0] == reply && data[4..6] == id.to_be_bytes() {
+            return Ok(());
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("no echo reply from {}", addr),
+    ))
+}
+
+/// Send an ARP probe as described in RFC 5227 for the address out of the
+/// given interface. Returns true if another host answers for the address or
+/// probes for it as well within the timeout.
+pub fn arp_probe(if_index: u32, mac: &[u8], addr: Ipv4Addr, timeout: Duration) -> io::Result<bool> {
+    if mac.len() != 6 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid mac address length {}", mac.len()),
+        ));
+    }
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_PACKET,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            ETH_P_ARP.to_be() as libc::c_int,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut sa: libc::sockaddr_ll = unsafe { mem::zeroed() };
+    sa.sll_family = libc::AF_PACKET as libc::c_ushort;
+    sa.sll_protocol = ETH_P_ARP.to_be();
+    sa.sll_ifindex = if_index as libc::c_int;
+    let ret = unsafe {
+        libc::bind(
+            sock.as_raw_fd(),
+            &sa as *const libc::sockaddr_ll as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    set_recv_timeout(&sock, timeout)?;
+
+    // the probe has no sender address so it does not pollute arp caches
+    let mut packet = [0u8; ARP_PACKET_LEN];
+    packet[0..2].copy_from_slice(&ARPHRD_ETHER.to_be_bytes());
+    packet[2..4].copy_from_slice(&ETH_P_IP.to_be_bytes());
+    packet[4] = 6;
+    packet[5] = 4;
+    packet[6..8].copy_from_slice(&ARP_REQUEST.to_be_bytes());
+    packet[8..14].copy_from_slice(mac);
+    packet[24..28].copy_from_slice(&addr.octets());
+
+    sa.sll_halen = 6;
+    sa.sll_addr[..6].copy_from_slice(&[0xff; 6]);
+    let ret = unsafe {
+        libc::sendto(
+            sock.as_raw_fd(),
+            packet.as_ptr() as *const libc::c_void,
+            packet.len(),
+            0,
+            &sa as *const libc::sockaddr_ll as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 1500];
+    while Instant::now() < deadline {
+        let n = unsafe {
+            libc::recv(
+                sock.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::WouldBlock {
+                break;
+            }
+            return Err(err);
+        }
+        if is_arp_conflict(&buf[..n as usize], mac, addr) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// An arp packet conflicts with the probed address if it was sent by another
+/// host which uses the address or probes for it.
+fn is_arp_conflict(data: &[u8], mac: &[u8], addr: Ipv4Addr) -> bool {
+    if data.len() < ARP_PACKET_LEN || data[2..4] != ETH_P_IP.to_be_bytes() {
+        return false;
+    }
+    // our own probe is looped back to the packet socket
+    if data[8..14] == *mac {
+        return false;
+    }
+    let sender = &data[14..18];
+    let target = &data[24..28];
+    let probe = sender == [0; 4] && data[6..8] == ARP_REQUEST.to_be_bytes();
+    sender == addr.octets() || (probe && target == addr.octets())
+}
+
+fn set_recv_timeout(sock: &OwnedFd, timeout: Duration) -> io::Result<()> {
+    let tv = libc::timeval {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &tv as *const libc::timeval as *const libc::c_void,
+            mem::size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn send_to(sock: &OwnedFd, packet: &[u8], addr: IpAddr) -> io::Result<()> {
+    let ret = match addr {
+        IpAddr::V4(v4) => {
+            let mut sa: libc::sockaddr_in = unsafe { mem::zeroed() };
+            sa.sin_family = libc::AF_INET as libc::sa_family_t;
+            sa.sin_addr.s_addr = u32::from_ne_bytes(v4.octets());
+            unsafe {
+                libc::sendto(
+                    sock.as_raw_fd(),
+                    packet.as_ptr() as *const libc::c_void,
+                    packet.len(),
+                    0,
+                    &sa as *const libc::sockaddr_in as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                )
+            }
+        }
+        IpAddr::V6(v6) => {
+            let mut sa: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+            sa.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sa.sin6_addr.s6_addr = v6.octets();
+            unsafe {
+                libc::sendto(
+                    sock.as_raw_fd(),
+                    packet.as_ptr() as *const libc::c_void,
+                    packet.len(),
+                    0,
+                    &sa as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                )
+            }
+        }
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// internet checksum as described in RFC 1071
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in data.chunks(2) {
+        let word = if chunk.len() == 2 {
+            u16::from_be_bytes([chunk[0], chunk[1]])
+        } else {
+            u16::from_be_bytes([chunk[0], 0])
+        };
+        sum += word as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arp(op: u16, sender_mac: u8, sender: [u8; 4], target: [u8; 4]) -> Vec<u8> {
+        let mut packet = vec![0u8; ARP_PACKET_LEN];
+        packet[0..2].copy_from_slice(&ARPHRD_ETHER.to_be_bytes());
+        packet[2..4].copy_from_slice(&ETH_P_IP.to_be_bytes());
+        packet[4] = 6;
+        packet[5] = 4;
+        packet[6..8].copy_from_slice(&op.to_be_bytes());
+        packet[8..14].copy_from_slice(&[sender_mac; 6]);
+        packet[14..18].copy_from_slice(&sender);
+        packet[24..28].copy_from_slice(&target);
+        packet
+    }
+
+    #[test]
+    fn test_is_arp_conflict() {
+        let mac = [1u8; 6];
+        let addr = Ipv4Addr::new(10, 88, 0, 2);
+        let other = [10, 88, 0, 3];
+        // a reply or request from the host using the address
+        assert!(is_arp_conflict(
+            &arp(2, 2, addr.octets(), [10, 88, 0, 1]),
+            &mac,
+            addr
+        ));
+        assert!(is_arp_conflict(
+            &arp(1, 2, addr.octets(), other),
+            &mac,
+            addr
+        ));
+        // another host probing for the same address
+        assert!(is_arp_conflict(
+            &arp(1, 2, [0; 4], addr.octets()),
+            &mac,
+            addr
+        ));
+        // our own looped back probe and unrelated traffic
+        assert!(!is_arp_conflict(
+            &arp(1, 1, [0; 4], addr.octets()),
+            &mac,
+            addr
+        ));
+        assert!(!is_arp_conflict(
+            &arp(1, 2, other, addr.octets()),
+            &mac,
+            addr
+        ));
+        assert!(!is_arp_conflict(
+            &arp(2, 2, other, [10, 88, 0, 1]),
+            &mac,
+            addr
+        ));
+        assert!(!is_arp_conflict(&[0u8; 10], &mac, addr));
+    }
+}
@@ -1,7 +1,17 @@
 use log::{debug, error};
-use std::{collections::HashMap, net::IpAddr, os::unix::prelude::RawFd};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    os::unix::{fs::MetadataExt, prelude::RawFd},
+    thread,
+    time::{Duration, Instant},
+};
 
-use netlink_packet_route::nlas::link::{InfoData, InfoIpVlan, InfoKind, InfoMacVlan, Nla};
+use ipnet::IpNet;
+use netlink_packet_route::{
+    nlas::link::{Info, InfoData, InfoIpVlan, InfoKind, InfoMacVlan, Nla},
+    LinkMessage, IFF_LOWER_UP,
+};
 use rand::distributions::{Alphanumeric, DistString};
 
 use crate::network::macvlan_dhcp::{get_dhcp_lease, release_dhcp_lease};
@@ -9,19 +19,26 @@ use crate::{
     dns::aardvark::AardvarkEntry,
     error::{ErrorWrap, NetavarkError, NetavarkResult},
     exec_netns,
+    firewall::FirewallRule,
     network::core_utils::{disable_ipv6_autoconf, join_netns},
+    warnings,
 };
 
+/// how often the parent interface is checked for carrier or its existence
+const CARRIER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 use super::{
     constants::{
-        NO_CONTAINER_INTERFACE_ERROR, OPTION_BCLIM, OPTION_METRIC, OPTION_MODE, OPTION_MTU,
-        OPTION_NO_DEFAULT_ROUTE,
+        MAX_CARRIER_WAIT, MAX_PARENT_WAIT, NO_CONTAINER_INTERFACE_ERROR, OPTION_BCLIM,
+        OPTION_CARRIER_WAIT, OPTION_METRIC, OPTION_MODE, OPTION_MTU, OPTION_NO_DEFAULT_ROUTE,
+        OPTION_NO_LINK_UP, OPTION_NO_SUBNET_ROUTE, OPTION_PARENT_NETNS, OPTION_PARENT_WAIT,
     },
     core_utils::{self, get_ipam_addresses, parse_option, CoreUtils},
     driver::{self, DriverInfo},
     internal_types::IPAMAddresses,
     netlink::{self, CreateLinkOptions},
     types::{NetInterface, StatusBlock},
+    validation,
 };
 
 enum KindData {
@@ -64,6 +81,26 @@ struct InternalData {
     kind: KindData,
     /// if set, no default gateway will be added
     no_default_route: bool,
+    /// additional dns search domains for the container
+    dns_search_domains: Vec<String>,
+    /// return the host nameservers when no dns servers are configured
+    host_dns: bool,
+    /// do not add the connected subnet routes, route everything via the gateway
+    no_subnet_route: bool,
+    /// how long to wait for the parent interface carrier before creating the child
+    carrier_wait: Duration,
+    /// how long to wait for the parent interface to appear when it is missing
+    parent_wait: Duration,
+    /// leave the interface down and do not add routes
+    no_link_up: bool,
+    /// alias set on the container interface
+    ifalias: Option<String>,
+    /// subnets whose traffic is dropped inside the container
+    blackhole_routes: Vec<IpNet>,
+    /// sysctls set in the container namespace
+    sysctls: Vec<(String, String)>,
+    /// network namespace of the parent interface, unset for the host namespace
+    parent_netns: Option<String>,
     // TODO: add vlan
 }
 
@@ -99,12 +136,63 @@ impl driver::NetworkDriver for Vlan<'_> {
         let metric = parse_option(&self.info.network.options, OPTION_METRIC)?.unwrap_or(100);
         let no_default_route: bool =
             parse_option(&self.info.network.options, OPTION_NO_DEFAULT_ROUTE)?.unwrap_or(false);
+        let dns_search_domains = core_utils::get_dns_search_domains(&self.info.network.options)?;
+        let host_dns = core_utils::get_host_dns_option(self.info.network)?;
 
         // Remove gateways when marked as internal network
         if self.info.network.internal {
             ipam.gateway_addresses = Vec::new();
         }
 
+        let no_subnet_route: bool =
+            parse_option(&self.info.network.options, OPTION_NO_SUBNET_ROUTE)?.unwrap_or(false);
+        if no_subnet_route {
+            core_utils::validate_no_subnet_route(self.info.network, &ipam, no_default_route)?;
+        }
+
+        let carrier_wait: u32 =
+            parse_option(&self.info.network.options, OPTION_CARRIER_WAIT)?.unwrap_or(0);
+        if carrier_wait > MAX_CARRIER_WAIT {
+            return Err(NetavarkError::Message(format!(
+                "invalid {} {}, must be at most {} seconds",
+                OPTION_CARRIER_WAIT, carrier_wait, MAX_CARRIER_WAIT
+            )));
+        }
+        let parent_wait: u32 =
+            parse_option(&self.info.network.options, OPTION_PARENT_WAIT)?.unwrap_or(0);
+        if parent_wait > MAX_PARENT_WAIT {
+            return Err(NetavarkError::Message(format!(
+                "invalid {} {}, must be at most {} seconds",
+                OPTION_PARENT_WAIT, parent_wait, MAX_PARENT_WAIT
+            )));
+        }
+        let no_link_up: bool =
+            parse_option(&self.info.network.options, OPTION_NO_LINK_UP)?.unwrap_or(false);
+        if no_link_up && ipam.dhcp_enabled {
+            return Err(NetavarkError::msg(
+                "no_link_up cannot be used with dhcp, the lease requires the interface to be up",
+            ));
+        }
+
+        let ifalias = core_utils::get_ifalias_option(&self.info.network.options)?;
+        let blackhole_routes = core_utils::get_blackhole_routes_option(&self.info.network.options)?;
+        let sysctls = core_utils::get_netns_sysctls(
+            &self.info.network.options,
+            &self.info.per_network_opts.sysctls,
+        )?;
+        let parent_netns: Option<String> =
+            parse_option::<String>(&self.info.network.options, OPTION_PARENT_NETNS)?
+                .filter(|path| !path.is_empty());
+        if let Some(path) = &parent_netns {
+            validate_parent_netns(path, self.info.netns_path)?;
+            if ipam.dhcp_enabled {
+                return Err(NetavarkError::Message(format!(
+                    "{} cannot be used with dhcp",
+                    OPTION_PARENT_NETNS
+                )));
+            }
+        }
+
         self.data = Some(InternalData {
             container_interface_name: self.info.per_network_opts.interface_name.clone(),
             host_interface_name: self
@@ -124,10 +212,12 @@ impl driver::NetworkDriver for Vlan<'_> {
                     let bclim = parse_option(&self.info.network.options, OPTION_BCLIM)?;
                     KindData::MacVlan {
                         mode: CoreUtils::get_macvlan_mode_from_string(mode.as_deref())?,
-                        mac_address: match &self.info.per_network_opts.static_mac {
-                            Some(mac) => Some(CoreUtils::decode_address_from_hex(mac)?),
-                            None => None,
-                        },
+                        mac_address: core_utils::get_container_mac(
+                            &self.info.network.options,
+                            &self.info.per_network_opts.static_mac,
+                            &self.info.network.name,
+                            self.info.container_id,
+                        )?,
                         bclim,
                     }
                 }
@@ -139,6 +229,16 @@ impl driver::NetworkDriver for Vlan<'_> {
                 }
             },
             no_default_route,
+            dns_search_domains,
+            host_dns,
+            no_subnet_route,
+            carrier_wait: Duration::from_secs(carrier_wait.into()),
+            parent_wait: Duration::from_secs(parent_wait.into()),
+            no_link_up,
+            ifalias,
+            blackhole_routes,
+            sysctls,
+            parent_netns,
         });
         Ok(())
     }
@@ -146,7 +246,7 @@ impl driver::NetworkDriver for Vlan<'_> {
     fn setup(
         &self,
         netlink_sockets: (&mut netlink::Socket, &mut netlink::Socket),
-    ) -> Result<(StatusBlock, Option<AardvarkEntry>), NetavarkError> {
+    ) -> Result<(StatusBlock, Option<AardvarkEntry>, Vec<FirewallRule>), NetavarkError> {
         let data = match &self.data {
             Some(d) => d,
             None => return Err(NetavarkError::msg("must call validate() before setup()")),
@@ -160,8 +260,19 @@ impl driver::NetworkDriver for Vlan<'_> {
 
         let (host_sock, netns_sock) = netlink_sockets;
 
+        // the interface is created in the namespace of the parent and moved
+        // to the container from there
+        let mut parent_ns = match &data.parent_netns {
+            Some(path) => Some(core_utils::open_netns(path, self.info.netns_host)?),
+            None => None,
+        };
+        let parent_sock = match &mut parent_ns {
+            Some(ns) => &mut ns.netlink,
+            None => host_sock,
+        };
+
         let container_vlan_mac = setup(
-            host_sock,
+            parent_sock,
             netns_sock,
             &self.info.per_network_opts.interface_name,
             data,
@@ -176,7 +287,19 @@ impl driver::NetworkDriver for Vlan<'_> {
             dns_server_ips: Some(Vec::<IpAddr>::new()),
             dns_search_domains: Some(Vec::<String>::new()),
             interfaces: Some(HashMap::new()),
+            gateway: None,
+            gateway_reachable: None,
+            warnings: None,
+            changed_sysctls: None,
+            netns_inode: None,
+            firewall_chains: None,
         };
+        core_utils::add_dns_search_domains(&mut response, &data.dns_search_domains);
+        if data.host_dns && self.info.container_dns_servers.is_none() {
+            response.dns_server_ips = Some(core_utils::get_host_nameservers(
+                core_utils::HOST_RESOLV_CONF,
+            ));
+        }
 
         // interfaces map, but we only ever expect one, for response
         let mut interfaces: HashMap<String, NetInterface> = HashMap::new();
@@ -195,15 +318,18 @@ impl driver::NetworkDriver for Vlan<'_> {
             data.ipam.net_addresses.clone()
         };
 
+        // with dhcp the gateway comes from the lease
+        response.gateway = core_utils::get_gateways(&subnets);
         let interface = NetInterface {
             mac_address: container_vlan_mac,
             subnets: Option::from(subnets),
+            stats: None,
         };
 
         // Add interface to interfaces (part of StatusBlock)
         interfaces.insert(self.info.per_network_opts.interface_name.clone(), interface);
         let _ = response.interfaces.insert(interfaces);
-        Ok((response, None))
+        Ok((response, None, Vec::new()))
     }
 
     fn teardown(
@@ -242,6 +368,7 @@ impl driver::NetworkDriver for Vlan<'_> {
         for route in routes.iter() {
             netlink_sockets.1.del_route(route)?;
         }
+        core_utils::remove_blackhole_routes(netlink_sockets.1, &self.info.network.options)?;
 
         netlink_sockets.1.del_link(netlink::LinkID::Name(
             self.info.per_network_opts.interface_name.to_string(),
@@ -264,7 +391,15 @@ fn setup(
         host_name => host_name.to_string(),
     };
 
-    let link = host.get_link(netlink::LinkID::Name(primary_ifname))?;
+    let mut link = if data.parent_wait.is_zero() {
+        host.get_link(netlink::LinkID::Name(primary_ifname.clone()))?
+    } else {
+        wait_for_parent(host, &primary_ifname, data.parent_wait)?
+    };
+    if !data.carrier_wait.is_zero() {
+        link = wait_for_carrier(host, link, &primary_ifname, data.carrier_wait)?;
+    }
+    check_parent_link(host, &link, &primary_ifname)?;
 
     let opts = match kind_data {
         KindData::IpVlan { mode } => {
@@ -296,7 +431,9 @@ fn setup(
             opts
         }
     };
-    let mut result = host.create_link(opts.clone());
+    let attempts = core_utils::get_netns_move_attempts()?;
+    let mut result =
+        core_utils::retry_netns_move(if_name, attempts, || host.create_link(opts.clone()));
     // Sigh, the kernel creates the interface first in the hostns before moving it into the netns.
     // Therefore it can fail with EEXIST if the name is already used on the host. Create the link
     // with tmp name, then rename it in the netns.
@@ -312,7 +449,9 @@ fn setup(
                     let tmp_name = "mv-".to_string() + &random;
                     let mut opts = opts.clone();
                     opts.name = tmp_name.clone();
-                    result = host.create_link(opts);
+                    result = core_utils::retry_netns_move(if_name, attempts, || {
+                        host.create_link(opts.clone())
+                    });
                     if let Err(ref e) = result {
                         // if last element return directly
                         if i == 2 {
@@ -347,27 +486,69 @@ fn setup(
                     // successful run, break out of loop
                     break;
                 }
-                err => return Err(err).wrap(format!("create {} interface", kind_data))?,
+                err => {
+                    return core_utils::wrap_netlink(
+                        Err(err),
+                        &format!("create {} interface", kind_data),
+                        if_name,
+                        "container",
+                    )
+                }
             },
         }
     }
 
-    exec_netns!(hostns_fd, netns_fd, res, { disable_ipv6_autoconf(if_name) });
-    res?; // return autoconf sysctl error
-
-    let dev = netns
-        .get_link(netlink::LinkID::Name(if_name.to_string()))
-        .wrap(format!("get {} interface", kind_data))?;
+    exec_netns!(hostns_fd, netns_fd, res, {
+        disable_ipv6_autoconf(if_name)?;
+        core_utils::apply_netns_sysctls(&data.sysctls)
+    });
+    res?; // return autoconf or sysctls option error
+
+    let dev = core_utils::wrap_netlink(
+        netns.get_link(netlink::LinkID::Name(if_name.to_string())),
+        &format!("get {} interface", kind_data),
+        if_name,
+        "container",
+    )?;
+
+    if let Some(alias) = &data.ifalias {
+        core_utils::wrap_netlink(
+            netns.set_link_alias(dev.header.index, alias.clone()),
+            &format!("set {} alias", kind_data),
+            if_name,
+            "container",
+        )?;
+    }
 
     for addr in &data.ipam.container_addresses {
-        netns
-            .add_addr(dev.header.index, addr)
-            .wrap(format!("add ip addr to {}", kind_data))?;
+        core_utils::wrap_netlink(
+            if data.no_subnet_route {
+                netns.add_addr_without_prefix_route(dev.header.index, addr)
+            } else {
+                netns.add_addr(dev.header.index, addr)
+            },
+            &format!("add ip addr {} to {}", addr, kind_data),
+            if_name,
+            "container",
+        )?;
+    }
+
+    // routes need the interface to be up, the caller has to add them
+    if data.no_link_up {
+        debug!("leaving {} interface {} down", kind_data, if_name);
+        return get_mac_address(dev.nlas);
     }
 
-    netns
-        .set_up(netlink::LinkID::ID(dev.header.index))
-        .wrap(format!("set {} up", kind_data))?;
+    core_utils::wrap_netlink(
+        netns.set_up(netlink::LinkID::ID(dev.header.index)),
+        &format!("set {} up", kind_data),
+        if_name,
+        "container",
+    )?;
+
+    if data.no_subnet_route {
+        core_utils::add_gateway_host_routes(netns, dev.header.index, &data.ipam.gateway_addresses)?;
+    }
 
     if !data.no_default_route {
         core_utils::add_default_routes(netns, &data.ipam.gateway_addresses, data.metric)?;
@@ -377,10 +558,156 @@ fn setup(
     for route in data.ipam.routes.iter() {
         netns.add_route(route)?
     }
+    for dest in &data.blackhole_routes {
+        netns.add_blackhole_route(dest)?;
+    }
 
     get_mac_address(dev.nlas)
 }
 
+/// Wait until the parent interface exists, e.g. a bond created later during
+/// boot. Fails when it is still missing after the timeout.
+fn wait_for_parent(
+    host: &mut netlink::Socket,
+    if_name: &str,
+    timeout: Duration,
+) -> NetavarkResult<LinkMessage> {
+    let start = Instant::now();
+    loop {
+        match host.get_link(netlink::LinkID::Name(if_name.to_string())) {
+            Ok(link) => {
+                debug!(
+                    "waited {:?} for parent interface {}",
+                    start.elapsed(),
+                    if_name
+                );
+                return Ok(link);
+            }
+            Err(NetavarkError::Netlink(e)) if -e.raw_code() == libc::ENODEV => {
+                if start.elapsed() >= timeout {
+                    return Err(NetavarkError::Message(format!(
+                        "parent interface {} does not exist after {} seconds",
+                        if_name,
+                        timeout.as_secs()
+                    )));
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        thread::sleep(CARRIER_POLL_INTERVAL);
+    }
+}
+
+/// Wait until the parent interface reports carrier or the timeout is reached,
+/// the child is still created without carrier but a warning is added.
+/// Returns the latest link message of the parent.
+fn wait_for_carrier(
+    host: &mut netlink::Socket,
+    mut link: LinkMessage,
+    if_name: &str,
+    timeout: Duration,
+) -> NetavarkResult<LinkMessage> {
+    let start = Instant::now();
+    while link.header.flags & IFF_LOWER_UP == 0 {
+        if start.elapsed() >= timeout {
+            warnings::push(format!(
+                "parent interface {} has no carrier after {} seconds",
+                if_name,
+                timeout.as_secs()
+            ));
+            break;
+        }
+        thread::sleep(CARRIER_POLL_INTERVAL);
+        link = host.get_link(netlink::LinkID::ID(link.header.index))?;
+    }
+    debug!(
+        "waited {:?} for carrier on parent interface {}",
+        start.elapsed(),
+        if_name
+    );
+    Ok(link)
+}
+
+/// Get the kind of an aggregate (bond or team) interface, None for all
+/// other interfaces.
+fn get_aggregate_kind(link: &LinkMessage) -> Option<&'static str> {
+    let infos = link.nlas.iter().find_map(|nla| match nla {
+        Nla::Info(infos) => Some(infos),
+        _ => None,
+    })?;
+    infos.iter().find_map(|info| match info {
+        Info::Kind(InfoKind::Bond) => Some("bond"),
+        Info::Kind(InfoKind::Other(kind)) if kind == "team" => Some("team"),
+        _ => None,
+    })
+}
+
+/// A port of a bond or team cannot be used as parent, the aggregate must be
+/// used instead. For a bond or team parent a warning is added when none of
+/// its ports has carrier as the child has no connectivity then.
+fn check_parent_link(
+    host: &mut netlink::Socket,
+    link: &LinkMessage,
+    if_name: &str,
+) -> NetavarkResult<()> {
+    let master = link.nlas.iter().find_map(|nla| match nla {
+        Nla::Master(index) => Some(*index),
+        _ => None,
+    });
+    if let Some(master) = master {
+        let master_link = host.get_link(netlink::LinkID::ID(master))?;
+        if let Some(kind) = get_aggregate_kind(&master_link) {
+            return Err(NetavarkError::Message(format!(
+                "parent interface {} is a port of the {} {}, use the {} as parent instead",
+                if_name,
+                kind,
+                get_link_name(&master_link).unwrap_or_default(),
+                kind
+            )));
+        }
+    }
+
+    if let Some(kind) = get_aggregate_kind(link) {
+        let ports = host
+            .dump_links(&mut vec![Nla::Master(link.header.index)])
+            .wrap(format!("failed to get the ports of {} {}", kind, if_name))?;
+        if !ports.iter().any(|p| p.header.flags & IFF_LOWER_UP != 0) {
+            warnings::push(format!(
+                "parent interface {} is a {} without an active port",
+                if_name, kind
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn get_link_name(link: &LinkMessage) -> Option<String> {
+    link.nlas.iter().find_map(|nla| match nla {
+        Nla::IfName(name) => Some(name.clone()),
+        _ => None,
+    })
+}
+
+/// The parent namespace must be a network namespace other than the one of
+/// the container, the container namespace is unknown for validate.
+fn validate_parent_netns(path: &str, netns_path: &str) -> NetavarkResult<()> {
+    validation::ns_checks(path).map_err(|e| {
+        NetavarkError::Message(format!("invalid {} {:?}: {}", OPTION_PARENT_NETNS, path, e))
+    })?;
+    if netns_path.is_empty() {
+        return Ok(());
+    }
+    let parent = std::fs::metadata(path)?;
+    let container = std::fs::metadata(netns_path)?;
+    if parent.dev() == container.dev() && parent.ino() == container.ino() {
+        return Err(NetavarkError::Message(format!(
+            "invalid {} {:?}: it is the container namespace",
+            OPTION_PARENT_NETNS, path
+        )));
+    }
+    Ok(())
+}
+
 fn get_mac_address(v: Vec<Nla>) -> NetavarkResult<String> {
     for nla in v.into_iter() {
         if let Nla::Address(ref addr) = nla {
@@ -3,6 +3,9 @@
 #[cfg(test)]
 mod tests {
     use netavark::network;
+    use rand::distributions::{Alphanumeric, DistString};
+    use std::collections::HashSet;
+    use std::net::IpAddr;
     #[test]
     // Test setup options loader
     fn test_setup_opts_load() {
@@ -14,6 +17,20 @@ mod tests {
         }
     }
 
+    // YAML configs are detected by extension and give the same options as JSON
+    #[test]
+    fn test_setup_opts_load_yaml() {
+        let load = |file: &str| {
+            let opts = network::types::NetworkOptions::load(Some(file.to_owned()))
+                .unwrap_or_else(|e| panic!("{}: {}", file, e));
+            serde_json::to_value(opts).unwrap()
+        };
+        assert_eq!(
+            load("src/test/config/setupopts.test.yaml"),
+            load("src/test/config/setupopts.test.json")
+        );
+    }
+
     // Test if we can deserialize values correctly
     #[test]
     fn test_setup_opts_assert() {
@@ -43,9 +60,1524 @@ mod tests {
         }
     }
 
+    // The given container id is used for all networks
+    #[test]
+    fn test_container_id_present() {
+        let opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/portmapping.json".to_owned(),
+        ))
+        .unwrap();
+        let ids = opts.get_container_ids(None).unwrap();
+        assert_eq!(
+            ids["podman"],
+            "ad1df727792c3041dc82c5b7791365debce6f5ada7c7a0320a2d1368e1635d30"
+        );
+    }
+
+    // Port forwarding requires a container id
+    #[test]
+    fn test_container_id_absent_with_ports() {
+        let opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/portmapping-no-container-id.json".to_owned(),
+        ))
+        .unwrap();
+        match opts.get_container_ids(None) {
+            Ok(_) => panic!("missing container id with port mappings must fail"),
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "container_id must be set when port mappings are given"
+            ),
+        }
+    }
+
+    // Without container id a deterministic one is derived from the network
+    #[test]
+    fn test_container_id_absent_without_ports() {
+        let opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/no-container-id.json".to_owned(),
+        ))
+        .unwrap();
+        let ids = opts.get_container_ids(None).unwrap();
+        assert_eq!(ids["podman"].len(), 64);
+        assert_eq!(
+            ids["podman"],
+            opts.get_container_ids(None).unwrap()["podman"]
+        );
+    }
+
+    fn load_ipam_allocation() -> network::types::NetworkOptions {
+        network::types::NetworkOptions::load(Some(
+            "src/test/config/ipam-allocation.json".to_owned(),
+        ))
+        .unwrap()
+    }
+
+    // The first free address skips the network address and gateway
+    #[test]
+    fn test_get_free_ip() {
+        let opts = load_ipam_allocation();
+        let subnets = opts.network_info["podman"].subnets.clone().unwrap();
+        let mut used = HashSet::new();
+
+        let ip = network::core_utils::get_free_ip(&subnets[0], &used, 0).unwrap();
+        assert_eq!(ip, Some("10.89.0.2".parse().unwrap()));
+        let ip = network::core_utils::get_free_ip(&subnets[1], &used, 0).unwrap();
+        assert_eq!(ip, Some("fd10:89::2".parse().unwrap()));
+
+        // the ipv4 broadcast address must not be used
+        used.insert("10.89.0.2".parse().unwrap());
+        let ip = network::core_utils::get_free_ip(&subnets[0], &used, 0).unwrap();
+        assert_eq!(ip, None);
+    }
+
+    // Only an enabled automatic helper assignment is reported
+    #[test]
+    fn test_conntrack_helper_warning() {
+        let path = std::env::temp_dir().join(format!("nf_conntrack_helper-{}", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        assert_eq!(
+            network::core_utils::conntrack_helper_warning(path_str),
+            None
+        );
+        std::fs::write(&path, "0\n").unwrap();
+        assert_eq!(
+            network::core_utils::conntrack_helper_warning(path_str),
+            None
+        );
+        std::fs::write(&path, "1\n").unwrap();
+        let warning = network::core_utils::conntrack_helper_warning(path_str);
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            warning
+                .as_deref()
+                .unwrap_or_default()
+                .starts_with("automatic conntrack helper assignment is enabled"),
+            "{:?}",
+            warning
+        );
+    }
+
+    // Only nameservers reachable from the container are taken from the host
+    #[test]
+    fn test_get_host_nameservers() {
+        let servers = network::core_utils::get_host_nameservers("src/test/config/resolv.conf");
+        let expected: Vec<std::net::IpAddr> = vec![
+            "192.0.2.53".parse().unwrap(),
+            "2001:db8::53".parse().unwrap(),
+        ];
+        assert_eq!(servers, expected);
+
+        let servers = network::core_utils::get_host_nameservers("src/test/config/missing.conf");
+        assert!(servers.is_empty());
+    }
+
+    // The ifalias option is bounded by the kernel limit
+    #[test]
+    fn test_get_ifalias_option() {
+        let opts = |alias: String| Some([("ifalias".to_string(), alias)].into());
+        assert_eq!(
+            network::core_utils::get_ifalias_option(&opts("rack 4".to_string())).unwrap(),
+            Some("rack 4".to_string())
+        );
+        assert_eq!(
+            network::core_utils::get_ifalias_option(&opts("".to_string())).unwrap(),
+            None
+        );
+        assert_eq!(
+            network::core_utils::get_ifalias_option(&None).unwrap(),
+            None
+        );
+        assert!(network::core_utils::get_ifalias_option(&opts("a".repeat(254))).is_ok());
+        assert_eq!(
+            network::core_utils::get_ifalias_option(&opts("a".repeat(255)))
+                .unwrap_err()
+                .to_string(),
+            "invalid ifalias of 255 bytes, must be at most 254 bytes"
+        );
+    }
+
+    // Generated macs are stable for the same network and container, static
+    // macs must match the oui
+    #[test]
+    fn test_get_container_mac() {
+        let mac = |opts: &Option<std::collections::HashMap<String, String>>, id: &str| {
+            network::core_utils::get_container_mac(opts, &None, "podman", id)
+        };
+        let opts = |oui: &str| Some([("mac_oui".to_string(), oui.to_string())].into());
+
+        let first = mac(&opts("02:42:ac"), "ad1df727792c").unwrap().unwrap();
+        assert_eq!(first[..3], [0x02, 0x42, 0xac]);
+        assert_eq!(
+            mac(&opts("02-42-AC"), "ad1df727792c").unwrap(),
+            Some(first.clone())
+        );
+        assert_ne!(mac(&opts("02:42:ac"), "6ce776ea58b5").unwrap(), Some(first));
+
+        // a static mac must be within the oui, without the option the kernel
+        // picks one
+        let static_mac = |opts: &Option<std::collections::HashMap<String, String>>, mac: &str| {
+            network::core_utils::get_container_mac(
+                opts,
+                &Some(mac.to_string()),
+                "podman",
+                "ad1df727792c",
+            )
+        };
+        assert_eq!(
+            static_mac(&opts("02:42:ac"), "02:42:AC:00:00:01").unwrap(),
+            Some(vec![2, 0x42, 0xac, 0, 0, 1])
+        );
+        assert_eq!(
+            static_mac(&opts("02:42:ac"), "02:00:00:00:00:01")
+                .unwrap_err()
+                .to_string(),
+            "static mac 02:00:00:00:00:01 is not within the mac_oui 02:42:ac"
+        );
+        assert_eq!(
+            static_mac(&None, "02:00:00:00:00:01").unwrap(),
+            Some(vec![2, 0, 0, 0, 0, 1])
+        );
+        assert!(static_mac(&opts("01:42:ac"), "01:42:ac:00:00:01").is_err());
+        assert_eq!(mac(&None, "ad1df727792c").unwrap(), None);
+
+        for oui in ["01:42:ac", "02:42", "02:42:ac:01", "zz:42:ac"] {
+            assert_eq!(
+                mac(&opts(oui), "ad1df727792c").unwrap_err().to_string(),
+                format!(
+                    "invalid mac_oui {:?}, must be three hex bytes of a unicast address like 02:42:ac",
+                    oui
+                )
+            );
+        }
+    }
+
+    // Bridges, ports and veths connected to another namespace can not be adopted
+    #[test]
+    fn test_check_adoptable_interface() {
+        use netlink_packet_route::nlas::link::{Info, InfoKind, Nla};
+        use netlink_packet_route::LinkMessage;
+
+        let check = |nlas: Vec<Nla>| {
+            let mut link = LinkMessage::default();
+            link.nlas = nlas;
+            network::core_utils::check_adoptable_interface(&link, "dummy0")
+                .map_err(|e| e.to_string())
+        };
+
+        assert!(check(vec![Nla::Info(vec![Info::Kind(InfoKind::Dummy)])]).is_ok());
+        assert_eq!(
+            check(vec![Nla::Info(vec![Info::Kind(InfoKind::Bridge)])]).unwrap_err(),
+            "interface dummy0 can not be adopted, it is a bridge"
+        );
+        assert_eq!(
+            check(vec![Nla::Master(3)]).unwrap_err(),
+            "interface dummy0 can not be adopted, it is attached to another interface"
+        );
+        assert_eq!(
+            check(vec![
+                Nla::Info(vec![Info::Kind(InfoKind::Veth)]),
+                Nla::NetnsId(1)
+            ])
+            .unwrap_err(),
+            "interface dummy0 can not be adopted, it is connected to another namespace"
+        );
+    }
+
+    // Blackhole routes must be subnets without host bits
+    #[test]
+    fn test_get_blackhole_routes_option() {
+        let opts =
+            |routes: &str| Some([("blackhole_routes".to_string(), routes.to_string())].into());
+        assert_eq!(
+            network::core_utils::get_blackhole_routes_option(&opts("192.0.2.0/24, 2001:db8::/32,"))
+                .unwrap(),
+            vec![
+                "192.0.2.0/24".parse::<ipnet::IpNet>().unwrap(),
+                "2001:db8::/32".parse().unwrap()
+            ]
+        );
+        assert!(network::core_utils::get_blackhole_routes_option(&None)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            network::core_utils::get_blackhole_routes_option(&opts("192.0.2.0"))
+                .unwrap_err()
+                .to_string(),
+            "invalid blackhole_routes entry \"192.0.2.0\": invalid IP address syntax"
+        );
+        assert_eq!(
+            network::core_utils::get_blackhole_routes_option(&opts("192.0.2.1/24"))
+                .unwrap_err()
+                .to_string(),
+            "invalid blackhole_routes entry \"192.0.2.1/24\": host bits are set, use 192.0.2.0/24"
+        );
+    }
+
+    #[test]
+    fn test_get_netns_sysctls() {
+        use network::core_utils::get_netns_sysctls;
+        let opts = |sysctls: &str| Some([("sysctls".to_string(), sysctls.to_string())].into());
+        let map = |entries: &[(&str, &str)]| {
+            Some(
+                entries
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            )
+        };
+        let entry = |k: &str, v: &str| (k.to_string(), v.to_string());
+
+        assert_eq!(
+            get_netns_sysctls(
+                &opts("net.ipv4.tcp_keepalive_time=60, net.ipv4.tcp_keepalive_probes = 3"),
+                &None
+            )
+            .unwrap(),
+            vec![
+                entry("net.ipv4.tcp_keepalive_probes", "3"),
+                entry("net.ipv4.tcp_keepalive_time", "60")
+            ]
+        );
+        assert!(get_netns_sysctls(&None, &None).unwrap().is_empty());
+
+        // the per network sysctls take precedence over the network option
+        assert_eq!(
+            get_netns_sysctls(
+                &opts("net.ipv4.tcp_keepalive_time=60"),
+                &map(&[
+                    ("net.ipv4.tcp_keepalive_time", "30"),
+                    ("net.ipv4.ip_local_port_range", "20000 30000"),
+                ])
+            )
+            .unwrap(),
+            vec![
+                entry("net.ipv4.ip_local_port_range", "20000 30000"),
+                entry("net.ipv4.tcp_keepalive_time", "30")
+            ]
+        );
+
+        let err = |sysctls: &[(&str, &str)]| {
+            get_netns_sysctls(&None, &map(sysctls))
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            get_netns_sysctls(&opts("net.ipv4.tcp_keepalive_time"), &None)
+                .unwrap_err()
+                .to_string(),
+            "invalid sysctls entry \"net.ipv4.tcp_keepalive_time\": must be key=value"
+        );
+        assert_eq!(
+            err(&[("kernel.shmmax", "1")]),
+            "invalid sysctls entry \"kernel.shmmax=1\": kernel.shmmax is not allowed, only net.* sysctls can be set"
+        );
+        let msg = err(&[("net.ipv4.ip_forward", "1")]);
+        assert!(
+            msg.starts_with(
+                "invalid sysctls entry \"net.ipv4.ip_forward=1\": net.ipv4.ip_forward is not allowed, must be one of net.core.somaxconn,"
+            ),
+            "{}",
+            msg
+        );
+        assert_eq!(
+            err(&[("net.ipv4.tcp_fin_timeout", "-1")]),
+            "invalid sysctls entry \"net.ipv4.tcp_fin_timeout=-1\": value must be a number: invalid digit found in string"
+        );
+        assert_eq!(
+            err(&[("net.ipv4.ping_group_range", "0")]),
+            "invalid sysctls entry \"net.ipv4.ping_group_range=0\": value must be two numbers separated by a space"
+        );
+    }
+
+    // Allocation starts after the reserved addresses following the gateway
+    #[test]
+    fn test_get_free_ip_reserved() {
+        let subnet = network::types::Subnet {
+            gateway: Some("10.88.0.1".parse().unwrap()),
+            lease_range: None,
+            subnet: "10.88.0.0/24".parse().unwrap(),
+        };
+        let mut used = HashSet::new();
+
+        let ip = network::core_utils::get_free_ip(&subnet, &used, 3).unwrap();
+        assert_eq!(ip, Some("10.88.0.5".parse().unwrap()));
+        used.insert("10.88.0.5".parse().unwrap());
+        let ip = network::core_utils::get_free_ip(&subnet, &used, 3).unwrap();
+        assert_eq!(ip, Some("10.88.0.6".parse().unwrap()));
+
+        let ip = network::core_utils::get_free_ip(&subnet, &HashSet::new(), 252).unwrap();
+        assert_eq!(ip, Some("10.88.0.254".parse().unwrap()));
+        let err = network::core_utils::get_free_ip(&subnet, &HashSet::new(), 253).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid reserved_count 253: no addresses left in subnet 10.88.0.0/24"
+        );
+    }
+
+    // Network names in allow_from and allow_to are replaced by their subnets
+    #[test]
+    fn test_resolve_allowed_networks() {
+        let opts = load_ipam_allocation();
+        let config_dir = std::env::temp_dir().join(format!(
+            "netavark-test-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 10)
+        ));
+        let config_dir = Some(config_dir.to_string_lossy().to_string());
+        network::state::record_network_subnets(&opts, &config_dir).unwrap();
+
+        // "podman" is not part of this config so it must be found in the state
+        let mut other = opts.clone();
+        let mut network = other.network_info.remove("podman").unwrap();
+        network.name = "other".to_string();
+        network.options = Some(
+            [
+                ("allow_from".to_string(), "192.0.2.0/24".to_string()),
+                ("allow_to".to_string(), "podman".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        other.network_info.insert("other".to_string(), network);
+        network::state::resolve_allowed_networks(&mut other, &config_dir).unwrap();
+        let options = other.network_info["other"].options.clone().unwrap();
+        assert_eq!(options["allow_from"], "192.0.2.0/24");
+        assert_eq!(options["allow_to"], "10.89.0.0/30,fd10:89::/126");
+
+        // without the state the name is unknown
+        let mut network = other.network_info["other"].clone();
+        network.options = Some(
+            [("allow_to".to_string(), "podman".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        other.network_info.insert("other".to_string(), network);
+        match network::state::resolve_allowed_networks(&mut other, &None) {
+            Ok(_) => panic!("unknown network must fail"),
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "invalid allow_to entry \"podman\": unknown network"
+            ),
+        }
+    }
+
+    // Static ips keyed by subnet are assigned to their subnet
+    // regardless of the order in the map
+    #[test]
+    fn test_static_ips_by_subnet() {
+        let opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/static-ips-by-subnet.json".to_owned(),
+        ))
+        .unwrap();
+        let network = &opts.network_info["podman"];
+        let mut per_net = opts.networks["podman"].clone();
+
+        let ipam = network::core_utils::get_ipam_addresses(&per_net, network).unwrap();
+        let addrs: Vec<String> = ipam
+            .container_addresses
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert_eq!(addrs, vec!["10.88.0.5/16", "fd10:88::5/64"]);
+
+        // the flat list is still assigned in subnet order
+        per_net.static_ips_by_subnet = None;
+        per_net.static_ips = Some(vec![
+            "10.88.0.6".parse().unwrap(),
+            "fd10:88::6".parse().unwrap(),
+        ]);
+        let ipam = network::core_utils::get_ipam_addresses(&per_net, network).unwrap();
+        let addrs: Vec<String> = ipam
+            .container_addresses
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert_eq!(addrs, vec!["10.88.0.6/16", "fd10:88::6/64"]);
+
+        // an ip outside of its subnet is rejected
+        per_net.static_ips_by_subnet = Some(
+            [
+                (
+                    "10.88.0.0/16".parse().unwrap(),
+                    "fd10:88::5".parse().unwrap(),
+                ),
+                (
+                    "fd10:88::/64".parse().unwrap(),
+                    "10.88.0.5".parse().unwrap(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let err = match network::core_utils::get_ipam_addresses(&per_net, network) {
+            Ok(_) => panic!("static ip outside of its subnet was accepted"),
+            Err(e) => e,
+        };
+        assert_eq!(
+            err.to_string(),
+            "static ip fd10:88::5 is not in subnet 10.88.0.0/16"
+        );
+    }
+
+    // Allocation fails once the subnet is exhausted
+    #[test]
+    fn test_allocate_ips_exhausted() {
+        let opts = load_ipam_allocation();
+        let network = &opts.network_info["podman"];
+        let mut state = network::state::State::default();
+
+        let ips = state.allocate_ips(network, "container1").unwrap();
+        assert_eq!(
+            ips,
+            vec![
+                "10.89.0.2".parse::<IpAddr>().unwrap(),
+                "fd10:89::2".parse().unwrap()
+            ]
+        );
+        // allocating again for the same container returns the same addresses
+        assert_eq!(state.allocate_ips(network, "container1").unwrap(), ips);
+
+        match state.allocate_ips(network, "container2") {
+            Ok(_) => panic!("allocation in exhausted subnet must fail"),
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "no free ip address left in subnet 10.89.0.0/30 of network podman"
+            ),
+        }
+    }
+
+    #[test]
+    fn test_parse_kernel_version() {
+        use network::core_utils::parse_kernel_version;
+        assert_eq!(parse_kernel_version("6.1.0-13-amd64"), Some((6, 1)));
+        assert_eq!(
+            parse_kernel_version("3.10.0-1160.el7.x86_64"),
+            Some((3, 10))
+        );
+        assert_eq!(parse_kernel_version("5.15"), Some((5, 15)));
+        assert_eq!(parse_kernel_version("6"), None);
+        assert_eq!(parse_kernel_version("unknown"), None);
+    }
+
+    // Addresses of exclude_ips are never allocated
+    #[test]
+    fn test_allocate_ips_exclude_ips() {
+        let mut opts = load_ipam_allocation();
+        let network = opts.network_info.get_mut("podman").unwrap();
+        network.options = Some([("exclude_ips".to_string(), "fd10:89::2".to_string())].into());
+        let mut state = network::state::State::default();
+        let ips = state.allocate_ips(network, "container1").unwrap();
+        assert_eq!(
+            ips,
+            vec![
+                "10.89.0.2".parse::<IpAddr>().unwrap(),
+                "fd10:89::3".parse().unwrap()
+            ]
+        );
+
+        network.options = Some([("exclude_ips".to_string(), "10.89.0.2".to_string())].into());
+        let mut state = network::state::State::default();
+        match state.allocate_ips(network, "container1") {
+            Ok(ips) => panic!("excluded address was allocated: {:?}", ips),
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "no free ip address left in subnet 10.89.0.0/30 of network podman"
+            ),
+        }
+
+        network.options = Some([("exclude_ips".to_string(), "10.90.0.2".to_string())].into());
+        assert_eq!(
+            state
+                .allocate_ips(network, "container1")
+                .unwrap_err()
+                .to_string(),
+            "invalid exclude_ips entry \"10.90.0.2\": not in a subnet of network podman"
+        );
+    }
+
+    // Released addresses are allocated again
+    #[test]
+    fn test_allocate_ips_reuse_after_teardown() {
+        let opts = load_ipam_allocation();
+        let ids = opts.get_container_ids(None).unwrap();
+        let config_dir = std::env::temp_dir().join(format!(
+            "netavark-test-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 10)
+        ));
+        let config_dir = Some(config_dir.to_string_lossy().to_string());
+
+        let mut setup_opts = opts.clone();
+        let allocated =
+            network::state::allocate_static_ips(&mut setup_opts, &ids, &config_dir).unwrap();
+        assert_eq!(allocated, vec!["podman".to_string()]);
+        let static_ips = setup_opts.networks["podman"].static_ips.clone();
+        assert_eq!(
+            static_ips,
+            Some(vec![
+                "10.89.0.2".parse().unwrap(),
+                "fd10:89::2".parse().unwrap()
+            ])
+        );
+
+        // teardown finds the allocated addresses
+        let mut teardown_opts = opts.clone();
+        network::state::lookup_static_ips(&mut teardown_opts, &ids, &config_dir).unwrap();
+        assert_eq!(teardown_opts.networks["podman"].static_ips, static_ips);
+        network::state::release_static_ips(&allocated, &ids, &config_dir).unwrap();
+
+        // after teardown the subnet is free for another container
+        let mut other_ids = ids.clone();
+        other_ids.insert("podman".to_string(), "container2".to_string());
+        let mut other_opts = opts.clone();
+        network::state::allocate_static_ips(&mut other_opts, &other_ids, &config_dir).unwrap();
+        assert_eq!(other_opts.networks["podman"].static_ips, static_ips);
+
+        std::fs::remove_dir_all(config_dir.unwrap()).unwrap();
+    }
+
+    // Containers without id get their own addresses, the teardown of one
+    // keeps the address of the other
+    #[test]
+    fn test_allocate_ips_without_container_id() {
+        let mut opts = load_ipam_allocation();
+        opts.container_id = None;
+        let subnets = opts
+            .network_info
+            .get_mut("podman")
+            .unwrap()
+            .subnets
+            .as_mut()
+            .unwrap();
+        subnets.truncate(1);
+        subnets[0].subnet = "10.89.0.0/24".parse().unwrap();
+        let dir = std::env::temp_dir().join(format!(
+            "netavark-test-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 10)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_dir = Some(dir.to_string_lossy().to_string());
+
+        // any file stands in for the namespace, only its inode matters
+        let netns1 = dir.join("netns1");
+        let netns2 = dir.join("netns2");
+        std::fs::write(&netns1, "").unwrap();
+        std::fs::write(&netns2, "").unwrap();
+        let ids1 = opts.get_container_ids(netns1.to_str()).unwrap();
+        let ids2 = opts.get_container_ids(netns2.to_str()).unwrap();
+        assert_ne!(ids1["podman"], ids2["podman"]);
+        assert_eq!(ids1, opts.get_container_ids(netns1.to_str()).unwrap());
+
+        let mut opts1 = opts.clone();
+        let allocated =
+            network::state::allocate_static_ips(&mut opts1, &ids1, &config_dir).unwrap();
+        let mut opts2 = opts.clone();
+        network::state::allocate_static_ips(&mut opts2, &ids2, &config_dir).unwrap();
+        let static_ips = opts2.networks["podman"].static_ips.clone();
+        assert_eq!(
+            opts1.networks["podman"].static_ips,
+            Some(vec!["10.89.0.2".parse().unwrap()])
+        );
+        assert_eq!(static_ips, Some(vec!["10.89.0.3".parse().unwrap()]));
+
+        network::state::release_static_ips(&allocated, &ids1, &config_dir).unwrap();
+        let mut teardown_opts = opts.clone();
+        network::state::lookup_static_ips(&mut teardown_opts, &ids2, &config_dir).unwrap();
+        assert_eq!(teardown_opts.networks["podman"].static_ips, static_ips);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // Rootless callers without config directory keep the netns locks in
+    // $XDG_RUNTIME_DIR as they cannot write the run directory
+    #[test]
+    fn test_lock_netns_rootless() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = std::env::temp_dir().join(format!(
+            "netavark-test-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 10)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("XDG_RUNTIME_DIR", &dir);
+        let netns = dir.join("netns");
+        std::fs::write(&netns, "").unwrap();
+
+        let lock = network::state::lock_netns(&None, netns.to_str().unwrap(), true).unwrap();
+        let meta = std::fs::metadata(&netns).unwrap();
+        let path =
+            dir.join("netavark-netns-locks")
+                .join(format!("{}-{}.lock", meta.dev(), meta.ino()));
+        assert!(path.exists());
+        lock.remove().unwrap();
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // The first subnet of the pool which does not overlap a used one is picked
+    #[test]
+    fn test_get_free_subnet() {
+        let pool = "10.88.0.0/16".parse().unwrap();
+        let subnet = network::core_utils::get_free_subnet(pool, 24, &[]);
+        assert_eq!(subnet, Some("10.88.0.0/24".parse().unwrap()));
+
+        let used = [
+            "10.88.0.0/24".parse().unwrap(),
+            "10.88.1.128/25".parse().unwrap(),
+        ];
+        let subnet = network::core_utils::get_free_subnet(pool, 24, &used);
+        assert_eq!(subnet, Some("10.88.2.0/24".parse().unwrap()));
+
+        // a used subnet containing the whole pool leaves nothing
+        let used = ["10.0.0.0/8".parse().unwrap()];
+        assert_eq!(network::core_utils::get_free_subnet(pool, 24, &used), None);
+    }
+
+    // Bridge networks without subnets get a free subnet and keep it
+    #[test]
+    fn test_assign_default_subnets() {
+        let mut opts = load_ipam_allocation();
+        let mut other = opts.network_info["podman"].clone();
+        other.name = "other".to_string();
+        other.subnets = Some(vec![network::types::Subnet {
+            gateway: None,
+            lease_range: None,
+            subnet: "10.88.0.0/24".parse().unwrap(),
+        }]);
+        opts.network_info.insert("other".to_string(), other);
+        opts.network_info.get_mut("podman").unwrap().subnets = None;
+
+        // the subnet of the other network is skipped
+        let mut preview = opts.clone();
+        network::state::preview_default_subnets(&mut preview).unwrap();
+        let subnets = preview.network_info["podman"].subnets.clone().unwrap();
+        assert_eq!(subnets[0].subnet, "10.88.1.0/24".parse().unwrap());
+        assert_eq!(subnets[0].gateway, Some("10.88.1.1".parse().unwrap()));
+
+        let config_dir = std::env::temp_dir().join(format!(
+            "netavark-test-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 10)
+        ));
+        let config_dir = Some(config_dir.to_string_lossy().to_string());
+
+        // the host routes are skipped as well, the chosen subnet is reused
+        let mut setup_opts = opts.clone();
+        network::state::assign_default_subnets(&mut setup_opts, &config_dir).unwrap();
+        let subnet = setup_opts.network_info["podman"].subnets.clone().unwrap()[0].subnet;
+        assert_ne!(subnet, "10.88.0.0/24".parse().unwrap());
+        let mut again = opts.clone();
+        network::state::assign_default_subnets(&mut again, &config_dir).unwrap();
+        assert_eq!(
+            again.network_info["podman"].subnets.clone().unwrap()[0].subnet,
+            subnet
+        );
+
+        let mut teardown_opts = opts.clone();
+        network::state::lookup_default_subnets(&mut teardown_opts, &config_dir).unwrap();
+        assert_eq!(
+            teardown_opts.network_info["podman"]
+                .subnets
+                .clone()
+                .unwrap()[0]
+                .subnet,
+            subnet
+        );
+
+        std::fs::remove_dir_all(config_dir.unwrap()).unwrap();
+    }
+
+    // conntrack_zone=true gets a zone no other network has and keeps it
+    #[test]
+    fn test_assign_conntrack_zones() {
+        use netavark::network::state::StateFile;
+
+        let mut opts = load_ipam_allocation();
+        let mut other = opts.network_info["podman"].clone();
+        other.name = "other".to_string();
+        opts.network_info.insert("other".to_string(), other);
+        for network in opts.network_info.values_mut() {
+            network.options = Some(std::collections::HashMap::from([(
+                "conntrack_zone".to_string(),
+                "true".to_string(),
+            )]));
+        }
+
+        let config_dir = std::env::temp_dir().join(format!(
+            "netavark-test-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 10)
+        ));
+        let dir = config_dir.to_string_lossy().to_string();
+        let config_dir = Some(dir.clone());
+
+        // a third network already has the zone derived from podman
+        let derived = network::core_utils::conntrack_zone_from_name("podman");
+        let mut state = StateFile::open(&dir).unwrap();
+        state
+            .state
+            .conntrack_zones
+            .insert("third".to_string(), derived);
+        state.save().unwrap();
+        drop(state);
+
+        let zone = |opts: &network::types::NetworkOptions, name: &str| {
+            opts.network_info[name].options.as_ref().unwrap()["conntrack_zone"].clone()
+        };
+        let mut setup_opts = opts.clone();
+        network::state::assign_conntrack_zones(&mut setup_opts, &config_dir).unwrap();
+        let podman = zone(&setup_opts, "podman");
+        assert_ne!(podman, derived.to_string());
+        assert_eq!(
+            zone(&setup_opts, "other"),
+            network::core_utils::conntrack_zone_from_name("other").to_string()
+        );
+
+        let mut again = opts.clone();
+        network::state::assign_conntrack_zones(&mut again, &config_dir).unwrap();
+        assert_eq!(zone(&again, "podman"), podman);
+
+        let mut teardown_opts = opts.clone();
+        network::state::lookup_conntrack_zones(&mut teardown_opts, &config_dir).unwrap();
+        assert_eq!(zone(&teardown_opts, "podman"), podman);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    // Pairs of containers whose namespace is gone are forgotten, an interface
+    // which no longer matches the record is not deleted
+    #[test]
+    fn test_remove_orphaned_veths() {
+        use netavark::network::state::{NetnsId, StateFile, VethPair};
+        use std::os::unix::fs::MetadataExt;
+
+        let config_dir = std::env::temp_dir().join(format!(
+            "netavark-test-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 10)
+        ));
+        let config_dir = Some(config_dir.to_string_lossy().to_string());
+
+        let pair = VethPair {
+            host_interface: "vethgone".to_string(),
+            host_index: u32::MAX,
+            container_interface: "eth0".to_string(),
+            container_index: 2,
+        };
+        let mut state = StateFile::open(config_dir.as_deref().unwrap()).unwrap();
+        for (id, path) in [("dead", "/proc/0/ns/net"), ("alive", "/proc/self/ns/net")] {
+            let meta = std::fs::metadata(path);
+            state.state.veth_netns.insert(
+                id.to_string(),
+                NetnsId {
+                    path: path.to_string(),
+                    dev: meta.as_ref().map_or(1, |m| m.dev()),
+                    ino: meta.as_ref().map_or(1, |m| m.ino()),
+                },
+            );
+            state.state.veth_pairs.insert(
+                id.to_string(),
+                [("podman".to_string(), pair.clone())].into(),
+            );
+        }
+        state.save().unwrap();
+        drop(state);
+
+        let removed = network::state::remove_orphaned_veths(&config_dir).unwrap();
+        assert_eq!(removed, Vec::<String>::new());
+        let state = network::state::read_state(config_dir.as_deref().unwrap()).unwrap();
+        assert_eq!(state.veth_pairs.keys().collect::<Vec<_>>(), vec!["alive"]);
+        assert_eq!(state.veth_netns.keys().collect::<Vec<_>>(), vec!["alive"]);
+
+        std::fs::remove_dir_all(config_dir.unwrap()).unwrap();
+    }
+
+    // Teardown gets the firewall rules setup recorded until they are forgotten
+    #[test]
+    fn test_record_firewall_rules() {
+        use netavark::firewall::{FirewallRule, RuleTeardown};
+
+        let opts = load_ipam_allocation();
+        let ids = opts.get_container_ids(None).unwrap();
+        let config_dir = std::env::temp_dir().join(format!(
+            "netavark-test-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 10)
+        ));
+        let config_dir = Some(config_dir.to_string_lossy().to_string());
+
+        let rule = |chain: &str, rule: &str, teardown| FirewallRule {
+            ipv6: false,
+            table: "nat".to_string(),
+            chain: chain.to_string(),
+            rule: rule.to_string(),
+            teardown,
+        };
+        let rules = vec![
+            rule(
+                "POSTROUTING",
+                "-s 10.89.0.0/24 -j NETAVARK-1D8721804F16F",
+                RuleTeardown::OnComplete,
+            ),
+            rule(
+                "NETAVARK-HOSTPORT-DNAT",
+                "-p tcp --dport 8080 -j NETAVARK-DN-1D8721804F16F",
+                RuleTeardown::Always,
+            ),
+        ];
+        assert!(!rules[0].removed_on(false));
+        assert!(rules[0].removed_on(true));
+        assert!(rules[1].removed_on(false));
+
+        let recorded = [("podman".to_string(), rules.clone())].into();
+        network::state::record_firewall_rules(&recorded, &ids, &config_dir).unwrap();
+        assert_eq!(
+            network::state::lookup_firewall_rules(&opts, &ids, &config_dir).unwrap(),
+            recorded
+        );
+        // the chains of the rules are recorded as well
+        let state = network::state::read_state(config_dir.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            state.firewall_chains[&ids["podman"]]["podman"],
+            netavark::firewall::FirewallChains::from_rules(&rules)
+        );
+
+        // replacing the rules with a comment keeps all other recorded rules
+        let comment = netavark::firewall::dns_rule_comment("1D8721804F16F");
+        let dns_rule = |port| {
+            rule(
+                "NETAVARK-HOSTPORT-DNAT",
+                &format!(
+                    "-d 10.89.0.1 -p udp --dport 53 -m comment --comment '{}' -j DNAT --to-destination 10.89.0.1:{}",
+                    comment, port
+                ),
+                RuleTeardown::OnComplete,
+            )
+        };
+        let with_dns = [(
+            "podman".to_string(),
+            vec![rules[0].clone(), dns_rule(10053)],
+        )]
+        .into();
+        network::state::record_firewall_rules(&with_dns, &ids, &config_dir).unwrap();
+        network::state::replace_firewall_rules("podman", &comment, &[dns_rule(10054)], &config_dir)
+            .unwrap();
+        assert_eq!(
+            network::state::lookup_firewall_rules(&opts, &ids, &config_dir).unwrap(),
+            [(
+                "podman".to_string(),
+                vec![rules[0].clone(), dns_rule(10054)]
+            )]
+            .into()
+        );
+
+        network::state::forget_firewall_rules(&opts, &ids, &config_dir).unwrap();
+        assert!(
+            network::state::lookup_firewall_rules(&opts, &ids, &config_dir)
+                .unwrap()
+                .is_empty()
+        );
+        let state = network::state::read_state(config_dir.as_deref().unwrap()).unwrap();
+        assert!(state.firewall_chains.is_empty());
+
+        std::fs::remove_dir_all(config_dir.unwrap()).unwrap();
+    }
+
+    // Search domains must be valid dns names
+    #[test]
+    fn test_validate_dns_name() {
+        for name in ["example.com", "corp.example.org.", "dns.podman", "a-b.c1"] {
+            assert!(
+                network::validation::validate_dns_name(name).is_ok(),
+                "{} must be valid",
+                name
+            );
+        }
+        for name in [
+            "",
+            ".",
+            "example..com",
+            "-example.com",
+            "exa_mple.com",
+            &"a".repeat(64),
+        ] {
+            assert!(
+                network::validation::validate_dns_name(name).is_err(),
+                "{} must be invalid",
+                name
+            );
+        }
+    }
+
+    // Extra rules must stay in the network chains
+    #[test]
+    fn test_validate_extra_rule() {
+        let rule = |chain: &str, rule: &str| network::types::ExtraRule {
+            chain: chain.to_string(),
+            rule: rule.to_string(),
+        };
+        for extra in [
+            rule("forward", "-s 10.88.0.0/16 -p tcp --dport 22 -j DROP"),
+            rule("forward", "-d fd00::/64 -j LOG --log-prefix test"),
+            rule("nat", "-d 192.168.1.0/24 -j RETURN"),
+            rule("forward", "-jDROP"),
+            rule("forward", "--jump=REJECT --reject-with tcp-reset -p tcp"),
+            rule("forward", "-p tcp --dport 22 --jum ACCEPT"),
+        ] {
+            assert!(
+                network::validation::validate_extra_rule(&extra).is_ok(),
+                "{:?} must be valid",
+                extra
+            );
+        }
+        for extra in [
+            rule("input", "-j ACCEPT"),
+            rule("forward", ""),
+            rule("forward", "-j DROP\n-A INPUT -j ACCEPT"),
+            rule("forward", "-A INPUT -j ACCEPT"),
+            rule("forward", "-t nat -j ACCEPT"),
+            rule("forward", "-j INPUT"),
+            rule("forward", "-g NETAVARK_ISOLATION_1"),
+            rule("forward", "-j"),
+            rule("nat", "-j DROP"),
+            rule("forward", "-s 10.88.0.0/16 -d fd00::/64 -j ACCEPT"),
+            // attached values and abbreviated long options
+            rule("forward", "-jINPUT"),
+            rule("forward", "--jump=INPUT"),
+            rule("forward", "--jum INPUT"),
+            rule("forward", "--j=INPUT"),
+            rule("forward", "--goto NETAVARK_ISOLATION_1"),
+            rule("forward", "--go=NETAVARK_ISOLATION_1"),
+            rule("forward", "-AINPUT -j ACCEPT"),
+            rule("forward", "--app INPUT -j ACCEPT"),
+            rule("forward", "--table=nat -j ACCEPT"),
+        ] {
+            assert!(
+                network::validation::validate_extra_rule(&extra).is_err(),
+                "{:?} must be invalid",
+                extra
+            );
+        }
+    }
+
+    // Port mappings must not forward the same host port twice
+    #[test]
+    fn test_validate_port_mappings() {
+        let check = |mappings: &[&str]| {
+            let mappings: Vec<network::types::PortMapping> =
+                mappings.iter().map(|m| m.parse().unwrap()).collect();
+            network::validation::validate_port_mappings(&mappings).map_err(|e| e.to_string())
+        };
+
+        for ok in [
+            vec!["8080:80", "8081:81"],
+            vec!["8080:80/tcp", "8080:80/udp"],
+            vec!["127.0.0.1:8080:80", "127.0.0.2:8080:80"],
+            vec!["0.0.0.0:8080:80", "[::1]:8080:80"],
+            vec!["8080-8081:80-81", "8082:82"],
+        ] {
+            assert_eq!(check(&ok), Ok(()), "{:?}", ok);
+        }
+
+        assert_eq!(
+            check(&["8080:80", "8080:80"]),
+            Err(
+                "port mappings 8080:80/tcp and 8080:80/tcp overlap on host port 8080/tcp"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check(&["8080-8082:80-82", "8081:81"]),
+            Err(
+                "port mappings 8080-8082:80-82/tcp and 8081:81/tcp overlap on host port 8081/tcp"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check(&["8080-8082:80-82/udp", "8082-8083:82-83/udp"]),
+            Err("port mappings 8080-8082:80-82/udp and 8082-8083:82-83/udp overlap on host port 8082/udp".to_string())
+        );
+        // an empty host ip binds all addresses, 0.0.0.0 all ipv4 addresses
+        assert_eq!(
+            check(&["127.0.0.1:8080:80", "8080:81"]),
+            Err(
+                "port mappings 127.0.0.1:8080:80/tcp and 8080:81/tcp overlap on host port 8080/tcp"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check(&["0.0.0.0:8080:80", "127.0.0.1:8080:81"]),
+            Err("port mappings 0.0.0.0:8080:80/tcp and 127.0.0.1:8080:81/tcp overlap on host port 8080/tcp".to_string())
+        );
+    }
+
+    // Static ips must match the subnets they are assigned to
+    #[test]
+    fn test_static_ip_mismatches() {
+        let opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/static-ips-by-subnet.json".to_owned(),
+        ))
+        .unwrap();
+        let network = &opts.network_info["podman"];
+        let mut per_net = opts.networks["podman"].clone();
+        per_net.static_ips_by_subnet = None;
+        let mismatches = |per_net: &network::types::PerNetworkOptions, ips: &[&str]| {
+            let mut per_net = per_net.clone();
+            per_net.static_ips = Some(ips.iter().map(|ip| ip.parse().unwrap()).collect());
+            network::validation::static_ip_mismatches(network, &per_net)
+        };
+
+        assert_eq!(
+            mismatches(&per_net, &["10.88.0.6", "fd10:88::6"]),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            mismatches(&per_net, &["10.88.0.6", "fd10:88::6", "10.88.0.7"]),
+            vec!["static ip 10.88.0.7 has no matching subnet"]
+        );
+        assert_eq!(
+            mismatches(&per_net, &["10.88.0.6", "10.90.0.2"]),
+            vec!["static ip 10.90.0.2 is not in subnet fd10:88::/64"]
+        );
+        assert_eq!(
+            mismatches(&per_net, &["10.88.0.6"]),
+            vec!["no static ip provided for subnet fd10:88::/64"]
+        );
+
+        // the per subnet map is checked by the ipam code
+        per_net.static_ips_by_subnet = opts.networks["podman"].static_ips_by_subnet.clone();
+        assert_eq!(mismatches(&per_net, &["10.90.0.2"]), Vec::<String>::new());
+    }
+
+    // Port forwarding chains are stale once their namespace is gone
+    #[test]
+    fn test_partition_port_forward_chains() {
+        use std::os::unix::fs::MetadataExt;
+
+        let path = std::env::temp_dir().join(format!(
+            "netavark-test-netns-{}",
+            Alphanumeric.sample_string(&mut rand::thread_rng(), 10)
+        ));
+        std::fs::write(&path, "").unwrap();
+        let meta = std::fs::metadata(&path).unwrap();
+
+        let mut state = network::state::State::default();
+        state.port_forward_chains.insert(
+            "NETAVARK-DN-1E9816D8AECFF".to_string(),
+            network::state::PortForwardChain {
+                container_id: "ad1df727792c".to_string(),
+                network: "podman".to_string(),
+                netns_path: path.to_string_lossy().to_string(),
+                netns_dev: meta.dev(),
+                netns_ino: meta.ino(),
+            },
+        );
+        let (stale, known) = state.partition_port_forward_chains();
+        assert!(stale.is_empty());
+        assert!(known.contains("NETAVARK-DN-1E9816D8AECFF"));
+
+        std::fs::remove_file(&path).unwrap();
+        let (stale, known) = state.partition_port_forward_chains();
+        assert!(stale.contains("NETAVARK-DN-1E9816D8AECFF"));
+        assert!(known.is_empty());
+    }
+
+    // The status block reports the container addresses with the prefix length
+    // of their subnet for the bridge and macvlan drivers
+    #[test]
+    fn test_status_addresses_cidr() {
+        for (file, expected) in [
+            ("twoNetworks.json", vec!["10.0.0.2/24", "10.1.0.2/24"]),
+            ("setupoptsmacvlan.test.json", vec!["10.88.0.2/16"]),
+        ] {
+            let opts =
+                network::types::NetworkOptions::load(Some(format!("src/test/config/{}", file)))
+                    .unwrap();
+            let mut names: Vec<&String> = opts.network_info.keys().collect();
+            names.sort();
+            let mut ipnets = Vec::new();
+            for name in names {
+                let addresses = network::core_utils::get_ipam_addresses(
+                    &opts.networks[name],
+                    &opts.network_info[name],
+                )
+                .unwrap();
+                for address in addresses.net_addresses {
+                    let json = serde_json::to_value(&address).unwrap();
+                    ipnets.push(json["ipnet"].as_str().unwrap().to_string());
+                }
+            }
+            assert_eq!(ipnets, expected, "{}", file);
+        }
+    }
+
+    // The status block gateways are the subnet gateways without duplicates
+    #[test]
+    fn test_get_gateways() {
+        let address = |ipnet: &str, gateway: Option<&str>| network::types::NetAddress {
+            gateway: gateway.map(|gw| gw.parse().unwrap()),
+            ipnet: ipnet.parse().unwrap(),
+        };
+        let gateways = network::core_utils::get_gateways(&[
+            address("10.88.0.2/16", Some("10.88.0.1")),
+            address("10.89.0.2/24", Some("10.88.0.1")),
+            address("fd10:88:a::2/64", Some("fd10:88:a::1")),
+            address("10.90.0.2/24", None),
+        ]);
+        assert_eq!(
+            gateways,
+            Some(vec![
+                "10.88.0.1".parse::<IpAddr>().unwrap(),
+                "fd10:88:a::1".parse().unwrap()
+            ])
+        );
+        assert_eq!(
+            network::core_utils::get_gateways(&[address("10.90.0.2/24", None)]),
+            None
+        );
+    }
+
+    // Netlink errors name the operation, the interface and the errno
+    #[test]
+    fn test_wrap_netlink_error() {
+        let mut msg = netlink_packet_core::error::ErrorMessage::default();
+        msg.code = std::num::NonZeroI32::new(-libc::EEXIST);
+        let result: netavark::error::NetavarkResult<()> = network::core_utils::wrap_netlink(
+            Err(netavark::error::NetavarkError::Netlink(msg)),
+            "create veth pair",
+            "eth0",
+            "container",
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "create veth pair on eth0 in container namespace: Netlink error: EEXIST: File exists (os error 17)"
+        );
+    }
+
+    // Moving an interface into the namespace is retried while it is busy
+    #[test]
+    fn test_retry_netns_move() {
+        let netlink_error = |code: i32| {
+            let mut msg = netlink_packet_core::error::ErrorMessage::default();
+            msg.code = std::num::NonZeroI32::new(-code);
+            netavark::error::NetavarkError::Netlink(msg)
+        };
+
+        // busy once, then moved
+        let mut calls = 0;
+        let result = network::core_utils::retry_netns_move("eth0", 3, || {
+            calls += 1;
+            if calls == 1 {
+                return Err(netlink_error(libc::EBUSY));
+            }
+            Ok(calls)
+        });
+        assert_eq!(result.unwrap(), 2);
+
+        // busy on every attempt
+        let mut calls = 0;
+        let result: netavark::error::NetavarkResult<()> =
+            network::core_utils::retry_netns_move("eth0", 3, || {
+                calls += 1;
+                Err(netlink_error(libc::EBUSY))
+            });
+        assert_eq!(calls, 3);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "move eth0 into the container namespace: still busy after 3 attempts: Netlink error: EBUSY: Device or resource busy (os error 16)"
+        );
+
+        // other errors are not retried
+        let mut calls = 0;
+        let result: netavark::error::NetavarkResult<()> =
+            network::core_utils::retry_netns_move("eth0", 3, || {
+                calls += 1;
+                Err(netlink_error(libc::EEXIST))
+            });
+        assert_eq!(calls, 1);
+        assert!(matches!(
+            result,
+            Err(netavark::error::NetavarkError::Netlink(_))
+        ));
+    }
+
+    // The logged options are pretty printed with secret values redacted
+    #[test]
+    fn test_redacted_json() {
+        let mut opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/setupopts.test.json".to_owned(),
+        ))
+        .unwrap();
+        let network = opts.network_info.values_mut().next().unwrap();
+        network.options = Some(
+            [
+                ("mtu".to_string(), "1500".to_string()),
+                ("api_token".to_string(), "hunter2".to_string()),
+            ]
+            .into(),
+        );
+        let json = opts.redacted_json();
+        assert!(json.contains("\n  \"network_info\": {"), "{}", json);
+        assert!(json.contains("\"mtu\": \"1500\""), "{}", json);
+        assert!(json.contains("\"api_token\": \"<redacted>\""), "{}", json);
+        assert!(!json.contains("hunter2"), "{}", json);
+    }
+
+    // Unset fields are filled with the values netavark uses for them
+    #[test]
+    fn test_apply_defaults() {
+        let mut opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/portmapping.json".to_owned(),
+        ))
+        .unwrap();
+        opts.network_info.get_mut("podman").unwrap().ipam_options = None;
+        opts.port_mappings.as_mut().unwrap()[0].range = 0;
+        opts.apply_defaults();
+        assert_eq!(
+            opts.network_info["podman"].ipam_options,
+            Some([("driver".to_string(), "host-local".to_string())].into())
+        );
+        assert_eq!(opts.port_mappings.unwrap()[0].range, 1);
+
+        // set values are kept
+        let mut opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/portmapping.json".to_owned(),
+        ))
+        .unwrap();
+        let ipam = opts.network_info["podman"].ipam_options.clone();
+        opts.apply_defaults();
+        assert_eq!(opts.network_info["podman"].ipam_options, ipam);
+    }
+
+    // Port mappings in the Docker -p format are parsed into the structured form
+    #[test]
+    fn test_parse_docker_port_mapping() {
+        let parse = |s: &str| {
+            let p: network::types::PortMapping = s.parse().unwrap();
+            (
+                p.host_ip,
+                p.host_port,
+                p.container_port,
+                p.range,
+                p.protocol,
+            )
+        };
+        let mapping = |ip: &str, host, container, range, proto: &str| {
+            (ip.to_string(), host, container, range, proto.to_string())
+        };
+        assert_eq!(parse("8080:80"), mapping("", 8080, 80, 1, "tcp"));
+        assert_eq!(parse("8080:80/udp"), mapping("", 8080, 80, 1, "udp"));
+        assert_eq!(
+            parse("0.0.0.0:8080:80/tcp"),
+            mapping("0.0.0.0", 8080, 80, 1, "tcp")
+        );
+        assert_eq!(
+            parse("[::1]:8080:80/sctp"),
+            mapping("::1", 8080, 80, 1, "sctp")
+        );
+        assert_eq!(
+            parse("127.0.0.1:8080-8082:80-82/tcp,udp"),
+            mapping("127.0.0.1", 8080, 80, 3, "tcp,udp")
+        );
+
+        for (port, err) in [
+            ("80", "a host port is required"),
+            ("127.0.0.1::80", "a host port is required"),
+            (
+                "8080:80/icmp",
+                "unknown protocol \"icmp\", must be tcp, udp or sctp",
+            ),
+            ("::1:8080:80", "ipv6 host ips must be in brackets"),
+            ("[::1:8080:80", "missing closing bracket of the host ip"),
+            ("host:8080:80", "invalid host ip \"host\""),
+            ("8080:0", "invalid port \"0\""),
+            ("70000:80", "invalid port \"70000\""),
+            ("8082-8080:80", "invalid port range \"8082-8080\""),
+            (
+                "8080-8081:80",
+                "host and container port ranges must have the same length",
+            ),
+        ] {
+            match port.parse::<network::types::PortMapping>() {
+                Ok(_) => panic!("{} must be invalid", port),
+                Err(e) => assert_eq!(e, format!("invalid port mapping {:?}: {}", port, err)),
+            }
+        }
+    }
+
+    // The config accepts both forms of port mappings
+    #[test]
+    fn test_load_docker_port_mappings() {
+        let mut value: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string("src/test/config/portmapping.json").unwrap(),
+        )
+        .unwrap();
+        let structured = value["port_mappings"][0].clone();
+        value["port_mappings"] = serde_json::json!(["8080:80/udp", structured]);
+        let opts: network::types::NetworkOptions = serde_json::from_value(value.clone()).unwrap();
+        let ports = opts.port_mappings.unwrap();
+        assert_eq!(ports.len(), 2);
+        assert_eq!(
+            (ports[0].host_port, ports[0].protocol.as_str()),
+            (8080, "udp")
+        );
+        assert_eq!(
+            ports[1].host_port,
+            structured["host_port"].as_u64().unwrap() as u16
+        );
+
+        value["port_mappings"] = serde_json::json!(["80"]);
+        let err = serde_json::from_value::<network::types::NetworkOptions>(value).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid port mapping \"80\": a host port is required"
+        );
+    }
+
+    // Docker style options are translated into the netavark settings
+    #[test]
+    fn test_apply_docker_options() {
+        let mut opts = network::types::NetworkOptions::load(Some(
+            "src/test/config/portmapping.json".to_owned(),
+        ))
+        .unwrap();
+        let network = opts.network_info.get_mut("podman").unwrap();
+        network.network_interface = None;
+        network.options = Some(
+            [
+                ("com.docker.network.bridge.name", "docker0"),
+                ("com.docker.network.driver.mtu", "1400"),
+                ("com.docker.network.bridge.enable_icc", "true"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .into(),
+        );
+        opts.apply_docker_options();
+        let network = &opts.network_info["podman"];
+        assert_eq!(network.network_interface.as_deref(), Some("docker0"));
+        let options = network.options.as_ref().unwrap();
+        assert_eq!(options.get("mtu").map(String::as_str), Some("1400"));
+        // unsupported options are kept but ignored
+        assert!(options.contains_key("com.docker.network.bridge.enable_icc"));
+        assert!(!options.contains_key("com.docker.network.driver.mtu"));
+
+        // the netavark settings take precedence
+        let network = opts.network_info.get_mut("podman").unwrap();
+        network.driver = "macvlan".to_string();
+        network.options = Some(
+            [
+                ("parent", "eth0.10"),
+                ("com.docker.network.driver.mtu", "1400"),
+                ("mtu", "9000"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .into(),
+        );
+        opts.apply_docker_options();
+        let network = &opts.network_info["podman"];
+        assert_eq!(network.network_interface.as_deref(), Some("docker0"));
+        let options = network.options.as_ref().unwrap();
+        assert_eq!(options.get("mtu").map(String::as_str), Some("9000"));
+        assert!(!options.contains_key("parent"));
+
+        // the vlan selects the vlan interface of the parent
+        let network = opts.network_info.get_mut("podman").unwrap();
+        network.network_interface = None;
+        network.options = Some(
+            [("parent", "eth0"), ("vlan", "10")]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .into(),
+        );
+        opts.apply_docker_options();
+        let network = &opts.network_info["podman"];
+        assert_eq!(network.network_interface.as_deref(), Some("eth0.10"));
+        assert!(network.options.as_ref().unwrap().is_empty());
+
+        // and the default pvid of a bridge
+        let network = opts.network_info.get_mut("podman").unwrap();
+        network.driver = "bridge".to_string();
+        network.options = Some(
+            [("vlan", "10")]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .into(),
+        );
+        opts.apply_docker_options();
+        let options = opts.network_info["podman"].options.as_ref().unwrap();
+        assert_eq!(
+            options.get("vlan_filtering").map(String::as_str),
+            Some("true")
+        );
+        assert_eq!(options.get("default_pvid").map(String::as_str), Some("10"));
+        assert!(!options.contains_key("vlan"));
+    }
+
+    // Only the selected network is kept, unknown networks are rejected
+    #[test]
+    fn test_retain_network() {
+        let load = || {
+            network::types::NetworkOptions::load(Some(
+                "src/test/config/twoNetworks.json".to_owned(),
+            ))
+            .unwrap()
+        };
+        let mut opts = load();
+        opts.retain_network("podman2").unwrap();
+        assert_eq!(opts.networks.keys().collect::<Vec<_>>(), vec!["podman2"]);
+        assert_eq!(
+            opts.network_info.keys().collect::<Vec<_>>(),
+            vec!["podman2"]
+        );
+
+        let mut opts = load();
+        assert_eq!(
+            opts.retain_network("podman3").unwrap_err().to_string(),
+            "network podman3 is not configured for the container"
+        );
+        assert_eq!(opts.networks.len(), 2);
+    }
+
     // Test commands::setup::ns_checks works correctly
     #[test]
     fn test_ns_checks() {
-        assert!(network::validation::ns_checks("src/test/config/setupopts.test.json").is_ok());
+        assert!(network::validation::ns_checks("/proc/self/ns/net").is_ok());
+
+        let err = |path| {
+            network::validation::ns_checks(path)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            err("src/test/config/nonexistent"),
+            "namespace path \"src/test/config/nonexistent\" does not exist"
+        );
+        assert_eq!(
+            err("src/test/config/setupopts.test.json"),
+            "\"src/test/config/setupopts.test.json\" is not a namespace file"
+        );
+        assert_eq!(
+            err("/proc/self/ns/mnt"),
+            "\"/proc/self/ns/mnt\" is a mount namespace, not a network namespace"
+        );
+    }
+
+    #[test]
+    fn test_logging_network_scope() {
+        use netavark::logging;
+
+        assert_eq!(logging::current_tag(), None);
+        {
+            let _t1 = logging::network_scope("t1", "abc");
+            assert_eq!(
+                logging::current_tag().as_deref(),
+                Some("network=t1 container=abc")
+            );
+            {
+                let _t2 = logging::network_scope("t2", "abc");
+                assert_eq!(
+                    logging::current_tag().as_deref(),
+                    Some("network=t2 container=abc")
+                );
+            }
+            assert_eq!(
+                logging::current_tag().as_deref(),
+                Some("network=t1 container=abc")
+            );
+        }
+        assert_eq!(logging::current_tag(), None);
     }
 }
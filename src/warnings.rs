@@ -0,0 +1,25 @@
+//! Collect non fatal warnings so they can be reported back to the caller.
+//!
+//! Warnings are logged like any other log message but are also stored so
+//! setup can add them to the status block returned to podman.
+use std::sync::Mutex;
+
+use log::warn;
+
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Log the warning and store it for the setup response.
+pub fn push(msg: String) {
+    warn!("{}", msg);
+    if let Ok(mut warnings) = WARNINGS.lock() {
+        warnings.push(msg);
+    }
+}
+
+/// Return all stored warnings and clear them.
+pub fn take() -> Vec<String> {
+    match WARNINGS.lock() {
+        Ok(mut warnings) => std::mem::take(&mut *warnings),
+        Err(_) => Vec::new(),
+    }
+}
@@ -19,10 +19,133 @@ load helpers
 
 @test "netavark error - invalid ns path" {
     expected_rc=1 run_netavark -f ${TESTSDIR}/testfiles/simplebridge.json setup /test/1
-    assert_json ".error" "invalid namespace path: IO error: No such file or directory (os error 2)" "Namespace path does not exists"
+    assert_json ".error" 'invalid namespace path: namespace path "/test/1" does not exist' "Namespace path does not exists"
+
+    expected_rc=1 run_netavark -f ${TESTSDIR}/testfiles/simplebridge.json setup ${TESTSDIR}/testfiles/simplebridge.json
+    assert_json ".error" "invalid namespace path: \"${TESTSDIR}/testfiles/simplebridge.json\" is not a namespace file" "regular file"
+
+    expected_rc=1 run_netavark -f ${TESTSDIR}/testfiles/simplebridge.json setup /proc/${CONTAINER_NS_PIDS[0]}/ns/mnt
+    assert_json ".error" "invalid namespace path: \"/proc/${CONTAINER_NS_PIDS[0]}/ns/mnt\" is a mount namespace, not a network namespace" "mount namespace"
 }
 
 @test "netavark error - invalid config path" {
     expected_rc=1 run_netavark -f /test/1 setup $(get_container_netns_path)
     assert_json ".error" "failed to load network options: IO error: No such file or directory (os error 2)" "Config file does not exists"
 }
+
+@test "netavark setup with netns pid" {
+    run_netavark -f ${TESTSDIR}/testfiles/simplebridge.json setup --netns-pid ${CONTAINER_NS_PIDS[0]}
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.0.2/16" "setup result"
+
+    run_in_container_netns ip addr show eth0
+    assert "$output" =~ "10.88.0.2/16" "address is set in the namespace of the pid"
+
+    run_netavark -f ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+
+    expected_rc=2 run_netavark -f ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path) --netns-pid ${CONTAINER_NS_PIDS[0]}
+    assert "$output" =~ "cannot be used with" "path and pid are exclusive"
+}
+
+@test "netavark setup reports the netns inode" {
+    run_netavark -f ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    inode=$(stat -L -c %i $(get_container_netns_path))
+    assert_json ".podman.netns_inode" "$inode" "inode of the namespace path"
+}
+
+@test "netavark setup with a read-only config directory" {
+    mkdir "$NETAVARK_TMPDIR/ro"
+    run_in_host_netns mount -t tmpfs -o ro tmpfs "$NETAVARK_TMPDIR/ro"
+    expected_rc=1 run_in_host_netns $NETAVARK --config "$NETAVARK_TMPDIR/ro/config" \
+        -f ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json ".error" "config directory $NETAVARK_TMPDIR/ro/config must be writable to store the netavark state, use --config to set another directory: Read-only file system (os error 30)" "directory is named"
+    expected_rc=1 run_in_container_netns ip link show eth0
+}
+
+@test "netavark trace logs the network options" {
+    config=$(jq '.network_info.podman.options = {"mtu": "1500", "api_token": "hunter2"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    RUST_LOG=netavark=trace run_netavark validate <<<"$config"
+    assert "$output" =~ "loaded network options:" "options are logged"
+    assert "$output" =~ '"container_id": "6ce776ea58b5"' "pretty printed options"
+    assert "$output" =~ '"api_token": "<redacted>"' "secret is redacted"
+    assert "$output" !~ "hunter2" "secret is not logged"
+}
+
+@test "netavark none driver" {
+    run_in_host_netns ip -o link show
+    links_before="$output"
+    run_in_host_netns iptables -S -t nat
+    nat_before="$output"
+
+    config=$(jq '.network_info.podman.driver = "none"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.interfaces" "{}" "no interfaces in the response"
+
+    run_in_host_netns ip -o link show
+    assert "$output" == "$links_before" "no host interfaces created"
+    run_in_host_netns iptables -S -t nat
+    assert "$output" == "$nat_before" "no firewall rules created"
+    expected_rc=1 run_in_container_netns ip link show eth0
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    assert "" "no errors"
+}
+
+@test "netavark validate" {
+    run_netavark -f ${TESTSDIR}/testfiles/simplebridge.json validate
+    assert '{"valid":true}' "valid config"
+
+    config=$(jq '.networks.podman.static_ips = ["10.89.0.2"] | .networks.podman.aliases = ["-bad"] | .network_info.podman.dns_enabled = true' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark validate <<<"$config"
+    result="$output"
+    assert_json "$result" ".valid" == "false" "invalid config"
+    assert_json "$result" ".problems | length" == "2" "two problems"
+    assert_json "$result" ".problems[0].network" == "podman" "problem network"
+    assert_json "$result" ".problems[0].error" == "static ip 10.89.0.2 is not in subnet 10.88.0.0/16" "static ip problem"
+    assert_json "$result" ".problems[1].error" =~ 'invalid dns name "-bad"' "alias problem"
+
+    run_netavark -f /test/1 validate
+    assert_json ".problems[0].error" "failed to load network options: IO error: No such file or directory (os error 2)" "load problem"
+
+    # nothing must be created
+    expected_rc=1 run_in_host_netns ip link show podman0
+}
+
+@test "netavark setup static ips must match the subnets" {
+    config=$(jq '.networks.podman.static_ips = ["10.88.0.2", "10.88.0.3"]' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "static ips do not match the subnets of network podman: static ip 10.88.0.3 has no matching subnet" "extra static ip"
+
+    config=$(jq '.networks.podman.static_ips = ["10.90.0.2"]' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "static ips do not match the subnets of network podman: static ip 10.90.0.2 is not in subnet 10.88.0.0/16" "static ip outside of the subnets"
+
+    # nothing must be created
+    expected_rc=1 run_in_host_netns ip link show podman0
+}
+
+@test "netavark quiet setup and teardown print nothing" {
+    run_in_host_netns sh -c "RUST_LOG=info $NETAVARK --config $NETAVARK_TMPDIR/config --quiet -f ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path) 2>/dev/null"
+    assert "$output" == "" "quiet setup prints nothing"
+    run_in_container_netns ip addr show eth0
+    assert "$output" =~ "10.88.0.2/16" "setup succeeded"
+
+    # teardown never prints to stdout, only the logs go to stderr
+    run_in_host_netns sh -c "RUST_LOG=info $NETAVARK --config $NETAVARK_TMPDIR/config -f ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path) 2>/dev/null"
+    assert "$output" == "" "teardown prints nothing"
+
+    # errors are still printed
+    expected_rc=1 run_netavark --quiet -f ${TESTSDIR}/testfiles/simplebridge.json setup /test/1
+    assert_json ".error" 'invalid namespace path: namespace path "/test/1" does not exist' "error is printed"
+}
+
+@test "netavark setup print config" {
+    config=$(jq 'del(.network_info.podman.ipam_options) | del(.container_name) | .port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 0, "protocol": "tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup --print-config <<<"$config"
+    result="$output"
+    assert_json "$result" ".network_info.podman.ipam_options.driver" == "host-local" "default ipam driver"
+    assert_json "$result" ".container_name" == "" "default container name"
+    assert_json "$result" ".port_mappings[0].range" == "1" "default port range"
+
+    # nothing must be created
+    expected_rc=1 run_in_host_netns ip link show podman0
+}
@@ -12,105 +12,1481 @@ fw_driver=iptables
     assert "${lines[0]}" "==" "[INFO  netavark::firewall] Using iptables firewall driver" "iptables driver is in use"
 }
 
+@test "--firewall-driver overrides NETAVARK_FW" {
+    RUST_LOG=netavark=info NETAVARK_FW=none run_netavark --firewall-driver iptables \
+        --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert "${lines[0]}" "==" "[INFO  netavark::firewall] Using iptables firewall driver" "iptables driver is in use"
+    run_in_host_netns iptables -S NETAVARK-1D8721804F16F -t nat
+}
+
+@test "--firewall-driver fails for backends which are not available" {
+    expected_rc=1 run_netavark --firewall-driver nftables \
+        --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json ".error" "nftables support presently not available" "nftables is not supported"
+
+    DBUS_SYSTEM_BUS_ADDRESS=unix:path=$NETAVARK_TMPDIR/nodbus expected_rc=1 run_netavark --firewall-driver firewalld \
+        --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json "$output" ".error" =~ "Error retrieving dbus connection for requested firewall backend" "firewalld is not reachable"
+
+    expected_rc=1 run_netavark --firewall-driver ufw \
+        --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json ".error" "Must provide a valid firewall backend, got ufw" "unknown backend"
+
+    # nothing was set up
+    expected_rc=1 run_in_container_netns ip link show eth0
+}
+
 @test "$fw_driver - internal network" {
    run_in_host_netns iptables -t nat -nvL
    before="$output"
 
-   run_netavark --file ${TESTSDIR}/testfiles/internal.json setup $(get_container_netns_path)
+   run_netavark --file ${TESTSDIR}/testfiles/internal.json setup $(get_container_netns_path)
+
+   run_in_host_netns iptables -t nat -nvL
+   assert "$output" == "$before" "make sure tables have not changed"
+
+   run_in_container_netns ip route show
+   assert "$output" "!~" "default" "No default route for internal networks"
+
+   run_in_container_netns ping -c 1 10.88.0.1
+
+   run_netavark --file ${TESTSDIR}/testfiles/internal.json teardown $(get_container_netns_path)
+}
+
+@test "$fw_driver - simple bridge" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    result="$output"
+    assert_json "$result" 'has("podman")' == "true" "object key exists"
+
+    mac=$(jq -r '.podman.interfaces.eth0.mac_address' <<<"$result")
+    # check that interface exists
+    run_in_container_netns ip -j --details link show eth0
+    link_info="$output"
+    assert_json "$link_info" ".[].address" == "$mac" "MAC matches container mac"
+    assert_json "$link_info" '.[].flags[] | select(.=="UP")' == "UP" "Container interface is up"
+    assert_json "$link_info" ".[].linkinfo.info_kind" == "veth" "Container interface is a veth device"
+
+    ipaddr="10.88.0.2/16"
+    run_in_container_netns ip addr show eth0
+    assert "$output" =~ "$ipaddr" "IP address matches container address"
+    assert_json "$result" ".podman.interfaces.eth0.subnets[0].ipnet" == "$ipaddr" "Result contains correct IP address"
+
+    run_in_host_netns ip -j --details link show podman0
+    link_info="$output"
+    assert_json "$link_info" '.[].flags[] | select(.=="UP")' == "UP" "Host bridge interface is up"
+    assert_json "$link_info" ".[].linkinfo.info_kind" == "bridge" "The bridge interface is actually a bridge"
+
+    ipaddr="10.88.0.1"
+    run_in_host_netns ip addr show podman0
+    assert "$output" =~ "$ipaddr" "IP address matches bridge gateway address"
+    assert_json "$result" ".podman.interfaces.eth0.subnets[0].gateway" == "$ipaddr" "Result contains gateway address"
+
+    # check that the loopback adapter is up
+    run_in_container_netns ip addr show lo
+    assert "$output" =~ "127.0.0.1" "Loopback adapter is up (has address)"
+
+    run_in_host_netns ping -c 1 10.88.0.2
+
+    # check iptables POSTROUTING chain
+    run_in_host_netns iptables -S POSTROUTING -t nat
+    assert "${lines[1]}" =~ "-A POSTROUTING -j NETAVARK-HOSTPORT-MASQ" "POSTROUTING HOSTPORT-MASQ rule"
+    assert "${lines[2]}" =~ "-A POSTROUTING -s 10.88.0.0/16 -m comment --comment netavark:v1 -j NETAVARK-1D8721804F16F" "POSTROUTING container rule"
+    assert "${#lines[@]}" = 3 "too many POSTROUTING rules"
+
+    # check iptables NETAVARK-1D8721804F16F chain
+    run_in_host_netns iptables -S NETAVARK-1D8721804F16F -t nat
+    assert "${lines[1]}" =~ "-A NETAVARK-1D8721804F16F -d 10.88.0.0/16 -j ACCEPT" "NETAVARK-1D8721804F16F ACCEPT rule"
+    assert "${lines[2]}" == "-A NETAVARK-1D8721804F16F ! -d 224.0.0.0/4 -j MASQUERADE --random-fully" "NETAVARK-1D8721804F16F MASQUERADE rule"
+    assert "${#lines[@]}" = 3 "too many NETAVARK-1D8721804F16F rules"
+
+    # check FORWARD rules
+    run_in_host_netns iptables -S FORWARD
+    assert "${lines[1]}" == "-A FORWARD -m comment --comment \"netavark firewall plugin rules\" -j NETAVARK_FORWARD" "FORWARD rule"
+    assert "${#lines[@]}" = 2 "too many FORWARD rules"
+
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "${lines[1]}" == "-A NETAVARK_FORWARD -m conntrack --ctstate INVALID -j DROP" "NETAVARK_FORWARD rule 1"
+    assert "${lines[2]}" == "-A NETAVARK_FORWARD -d 10.88.0.0/16 -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT" "NETAVARK_FORWARD rule 2"
+    assert "${lines[3]}" == "-A NETAVARK_FORWARD -s 10.88.0.0/16 -j ACCEPT" "NETAVARK_FORWARD rule 3"
+    assert "${#lines[@]}" = 4 "too many NETAVARK_FORWARD rules"
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+
+    # now check that iptables rules are gone
+    run_in_host_netns iptables -S
+
+    # check FORWARD rules
+    run_in_host_netns iptables -S FORWARD
+    assert "${lines[1]}" == "-A FORWARD -m comment --comment \"netavark firewall plugin rules\" -j NETAVARK_FORWARD" "FORWARD rule"
+    assert "${#lines[@]}" = 2 "too many FORWARD rules after teardown"
+
+    # rule 1 should be DROP for any existing networks
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "${lines[1]}" == "-A NETAVARK_FORWARD -m conntrack --ctstate INVALID -j DROP" "NETAVARK_FORWARD rule 1"
+    assert "${#lines[@]}" = 2 "too many NETAVARK_FORWARD rules after teardown"
+
+    # check POSTROUTING nat rules
+    run_in_host_netns iptables -S POSTROUTING -t nat
+    assert "${lines[1]}" =~ "-A POSTROUTING -j NETAVARK-HOSTPORT-MASQ" "POSTROUTING HOSTPORT-MASQ rule"
+    assert "${#lines[@]}" = 2 "too many POSTROUTING rules after teardown"
+
+    # NETAVARK-1D8721804F16F chain should not exists
+    expected_rc=1 run_in_host_netns iptables -nvL NETAVARK-1D8721804F16F -t nat
+
+    # bridge should be removed on teardown
+    expected_rc=1 run_in_host_netns ip addr show podman0
+}
+
+@test "$fw_driver - simple bridge with iptables-restore" {
+    NETAVARK_IPTABLES_RESTORE=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    run_in_host_netns iptables -S POSTROUTING -t nat
+    assert "${lines[1]}" =~ "-A POSTROUTING -j NETAVARK-HOSTPORT-MASQ" "POSTROUTING HOSTPORT-MASQ rule"
+    assert "${lines[2]}" =~ "-A POSTROUTING -s 10.88.0.0/16 -m comment --comment netavark:v1 -j NETAVARK-1D8721804F16F" "POSTROUTING container rule"
+    assert "${#lines[@]}" = 3 "too many POSTROUTING rules"
+
+    run_in_host_netns iptables -S NETAVARK-1D8721804F16F -t nat
+    assert "${lines[1]}" =~ "-A NETAVARK-1D8721804F16F -d 10.88.0.0/16 -j ACCEPT" "NETAVARK-1D8721804F16F ACCEPT rule"
+    assert "${lines[2]}" == "-A NETAVARK-1D8721804F16F ! -d 224.0.0.0/4 -j MASQUERADE --random-fully" "NETAVARK-1D8721804F16F MASQUERADE rule"
+    assert "${#lines[@]}" = 3 "too many NETAVARK-1D8721804F16F rules"
+
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "${lines[1]}" == "-A NETAVARK_FORWARD -m conntrack --ctstate INVALID -j DROP" "NETAVARK_FORWARD rule 1"
+    assert "${#lines[@]}" = 4 "too many NETAVARK_FORWARD rules"
+
+    run_in_host_netns ping -c 1 10.88.0.2
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    expected_rc=1 run_in_host_netns iptables -nvL NETAVARK-1D8721804F16F -t nat
+}
+
+@test "$fw_driver - iptables-restore applies the rules of all networks at once" {
+    NETAVARK_IPTABLES_RESTORE=1 RUST_LOG=netavark=debug run_netavark --file ${TESTSDIR}/testfiles/two-networks.json setup $(get_container_netns_path)
+    count=$(grep -c "applying rules with iptables-restore" <<<"$output")
+    assert "$count" == "1" "one transaction for both networks and the port mapping"
+
+    run_in_host_netns iptables -S POSTROUTING -t nat
+    assert "$output" =~ "-s 10.89.1.0/24" "rule of the first network"
+    assert "$output" =~ "-s 10.89.2.0/24" "rule of the second network"
+    run_in_host_netns iptables -S NETAVARK-HOSTPORT-DNAT -t nat
+    assert "$output" =~ "--dport 8080" "port forwarding rule"
+
+    run_netavark --file ${TESTSDIR}/testfiles/two-networks.json teardown $(get_container_netns_path)
+    run_in_host_netns iptables -S POSTROUTING -t nat
+    assert "$output" !~ "10.89" "rules are removed"
+}
+
+@test "$fw_driver - concurrent setup of the same netns" {
+    netns=$(get_container_netns_path)
+    cmd="$NETAVARK --config $NETAVARK_TMPDIR/config --file ${TESTSDIR}/testfiles/simplebridge.json setup $netns"
+    # both setups are serialized so exactly one of them can create the interface
+    run_in_host_netns bash -c "$cmd >$NETAVARK_TMPDIR/out1 2>&1 & pid1=\$!
+        $cmd >$NETAVARK_TMPDIR/out2 2>&1 & pid2=\$!
+        failed=0
+        wait \$pid1 || failed=\$((failed + 1))
+        wait \$pid2 || failed=\$((failed + 1))
+        echo \$failed"
+    assert "$output" == "1" "exactly one setup failed"
+
+    run cat $NETAVARK_TMPDIR/out1 $NETAVARK_TMPDIR/out2
+    assert "$output" =~ "File exists" "second setup failed because eth0 exists"
+
+    run_in_container_netns ip -j addr show eth0
+    assert_json "$output" '.[].addr_info | map(select(.family == "inet")) | length' == "1" "eth0 has one ipv4 address"
+
+    run_in_host_netns iptables -S POSTROUTING -t nat
+    assert "${#lines[@]}" = 3 "no duplicated POSTROUTING rules"
+
+    run_in_host_netns ping -c 1 10.88.0.2
+
+    run ls $NETAVARK_TMPDIR/config/netavark-netns-locks
+    assert "${#lines[@]}" == "1" "one lock file for the netns"
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $netns
+    run ls $NETAVARK_TMPDIR/config/netavark-netns-locks
+    assert "$output" == "" "lock file is removed on teardown"
+}
+
+@test "$fw_driver - isolate networks with allow_from" {
+    # isolate1: 10.89.0.2/24, fd90::2, isolate=true
+    run_netavark --file ${TESTSDIR}/testfiles/isolate1.json setup $(get_container_netns_path)
+
+    # isolate2: 10.89.1.2/24, fd99::2, isolate=true, allow_from=isolate1
+    config=$(jq '.network_info.isolate2.options.allow_from = "isolate1"' ${TESTSDIR}/testfiles/isolate2.json)
+    create_container_ns
+    run_netavark setup $(get_container_netns_path 1) <<<"$config"
+
+    # isolate3: 10.89.2.2/24, fd92::2, isolate=strict
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/isolate3.json setup $(get_container_netns_path 2)
+
+    run_in_host_netns iptables -S NETAVARK_ALLOW
+    assert "${lines[1]}" == "-A NETAVARK_ALLOW -m conntrack --ctstate INVALID -j RETURN"
+    assert "${lines[2]}" =~ "-A NETAVARK_ALLOW -s 10.89.0.0/24 -d 10.89.1.0/24 -m comment --comment \"netavark allow: [0-9a-f]+\" -j ACCEPT"
+    assert "${lines[3]}" =~ "-A NETAVARK_ALLOW -s 10.89.1.0/24 -d 10.89.0.0/24 -m conntrack --ctstate RELATED,ESTABLISHED -m comment --comment \"netavark allow: [0-9a-f]+\" -j ACCEPT"
+
+    # from network isolate1 to isolate2 is allowed
+    run_in_container_netns ping -w 1 -c 1 10.89.1.2
+    run_in_container_netns ping -w 1 -c 1 fd99::2
+    # but not the other way around
+    expected_rc=1 run_in_container_netns 1 ping -w 1 -c 1 10.89.0.2
+    # from network isolate1 to isolate3 stays blocked
+    expected_rc=1 run_in_container_netns ping -w 1 -c 1 10.89.2.2
+    expected_rc=1 run_in_container_netns ping -w 1 -c 1 fd92::2
+
+    # setting up isolate1 again re-adds the isolation jump,
+    # the allowed flows must still be accepted first
+    run_netavark --file ${TESTSDIR}/testfiles/isolate1.json teardown $(get_container_netns_path)
+    run_netavark --file ${TESTSDIR}/testfiles/isolate1.json setup $(get_container_netns_path)
+    run_in_host_netns iptables -S FORWARD
+    assert "${lines[1]}" == "-A FORWARD -j NETAVARK_ALLOW" "allow jump is the first FORWARD rule"
+    run_in_container_netns ping -w 1 -c 1 10.89.1.2
+
+    # teardown removes the allowed flows
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config"
+    run_in_host_netns iptables -S NETAVARK_ALLOW
+    assert "${#lines[@]}" = 2 "only the INVALID rule is left"
+
+    # isolate1 allows the same flow with allow_to, the rules of isolate1 are
+    # kept when isolate2 is torn down
+    run_netavark --file ${TESTSDIR}/testfiles/isolate1.json teardown $(get_container_netns_path)
+    config1=$(jq '.network_info.isolate1.options.allow_to = "isolate2"' ${TESTSDIR}/testfiles/isolate1.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config1"
+    run_netavark setup $(get_container_netns_path 1) <<<"$config"
+    run_in_host_netns iptables -S NETAVARK_ALLOW
+    assert "${#lines[@]}" = 6 "both networks have their own rules"
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config"
+    run_in_host_netns iptables -S NETAVARK_ALLOW
+    assert "${#lines[@]}" = 4 "the rules of isolate1 are kept"
+    assert "${lines[2]}" =~ "-A NETAVARK_ALLOW -s 10.89.0.0/24 -d 10.89.1.0/24 -m comment"
+
+    # the chain and its jump are removed with the last allowed flow
+    run_netavark teardown $(get_container_netns_path) <<<"$config1"
+    expected_rc=1 run_in_host_netns iptables -S NETAVARK_ALLOW
+    run_in_host_netns iptables -S FORWARD
+    assert "$output" !~ "NETAVARK_ALLOW" "allow jump is removed"
+}
+
+@test "$fw_driver - bridge with group_fwd_mask" {
+    config=$(jq '.network_info.podman.options.group_fwd_mask = "0x4008"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns ip -j --details link show podman0
+    assert_json "$output" ".[].linkinfo.info_data.group_fwd_mask" == "0x4008" "group_fwd_mask is applied"
+}
+
+@test "$fw_driver - bridge with multicast_snooping" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_in_host_netns ip -j --details link show podman0
+    assert_json "$output" ".[].linkinfo.info_data.mcast_snooping" == "1" "kernel default is kept"
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+
+    config=$(jq '.network_info.podman.options.multicast_snooping = "false"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns ip -j --details link show podman0
+    assert_json "$output" ".[].linkinfo.info_data.mcast_snooping" == "0" "multicast_snooping is applied"
+    # without snooping the multicast database stays empty and traffic is flooded
+    run_in_host_netns cat /sys/class/net/podman0/bridge/multicast_snooping
+    assert "$output" == "0" "snooping disabled in sysfs"
+}
+
+@test "$fw_driver - bridge without subnets gets a default subnet" {
+    # the host already reaches the first subnet of the pool
+    add_dummy_interface_on_host dummy0 "10.88.0.1/24"
+    config=$(jq 'del(.network_info.podman.subnets) | del(.networks.podman.static_ips)' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.1.2/24" "next free subnet is used"
+    assert_json ".podman.interfaces.eth0.subnets[0].gateway" "10.88.1.1" "gateway of the subnet"
+    run_helper jq -r '.networks.podman[0]' $NETAVARK_TMPDIR/config/netavark-state.json
+    assert "$output" == "10.88.1.0/24" "subnet is recorded"
+
+    # the bridge route must not make the second container pick another subnet
+    create_container_ns
+    config2=$(jq '.container_id = "second"' <<<"$config")
+    run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.1.3/24" "subnet is reused"
+
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config2"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    expected_rc=1 run_in_host_netns ip link show podman0
+}
+
+@test "$fw_driver - containers without container_id get their own addresses" {
+    config=$(jq 'del(.container_id) | del(.networks.podman.static_ips)' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.0.2/16" "first container address"
+
+    create_container_ns
+    run_netavark setup $(get_container_netns_path 1) <<<"$config"
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.0.3/16" "second container address"
+
+    # the teardown of the first container must keep the lease of the second
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_helper jq -r '.ipam.podman | to_entries | map(.key) | join(",")' $NETAVARK_TMPDIR/config/netavark-state.json
+    assert "$output" == "10.88.0.3" "second lease is kept"
+
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config"
+}
+
+@test "$fw_driver - setup removes orphaned veths" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    # a veth recorded for a container whose namespace is gone
+    run_in_host_netns ip link add vethorphan type veth peer name vethorphan1
+    run_in_host_netns ip -j link show vethorphan
+    index=$(jq '.[0].ifindex' <<<"$output")
+    state="$NETAVARK_TMPDIR/config/netavark-state.json"
+    jq --argjson index "$index" '.veth_pairs.dead.podman = {"host_interface": "vethorphan", "host_index": $index,
+        "container_interface": "eth0", "container_index": 2} |
+        .veth_netns.dead = {"path": "/proc/0/ns/net", "dev": 1, "ino": 1}' "$state" >"$state.new"
+    mv "$state.new" "$state"
+
+    # nothing is removed without the flag
+    create_container_ns
+    config=$(jq '.container_id = "second" | .networks.podman.static_ips = ["10.88.0.3"]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path 1) <<<"$config"
+    run_in_host_netns ip link show vethorphan
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config"
+
+    run_netavark setup --remove-orphaned-veths $(get_container_netns_path 1) <<<"$config"
+    expected_rc=1 run_in_host_netns ip link show vethorphan
+    run_helper jq -r '.veth_pairs | keys | join(",")' "$state"
+    assert "$output" == "6ce776ea58b5,second" "only the orphaned pair is forgotten"
+    run_in_container_netns ip link show eth0
+}
+
+@test "$fw_driver - failed state recording tears the network down again" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    # the state file can no longer be written once the networks are set up
+    mkdir "$NETAVARK_TMPDIR/config/netavark-state.tmp"
+    create_container_ns
+    config=$(jq '.container_id = "second" | .networks.podman.static_ips = ["10.88.0.3"]' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path 1) <<<"$config"
+    assert "$output" =~ "create state file" "state write error is returned"
+
+    expected_rc=1 run_in_container_netns 1 ip link show eth0
+    run_in_host_netns ip -j link show master podman0
+    assert "$(jq length <<<"$output")" == "1" "only the veth of the first container is left"
+    run_in_host_netns iptables -t nat -S POSTROUTING
+    assert "$output" =~ "NETAVARK-1D8721804F16F" "rules of the first container are kept"
+
+    rmdir "$NETAVARK_TMPDIR/config/netavark-state.tmp"
+    run_helper jq -r '.veth_pairs | keys | join(",")' "$NETAVARK_TMPDIR/config/netavark-state.json"
+    assert "$output" == "6ce776ea58b5" "nothing is recorded for the failed container"
+}
+
+@test "$fw_driver - bridge with mcast_flood" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_in_host_netns bridge -j -d link show master podman0
+    assert_json "$output" ".[0].mcast_flood" == "true" "multicast is flooded by default"
+
+    # every host is a member of the all hosts group, like mDNS it is link local
+    # and only reaches the other container when it is flooded
+    create_container_ns
+    run_in_container_netns 1 sysctl -w net.ipv4.icmp_echo_ignore_broadcasts=0
+    config=$(jq '.container_id = "second" | .networks.podman.static_ips = ["10.88.0.3"]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path 1) <<<"$config"
+    run_in_container_netns ping -w 1 -c 1 224.0.0.1
+    assert "$output" =~ "from 10.88.0.3" "multicast reaches the other container"
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config"
+
+    config=$(jq '.networks.podman.static_ips = ["10.88.0.3"] | .container_id = "second" |
+        .network_info.podman.options.mcast_flood = "false"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path 1) <<<"$config"
+    run_in_host_netns bridge -j -d link show master podman0
+    assert_json "$output" "[.[].mcast_flood] | sort | .[0]" == "false" "mcast_flood is applied"
+    expected_rc=1 run_in_container_netns ping -w 1 -c 1 224.0.0.1
+}
+
+@test "$fw_driver - bridge with txqueuelen" {
+    config=$(jq '.network_info.podman.options.txqueuelen = "5000"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_container_netns ip -j link show eth0
+    assert_json "$output" ".[0].txqlen" == "5000" "txqueuelen is set on the container veth"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    config=$(jq '.network_info.podman.options.txqueuelen = "0"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid txqueuelen 0, must be between 1 and 100000" "out of range value is rejected"
+}
+
+@test "$fw_driver - bridge with qdisc" {
+    config=$(jq '.network_info.podman.options.qdisc = "fq_codel"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_container_netns tc -j qdisc show dev eth0 root
+    assert_json "$output" ".[0].kind" == "fq_codel" "qdisc is set on the container veth"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    config=$(jq '.network_info.podman.options.qdisc = "htb"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'invalid qdisc "htb", must be one of fq, fq_codel, cake' "unknown qdisc is rejected"
+}
+
+@test "$fw_driver - setup reports changed host sysctls" {
+    run_in_host_netns sysctl -w net.ipv4.ip_forward=0
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json '.podman.changed_sysctls[] | select(.name == "net.ipv4.ip_forward") | .old_value' "0" "old value is reported"
+    assert_json '.podman.changed_sysctls[] | select(.name == "net.ipv4.ip_forward") | .new_value' "1" "new value is reported"
+
+    # nothing is changed for the second container
+    create_container_ns
+    config=$(jq '.container_id = "second" | .networks.podman.static_ips = ["10.88.0.3"]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path 1) <<<"$config"
+    assert_json ".podman.changed_sysctls" "null" "no changes are reported"
+}
+
+@test "$fw_driver - bridge with invalid group_fwd_mask" {
+    config=$(jq '.network_info.podman.options.group_fwd_mask = "0x4"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'invalid group_fwd_mask "0x4": STP, MAC pause and LACP frames (bits 0-2) cannot be forwarded' "restricted bits are rejected"
+}
+
+@test "$fw_driver - container to container traffic is not masqueraded" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    create_container_ns
+    config=$(jq '.container_id = "second" | .networks.podman.static_ips = ["10.88.0.3"]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path 1) <<<"$config"
+
+    # traffic within the subnet is accepted before the MASQUERADE rule
+    run_in_host_netns iptables -S NETAVARK-1D8721804F16F -t nat
+    assert "${lines[1]}" == "-A NETAVARK-1D8721804F16F -d 10.88.0.0/16 -j ACCEPT" "subnet is excluded from masquerading"
+
+    nsenter -n -t "${CONTAINER_NS_PIDS[0]}" timeout --foreground -v --kill=10 5 \
+        nc -4 -v -l -p 8080 &>"$NETAVARK_TMPDIR/nc-out" </dev/null &
+    wait_for_port "${CONTAINER_NS_PIDS[0]}" 8080 tcp
+
+    run_in_container_netns 1 nc -4 -w 1 10.88.0.2 8080 <<<"hello"
+    wait
+    run cat "$NETAVARK_TMPDIR/nc-out"
+    assert "$output" =~ "Connection from 10.88.0.3" "source ip of the other container is kept"
+}
+
+@test "$fw_driver - bridge with small mtu clamps the tcp mss" {
+    config=$(jq '.network_info.podman.options.mtu = "1400"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -t mangle -S FORWARD
+    assert "$output" =~ "-A FORWARD -s 10.88.0.0/16 -p tcp -m tcp --tcp-flags SYN,RST SYN -j TCPMSS --clamp-mss-to-pmtu" "TCPMSS rule for outgoing traffic"
+    assert "$output" =~ "-A FORWARD -d 10.88.0.0/16 -p tcp -m tcp --tcp-flags SYN,RST SYN -j TCPMSS --clamp-mss-to-pmtu" "TCPMSS rule for incoming traffic"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -t mangle -S FORWARD
+    assert "$output" !~ "TCPMSS" "TCPMSS rules are removed"
+}
+
+@test "$fw_driver - bridge with mss_clamp disabled" {
+    config=$(jq '.network_info.podman.options.mtu = "1400" | .network_info.podman.options.mss_clamp = "false"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -t mangle -S FORWARD
+    assert "$output" !~ "TCPMSS" "no TCPMSS rules"
+}
+
+@test "$fw_driver - bridge with extra rules" {
+    config=$(jq '.network_info.podman.extra_rules = [{"chain": "forward", "rule": "-s 10.88.0.0/16 -p tcp --dport 9999 -j DROP"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -S NETAVARK-EX-1D8721804F16F
+    assert "${lines[1]}" == "-A NETAVARK-EX-1D8721804F16F -s 10.88.0.0/16 -p tcp -m tcp --dport 9999 -j DROP" "extra rule is in the chain of the network"
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "${lines[-2]}" == "-A NETAVARK_FORWARD -s 10.88.0.0/16 -j NETAVARK-EX-1D8721804F16F" "traffic from the network reaches the extra rules"
+    assert "${lines[-1]}" == "-A NETAVARK_FORWARD -d 10.88.0.0/16 -j NETAVARK-EX-1D8721804F16F" "traffic to the network reaches the extra rules"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "$output" !~ "NETAVARK-EX" "extra rules are removed"
+    expected_rc=1 run_in_host_netns iptables -S NETAVARK-EX-1D8721804F16F
+}
+
+@test "$fw_driver - bridge with extra rule jumping to a builtin chain" {
+    config=$(jq '.network_info.podman.extra_rules = [{"chain": "forward", "rule": "-j INPUT"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'invalid extra rule "-j INPUT": target INPUT is not allowed, must be one of ACCEPT, DROP, REJECT, RETURN, LOG' "extra rule is rejected"
+}
+
+@test "$fw_driver - bridge with extra rule using an abbreviated jump" {
+    config=$(jq '.network_info.podman.extra_rules = [{"chain": "forward", "rule": "--jum=INPUT"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'invalid extra rule "--jum=INPUT": target INPUT is not allowed, must be one of ACCEPT, DROP, REJECT, RETURN, LOG' "extra rule is rejected"
+}
+
+@test "$fw_driver - bridge with rp_filter" {
+    config=$(jq '.network_info.podman.options.rp_filter = "1"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_container_netns cat /proc/sys/net/ipv4/conf/eth0/rp_filter
+    assert "$output" == "1" "strict rp_filter is set"
+}
+
+@test "$fw_driver - two networks use loose rp_filter" {
+    run_netavark --file ${TESTSDIR}/testfiles/two-networks.json setup $(get_container_netns_path)
+
+    run_in_container_netns cat /proc/sys/net/ipv4/conf/eth0/rp_filter
+    assert "$output" == "2" "eth0 uses loose rp_filter"
+    run_in_container_netns cat /proc/sys/net/ipv4/conf/eth1/rp_filter
+    assert "$output" == "2" "eth1 uses loose rp_filter"
+}
+
+@test "$fw_driver - bridge with invalid rp_filter" {
+    config=$(jq '.network_info.podman.options.rp_filter = "3"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid rp_filter mode 3, must be 0 (off), 1 (strict) or 2 (loose)" "invalid mode is rejected"
+}
+
+@test "$fw_driver - setup reports the firewall chains" {
+    config=$(jq '.port_mappings = ["8080:80"]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup --verbose $(get_container_netns_path) <<<"$config"
+    chains="$output"
+    assert_json "$chains" '.podman.firewall_chains.network | map(.chain) | index("NETAVARK-1D8721804F16F") != null' == "true" "nat chain of the network"
+    assert_json "$chains" '.podman.firewall_chains.network | map(.chain) | index("NETAVARK_FORWARD") != null' == "true" "shared forward chain"
+    assert_json "$chains" '.podman.firewall_chains.container | length' == "1" "port forwarding chain of the container"
+    assert_json "$chains" '.podman.firewall_chains.container[0].chain | startswith("NETAVARK-DN-1D8721804F16F")' == "true" "port forwarding chain name"
+
+    # every reported chain exists
+    while read -r table chain; do
+        run_in_host_netns iptables -t "$table" -n -L "$chain"
+    done < <(jq -r '.podman.firewall_chains | .network + .container | .[] | select(.ipv6 | not) | "\(.table) \(.chain)"' <<<"$chains")
+
+    # the state has the same chains
+    run_helper jq -c '.firewall_chains["6ce776ea58b5"].podman' $NETAVARK_TMPDIR/config/netavark-state.json
+    assert "$output" == "$(jq -c '.podman.firewall_chains' <<<"$chains")" "chains are recorded in the state"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_helper jq -c '.firewall_chains' $NETAVARK_TMPDIR/config/netavark-state.json
+    assert "$output" == "{}" "chains are forgotten on teardown"
+
+    # without --verbose the status block has no chains
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json '.podman | has("firewall_chains")' "false" "no chains without --verbose"
+}
+
+@test "$fw_driver - overlapping port mappings are rejected" {
+    config=$(jq '.port_mappings = [
+        {"host_ip": "", "container_port": 80, "host_port": 8080, "range": 3, "protocol": "tcp"},
+        {"host_ip": "127.0.0.1", "container_port": 90, "host_port": 8082, "range": 1, "protocol": "udp,tcp"}]' \
+        ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "port mappings 8080-8082:80-82/tcp and 127.0.0.1:8082:90/udp,tcp overlap on host port 8082/tcp" "overlap is rejected"
+
+    # nothing was set up
+    expected_rc=1 run_in_host_netns iptables -t nat -S NETAVARK-HOSTPORT-DNAT
+    expected_rc=1 run_in_host_netns ip link show podman0
+
+    config=$(jq '.port_mappings = ["8080-8082:80-82", "127.0.0.1:8083:90", "8082:82/udp"]' \
+        ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -t nat -S NETAVARK-HOSTPORT-DNAT
+    assert "$output" =~ "--dport 8083" "non overlapping mappings are set up"
+}
+
+@test "$fw_driver - gc removes port forwarding chains of removed containers" {
+    config=$(jq '.port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 1, "protocol": "tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    create_container_ns
+    run_netavark setup $(get_container_netns_path 1) <<<"$config"
+
+    run_in_host_netns iptables -t nat -S NETAVARK-HOSTPORT-DNAT
+    chain=$(grep -o "NETAVARK-DN-[0-9A-F]*" <<<"$output")
+    assert "$chain" != "" "container has a port forwarding chain"
+
+    # nothing is removed while the container exists
+    run_netavark gc
+    assert_json "$output" ".removed_chains | length" == "0" "no chains removed"
+
+    # the container is gone without teardown
+    kill -9 ${CONTAINER_NS_PIDS[1]}
+    run_netavark gc
+    assert_json "$output" ".removed_chains[0]" == "$chain" "stale chain removed"
+
+    run_in_host_netns iptables -t nat -S
+    assert "$output" !~ "$chain" "chain and jump rules are removed"
+}
+
+@test "$fw_driver - gc removes unreferenced port forwarding chains" {
+    run_in_host_netns iptables -t nat -N NETAVARK-DN-DEADBEEF00000
+    run_in_host_netns iptables -t nat -A NETAVARK-DN-DEADBEEF00000 -p tcp -j DNAT --to-destination 10.88.0.2:80
+
+    run_netavark gc
+    assert_json "$output" ".removed_chains[0]" == "NETAVARK-DN-DEADBEEF00000" "seeded chain removed"
+
+    expected_rc=1 run_in_host_netns iptables -t nat -S NETAVARK-DN-DEADBEEF00000
+}
+
+@test "$fw_driver - overlapping networks use distinct conntrack zones" {
+    config=$(jq '.network_info.podman.options.conntrack_zone = "true"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    # second network with the same subnet on another bridge
+    config2=$(jq '.container_id = "a5b4c3d2e1f0" |
+        .networks = {"other": (.networks.podman | .static_ips = ["10.88.0.3"])} |
+        .network_info = {"other": (.network_info.podman | .name = "other" | .network_interface = "podman1")}' <<<"$config")
+    create_container_ns
+    run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+
+    run_in_host_netns iptables -t raw -S PREROUTING
+    zone0=$(grep -oP -- "-i podman0 -j CT --zone-orig \K[0-9]+" <<<"$output")
+    zone1=$(grep -oP -- "-i podman1 -j CT --zone-orig \K[0-9]+" <<<"$output")
+    assert "$zone0" != "" "podman0 has a conntrack zone"
+    assert "$zone1" != "" "podman1 has a conntrack zone"
+    assert "$zone0" != "$zone1" "the networks use distinct zones"
+
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config2"
+    run_in_host_netns iptables -t raw -S PREROUTING
+    assert "$output" !~ "podman1" "zone rule of the removed network is gone"
+    assert "$output" =~ "-i podman0 -j CT --zone-orig $zone0" "zone rule of the other network is kept"
+}
+
+@test "$fw_driver - bridge with invalid conntrack_zone" {
+    config=$(jq '.network_info.podman.options.conntrack_zone = "0"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'invalid conntrack_zone "0", must be true, false or a zone between 1 and 65535' "zone 0 is rejected"
+}
+
+@test "$fw_driver - bridge traffic gets a conntrack template without helper" {
+    run_netavark setup $(get_container_netns_path) <${TESTSDIR}/testfiles/simplebridge.json
+    run_in_host_netns iptables -t raw -S PREROUTING
+    assert "${lines[1]}" == "-A PREROUTING -i podman0 -j CT" "template rule is first in raw PREROUTING"
+
+    run_netavark teardown $(get_container_netns_path) <${TESTSDIR}/testfiles/simplebridge.json
+    run_in_host_netns iptables -t raw -S PREROUTING
+    assert "$output" !~ "podman0" "template rule is removed on teardown"
+
+    config=$(jq '.network_info.podman.options.conntrack_helpers = "true"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -t raw -S PREROUTING
+    assert "$output" !~ "podman0" "no template rule with conntrack_helpers=true"
+}
+
+@test "$fw_driver - status block contains the network gateways" {
+    run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge.json setup $(get_container_netns_path)
+    assert_json "$output" '.podman1.gateway | join(",")' == "10.89.3.1,fd10:88:a::1" "gateway per subnet"
+
+    run_in_container_netns ip -j route show default
+    assert_json "$output" ".[0].gateway" == "10.89.3.1" "default route uses the reported gateway"
+}
+
+@test "$fw_driver - ipv6 nat option" {
+    run_netavark --file ${TESTSDIR}/testfiles/ipv6-bridge.json setup $(get_container_netns_path)
+    run_in_host_netns ip6tables -t nat -S
+    assert "$output" =~ "! -d ff00::/8 -j MASQUERADE" "ipv6 is masqueraded by default"
+    run_netavark --file ${TESTSDIR}/testfiles/ipv6-bridge.json teardown $(get_container_netns_path)
+    run_in_host_netns ip6tables -t nat -S
+    assert "$output" !~ "ff00::/8 -j MASQUERADE" "ipv6 masquerade removed on teardown"
+
+    config=$(jq '.network_info.podman1.options = {"ipv6_nat": "false"}' ${TESTSDIR}/testfiles/ipv6-bridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns ip6tables -t nat -S
+    assert "$output" !~ "ff00::/8 -j MASQUERADE" "ipv6 is routed without ipv6_nat"
+    assert "$output" =~ "-s fd10:88:a::/64 -j NETAVARK-" "network chain still exists"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+}
+
+@test "$fw_driver - random_fully option" {
+    config=$(jq '.network_info.podman.options = {"random_fully": "false"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -t nat -S NETAVARK-1D8721804F16F
+    assert "$output" =~ "-j MASQUERADE" "network is masqueraded"
+    assert "$output" !~ "--random-fully" "source ports are not randomized"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -t nat -S
+    assert "$output" !~ "NETAVARK-1D8721804F16F" "network chain removed on teardown"
+
+    config=$(jq '.network_info.podman.options = {"random_fully": "maybe"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'unable to parse "random_fully": provided string was not `true` or `false`' "invalid value is rejected"
+}
+
+@test "$fw_driver - bridge with bridge_addresses" {
+    config=$(jq '.network_info.podman.options.bridge_addresses = "192.168.100.1/24, fd99::1/64"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns ip -j addr show podman0
+    assert_json "$output" '[.[].addr_info[] | select(.scope == "global") | "\(.local)/\(.prefixlen)"] | join(",")' == "10.88.0.1/16,192.168.100.1/24,fd99::1/64" "gateway and management addresses on the bridge"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    assert "" "no errors"
+}
+
+@test "$fw_driver - bridge with bridge_addresses in the network subnet" {
+    config=$(jq '.network_info.podman.options.bridge_addresses = "10.88.0.2/16"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'invalid bridge_addresses entry "10.88.0.2/16": address is in the subnet 10.88.0.0/16 of the network' "collision is rejected"
+}
+
+@test "$fw_driver - forward jump position" {
+    run_in_host_netns iptables -N custom
+    run_in_host_netns iptables -A FORWARD -j custom
+
+    NETAVARK_FORWARD_POSITION=bottom run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_in_host_netns iptables -S FORWARD
+    assert "${lines[1]}" == "-A FORWARD -j custom" "custom jump first"
+    assert "${lines[2]}" =~ "-j NETAVARK_FORWARD" "netavark jump at the bottom"
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+
+    # the jump is kept on teardown, the next setup moves it back to the top
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_in_host_netns iptables -S FORWARD
+    assert "${lines[1]}" =~ "-j NETAVARK_FORWARD" "netavark jump moved back to the top"
+    assert "${lines[2]}" == "-A FORWARD -j custom" "custom jump after the netavark jump"
+    assert "${#lines[@]}" = 3 "the jump is not duplicated"
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+
+    run_in_host_netns iptables -A FORWARD -j ACCEPT
+    NETAVARK_FORWARD_POSITION=after:custom run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_in_host_netns iptables -S FORWARD
+    assert "${lines[1]}" == "-A FORWARD -j custom" "custom jump first"
+    assert "${lines[2]}" =~ "-j NETAVARK_FORWARD" "netavark jump after custom"
+    assert "${lines[3]}" == "-A FORWARD -j ACCEPT" "other rules stay after the netavark jump"
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+
+    NETAVARK_FORWARD_POSITION=after:missing run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json ".podman.warnings[0]" "no rule jumping to missing in the FORWARD chain, keeping the netavark jump at the top" "warning for missing chain"
+    run_in_host_netns iptables -S FORWARD
+    assert "${lines[1]}" =~ "-j NETAVARK_FORWARD" "netavark jump stays at the top"
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+
+    expected_rc=1 NETAVARK_FORWARD_POSITION=middle run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json ".error" 'invalid NETAVARK_FORWARD_POSITION value "middle", must be top, bottom or after:CHAIN' "invalid position"
+}
+
+@test "$fw_driver - bridge with ifalias" {
+    config=$(jq '.network_info.podman.options.ifalias = "rack 4 uplink"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns cat /sys/class/net/podman0/ifalias
+    assert "$output" == "rack 4 uplink" "bridge alias"
+    run_in_container_netns cat /sys/class/net/eth0/ifalias
+    assert "$output" == "rack 4 uplink" "container interface alias"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    config=$(jq ".network_info.podman.options.ifalias = \"$(printf 'a%.0s' {1..255})\"" ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid ifalias of 255 bytes, must be at most 254 bytes" "alias length is validated"
+}
+
+@test "$fw_driver - bridge with docker options" {
+    config=$(jq 'del(.network_info.podman.network_interface) | .network_info.podman.options = {"com.docker.network.bridge.name": "docker1", "com.docker.network.driver.mtu": "1400", "com.docker.network.bridge.enable_icc": "true"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns ip -j link show docker1
+    assert_json "$output" ".[0].mtu" == "1400" "bridge named and mtu set from the docker options"
+    run_in_container_netns ip -j link show eth0
+    assert_json "$output" ".[0].mtu" == "1400" "container mtu from the docker options"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    assert "" "no errors"
+}
+
+@test "$fw_driver - interfaces of a container" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    run_netavark interfaces 6ce776ea58b5
+    result="$output"
+    assert_json "$result" ".podman.container_interface" == "eth0" "container interface name"
+    run_in_container_netns ip -j link show eth0
+    assert_json "$result" ".podman.container_index" == "$(jq '.[0].ifindex' <<<"$output")" "container interface index"
+
+    host_veth=$(jq -r '.podman.host_interface' <<<"$result")
+    run_in_host_netns ip -j link show "$host_veth"
+    assert_json "$output" ".[0].master" == "podman0" "host veth is connected to the bridge"
+    assert_json "$result" ".podman.host_index" == "$(jq '.[0].ifindex' <<<"$output")" "host interface index"
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    expected_rc=1 run_netavark interfaces 6ce776ea58b5
+    assert_json ".error" "no interfaces recorded for container 6ce776ea58b5" "interfaces are forgotten on teardown"
+}
+
+@test "$fw_driver - bundle of state and rules" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    run_netavark bundle
+    assert_json "$output" ".environment.kernel" == "$(uname -r)" "kernel release"
+    assert_json "$output" ".environment.firewall_driver" == "iptables" "firewall driver"
+    assert_json "$output" '.state.veth_pairs[].podman.container_interface' == "eth0" "state is included"
+    assert_json "$output" '.rules.iptables | map(select(contains("NETAVARK-1D8721804F16F"))) | length > 0' == "true" "network rules are included"
+    assert_json "$output" '.rules.iptables | map(select(contains("NETAVARK") | not)) | length' == "0" "only netavark rules are included"
+    assert_json "$output" ".errors" == "null" "no errors"
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+}
+
+@test "$fw_driver - bridge with keep_carrier" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_in_container_netns ip link del eth0
+    run_in_host_netns cat /sys/class/net/podman0/carrier
+    assert "$output" == "0" "empty bridge has no carrier by default"
+    run_in_host_netns ip link del podman0
+
+    config=$(jq '.network_info.podman.options = {"keep_carrier": "true"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns ip -j link show master podman0 type dummy
+    assert_json "$output" '.[0].ifname | startswith("nvc-")' == "true" "carrier dummy is attached to the bridge"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns ip -j link show type dummy
+    assert "$output" == "[]" "carrier dummy is removed with the bridge"
+
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_container_netns ip link del eth0
+    run_in_host_netns cat /sys/class/net/podman0/carrier
+    assert "$output" == "1" "bridge keeps carrier without containers"
+}
+
+@test "$fw_driver - bridge with neigh_gc_thresh" {
+    run_in_host_netns cat /proc/sys/net/ipv4/neigh/default/gc_thresh1
+    thresh1=$output
+    run_in_host_netns cat /proc/sys/net/ipv4/neigh/default/gc_thresh3
+    thresh3=$output
+
+    config=$(jq ".network_info.podman.options = {\"neigh_gc_thresh1\": \"$((thresh1 + 1))\", \"neigh_gc_thresh3\": \"$((thresh3 - 1))\"}" ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns cat /proc/sys/net/ipv4/neigh/default/gc_thresh1
+    assert "$output" == "$((thresh1 + 1))" "gc_thresh1 is raised"
+    run_in_host_netns cat /proc/sys/net/ipv4/neigh/default/gc_thresh3
+    assert "$output" == "$thresh3" "gc_thresh3 is not lowered"
+
+    # a second container of the network keeps the raised value
+    create_container_ns
+    config2=$(jq '.container_id = "second" | .networks.podman.static_ips = ["10.88.0.3"]' <<<"$config")
+    run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns cat /proc/sys/net/ipv4/neigh/default/gc_thresh1
+    assert "$output" == "$((thresh1 + 1))" "gc_thresh1 is kept while the network is used"
+
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config2"
+    run_in_host_netns cat /proc/sys/net/ipv4/neigh/default/gc_thresh1
+    assert "$output" == "$thresh1" "gc_thresh1 is restored on the last teardown"
+    run_helper jq -c '.neigh_gc_thresh' "$NETAVARK_TMPDIR/config/netavark-state.json"
+    assert "$output" == "{}" "recorded thresholds are forgotten"
+
+    config=$(jq '.network_info.podman.options = {"neigh_gc_thresh2": "2048", "neigh_gc_thresh3": "1024"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid neigh_gc_thresh3 1024, must not be lower than neigh_gc_thresh2 2048" "thresholds must be ascending"
+}
+
+@test "$fw_driver - bridge with ip_conflict_check" {
+    # a second container already uses 10.88.0.2
+    create_container_ns
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path 1)
+
+    config=$(jq '.network_info.podman.options.ip_conflict_check = "error"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "ip address 10.88.0.2 is already in use on bridge podman0" "conflict is an error"
+    expected_rc=1 run_in_container_netns ip link show eth0
+
+    config=$(jq '.network_info.podman.options.ip_conflict_check = "warning"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.warnings[0]" "ip address 10.88.0.2 is already in use on bridge podman0" "conflict is a warning"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    # without a conflict there is no warning
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path 1)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json '.podman | has("warnings")' "false" "no conflict"
+}
+
+@test "$fw_driver - bridge with default_policy drop" {
+    config=$(jq '.network_info.podman.options.default_policy = "drop" |
+        .port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 1, "protocol": "tcp"}]' \
+        ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "${lines[2]}" == "-A NETAVARK_FORWARD -d 10.88.0.0/16 -j NETAVARK-DP-1D8721804F16F" "jump after the INVALID rule"
+    run_in_host_netns iptables -S NETAVARK-DP-1D8721804F16F
+    assert "${lines[-1]}" == "-A NETAVARK-DP-1D8721804F16F -j DROP" "default drop rule"
+
+    # a container on another network
+    create_container_ns
+    other=$(jq 'del(.network_info.isolate4.options)' ${TESTSDIR}/testfiles/isolate4.json)
+    run_netavark setup $(get_container_netns_path 1) <<<"$other"
+
+    # ICMP is allowed
+    run_in_container_netns 1 ping -w 1 -c 1 10.88.0.2
+
+    nsenter -n -t "${CONTAINER_NS_PIDS[0]}" timeout --foreground -v --kill=10 5 \
+        nc -4 -k -l -p 80 &>/dev/null </dev/null &
+    wait_for_port "${CONTAINER_NS_PIDS[0]}" 80 tcp
+
+    # the published port is allowed
+    run_in_container_netns 1 nc -4 -z -w 1 10.89.3.1 8080
+    # but the same port without the port forwarding is dropped
+    expected_rc=1 run_in_container_netns 1 nc -4 -z -w 1 10.88.0.2 80
+    wait
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    expected_rc=1 run_in_host_netns iptables -S NETAVARK-DP-1D8721804F16F
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "$output" !~ "NETAVARK-DP-" "jump is removed"
+}
+
+@test "$fw_driver - bridge with log_drops" {
+    config=$(jq '.network_info.podman.options.default_policy = "drop" |
+        .network_info.podman.options.log_drops = "true"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -S NETAVARK-DP-1D8721804F16F
+    assert "${lines[-2]}" == '-A NETAVARK-DP-1D8721804F16F -m limit --limit 5/min --limit-burst 10 -j LOG --log-prefix "netavark podman: "' "rate limited LOG rule"
+    assert "${lines[-1]}" == "-A NETAVARK-DP-1D8721804F16F -j DROP" "before the drop rule"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    expected_rc=1 run_in_host_netns iptables -S NETAVARK-DP-1D8721804F16F
+
+    config=$(jq '.network_info.podman.options.log_drops = "true"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "log_drops requires default_policy drop or egress_only" "nothing is dropped without them"
+}
+
+@test "$fw_driver - bridge with conn_limit" {
+    config=$(jq '.network_info.podman.options.conn_limit = "1" |
+        .network_info.podman.options.conn_rate_limit = "100"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "${lines[2]}" =~ "-A NETAVARK_FORWARD -s 10.88.0.0/16 -m conntrack --ctstate NEW -m hashlimit --hashlimit-above 100/sec --hashlimit-burst 100 --hashlimit-mode srcip --hashlimit-name nv1D8721804F16F -j DROP" "rate limit rule"
+    assert "${lines[3]}" =~ "-A NETAVARK_FORWARD -s 10.88.0.0/16 -m conntrack --ctstate NEW -m connlimit --connlimit-above 1 --connlimit-mask 32 .*-j REJECT" "connlimit rule"
+
+    # a server on another network
+    create_container_ns
+    other=$(jq 'del(.network_info.isolate4.options)' ${TESTSDIR}/testfiles/isolate4.json)
+    run_netavark setup $(get_container_netns_path 1) <<<"$other"
+    nsenter -n -t "${CONTAINER_NS_PIDS[1]}" timeout --foreground -v --kill=10 5 \
+        nc -4 -k -l -p 80 &>/dev/null </dev/null &
+    wait_for_port "${CONTAINER_NS_PIDS[1]}" 80 tcp
+
+    # the first connection is kept open, the second one is above the limit
+    nsenter -n -t "${CONTAINER_NS_PIDS[0]}" timeout --foreground --kill=10 4 \
+        nc -4 10.89.3.2 80 &>/dev/null < <(sleep 4) &
+    sleep 1
+    expected_rc=1 run_in_container_netns 0 nc -4 -z -w 1 10.89.3.2 80
+    wait
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -S NETAVARK_FORWARD
+    assert "$output" !~ "connlimit|hashlimit" "limit rules are removed"
+
+    config=$(jq '.network_info.podman.options.conn_rate_limit = "100000"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid conn_rate_limit 100000, must be between 1 and 10000 connections per second" "rate above the limit"
+}
+
+@test "$fw_driver - bridge with invalid default_policy" {
+    config=$(jq '.network_info.podman.options.default_policy = "reject"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'invalid default_policy "reject", must be accept or drop' "invalid policy is rejected"
+}
+
+@test "$fw_driver - bridge with egress_only" {
+    config=$(jq '.network_info.podman.options.egress_only = "true"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -S NETAVARK-DP-1D8721804F16F
+    assert "${lines[1]}" == "-A NETAVARK-DP-1D8721804F16F -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT" "replies are accepted"
+    assert "${lines[-1]}" == "-A NETAVARK-DP-1D8721804F16F -j DROP" "default drop rule"
+
+    # a container on another network
+    create_container_ns
+    other=$(jq 'del(.network_info.isolate4.options)' ${TESTSDIR}/testfiles/isolate4.json)
+    run_netavark setup $(get_container_netns_path 1) <<<"$other"
+
+    # new connections into the network are dropped, ICMP as well
+    expected_rc=1 run_in_container_netns 1 ping -w 1 -c 1 10.88.0.2
+    nsenter -n -t "${CONTAINER_NS_PIDS[0]}" timeout --foreground -v --kill=10 5 \
+        nc -4 -k -l -p 80 &>/dev/null </dev/null &
+    wait_for_port "${CONTAINER_NS_PIDS[0]}" 80 tcp
+    expected_rc=1 run_in_container_netns 1 nc -4 -z -w 1 10.88.0.2 80
+    wait
+
+    # connections from the container work
+    nsenter -n -t "${CONTAINER_NS_PIDS[1]}" timeout --foreground -v --kill=10 5 \
+        nc -4 -k -l -p 80 &>/dev/null </dev/null &
+    wait_for_port "${CONTAINER_NS_PIDS[1]}" 80 tcp
+    run_in_container_netns 0 nc -4 -z -w 1 10.89.3.2 80
+    run_in_container_netns 0 ping -w 1 -c 1 10.89.3.2
+    wait
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    expected_rc=1 run_in_host_netns iptables -S NETAVARK-DP-1D8721804F16F
+
+    # published ports would accept connections from outside
+    config=$(jq '.network_info.podman.options.egress_only = "true" |
+        .port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 1, "protocol": "tcp"}]' \
+        ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "port mappings cannot be used with the egress_only option of network podman" "port mappings are rejected"
+}
+
+@test "$fw_driver - bridge with block_host_access" {
+    # a service on another address of the host
+    add_dummy_interface_on_host dummy0 "198.51.100.1/32"
+    config=$(jq '.network_info.podman.options.block_host_access = "true"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -S INPUT
+    assert "${lines[1]}" == "-A INPUT -s 10.88.0.0/16 ! -d 10.88.0.1/32 -m addrtype --dst-type LOCAL -m conntrack ! --ctstate RELATED,ESTABLISHED -j DROP" "host access drop rule"
+
+    nsenter -n -t $HOST_NS_PID timeout --foreground -v --kill=10 5 \
+        nc -4 -k -l -p 8080 &>/dev/null </dev/null &
+    wait_for_port $HOST_NS_PID 8080 tcp
+    expected_rc=1 run_in_container_netns nc -4 -z -w 1 198.51.100.1 8080
+    # the gateway stays reachable
+    run_in_container_netns nc -4 -z -w 1 10.88.0.1 8080
+    wait
+
+    # a container on another network stands in for the internet
+    create_container_ns
+    other=$(jq 'del(.network_info.isolate4.options)' ${TESTSDIR}/testfiles/isolate4.json)
+    run_netavark setup $(get_container_netns_path 1) <<<"$other"
+    nsenter -n -t "${CONTAINER_NS_PIDS[1]}" timeout --foreground -v --kill=10 5 \
+        nc -4 -k -l -p 80 &>/dev/null </dev/null &
+    wait_for_port "${CONTAINER_NS_PIDS[1]}" 80 tcp
+    run_in_container_netns 0 nc -4 -z -w 1 10.89.3.2 80
+    wait
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -S INPUT
+    assert "$output" !~ "10.88.0.0/16" "drop rule is removed"
+
+    config=$(jq '.network_info.podman.options.block_host_access = "yes"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'unable to parse "block_host_access": provided string was not `true` or `false`' "invalid value is rejected"
+}
+
+@test "$fw_driver - rules of an older version are migrated" {
+    # rules as created by a netavark version without the version marker
+    run_in_host_netns iptables -t nat -N NETAVARK-1D8721804F16F
+    run_in_host_netns iptables -t nat -A NETAVARK-1D8721804F16F -d 10.88.0.0/16 -j ACCEPT
+    run_in_host_netns iptables -t nat -A POSTROUTING -s 10.88.0.0/16 -j NETAVARK-1D8721804F16F
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_in_host_netns iptables -S POSTROUTING -t nat
+    assert "$output" =~ "-A POSTROUTING -s 10.88.0.0/16 -m comment --comment netavark:v1 -j NETAVARK-1D8721804F16F" "rule with the current marker"
+    assert "$output" !~ "-s 10.88.0.0/16 -j NETAVARK-1D8721804F16F" "old rule is removed"
+
+    # teardown removes old rules as well so the network chain can be deleted
+    run_in_host_netns iptables -t nat -A POSTROUTING -s 10.88.0.0/16 -j NETAVARK-1D8721804F16F
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+    expected_rc=1 run_in_host_netns iptables -t nat -S NETAVARK-1D8721804F16F
+}
+
+@test "$fw_driver - bridge with peer_address" {
+    config=$(jq '.network_info.podman.subnets = [{"subnet": "10.88.0.2/31", "gateway": "10.88.0.3"}] |
+        .networks.podman.static_ips = ["10.88.0.2"] |
+        .network_info.podman.options.peer_address = "10.88.0.3"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_container_netns ip -j addr show eth0
+    assert_json "$output" '.[].addr_info[] | select(.family == "inet") | .local' == "10.88.0.2" "container address"
+    assert_json "$output" '.[].addr_info[] | select(.family == "inet") | .address' == "10.88.0.3" "peer address"
+    assert_json "$output" '.[].addr_info[] | select(.family == "inet") | has("broadcast")' == "false" "no broadcast address"
+
+    run_in_container_netns ping -w 1 -c 1 10.88.0.3
+}
+
+@test "$fw_driver - bridge with invalid peer_address" {
+    config=$(jq '.network_info.podman.options.peer_address = "10.88.0.1"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid peer_address 10.88.0.1: container address 10.88.0.2/16 must be a /32 or in the same /31 as the peer" "subnet too large"
+
+    config=$(jq '.network_info.podman.options.peer_address = "fd10::1"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid peer_address fd10::1: no container address of the same ip family" "other ip family"
+}
+
+@test "$fw_driver - bridge disables icmp redirects" {
+    run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge.json setup $(get_container_netns_path)
+
+    run_in_host_netns cat /proc/sys/net/ipv4/conf/podman1/accept_redirects \
+        /proc/sys/net/ipv4/conf/podman1/send_redirects /proc/sys/net/ipv6/conf/podman1/accept_redirects
+    assert "${lines[*]}" == "0 0 0" "redirects are disabled on the bridge"
+
+    run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge.json teardown $(get_container_netns_path)
+
+    config=$(jq '.network_info.podman.options.icmp_redirects = "true"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns cat /proc/sys/net/ipv4/conf/podman0/send_redirects
+    assert "$output" == "1" "kernel default is kept with icmp_redirects"
+}
+
+@test "$fw_driver - teardown removes the recorded rules" {
+    config=$(jq '.port_mappings = [{"host_ip": "", "container_port": 80, "host_port": 8080, "range": 1, "protocol": "tcp"}]' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    # every rule setup recorded exists
+    state="$NETAVARK_TMPDIR/config/netavark-state.json"
+    run_helper jq -r '.firewall_rules["6ce776ea58b5"].podman[] | select(.ipv6 | not) | "-t \(.table) -C \(.chain) \(.rule)"' "$state"
+    assert "${#lines[@]}" != "0" "rules are recorded"
+    rules=("${lines[@]}")
+    for rule in "${rules[@]}"; do
+        run_in_host_netns sh -c "iptables $rule"
+    done
+
+    # a recorded rule netavark would not create from the config
+    run_in_host_netns iptables -t nat -A POSTROUTING -s 10.88.0.0/16 -p tcp --dport 8081 -j ACCEPT
+    jq '.firewall_rules["6ce776ea58b5"].podman += [{"ipv6": false, "table": "nat", "chain": "POSTROUTING",
+        "rule": "-s 10.88.0.0/16 -p tcp --dport 8081 -j ACCEPT", "teardown": "on_complete"}]' "$state" >"$state.new"
+    mv "$state.new" "$state"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -t nat -S POSTROUTING
+    assert "$output" !~ "--dport 8081" "recorded rule is removed"
+    run_helper jq -c '.firewall_rules' "$state"
+    assert "$output" == "{}" "rules are forgotten"
+}
+
+@test "$fw_driver - bridge with mode l2" {
+    config=$(jq '.network_info.podman.options.mode = "l2"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.interfaces.eth0.subnets[0].ipnet" "10.88.0.2/16" "container address"
+    assert_json ".podman.interfaces.eth0.subnets[0].gateway" "null" "no gateway"
+
+    run_in_host_netns ip -j addr show podman0
+    assert_json "$output" ".[0].addr_info | map(select(.family == \"inet\")) | length" == "0" "bridge has no ipv4 address"
+    run_in_container_netns ip -j route show default
+    assert "$output" == "[]" "no default route"
+    run_in_host_netns iptables -t nat -S POSTROUTING
+    assert "$output" !~ "10.88.0.0/16" "no masquerade rules"
+
+    config2=$(jq '.container_id = "b2a2c2a4f6b8" | .networks.podman.static_ips = ["10.88.0.3"]' <<<"$config")
+    create_container_ns
+    run_netavark setup $(get_container_netns_path 1) <<<"$config2"
+    run_in_container_netns ping -w 1 -c 1 10.88.0.3
+    run_in_container_netns 1 ping -w 1 -c 1 10.88.0.2
+
+    run_netavark teardown $(get_container_netns_path 1) <<<"$config2"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+}
+
+@test "$fw_driver - bridge with invalid mode" {
+    config=$(jq '.network_info.podman.options.mode = "l3"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid bridge mode \"l3\", must be managed or l2" "unknown mode"
+
+    config=$(jq '.network_info.podman.options.mode = "l2" | .network_info.podman.dns_enabled = true' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "dns is not supported with bridge mode l2, the bridge has no address" "dns"
+}
+
+@test "$fw_driver - bridge with mac_oui" {
+    config=$(jq '.network_info.podman.options.mac_oui = "02:42:ac"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    mac=$(jq -r '.podman.interfaces.eth0.mac_address' <<<"$output")
+    assert "$mac" =~ "^02:42:ac:" "mac is in the oui"
+
+    run_in_container_netns cat /sys/class/net/eth0/address
+    assert "$output" == "$mac" "reported mac is set on the interface"
+
+    # the same container gets the same mac again
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.interfaces.eth0.mac_address" "$mac" "mac is stable"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    # static macs must be within the oui
+    config=$(jq '.network_info.podman.options.mac_oui = "02:42:ac" |
+        .networks.podman.static_mac = "02:42:ac:11:22:33"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".podman.interfaces.eth0.mac_address" "02:42:ac:11:22:33" "static mac in the oui is used"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
 
-   run_in_host_netns iptables -t nat -nvL
-   assert "$output" == "$before" "make sure tables have not changed"
+    config=$(jq '.network_info.podman.options.mac_oui = "02:42:ac" |
+        .networks.podman.static_mac = "02:00:00:11:22:33"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "static mac 02:00:00:11:22:33 is not within the mac_oui 02:42:ac" "static mac outside the oui"
 
-   run_in_container_netns ip route show
-   assert "$output" "!~" "default" "No default route for internal networks"
+    config=$(jq '.network_info.podman.options.mac_oui = "01:42:ac"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'invalid mac_oui "01:42:ac", must be three hex bytes of a unicast address like 02:42:ac' "multicast oui"
+}
 
-   run_in_container_netns ping -c 1 10.88.0.1
+@test "$fw_driver - bridge options of an existing bridge" {
+    run_in_host_netns ip link add podman0 type bridge
 
-   run_netavark --file ${TESTSDIR}/testfiles/internal.json teardown $(get_container_netns_path)
+    config=$(jq '.network_info.podman.options.vlan_filtering = "true"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "bridge podman0 already exists without the vlan_filtering option" "vlan filtering"
+
+    config=$(jq '.network_info.podman.options.group_fwd_mask = "0x8"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "bridge podman0 already exists with group_fwd_mask 0x0 instead of 0x8" "group_fwd_mask"
+
+    config=$(jq '.network_info.podman.options.bridge_addresses = "192.168.100.1/24"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "bridge podman0 already exists without the bridge_addresses 192.168.100.1/24" "bridge addresses"
+
+    # a new bridge must not take an address of a subnet used on the host
+    run_in_host_netns ip link del podman0
+    add_dummy_interface_on_host dummy0 "192.168.100.2/24"
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "bridge_addresses 192.168.100.1/24 is in the subnet 192.168.100.0/24 which is already used on the host" "subnet in use"
 }
 
-@test "$fw_driver - simple bridge" {
+@test "$fw_driver - bridge with adopt_interface" {
+    add_dummy_interface_on_host dummy0
+    config=$(jq '.networks.podman.adopt_interface = "dummy0"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup --verify $(get_container_netns_path) <<<"$config"
+    # the interface is not on the bridge so no gateway is reported
+    assert_json "$output" ".podman.interfaces.eth0.subnets[0].gateway" == "null" "no gateway in the response"
+    assert_json "$output" ".podman.gateway_reachable" == "true" "no unreachable gateway is verified"
+
+    run_in_container_netns ip -j -d link show eth0
+    assert_json "$output" '.[0].linkinfo.info_kind' == "dummy" "adopted interface is renamed"
+    run_in_container_netns ip -j addr show eth0
+    assert_json "$output" '.[].addr_info[] | select(.family == "inet") | .local' == "10.88.0.2" "container address"
+    expected_rc=1 run_in_host_netns ip link show dummy0
+    run_in_container_netns ip route show default
+    assert "$output" == "" "no default route via the gateway"
+    expected_rc=1 run_in_container_netns ping -W 1 -c 1 10.88.0.1
+    expected_rc=1 run_netavark interfaces 6ce776ea58b5
+    assert_json ".error" "no interfaces recorded for container 6ce776ea58b5" "no veth pair is recorded"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns ip -j -d link show dummy0
+    assert_json "$output" '.[0].linkinfo.info_kind' == "dummy" "interface is back on the host"
+    expected_rc=1 run_in_container_netns ip link show eth0
+
+    # the host veth of another container is in use
     run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
-    result="$output"
-    assert_json "$result" 'has("podman")' == "true" "object key exists"
+    run_in_host_netns ip -j link show master podman0
+    veth=$(jq -r '.[0].ifname' <<<"$output")
+    create_container_ns
+    config=$(jq --arg veth "$veth" '.networks.podman.adopt_interface = $veth |
+        .networks.podman.static_ips = ["10.88.0.3"]' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path 1) <<<"$config"
+    assert_json ".error" "interface $veth can not be adopted, it is attached to another interface" "port of the bridge"
+
+    config=$(jq '.networks.podman.adopt_interface = "podman0"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path 1) <<<"$config"
+    assert_json ".error" "interface podman0 can not be adopted, it is a bridge" "bridge"
+
+    config=$(jq '.networks.podman.adopt_interface = "missing0"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path 1) <<<"$config"
+    assert_json ".error" "interface missing0 to adopt does not exist" "missing interface"
+
+    config=$(jq '.networks.podman.adopt_interface = "dummy0" |
+        .networks.podman.static_mac = "02:42:ac:11:22:33"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path 1) <<<"$config"
+    assert_json ".error" "static_mac, pvid and tagged_vlans can not be used with adopt_interface" "no bridge port options"
+
+    config=$(jq '.networks.podman.adopt_interface = "dummy0" |
+        .network_info.podman.dns_enabled = true' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path 1) <<<"$config"
+    assert_json ".error" "dns and policy_routing can not be used with adopt_interface, the gateway on the bridge is not reachable" "dns needs the gateway"
+}
 
-    mac=$(jq -r '.podman.interfaces.eth0.mac_address' <<<"$result")
-    # check that interface exists
-    run_in_container_netns ip -j --details link show eth0
-    link_info="$output"
-    assert_json "$link_info" ".[].address" == "$mac" "MAC matches container mac"
-    assert_json "$link_info" '.[].flags[] | select(.=="UP")' == "UP" "Container interface is up"
-    assert_json "$link_info" ".[].linkinfo.info_kind" == "veth" "Container interface is a veth device"
+@test "$fw_driver - bridge with blackhole_routes" {
+    config=$(jq '.network_info.podman.options.blackhole_routes = "192.0.2.0/24,2001:db8::/32"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
 
-    ipaddr="10.88.0.2/16"
-    run_in_container_netns ip addr show eth0
-    assert "$output" =~ "$ipaddr" "IP address matches container address"
-    assert_json "$result" ".podman.interfaces.eth0.subnets[0].ipnet" == "$ipaddr" "Result contains correct IP address"
+    run_in_container_netns ip -j route show type blackhole
+    assert_json "$output" ".[0].dst" == "192.0.2.0/24" "ipv4 blackhole route"
+    run_in_container_netns ip -j -6 route show type blackhole
+    assert_json "$output" ".[0].dst" == "2001:db8::/32" "ipv6 blackhole route"
 
-    run_in_host_netns ip -j --details link show podman0
-    link_info="$output"
-    assert_json "$link_info" '.[].flags[] | select(.=="UP")' == "UP" "Host bridge interface is up"
-    assert_json "$link_info" ".[].linkinfo.info_kind" == "bridge" "The bridge interface is actually a bridge"
+    # the traffic is dropped locally, it does not reach the host
+    expected_rc=? run_in_container_netns ping -w 1 -c 1 192.0.2.1
+    assert "$status" != "0" "ping to the blackholed subnet fails"
+    run_in_container_netns ip route get 192.0.2.1
+    assert "$output" =~ "blackhole" "route lookup hits the blackhole"
 
-    ipaddr="10.88.0.1"
-    run_in_host_netns ip addr show podman0
-    assert "$output" =~ "$ipaddr" "IP address matches bridge gateway address"
-    assert_json "$result" ".podman.interfaces.eth0.subnets[0].gateway" == "$ipaddr" "Result contains gateway address"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_container_netns ip -j route show type blackhole
+    assert "$output" == "[]" "blackhole routes are removed"
+}
 
-    # check that the loopback adapter is up
-    run_in_container_netns ip addr show lo
-    assert "$output" =~ "127.0.0.1" "Loopback adapter is up (has address)"
+@test "$fw_driver - bridge with sysctls" {
+    run_in_host_netns cat /proc/sys/net/ipv4/tcp_keepalive_time
+    host_value="$output"
+    config=$(jq '.network_info.podman.options.sysctls = "net.ipv4.tcp_keepalive_time=60,net.ipv4.tcp_keepalive_intvl=5"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
 
-    run_in_host_netns ping -c 1 10.88.0.2
+    run_in_container_netns cat /proc/sys/net/ipv4/tcp_keepalive_time
+    assert "$output" == "60" "keepalive time set in the container"
+    run_in_container_netns cat /proc/sys/net/ipv4/tcp_keepalive_intvl
+    assert "$output" == "5" "keepalive interval set in the container"
+    run_in_host_netns cat /proc/sys/net/ipv4/tcp_keepalive_time
+    assert "$output" == "$host_value" "host value is unchanged"
 
-    # check iptables POSTROUTING chain
-    run_in_host_netns iptables -S POSTROUTING -t nat
-    assert "${lines[1]}" =~ "-A POSTROUTING -j NETAVARK-HOSTPORT-MASQ" "POSTROUTING HOSTPORT-MASQ rule"
-    assert "${lines[2]}" =~ "-A POSTROUTING -s 10.88.0.0/16 -j NETAVARK-1D8721804F16F" "POSTROUTING container rule"
-    assert "${#lines[@]}" = 3 "too many POSTROUTING rules"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
 
-    # check iptables NETAVARK-1D8721804F16F chain
-    run_in_host_netns iptables -S NETAVARK-1D8721804F16F -t nat
-    assert "${lines[1]}" =~ "-A NETAVARK-1D8721804F16F -d 10.88.0.0/16 -j ACCEPT" "NETAVARK-1D8721804F16F ACCEPT rule"
-    assert "${lines[2]}" == "-A NETAVARK-1D8721804F16F ! -d 224.0.0.0/4 -j MASQUERADE" "NETAVARK-1D8721804F16F MASQUERADE rule"
-    assert "${#lines[@]}" = 3 "too many NETAVARK-1D8721804F16F rules"
+    config=$(jq '.network_info.podman.options.sysctls = "net.ipv4.ip_forward=0"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert "$output" =~ "net.ipv4.ip_forward is not allowed" "sysctl outside the allowlist is rejected"
+}
 
-    # check FORWARD rules
-    run_in_host_netns iptables -S FORWARD
-    assert "${lines[1]}" == "-A FORWARD -m comment --comment \"netavark firewall plugin rules\" -j NETAVARK_FORWARD" "FORWARD rule"
-    assert "${#lines[@]}" = 2 "too many FORWARD rules"
+@test "$fw_driver - bridge with per network sysctls" {
+    config=$(jq '.network_info.podman.options.sysctls = "net.ipv4.tcp_keepalive_time=60" |
+        .networks.podman.sysctls = {"net.ipv4.tcp_keepalive_time": "30", "net.ipv4.ip_local_port_range": "20000 30000"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
 
-    run_in_host_netns iptables -S NETAVARK_FORWARD
-    assert "${lines[1]}" == "-A NETAVARK_FORWARD -m conntrack --ctstate INVALID -j DROP" "NETAVARK_FORWARD rule 1"
-    assert "${lines[2]}" == "-A NETAVARK_FORWARD -d 10.88.0.0/16 -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT" "NETAVARK_FORWARD rule 2"
-    assert "${lines[3]}" == "-A NETAVARK_FORWARD -s 10.88.0.0/16 -j ACCEPT" "NETAVARK_FORWARD rule 3"
-    assert "${#lines[@]}" = 4 "too many NETAVARK_FORWARD rules"
+    run_in_container_netns cat /proc/sys/net/ipv4/tcp_keepalive_time
+    assert "$output" == "30" "per network sysctl takes precedence"
+    run_in_container_netns cat /proc/sys/net/ipv4/ip_local_port_range
+    assert "$output" =~ "20000.*30000" "port range set in the container"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    config=$(jq '.networks.podman.sysctls = {"kernel.shmmax": "1"}' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'invalid sysctls entry "kernel.shmmax=1": kernel.shmmax is not allowed, only net.* sysctls can be set' "non net sysctl is rejected"
+}
+
+@test "$fw_driver - bridge with invalid blackhole_routes" {
+    config=$(jq '.network_info.podman.options.blackhole_routes = "192.0.2.1/24"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid blackhole_routes entry \"192.0.2.1/24\": host bits are set, use 192.0.2.0/24" "host bits"
+}
+
+@test "$fw_driver - log lines are tagged with the network" {
+    container_id=a417588994662895d8b41adf8d74a83ac0cc38eb56d85d8e1268aae1e19e07e1
+    RUST_LOG=netavark=debug run_netavark --file ${TESTSDIR}/testfiles/two-networks.json setup $(get_container_netns_path)
+    assert "$output" =~ "\[DEBUG netavark::network::bridge network=t1 container=$container_id\] Setup network t1"
+    assert "$output" =~ "\[DEBUG netavark::network::bridge network=t2 container=$container_id\] Setup network t2"
+    assert "$output" !~ "network=t2 container=$container_id\] Setup network t1"
+
+    RUST_LOG=netavark=debug run_netavark --file ${TESTSDIR}/testfiles/two-networks.json teardown $(get_container_netns_path)
+    assert "$output" =~ "\] Tearing down container"
+    assert "$output" !~ "network=.*\] Tearing down container"
+}
+
+@test "$fw_driver - two phase setup matches single setup" {
+    run_netavark --file ${TESTSDIR}/testfiles/two-networks.json setup $(get_container_netns_path)
+    single_status="$output"
+    run_in_host_netns sh -c "iptables -S; iptables -t nat -S"
+    single_rules="$output"
+    run_netavark bundle
+    single_state=$(jq -c '.state.firewall_rules' <<<"$output")
+    run_netavark --file ${TESTSDIR}/testfiles/two-networks.json teardown $(get_container_netns_path)
+
+    run_netavark --file ${TESTSDIR}/testfiles/two-networks.json setup --no-firewall $(get_container_netns_path)
+    assert "$output" == "$single_status" "same status block without firewall"
+    run_in_host_netns iptables -t nat -S
+    assert "$output" !~ "NETAVARK-HOSTPORT-DNAT" "no port forwarding rules yet"
+    run_in_container_netns ip -j addr show eth0
+    assert_json "$output" ".[0].addr_info[0].local" == "10.89.1.2" "interface is set up"
+
+    run_netavark --file ${TESTSDIR}/testfiles/two-networks.json setup-firewall $(get_container_netns_path)
+    run_in_host_netns sh -c "iptables -S; iptables -t nat -S"
+    assert "$output" == "$single_rules" "same rules as a single setup"
+    run_netavark bundle
+    assert "$(jq -c '.state.firewall_rules' <<<"$output")" == "$single_state" "same recorded rules as a single setup"
+
+    run_netavark --file ${TESTSDIR}/testfiles/two-networks.json teardown $(get_container_netns_path)
+    run_in_host_netns iptables -t nat -S
+    assert "$output" !~ "NETAVARK-HOSTPORT-DNAT" "rules removed on teardown"
+}
+
+@test "$fw_driver - simple bridge with verify" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --verify $(get_container_netns_path)
+    assert_json "$output" ".podman.gateway_reachable" == "true" "gateway is reachable"
 
     run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
 
-    # now check that iptables rules are gone
-    run_in_host_netns iptables -S
+    # without --verify the field must not be set
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json "$output" '.podman | has("gateway_reachable")' == "false" "no verify result"
+    assert_json "$output" '.podman | has("warnings")' == "false" "no warnings"
+}
 
-    # check FORWARD rules
-    run_in_host_netns iptables -S FORWARD
-    assert "${lines[1]}" == "-A FORWARD -m comment --comment \"netavark firewall plugin rules\" -j NETAVARK_FORWARD" "FORWARD rule"
-    assert "${#lines[@]}" = 2 "too many FORWARD rules after teardown"
+@test "$fw_driver - simple bridge with stats" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup --stats $(get_container_netns_path)
+    for field in rx_bytes tx_bytes rx_packets tx_packets rx_errors tx_errors rx_dropped tx_dropped; do
+        assert_json "$output" ".podman.interfaces.eth0.stats.$field | type" == "number" "$field is a number"
+    done
+    assert_json "$output" ".podman.interfaces.eth0.stats.rx_errors" == "0" "no errors after setup"
 
-    # rule 1 should be DROP for any existing networks
-    run_in_host_netns iptables -S NETAVARK_FORWARD
-    assert "${lines[1]}" == "-A NETAVARK_FORWARD -m conntrack --ctstate INVALID -j DROP" "NETAVARK_FORWARD rule 1"
-    assert "${#lines[@]}" = 2 "too many NETAVARK_FORWARD rules after teardown"
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
 
-    # check POSTROUTING nat rules
-    run_in_host_netns iptables -S POSTROUTING -t nat
-    assert "${lines[1]}" =~ "-A POSTROUTING -j NETAVARK-HOSTPORT-MASQ" "POSTROUTING HOSTPORT-MASQ rule"
-    assert "${#lines[@]}" = 2 "too many POSTROUTING rules after teardown"
+    # without --stats the field must not be set
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json "$output" '.podman.interfaces.eth0 | has("stats")' == "false" "no stats"
+}
+
+@test "$fw_driver - loopback is up after setup" {
+    # lo may already be up, setup must not fail in that case
+    run_in_container_netns ip link set lo up
+
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    run_in_container_netns ip -j link show lo
+    assert_json "$output" '.[].flags[] | select(.=="UP")' == "UP" "Loopback interface is up"
+}
+
+@test "$fw_driver - bridge with dns search domains" {
+    config=$(jq '.network_info.podman.options.dns_search_domains = "example.com,corp.example.org,example.com"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json "$output" '.podman.dns_search_domains | join(",")' == "example.com,corp.example.org" "search domains in response"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    config=$(jq '.network_info.podman.options.dns_search_domains = "example..com"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'invalid dns name "example..com": empty label' "error message"
+}
+
+@test "$fw_driver - bridge without subnet route" {
+    config=$(jq '.network_info.podman.options.no_subnet_route = "true"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_container_netns ip -j route show
+    routes="$output"
+    assert_json "$routes" '[.[] | select(.dst == "10.88.0.0/16")] | length' == "0" "no connected subnet route"
+    assert_json "$routes" '.[] | select(.dst == "10.88.0.1") | .scope' == "link" "link scoped host route to the gateway"
+    assert_json "$routes" '.[] | select(.dst == "default") | .gateway' == "10.88.0.1" "default route via gateway"
+
+    # the gateway is still reachable
+    run_in_container_netns ping -c 1 10.88.0.1
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    config=$(jq '.network_info.podman.options.no_subnet_route = "true" | .network_info.podman.options.no_default_route = "true"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "no_subnet_route requires a default route, it cannot be used with internal networks or no_default_route" "error message"
+}
+
+@test "$fw_driver - setup metrics" {
+    metrics=$NETAVARK_TMPDIR/metrics.prom
+    run_netavark --metrics-file $metrics --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    run cat $metrics
+    assert "$output" =~ 'netavark_operations_total\{operation="setup"\} 1' "setup counter"
+    assert "$output" =~ 'netavark_operation_duration_seconds_bucket\{operation="setup",le="\+Inf"\} 1' "setup duration +Inf bucket"
+    assert "$output" =~ 'netavark_operation_duration_seconds_count\{operation="setup"\} 1' "setup duration count"
+
+    # a failed setup, the bridge is already set up, must update the same file
+    NETAVARK_METRICS_FILE=$metrics expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    run cat $metrics
+    assert "$output" =~ 'netavark_operations_total\{operation="setup"\} 2' "setup counter incremented"
+    assert "$output" =~ 'netavark_operation_errors_total\{operation="setup",category="[a-z_]+"\} 1' "setup error counter"
+}
+
+@test "$fw_driver - setup warnings" {
+    run_netavark --aardvark-binary /nonexistent/aardvark-dns --file ${TESTSDIR}/testfiles/dualstack-bridge-custom-dns-server.json \
+        setup $(get_container_netns_path)
+    assert_json "$output" ".podman1.warnings[0]" == 'dns disabled because aardvark-dns path "/nonexistent/aardvark-dns" does not exists' "aardvark warning"
+    assert_json "$output" '.podman1.warnings | length' == "1" "only one warning"
+}
+
+@test "$fw_driver - bridge with snat ip" {
+    add_dummy_interface_on_host dummy0 "172.16.0.1/24"
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-snat.json setup $(get_container_netns_path)
+
+    run_in_host_netns iptables -S NETAVARK-1D8721804F16F -t nat
+    assert "${lines[1]}" =~ "-A NETAVARK-1D8721804F16F -d 10.88.0.0/16 -j ACCEPT" "NETAVARK-1D8721804F16F ACCEPT rule"
+    assert "${lines[2]}" == "-A NETAVARK-1D8721804F16F ! -d 224.0.0.0/4 -j SNAT --to-source 172.16.0.1 --random-fully" "NETAVARK-1D8721804F16F SNAT rule"
+    assert "${#lines[@]}" = 3 "too many NETAVARK-1D8721804F16F rules"
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-snat.json teardown $(get_container_netns_path)
 
-    # NETAVARK-1D8721804F16F chain should not exists
     expected_rc=1 run_in_host_netns iptables -nvL NETAVARK-1D8721804F16F -t nat
+}
 
-    # bridge should be removed on teardown
-    expected_rc=1 run_in_host_netns ip addr show podman0
+@test "$fw_driver - bridge with snat ip not on host" {
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/bridge-snat.json setup $(get_container_netns_path)
+    assert_json ".error" "snat_ip 172.16.0.1 is not assigned to any host interface" "error message"
 }
 
 @test "$fw_driver - bridge with static routes" {
@@ -135,6 +1511,35 @@ fw_driver=iptables
     assert "$output" "!~" "10.92.0.0/24 via 10.91.0.1" "static route not removed"
 }
 
+@test "$fw_driver - bridge with route_table" {
+    run_in_container_netns ip link add type dummy
+    run_in_container_netns ip a add 10.91.0.10/24 dev dummy0
+    run_in_container_netns ip link set dummy0 up
+
+    config=$(jq '.network_info.podman.options.route_table = "true"' ${TESTSDIR}/testfiles/bridge-staticroutes.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    # the static routes live in the container table, not in the main table
+    run_in_container_netns ip r show table 2000
+    assert "$output" "=~" "10.89.0.0/24 via 10.88.0.2" "static route in the container table"
+    assert "$output" "=~" "10.92.0.0/24 via 10.91.0.1" "static route via another interface"
+    run_in_container_netns ip r show table main
+    assert "$output" "!~" "10.89.0.0/24" "static route not in the main table"
+    assert "$output" "=~" "default via 10.88.0.1" "default route stays in the main table"
+    run_in_container_netns ip rule
+    assert "$output" "=~" "200:	from all lookup 2000" "rule references the table"
+
+    state="$NETAVARK_TMPDIR/config/netavark-state.json"
+    run_helper jq -r '.route_tables["6ce776ea58b5"].podman' "$state"
+    assert "$output" == "2000" "table id is recorded"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_container_netns ip rule
+    assert "$output" "!~" "lookup 2000" "rule is removed"
+    run_helper jq -c '.route_tables' "$state"
+    assert "$output" == "{}" "table id is forgotten"
+}
+
 @test "$fw_driver - bridge with no default route" {
     run_netavark --file ${TESTSDIR}/testfiles/bridge-nodefaultroute.json setup $(get_container_netns_path)
 
@@ -315,6 +1720,119 @@ fw_driver=iptables
     assert "${lines[1]}" =~ ".*aardvark-dns --config $NETAVARK_TMPDIR/config/aardvark-dns -p $dns_port run" "aardvark not running or bad options"
 }
 
+@test "$fw_driver - dns is allowed to the gateway" {
+    dns_port=$((RANDOM+10000))
+    NETAVARK_DNS_PORT="$dns_port" run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge.json \
+        setup $(get_container_netns_path)
+
+    run_in_host_netns iptables -S INPUT
+    assert "$output" =~ "-A INPUT -s 10.89.3.0/24 -d 10.89.3.1/32 -p udp -m udp --dport $dns_port -m comment --comment \"dns network: F11DC6A6D09CF\" -j ACCEPT" "ipv4 udp dns rule"
+    assert "$output" =~ "-A INPUT -s 10.89.3.0/24 -d 10.89.3.1/32 -p tcp -m tcp --dport $dns_port -m comment --comment \"dns network: F11DC6A6D09CF\" -j ACCEPT" "ipv4 tcp dns rule"
+    run_in_host_netns ip6tables -S INPUT
+    assert "$output" =~ "-A INPUT -s fd10:88:a::/64 -d fd10:88:a::1/128 -p udp -m udp --dport $dns_port -m comment --comment \"dns network: F11DC6A6D09CF\" -j ACCEPT" "ipv6 udp dns rule"
+
+    NETAVARK_DNS_PORT="$dns_port" run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge.json \
+        teardown $(get_container_netns_path)
+    run_in_host_netns iptables -S INPUT
+    assert "$output" !~ "--dport $dns_port" "dns rules are removed"
+
+    # no rules without dns
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    run_in_host_netns iptables -S INPUT
+    assert "$output" !~ "10.88.0.1" "no dns rules"
+}
+
+@test "$fw_driver - bridge with dns_port" {
+    config=$(jq '.network_info.podman.options.dns_port = "10053"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -t nat -S NETAVARK-HOSTPORT-DNAT
+    assert "${lines[1]}" == "-A NETAVARK-HOSTPORT-DNAT -d 10.88.0.1/32 -p udp -m udp --dport 53 -m comment --comment \"dns network: 1D8721804F16F\" -j DNAT --to-destination 10.88.0.1:10053" "udp dns redirect"
+    assert "${lines[2]}" == "-A NETAVARK-HOSTPORT-DNAT -d 10.88.0.1/32 -p tcp -m tcp --dport 53 -m comment --comment \"dns network: 1D8721804F16F\" -j DNAT --to-destination 10.88.0.1:10053" "tcp dns redirect"
+
+    # queries to the gateway reach the resolver port
+    nsenter -n -t $HOST_NS_PID timeout --foreground -v --kill=10 5 \
+        nc -4 -u -l -p 10053 &>"$NETAVARK_TMPDIR/nc-udp" </dev/null &
+    wait_for_port $HOST_NS_PID 10053 udp
+    run_in_container_netns nc -4 -u -w 1 10.88.0.1 53 <<<"udp query"
+    wait
+    run cat "$NETAVARK_TMPDIR/nc-udp"
+    assert "$output" == "udp query" "udp query is redirected"
+
+    nsenter -n -t $HOST_NS_PID timeout --foreground -v --kill=10 5 \
+        nc -4 -l -p 10053 &>"$NETAVARK_TMPDIR/nc-tcp" </dev/null &
+    wait_for_port $HOST_NS_PID 10053 tcp
+    run_in_container_netns nc -4 -w 1 10.88.0.1 53 <<<"tcp query"
+    wait
+    run cat "$NETAVARK_TMPDIR/nc-tcp"
+    assert "$output" == "tcp query" "tcp query is redirected"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -t nat -S NETAVARK-HOSTPORT-DNAT
+    assert "${#lines[@]}" = 1 "dns redirect rules are removed"
+
+    config=$(jq '.network_info.podman.options.dns_port = "10053" | .network_info.podman.dns_enabled = true' \
+        ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "dns_port 10053 cannot be used with dns_enabled, the netavark dns server listens on port 53" "conflicts with the netavark dns server"
+}
+
+@test "$fw_driver - update-dns-rules replaces the dns rules" {
+    NETAVARK_DNS_PORT=10053 run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge.json \
+        setup $(get_container_netns_path)
+    run_in_host_netns iptables -t nat -S POSTROUTING
+    postrouting="$output"
+
+    # the resolver was restarted on another port
+    run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge.json update-dns-rules podman1 --dns-port 10054
+
+    run_in_host_netns iptables -t nat -S NETAVARK-HOSTPORT-DNAT
+    assert "${lines[1]}" == "-A NETAVARK-HOSTPORT-DNAT -d 10.89.3.1/32 -p udp -m udp --dport 53 -m comment --comment \"dns network: F11DC6A6D09CF\" -j DNAT --to-destination 10.89.3.1:10054" "ipv4 redirect uses the new port"
+    assert "${lines[2]}" == "-A NETAVARK-HOSTPORT-DNAT -d 10.89.3.1/32 -p tcp -m tcp --dport 53 -m comment --comment \"dns network: F11DC6A6D09CF\" -j DNAT --to-destination 10.89.3.1:10054" "ipv4 tcp redirect uses the new port"
+    assert "${#lines[@]}" = 3 "the old redirect rules are removed"
+    run_in_host_netns ip6tables -t nat -S NETAVARK-HOSTPORT-DNAT
+    assert "${lines[1]}" == "-A NETAVARK-HOSTPORT-DNAT -d fd10:88:a::1/128 -p udp -m udp --dport 53 -m comment --comment \"dns network: F11DC6A6D09CF\" -j DNAT --to-destination [fd10:88:a::1]:10054" "ipv6 redirect uses the new port"
+    run_in_host_netns iptables -S INPUT
+    assert "$output" =~ "--dport 10054 -m comment" "allow rule uses the new port"
+    assert "$output" !~ "--dport 10053" "old allow rules are removed"
+
+    # other rules and the interfaces are untouched
+    run_in_host_netns iptables -t nat -S POSTROUTING
+    assert "$output" == "$postrouting" "network rules are unchanged"
+    run_in_container_netns ip link show eth0
+
+    # queries reach the new port
+    nsenter -n -t $HOST_NS_PID timeout --foreground -v --kill=10 5 \
+        nc -4 -u -l -p 10054 &>"$NETAVARK_TMPDIR/nc-udp" </dev/null &
+    wait_for_port $HOST_NS_PID 10054 udp
+    run_in_container_netns nc -4 -u -w 1 10.89.3.1 53 <<<"query"
+    wait
+    run cat "$NETAVARK_TMPDIR/nc-udp"
+    assert "$output" == "query" "query is redirected to the new port"
+
+    # teardown removes the new rules, running it twice changes nothing
+    run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge.json update-dns-rules podman1 --dns-port 10054
+    NETAVARK_DNS_PORT=10054 run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge.json \
+        teardown $(get_container_netns_path)
+    run_in_host_netns iptables -t nat -S NETAVARK-HOSTPORT-DNAT
+    assert "${#lines[@]}" = 1 "dns rules are removed on teardown"
+    run_in_host_netns iptables -S INPUT
+    assert "$output" !~ "dns network" "allow rules are removed on teardown"
+
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/dualstack-bridge.json update-dns-rules other --dns-port 10054
+    assert_json ".error" "network info for network other not found" "unknown network"
+
+    # only networks setup creates dns rules for are accepted
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json update-dns-rules podman --dns-port 10054
+    assert_json ".error" "network podman has no dns rules, only non internal bridge networks with dns_enabled or the dns_port option have them" "network without dns"
+    config=$(jq '.network_info.podman.options.mode = "l2" | .network_info.podman.options.dns_port = "10053"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark update-dns-rules podman --dns-port 10054 <<<"$config"
+    assert_json ".error" "network podman has no dns rules, only non internal bridge networks with dns_enabled or the dns_port option have them" "l2 network"
+    config=$(jq '.network_info.podman1.options.dns_port = "10053"' ${TESTSDIR}/testfiles/dualstack-bridge.json)
+    expected_rc=1 run_netavark update-dns-rules podman1 --dns-port 10054 <<<"$config"
+    assert_json ".error" "dns_port 10053 cannot be used with dns_enabled, the netavark dns server listens on port 10054" "dns_port is checked like setup"
+}
+
 @test "$fw_driver - dual stack dns with alt port" {
     # get a random port directly to avoid low ports e.g. 53 would not create iptables
     dns_port=$((RANDOM+10000))
@@ -324,9 +1842,11 @@ fw_driver=iptables
 
     # check iptables
     run_in_host_netns iptables -t nat -S NETAVARK-HOSTPORT-DNAT
-    assert "${lines[1]}" == "-A NETAVARK-HOSTPORT-DNAT -d 10.89.3.1/32 -p udp -m udp --dport 53 -j DNAT --to-destination 10.89.3.1:$dns_port" "ipv4 dns forward rule"
+    assert "${lines[1]}" == "-A NETAVARK-HOSTPORT-DNAT -d 10.89.3.1/32 -p udp -m udp --dport 53 -m comment --comment \"dns network: F11DC6A6D09CF\" -j DNAT --to-destination 10.89.3.1:$dns_port" "ipv4 dns forward rule"
+    assert "${lines[2]}" == "-A NETAVARK-HOSTPORT-DNAT -d 10.89.3.1/32 -p tcp -m tcp --dport 53 -m comment --comment \"dns network: F11DC6A6D09CF\" -j DNAT --to-destination 10.89.3.1:$dns_port" "ipv4 dns tcp forward rule"
     run_in_host_netns ip6tables -t nat -S NETAVARK-HOSTPORT-DNAT
-    assert "${lines[1]}" == "-A NETAVARK-HOSTPORT-DNAT -d fd10:88:a::1/128 -p udp -m udp --dport 53 -j DNAT --to-destination [fd10:88:a::1]:$dns_port" "ipv6 dns forward rule"
+    assert "${lines[1]}" == "-A NETAVARK-HOSTPORT-DNAT -d fd10:88:a::1/128 -p udp -m udp --dport 53 -m comment --comment \"dns network: F11DC6A6D09CF\" -j DNAT --to-destination [fd10:88:a::1]:$dns_port" "ipv6 dns forward rule"
+    assert "${lines[2]}" == "-A NETAVARK-HOSTPORT-DNAT -d fd10:88:a::1/128 -p tcp -m tcp --dport 53 -m comment --comment \"dns network: F11DC6A6D09CF\" -j DNAT --to-destination [fd10:88:a::1]:$dns_port" "ipv6 dns tcp forward rule"
 
     # check aardvark config and running
     run_helper cat "$NETAVARK_TMPDIR/config/aardvark-dns/podman1"
@@ -366,7 +1886,7 @@ fw_driver=iptables
     run_in_container_netns ip link add eth0 type dummy
 
     expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
-    assert_json ".error" "create veth pair: interface eth0 already exists on container namespace: Netlink error: File exists (os error 17)" "interface exists on netns"
+    assert_json ".error" "create veth pair: interface eth0 already exists on container namespace: Netlink error: EEXIST: File exists (os error 17)" "interface exists on netns"
 }
 
 @test "$fw_driver - port forwarding ipv4 - tcp" {
@@ -483,6 +2003,68 @@ fw_driver=iptables
     test_port_fw ip=6 proto=udp hostip="fd65:8371:648b:0c06::1"
 }
 
+@test "$fw_driver - port forwarding with allowed sources" {
+    run_netavark --file ${TESTSDIR}/testfiles/allowed-sources.json setup $(get_container_netns_path)
+
+    # only connections from the allowed ipv4 source must be forwarded
+    run_in_host_netns iptables -S NETAVARK-DN-7A94BAAB79117 -t nat
+    assert "$output" =~ "-A NETAVARK-DN-7A94BAAB79117 -s 192.0.2.0/24 -p tcp -m tcp --dport 8080 -j DNAT --to-destination 10.88.0.2:80" "DNAT rule with allowed source"
+    assert "${#lines[@]}" = 4 "too many NETAVARK-DN-7A94BAAB79117 rules"
+
+    # other sources are rejected on the host port
+    run_in_host_netns iptables -S INPUT
+    assert "$output" =~ "-A INPUT -p tcp -m tcp --dport 8080 -m comment --comment \"dnat name: podman id: 6ce776ea58b5 container: testcontainer\" -j REJECT" "reject rule for other sources"
+
+    # a host address in the allowed subnet
+    add_dummy_interface_on_host dummy0 "192.0.2.1/32"
+    nsenter -n -t "${CONTAINER_NS_PIDS[0]}" timeout --foreground -v --kill=10 5 \
+        nc -4 -k -l -p 80 &>/dev/null </dev/null &
+    wait_for_port "${CONTAINER_NS_PIDS[0]}" 80 tcp
+
+    # a listener on the host port must not be reachable either
+    nsenter -n -t $HOST_NS_PID timeout --foreground -v --kill=10 5 \
+        nc -4 -k -l -p 8080 &>/dev/null </dev/null &
+    wait_for_port "$HOST_NS_PID" 8080 tcp
+
+    run_in_host_netns nc -4 -z -w 1 -s 192.0.2.1 10.88.0.1 8080
+    # connecting from the gateway must be rejected as it is not an allowed source
+    expected_rc=1 run_in_host_netns nc -4 -z -w 1 10.88.0.1 8080
+    wait
+
+    run_netavark --file ${TESTSDIR}/testfiles/allowed-sources.json teardown $(get_container_netns_path)
+
+    expected_rc=1 run_in_host_netns iptables -nvL NETAVARK-DN-7A94BAAB79117 -t nat
+    run_in_host_netns iptables -S INPUT
+    assert "$output" !~ "dnat name" "no reject rule left"
+}
+
+@test "$fw_driver - port forwarding comment with container name" {
+    run_netavark --file ${TESTSDIR}/testfiles/allowed-sources.json setup $(get_container_netns_path)
+
+    run_in_host_netns iptables -S NETAVARK-HOSTPORT-DNAT -t nat
+    assert "$output" =~ "container: testcontainer" "comment contains the container name"
+
+    # teardown must find the rules even if the container was renamed
+    config=$(jq '.container_name = "renamed"' ${TESTSDIR}/testfiles/allowed-sources.json)
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns iptables -S NETAVARK-HOSTPORT-DNAT -t nat
+    assert "$output" !~ "dnat name" "no port forwarding rules left"
+    run_in_host_netns iptables -S INPUT
+    assert "$output" !~ "dnat name" "no reject rule left"
+    expected_rc=1 run_in_host_netns iptables -nvL NETAVARK-DN-7A94BAAB79117 -t nat
+
+    # long names with quotes are cut to fit the comment
+    long_name="it's \"$(printf 'a%.0s' {1..300})\""
+    config=$(jq --arg name "$long_name" '.container_name = $name' ${TESTSDIR}/testfiles/allowed-sources.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -S NETAVARK-HOSTPORT-DNAT -t nat
+    assert "$output" =~ "container: it_s__aaaa" "comment contains the sanitized container name"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns iptables -S NETAVARK-HOSTPORT-DNAT -t nat
+    assert "$output" !~ "dnat name" "no port forwarding rules left"
+}
+
 @test "bridge ipam none" {
            read -r -d '\0' config <<EOF
 {
@@ -785,6 +2367,140 @@ EOF
 }
 
 
+@test "$fw_driver - bridge vlan filtering" {
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-vlan-filtering.json setup $(get_container_netns_path)
+
+    run_in_host_netns ip -j --details link show podman0
+    assert_json "$output" ".[].linkinfo.info_data.vlan_filtering" == "1" "vlan filtering is enabled on the bridge"
+
+    run_in_host_netns bridge -j vlan show
+    vlans="$output"
+    assert_json "$vlans" '.[] | select(.ifname != "podman0") | .vlans[] | select(.vlan == 10) | .flags | join(",")' == "PVID,Egress Untagged" "port pvid"
+    assert_json "$vlans" '.[] | select(.ifname != "podman0") | .vlans[] | select(.vlan == 20) | has("flags")' == "false" "port tagged vlan"
+    assert_json "$vlans" '[.[] | select(.ifname != "podman0") | .vlans[] | select(.vlan == 1)] | length' == "0" "default vlan removed from port"
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-vlan-filtering.json teardown $(get_container_netns_path)
+}
+
+@test "$fw_driver - bridge with default_pvid" {
+    config=$(jq 'del(.networks.podman.pvid) | .network_info.podman.options.default_pvid = "30"' \
+        ${TESTSDIR}/testfiles/bridge-vlan-filtering.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns ip -j --details link show podman0
+    assert_json "$output" ".[].linkinfo.info_data.vlan_default_pvid" == "30" "default pvid of the bridge"
+
+    run_in_host_netns bridge -j vlan show
+    vlans="$output"
+    assert_json "$vlans" '.[] | select(.ifname != "podman0") | .vlans[] | select(.vlan == 30) | .flags | join(",")' == "PVID,Egress Untagged" "port inherits the default pvid"
+    assert_json "$vlans" '[.[] | select(.ifname != "podman0") | .vlans[] | select(.vlan == 1)] | length' == "0" "port is not in vlan 1"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    # a port pvid replaces the default pvid
+    config=$(jq '.network_info.podman.options.default_pvid = "30"' ${TESTSDIR}/testfiles/bridge-vlan-filtering.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_host_netns bridge -j vlan show
+    vlans="$output"
+    assert_json "$vlans" '.[] | select(.ifname != "podman0") | .vlans[] | select(.vlan == 10) | .flags | join(",")' == "PVID,Egress Untagged" "port pvid"
+    assert_json "$vlans" '[.[] | select(.ifname != "podman0") | .vlans[] | select(.vlan == 30)] | length' == "0" "default pvid removed from port"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    config=$(jq '.network_info.podman.options.default_pvid = "4095"' ${TESTSDIR}/testfiles/bridge-vlan-filtering.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid vlan id 4095, must be between 1 and 4094" "invalid vlan id"
+
+    config=$(jq '.network_info.podman.options.default_pvid = "30"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "default_pvid requires the vlan_filtering option" "vlan filtering is required"
+}
+
+@test "$fw_driver - bridge with veth queues" {
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-queues.json setup $(get_container_netns_path)
+
+    run_in_container_netns ip -j --details link show eth0
+    assert_json "$output" ".[].num_rx_queues" == "4" "container veth rx queues"
+    assert_json "$output" ".[].num_tx_queues" == "4" "container veth tx queues"
+
+    run_in_host_netns ip -j --details link show master podman0
+    assert_json "$output" ".[].num_rx_queues" == "4" "host veth rx queues"
+
+    run_netavark --file ${TESTSDIR}/testfiles/bridge-queues.json teardown $(get_container_netns_path)
+
+    # an unset queue count keeps the kernel default of a veth
+    run_in_host_netns ip link add vethref type veth peer name vethref1
+    run_in_host_netns ip -j --details link show vethref
+    default_tx=$(jq '.[].num_tx_queues' <<<"$output")
+    config=$(jq 'del(.network_info.podman.options.num_tx_queues)' ${TESTSDIR}/testfiles/bridge-queues.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    run_in_container_netns ip -j --details link show eth0
+    assert_json "$output" ".[].num_rx_queues" == "4" "container veth rx queues"
+    assert_json "$output" ".[].num_tx_queues" == "$default_tx" "container veth keeps the default tx queues"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+}
+
+@test "$fw_driver - bridge with invalid veth queues" {
+    config=$(jq '.network_info.podman.options.num_rx_queues = "0"' ${TESTSDIR}/testfiles/bridge-queues.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid num_rx_queues 0, must be between 1 and 256" "error message"
+
+    config=$(jq '.network_info.podman.options.num_tx_queues = "1000"' ${TESTSDIR}/testfiles/bridge-queues.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid num_tx_queues 1000, must be between 1 and 256" "error message"
+}
+
+@test "$fw_driver - bridge with gro_flush_timeout and napi_defer_hard_irqs" {
+    if [ ! -e /sys/class/net/lo/napi_defer_hard_irqs ]; then
+        skip "kernel does not support napi_defer_hard_irqs"
+    fi
+    config=$(jq '.network_info.podman.options.gro_flush_timeout = "20000" |
+        .network_info.podman.options.napi_defer_hard_irqs = "2"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    # the host sysfs does not show the container interfaces
+    run_in_container_netns unshare -m sh -c \
+        "mount -t sysfs sysfs /sys && cat /sys/class/net/eth0/gro_flush_timeout /sys/class/net/eth0/napi_defer_hard_irqs"
+    assert "${lines[0]}" == "20000" "gro_flush_timeout is applied"
+    assert "${lines[1]}" == "2" "napi_defer_hard_irqs is applied"
+}
+
+@test "$fw_driver - bridge with invalid napi_defer_hard_irqs" {
+    config=$(jq '.network_info.podman.options.napi_defer_hard_irqs = "2147483648"' ${TESTSDIR}/testfiles/simplebridge.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid napi_defer_hard_irqs 2147483648, must be at most 2147483647" "error message"
+}
+
+@test "$fw_driver - two networks with policy routing" {
+    run_netavark --file ${TESTSDIR}/testfiles/two-networks-policy-routing.json setup $(get_container_netns_path)
+
+    run_in_container_netns ip -j rule show
+    rules="$output"
+    for net in 1 2; do
+        ip="10.89.$net.2"
+        assert_json "$rules" ".[] | select(.src == \"$ip\") | .priority" == "100" "rule for $ip"
+        table=$(jq -r ".[] | select(.src == \"$ip\") | .table" <<<"$rules")
+
+        run_in_container_netns ip -j route show table $table
+        assert_json "$output" '.[] | select(.dst == "default") | .gateway' == "10.89.$net.1" "default route in table $table"
+        assert_json "$output" ".[] | select(.dst == \"10.89.$net.0/24\") | .dev" == "eth$((net - 1))" "subnet route in table $table"
+    done
+
+    run_netavark --file ${TESTSDIR}/testfiles/two-networks-policy-routing.json teardown $(get_container_netns_path)
+
+    run_in_container_netns ip -j rule show
+    assert_json "$output" '[.[] | select(.priority == 100)] | length' == "0" "policy rules removed"
+}
+
+@test "$fw_driver - teardown with a malformed policy_routing option" {
+    run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+
+    # the option is only logged, everything else is still removed
+    config=$(jq '.network_info.podman.options.policy_routing = "maybe"' ${TESTSDIR}/testfiles/simplebridge.json)
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    expected_rc=1 run_in_container_netns ip link show eth0
+    expected_rc=1 run_in_host_netns ip link show podman0
+    expected_rc=1 run_in_host_netns iptables -t nat -nvL NETAVARK-1D8721804F16F
+}
+
 @test "$fw_driver - bridge teardown" {
     create_container_ns
     configs=()
@@ -868,8 +2584,8 @@ EOF
 
     expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/two-networks.json teardown $(get_container_netns_path)
     # order is not deterministic so we match twice with different eth name
-    assert "$output" =~ 'failed to delete container veth eth0\: Netlink error\: No such device \(os error 19\)' "correct eth0 error message"
-    assert "$output" =~ 'failed to delete container veth eth1\: Netlink error\: No such device \(os error 19\)' "correct eth1 error message"
+    assert "$output" =~ 'failed to delete container veth eth0\: Netlink error\: ENODEV\: No such device \(os error 19\)' "correct eth0 error message"
+    assert "$output" =~ 'failed to delete container veth eth1\: Netlink error\: ENODEV\: No such device \(os error 19\)' "correct eth1 error message"
 
     # now make sure that it actually removed the iptables rule even with the errors
     run_in_host_netns iptables -S -t nat
@@ -877,12 +2593,39 @@ EOF
     assert "$output" !~ "--to-destination 10.89.2.2:8080" "eth1 port fw rule should not exist"
 }
 
+@test "$fw_driver - two networks teardown of one network" {
+    run_netavark --file ${TESTSDIR}/testfiles/two-networks.json setup $(get_container_netns_path)
+
+    run_netavark --file ${TESTSDIR}/testfiles/two-networks.json teardown --network t1 $(get_container_netns_path)
+    assert "" "no errors"
+
+    expected_rc=1 run_in_container_netns ip link show eth0
+    run_in_container_netns ip addr show eth1
+    assert "$output" =~ "10.89.2.2/24" "eth1 keeps its address"
+    run_in_container_netns ping -c 1 10.89.2.1
+    run_in_host_netns ping -c 1 10.89.2.2
+
+    run_in_host_netns iptables -S -t nat
+    assert "$output" !~ "--to-destination 10.89.1.2:8080" "t1 port fw rule removed"
+    assert "$output" =~ "--to-destination 10.89.2.2:8080" "t2 port fw rule kept"
+    assert "$output" !~ "-s 10.89.1.0/24" "t1 nat rules removed"
+    assert "$output" =~ "-s 10.89.2.0/24" "t2 nat rules kept"
+
+    expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/two-networks.json teardown --network t3 $(get_container_netns_path)
+    assert_json ".error" "network t3 is not configured for the container" "unknown network"
+
+    run_netavark --file ${TESTSDIR}/testfiles/two-networks.json teardown --network t2 $(get_container_netns_path)
+    expected_rc=1 run_in_container_netns ip link show eth1
+    run_in_host_netns iptables -S -t nat
+    assert "$output" !~ "--to-destination 10.89.2.2:8080" "t2 port fw rule removed"
+}
+
 @test "$fw_driver - ipv6 disabled error message" {
     # disable ipv6 in the netns
      run_in_host_netns sysctl net.ipv6.conf.all.disable_ipv6=1
 
     expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/ipv6-bridge.json setup $(get_container_netns_path)
-    assert '{"error":"add ip addr to bridge: failed to add ipv6 address, is ipv6 enabled in the kernel?: Netlink error: Permission denied (os error 13)"}' "error message"
+    assert '{"error":"add ip addr fd10:88:a::1/64 to bridge on podman1 in host namespace: failed to add ipv6 address, is ipv6 enabled in the kernel?: Netlink error: EACCES: Permission denied (os error 13)"}' "error message"
 }
 
 @test "$fw_driver - route metric from config" {
@@ -43,7 +43,9 @@ function setup() {
 }
 
 function teardown() {
-    kill -9 $FIREWALLD_PID
+    if [ -n "$FIREWALLD_PID" ]; then
+        kill -9 $FIREWALLD_PID
+    fi
     kill -9 $DBUS_PID
 
     unset DBUS_SYSTEM_BUS_ADDRESS
@@ -57,6 +59,20 @@ function teardown() {
     assert "${lines[0]}" "==" "[INFO  netavark::firewall] Using firewalld firewall driver" "firewalld driver is in use"
 }
 
+@test "--firewall-driver firewalld requires a running firewalld" {
+    RUST_LOG=netavark=info run_netavark --firewall-driver firewalld \
+        --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert "${lines[0]}" "==" "[INFO  netavark::firewall] Using firewalld firewall driver" "firewalld driver is in use"
+    run_netavark --firewall-driver firewalld --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+
+    kill -9 $FIREWALLD_PID
+    wait $FIREWALLD_PID || true
+    FIREWALLD_PID=
+    expected_rc=1 run_netavark --firewall-driver firewalld \
+        --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert_json ".error" "firewalld firewall backend requested but firewalld is not running" "firewalld is not running"
+}
+
 @test "$fw_driver - simple bridge" {
     run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
     result="$output"
@@ -246,7 +262,7 @@ function teardown() {
     run_in_container_netns ip link add eth0 type dummy
 
     expected_rc=1 run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
-    assert_json ".error" "create veth pair: interface eth0 already exists on container namespace: Netlink error: File exists (os error 17)" "interface exists on netns"
+    assert_json ".error" "create veth pair: interface eth0 already exists on container namespace: Netlink error: EEXIST: File exists (os error 17)" "interface exists on netns"
 }
 
 @test "$fw_driver - port forwarding ipv4 - tcp" {
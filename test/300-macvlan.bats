@@ -33,6 +33,7 @@ function setup() {
     run_in_container_netns ip r
     assert "$output" "=~" "default via 10.88.0.1" "gateway must be there in default route"
     assert_json "$result" ".podman.interfaces.eth0.subnets[0].gateway" == "10.88.0.1" "Result contains gateway address"
+    assert_json "$result" ".podman.gateway[0]" == "10.88.0.1" "Result contains network gateway"
 
     run_in_container_netns cat /proc/sys/net/ipv6/conf/eth0/autoconf
     assert "0" "autoconf is disabled"
@@ -84,6 +85,147 @@ function setup() {
     assert "" "no errors"
 }
 
+@test "macvlan setup no link up" {
+    config=$(jq '.network_info.podman.options = {"no_link_up": "true"}' ${TESTSDIR}/testfiles/macvlan.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    result="$output"
+    assert_json "$result" ".podman.interfaces.eth0.subnets[0].ipnet" "==" "10.88.0.2/16" "Result contains correct IP address"
+
+    run_in_container_netns ip -j link show eth0
+    assert_json "$output" '.[].flags | index("UP")' "==" "null" "Container interface is down"
+
+    run_in_container_netns ip addr show eth0
+    assert "$output" "=~" "10.88.0.2/16" "IP address is assigned"
+
+    run_in_container_netns ip r
+    assert "$output" "!~" "default" "no default route without link"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    assert "" "no errors"
+}
+
+@test "macvlan setup carrier wait" {
+    # dummy0 is down so it never gets carrier
+    config=$(jq '.network_info.podman.options = {"carrier_wait": "1"}' ${TESTSDIR}/testfiles/macvlan.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json "$output" ".podman.warnings[0]" "==" "parent interface dummy0 has no carrier after 1 seconds" "carrier warning"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    assert "" "no errors"
+
+    run_in_host_netns ip link set dummy0 up
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json "$output" '.podman | has("warnings")' "==" "false" "no warnings with carrier"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    config=$(jq '.network_info.podman.options = {"carrier_wait": "61"}' ${TESTSDIR}/testfiles/macvlan.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid carrier_wait 61, must be at most 60 seconds" "carrier_wait bound"
+}
+
+@test "macvlan setup waits for the parent with parent_wait" {
+    config=$(jq '.network_info.podman.network_interface = "uplink0" |
+        .network_info.podman.options = {"parent_wait": "5"}' ${TESTSDIR}/testfiles/macvlan.json)
+
+    # the parent appears while setup waits for it
+    (sleep 1; nsenter -n -t $HOST_NS_PID ip link add uplink0 type dummy) &
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    wait
+    run_in_container_netns ip -j --details link show eth0
+    assert_json "$output" ".[].linkinfo.info_kind" "==" "macvlan" "Container interface is a macvlan device"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    run_in_host_netns ip link del uplink0
+
+    config=$(jq '.network_info.podman.network_interface = "uplink0" |
+        .network_info.podman.options = {"parent_wait": "1"}' ${TESTSDIR}/testfiles/macvlan.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "parent interface uplink0 does not exist after 1 seconds" "missing parent after the timeout"
+
+    config=$(jq '.network_info.podman.options = {"parent_wait": "61"}' ${TESTSDIR}/testfiles/macvlan.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid parent_wait 61, must be at most 60 seconds" "parent_wait bound"
+}
+
+@test "macvlan setup on a bond parent" {
+    run_in_host_netns ip link add bond0 type bond mode active-backup
+    run_in_host_netns ip link set dummy0 master bond0
+    run_in_host_netns ip link set bond0 up
+    config=$(jq '.network_info.podman.network_interface = "bond0"' ${TESTSDIR}/testfiles/macvlan.json)
+
+    # dummy0 is down so the bond has no active port
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json "$output" ".podman.warnings[0]" "==" "parent interface bond0 is a bond without an active port" "bond warning"
+    run_in_container_netns ip -j --details link show eth0
+    assert_json "$output" ".[].linkinfo.info_kind" "==" "macvlan" "Container interface is a macvlan device"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    run_in_host_netns ip link set dummy0 up
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json "$output" '.podman | has("warnings")' "==" "false" "no warnings with an active port"
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+
+    config=$(jq '.network_info.podman.network_interface = "dummy0"' ${TESTSDIR}/testfiles/macvlan.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "parent interface dummy0 is a port of the bond bond0, use the bond as parent instead" "bond port is rejected"
+}
+
+@test "macvlan setup with ifalias" {
+    config=$(jq '.network_info.podman.options = {"ifalias": "rack 4 uplink"}' ${TESTSDIR}/testfiles/macvlan.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_container_netns cat /sys/class/net/eth0/ifalias
+    assert "$output" == "rack 4 uplink" "container interface alias"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    assert "" "no errors"
+}
+
+@test "macvlan setup with docker parent option" {
+    config=$(jq 'del(.network_info.podman.network_interface) | .network_info.podman.options = {"parent": "dummy0"}' ${TESTSDIR}/testfiles/macvlan.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_container_netns ip -j --details link show eth0
+    link_info="$output"
+    assert_json "$link_info" ".[].linkinfo.info_kind" "==" "macvlan" "Container interface is a macvlan device"
+    run_in_host_netns ip -j link show dummy0
+    parent_index=$(jq '.[0].ifindex' <<<"$output")
+    assert_json "$link_info" ".[0].link_index" "==" "$parent_index" "macvlan uses the parent interface"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    assert "" "no errors"
+}
+
+@test "macvlan setup with parent in another namespace" {
+    # the parent lives in an auxiliary namespace, not on the host
+    create_container_ns
+    run_in_container_netns 1 ip link add auxparent0 type dummy
+    run_in_container_netns 1 ip link set auxparent0 up
+
+    config=$(jq --arg ns "$(get_container_netns_path 1)" '.network_info.podman.network_interface = "auxparent0" |
+        .network_info.podman.options = {"parent_netns": $ns}' ${TESTSDIR}/testfiles/macvlan.json)
+    run_netavark setup $(get_container_netns_path) <<<"$config"
+
+    run_in_container_netns ip -j --details link show eth0
+    link_info="$output"
+    assert_json "$link_info" ".[].linkinfo.info_kind" "==" "macvlan" "Container interface is a macvlan device"
+    run_in_container_netns 1 ip -j link show auxparent0
+    parent_index=$(jq '.[0].ifindex' <<<"$output")
+    assert_json "$link_info" ".[0].link_index" "==" "$parent_index" "macvlan uses the parent interface"
+    assert_json "$link_info" ".[0].link_netnsid" "!=" "null" "parent is in another namespace"
+
+    run_netavark teardown $(get_container_netns_path) <<<"$config"
+    assert "" "no errors"
+
+    config=$(jq '.network_info.podman.options = {"parent_netns": "/test/1"}' ${TESTSDIR}/testfiles/macvlan.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" 'invalid parent_netns "/test/1": namespace path "/test/1" does not exist' "missing namespace"
+
+    config=$(jq --arg ns "$(get_container_netns_path)" '.network_info.podman.options = {"parent_netns": $ns}' ${TESTSDIR}/testfiles/macvlan.json)
+    expected_rc=1 run_netavark setup $(get_container_netns_path) <<<"$config"
+    assert_json ".error" "invalid parent_netns \"$(get_container_netns_path)\": it is the container namespace" "container namespace"
+}
+
 @test "macvlan setup internal" {
     run_netavark --file ${TESTSDIR}/testfiles/macvlan-internal.json setup $(get_container_netns_path)
     result="$output"
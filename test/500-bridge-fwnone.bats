@@ -11,3 +11,15 @@ fw_driver=none
     RUST_LOG=netavark=info NETAVARK_FW="none" run_netavark --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
     assert "${lines[0]}" "==" "[INFO  netavark::firewall] Not using firewall" "none firewall driver is in use"
 }
+
+@test "--firewall-driver none skips the firewall" {
+    RUST_LOG=netavark=info NETAVARK_FW=iptables run_netavark --firewall-driver none \
+        --file ${TESTSDIR}/testfiles/simplebridge.json setup $(get_container_netns_path)
+    assert "${lines[0]}" "==" "[INFO  netavark::firewall] Not using firewall" "none firewall driver is in use"
+
+    run_in_host_netns iptables-save
+    assert "$output" "!~" "NETAVARK" "no netavark rules are added"
+    run_in_container_netns ip link show eth0
+
+    run_netavark --firewall-driver none --file ${TESTSDIR}/testfiles/simplebridge.json teardown $(get_container_netns_path)
+}